@@ -0,0 +1,22 @@
+// cargo +nightly fuzz run cartridge_file
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// the emulator is only a binary, so the parser is pulled in directly. It doesn't depend on anything else in the crate.
+#[allow(dead_code)]
+#[path = "../../src/cartridge_file.rs"]
+mod cartridge_file;
+
+use cartridge_file::{Cartridge, Strictness};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Cartridge::from_bytes_with_strictness(data.to_vec(), Strictness::Strict);
+    if let Ok(cartridge) = Cartridge::from_bytes(data.to_vec()) {
+        // whatever loads has to write back out as something that loads too
+        if let Ok(bytes) = cartridge.to_bytes() {
+            Cartridge::from_bytes(bytes).unwrap();
+        }
+    }
+});
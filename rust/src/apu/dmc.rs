@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+
+use crate::region::Region;
+
+/// Timer periods in CPU cycles.
+const RATES_NTSC: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const RATES_PAL: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+#[derive(Serialize, Deserialize)]
+pub struct DMC {
+    irq_enabled: bool,
+    irq: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+    output_level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    /// Left out of states, the region doesn't change.
+    #[serde(skip, default = "ntsc_rates")]
+    rates: &'static [u16; 16],
+}
+
+fn ntsc_rates() -> &'static [u16; 16] {
+    &RATES_NTSC
+}
+
+impl DMC {
+    pub fn new(region: Region) -> Self {
+        let rates = if region.has_pal_apu() {
+            &RATES_PAL
+        } else {
+            &RATES_NTSC
+        };
+        Self {
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            timer_period: rates[0],
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            rates,
+        }
+    }
+
+    /// Takes everything but the region's rates from the state.
+    pub fn load_state(&mut self, state: Self) {
+        *self = Self {
+            rates: self.rates,
+            ..state
+        };
+    }
+
+    /// Register is 0..4, relative to $4010.
+    pub fn write8(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = (value & 0b1000_0000) != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = (value & 0b0100_0000) != 0;
+                self.timer_period = self.rates[(value & 0b0000_1111) as usize];
+            }
+            1 => {
+                self.output_level = value & 0b0111_1111;
+            }
+            2 => {
+                self.sample_address = 0xc000 | ((value as u16) << 6);
+            }
+            _ => {
+                self.sample_length = ((value as u16) << 4) | 1;
+            }
+        }
+    }
+
+    /// Writing the DMC bit of $4015 restarts the sample if it had finished, or stops it.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    /// The address the memory reader wants to fetch next, if the sample buffer is empty.
+    pub fn pending_read(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    /// Completes a read started by `pending_read`.
+    pub fn fill_sample_buffer(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+        // address wraps around to $8000, not $0000
+        self.current_address = self.current_address.wrapping_add(1) | 0x8000;
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if (self.shift_register & 1) != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(value) => {
+                    self.silence = false;
+                    self.shift_register = value;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+}
@@ -0,0 +1,286 @@
+// see https://www.nesdev.org/wiki/FDS_audio
+
+use serde::{Deserialize, Serialize};
+
+use crate::save_state;
+
+pub const WAVETABLE_START: u16 = 0x4040;
+pub const WAVETABLE_END: u16 = 0x4080;
+pub const REGISTERS_END: u16 = 0x4098;
+const VOLUME_ENVELOPE_ADDRESS: u16 = 0x4080;
+const FREQUENCY_LOW_ADDRESS: u16 = 0x4082;
+const FREQUENCY_HIGH_ADDRESS: u16 = 0x4083;
+const MOD_ENVELOPE_ADDRESS: u16 = 0x4084;
+const MOD_COUNTER_ADDRESS: u16 = 0x4085;
+const MOD_FREQUENCY_LOW_ADDRESS: u16 = 0x4086;
+const MOD_FREQUENCY_HIGH_ADDRESS: u16 = 0x4087;
+const MOD_TABLE_ADDRESS: u16 = 0x4088;
+const WAVE_WRITE_ADDRESS: u16 = 0x4089;
+const ENVELOPE_SPEED_ADDRESS: u16 = 0x408a;
+const VOLUME_GAIN_ADDRESS: u16 = 0x4090;
+const MOD_GAIN_ADDRESS: u16 = 0x4092;
+
+const WAVETABLE_SIZE: usize = (WAVETABLE_END - WAVETABLE_START) as usize;
+const MOD_TABLE_SIZE: usize = 32;
+
+/// How much the mod counter changes by for each mod table entry, None resets it.
+const MOD_ADJUSTMENTS: [Option<i8>; 8] = [
+    Some(0),
+    Some(1),
+    Some(2),
+    Some(4),
+    None,
+    Some(-4),
+    Some(-2),
+    Some(-1),
+];
+
+/// Master volume of 2/2, 2/3, 2/4, 2/5 as a fraction of 60ths.
+const MASTER_VOLUMES: [u32; 4] = [60, 40, 30, 24];
+
+/// Gains above this still count down from where they are, but output as if they were this.
+const MAX_GAIN: u8 = 32;
+const MAX_OUTPUT: f32 = (63 * MAX_GAIN as u32 * 60) as f32;
+
+const DEFAULT_ENVELOPE_SPEED: u8 = 0xe8;
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    disabled: bool,
+    increasing: bool,
+    speed: u8,
+    gain: u8,
+    timer: u32,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Self {
+            disabled: true,
+            increasing: false,
+            speed: 0,
+            gain: 0,
+            timer: 0,
+        }
+    }
+
+    /// $4080 or $4084.
+    fn write8(&mut self, value: u8, master_speed: u8) {
+        self.disabled = (value & 0b1000_0000) != 0;
+        self.increasing = (value & 0b0100_0000) != 0;
+        self.speed = value & 0b0011_1111;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.reset_timer(master_speed);
+    }
+
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (master_speed as u32 + 1) * (self.speed as u32 + 1);
+    }
+
+    /// Clocked every CPU cycle unless envelopes are halted.
+    fn clock(&mut self, master_speed: u8) {
+        if self.disabled || master_speed == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.reset_timer(master_speed);
+        if self.increasing {
+            if self.gain < MAX_GAIN {
+                self.gain += 1;
+            }
+        } else if self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+}
+
+/// The wavetable channel the RAM adapter adds.
+#[derive(Serialize, Deserialize)]
+pub struct FdsAudio {
+    #[serde(with = "save_state::hex")]
+    wavetable: [u8; WAVETABLE_SIZE],
+    wave_write_enabled: bool,
+    wave_halted: bool,
+    wave_accumulator: u32,
+    wave_position: u8,
+    frequency: u16,
+    envelopes_halted: bool,
+    envelope_speed: u8,
+    volume: Envelope,
+    master_volume: u8,
+    mod_envelope: Envelope,
+    #[serde(with = "save_state::hex")]
+    mod_table: [u8; MOD_TABLE_SIZE],
+    mod_position: u8,
+    mod_halted: bool,
+    mod_accumulator: u32,
+    mod_frequency: u16,
+    /// 7 bit signed.
+    mod_counter: i8,
+    /// Held while the wavetable is being written, like the real thing.
+    last_output: u32,
+}
+
+impl FdsAudio {
+    pub fn new() -> Self {
+        Self {
+            wavetable: [0; WAVETABLE_SIZE],
+            wave_write_enabled: false,
+            wave_halted: true,
+            wave_accumulator: 0,
+            wave_position: 0,
+            frequency: 0,
+            envelopes_halted: false,
+            envelope_speed: DEFAULT_ENVELOPE_SPEED,
+            volume: Envelope::new(),
+            master_volume: 0,
+            mod_envelope: Envelope::new(),
+            mod_table: [0; MOD_TABLE_SIZE],
+            mod_position: 0,
+            mod_halted: true,
+            mod_accumulator: 0,
+            mod_frequency: 0,
+            mod_counter: 0,
+            last_output: 0,
+        }
+    }
+
+    /// Only the wavetable and the two gains can be read, everything else is open bus.
+    pub fn read8(&self, address: u16) -> Option<u8> {
+        match address {
+            WAVETABLE_START..WAVETABLE_END => Some(if self.wave_write_enabled {
+                self.wavetable[(address - WAVETABLE_START) as usize]
+            } else {
+                self.wavetable[self.wave_position as usize]
+            }),
+            VOLUME_GAIN_ADDRESS => Some(self.volume.gain),
+            MOD_GAIN_ADDRESS => Some(self.mod_envelope.gain),
+            _ => None,
+        }
+    }
+
+    pub fn write8(&mut self, address: u16, value: u8) {
+        match address {
+            WAVETABLE_START..WAVETABLE_END if self.wave_write_enabled => {
+                self.wavetable[(address - WAVETABLE_START) as usize] = value & 0b0011_1111;
+            }
+            VOLUME_ENVELOPE_ADDRESS => self.volume.write8(value, self.envelope_speed),
+            FREQUENCY_LOW_ADDRESS => self.frequency = (self.frequency & 0x0f00) | (value as u16),
+            FREQUENCY_HIGH_ADDRESS => {
+                self.frequency = (self.frequency & 0x00ff) | (((value & 0x0f) as u16) << 8);
+                self.wave_halted = (value & 0b1000_0000) != 0;
+                self.envelopes_halted = (value & 0b0100_0000) != 0;
+                if self.wave_halted {
+                    self.wave_accumulator = 0;
+                    self.wave_position = 0;
+                }
+                if self.envelopes_halted {
+                    self.volume.reset_timer(self.envelope_speed);
+                    self.mod_envelope.reset_timer(self.envelope_speed);
+                }
+            }
+            MOD_ENVELOPE_ADDRESS => self.mod_envelope.write8(value, self.envelope_speed),
+            MOD_COUNTER_ADDRESS => self.mod_counter = sign_extend_7(value),
+            MOD_FREQUENCY_LOW_ADDRESS => {
+                self.mod_frequency = (self.mod_frequency & 0x0f00) | (value as u16)
+            }
+            MOD_FREQUENCY_HIGH_ADDRESS => {
+                self.mod_frequency = (self.mod_frequency & 0x00ff) | (((value & 0x0f) as u16) << 8);
+                self.mod_halted = (value & 0b1000_0000) != 0;
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            }
+            // the table can only be written while the unit is halted, and each write advances it
+            MOD_TABLE_ADDRESS if self.mod_halted => {
+                self.mod_table[self.mod_position as usize] = value & 0b111;
+                self.mod_position = (self.mod_position + 1) % MOD_TABLE_SIZE as u8;
+            }
+            WAVE_WRITE_ADDRESS => {
+                self.wave_write_enabled = (value & 0b1000_0000) != 0;
+                self.master_volume = value & 0b11;
+            }
+            ENVELOPE_SPEED_ADDRESS => self.envelope_speed = value,
+            _ => (),
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    pub fn clock(&mut self) {
+        if !self.envelopes_halted && !self.wave_halted {
+            self.volume.clock(self.envelope_speed);
+            self.mod_envelope.clock(self.envelope_speed);
+        }
+
+        if !self.mod_halted && self.mod_frequency != 0 {
+            self.mod_accumulator += self.mod_frequency as u32;
+            if self.mod_accumulator >= 0x1_0000 {
+                self.mod_accumulator &= 0xffff;
+                let entry = self.mod_table[self.mod_position as usize];
+                self.mod_counter = match MOD_ADJUSTMENTS[entry as usize] {
+                    Some(x) => sign_extend_7(self.mod_counter.wrapping_add(x) as u8),
+                    None => 0,
+                };
+                self.mod_position = (self.mod_position + 1) % MOD_TABLE_SIZE as u8;
+            }
+        }
+
+        if !self.wave_halted {
+            self.wave_accumulator += self.pitch();
+            if self.wave_accumulator >= 0x1_0000 {
+                self.wave_position = ((self.wave_position as u32 + (self.wave_accumulator >> 16))
+                    % WAVETABLE_SIZE as u32) as u8;
+                self.wave_accumulator &= 0xffff;
+            }
+        }
+
+        if !self.wave_write_enabled {
+            self.last_output = self.wavetable[self.wave_position as usize] as u32
+                * self.volume.gain.min(MAX_GAIN) as u32
+                * MASTER_VOLUMES[self.master_volume as usize];
+        }
+    }
+
+    /// From 0 to 1.
+    pub fn output(&self) -> f32 {
+        self.last_output as f32 / MAX_OUTPUT
+    }
+
+    /// The wave frequency after modulation, straight from the algorithm on the wiki.
+    fn pitch(&self) -> u32 {
+        if self.mod_halted {
+            return self.frequency as u32;
+        }
+        let mut temp = self.mod_counter as i32 * self.mod_envelope.gain as i32;
+        let remainder = temp & 0x0f;
+        temp >>= 4;
+        if remainder > 0 && (temp & 0x80) == 0 {
+            if self.mod_counter < 0 {
+                temp -= 1;
+            } else {
+                temp += 2;
+            }
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= self.frequency as i32;
+        let remainder = temp & 0x3f;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        (self.frequency as i32 + temp).max(0) as u32
+    }
+}
+
+fn sign_extend_7(value: u8) -> i8 {
+    ((value << 1) as i8) >> 1
+}
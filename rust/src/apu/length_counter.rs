@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+#[derive(Serialize, Deserialize)]
+pub struct LengthCounter {
+    enabled: bool,
+    halted: bool,
+    value: u8,
+}
+
+impl LengthCounter {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            halted: false,
+            value: 0,
+        }
+    }
+
+    /// Disabling a channel through $4015 also silences it immediately.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.value = 0;
+        }
+    }
+
+    pub fn set_halted(&mut self, halted: bool) {
+        self.halted = halted;
+    }
+
+    /// Index is the top 5 bits of the channel's length register, already shifted down.
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.value = LENGTH_TABLE[(index & 0b0001_1111) as usize];
+        }
+    }
+
+    /// Clocked by the frame counter on half frames.
+    pub fn clock(&mut self) {
+        if !self.halted && self.value > 0 {
+            self.value -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.value > 0
+    }
+}
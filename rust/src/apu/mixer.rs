@@ -0,0 +1,166 @@
+// see https://www.nesdev.org/wiki/APU_Mixer
+
+use std::{fmt::Display, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    DMC,
+    /// Whatever sound chip the cartridge has, mixed in linearly.
+    Expansion,
+}
+
+const CHANNEL_COUNT: usize = 6;
+
+impl Channel {
+    pub const ALL: [Channel; CHANNEL_COUNT] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::DMC,
+        Channel::Expansion,
+    ];
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::DMC => "dmc",
+            Channel::Expansion => "expansion",
+        }
+    }
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Channel::ALL
+            .iter()
+            .find(|x| x.name().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "unrecognized channel {:?}, expected one of {}",
+                    s,
+                    Channel::ALL.map(|x| x.name()).join(", ")
+                )
+            })
+    }
+}
+
+/// What the expansion channel contributes at its loudest, about 2.4 times a pulse channel at full volume.
+const EXPANSION_MAX_LEVEL: f32 = 0.36;
+
+/// Combines the channels the way the hardware does, with per-channel debug controls applied to each channel's level
+/// before mixing.
+pub struct Mixer {
+    gains: [f32; CHANNEL_COUNT],
+    muted: [bool; CHANNEL_COUNT],
+    soloed: [bool; CHANNEL_COUNT],
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self {
+            gains: [1.0; CHANNEL_COUNT],
+            muted: [false; CHANNEL_COUNT],
+            soloed: [false; CHANNEL_COUNT],
+        }
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel.index()] = muted;
+    }
+
+    /// While any channel is soloed every channel that isn't is silent.
+    pub fn set_soloed(&mut self, channel: Channel, soloed: bool) {
+        self.soloed[channel.index()] = soloed;
+    }
+
+    /// Scales the channel's level going into the mixer, so 1 is unchanged.
+    pub fn set_gain(&mut self, channel: Channel, gain: f32) {
+        self.gains[channel.index()] = gain.max(0.0);
+    }
+
+    /// The expansion level is in 0..1, the rest are the channels' DAC inputs.
+    pub fn mix(
+        &self,
+        pulse_1: u8,
+        pulse_2: u8,
+        triangle: u8,
+        noise: u8,
+        dmc: u8,
+        expansion: f32,
+    ) -> f32 {
+        let pulse = self.level(Channel::Pulse1, pulse_1) + self.level(Channel::Pulse2, pulse_2);
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let tnd = self.level(Channel::Triangle, triangle) / 8227.0
+            + self.level(Channel::Noise, noise) / 12241.0
+            + self.level(Channel::DMC, dmc) / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out + self.level(Channel::Expansion, expansion) * EXPANSION_MAX_LEVEL
+    }
+
+    fn level<T>(&self, channel: Channel, value: T) -> f32
+    where
+        T: Into<f32>,
+    {
+        let i = channel.index();
+        let any_soloed = self.soloed.iter().any(|x| *x);
+        if self.muted[i] || (any_soloed && !self.soloed[i]) {
+            0.0
+        } else {
+            value.into() * self.gains[i]
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Channel, Mixer};
+
+    #[test]
+    pub fn expansion() {
+        let mut mixer = Mixer::new();
+        let full = mixer.mix(0, 0, 0, 0, 0, 1.0);
+        assert!(full > 0.0);
+        mixer.set_gain(Channel::Expansion, 0.5);
+        assert_eq!(mixer.mix(0, 0, 0, 0, 0, 1.0), full / 2.0);
+
+        mixer.set_soloed(Channel::Pulse1, true);
+        assert_eq!(mixer.mix(0, 0, 0, 0, 0, 1.0), 0.0);
+        mixer.set_soloed(Channel::Expansion, true);
+        assert_eq!(mixer.mix(0, 0, 0, 0, 0, 1.0), full / 2.0);
+
+        mixer.set_muted(Channel::Expansion, true);
+        assert_eq!(mixer.mix(0, 0, 0, 0, 0, 1.0), 0.0);
+        assert_eq!("Expansion".parse(), Ok(Channel::Expansion));
+    }
+}
@@ -0,0 +1,393 @@
+// see https://www.nesdev.org/wiki/APU
+
+mod dmc;
+mod envelope;
+pub mod fds;
+mod length_counter;
+mod mixer;
+mod noise;
+mod pulse;
+mod triangle;
+
+use dmc::DMC;
+pub use mixer::Channel;
+use mixer::Mixer;
+use noise::Noise;
+use pulse::Pulse;
+use triangle::Triangle;
+
+use serde::{Deserialize, Serialize};
+
+use crate::region::Region;
+
+const REGISTERS_START: u16 = 0x4000;
+const PULSE_1_END: u16 = 0x4004;
+const PULSE_2_END: u16 = 0x4008;
+const TRIANGLE_END: u16 = 0x400c;
+const NOISE_END: u16 = 0x4010;
+const DMC_END: u16 = 0x4014;
+const OAM_DMA_ADDRESS: u16 = 0x4014;
+pub const STATUS_ADDRESS: u16 = 0x4015;
+pub const FRAME_COUNTER_ADDRESS: u16 = 0x4017;
+
+/// CPU cycles at which the frame counter sequencer steps, in 4-step and 5-step modes.
+const FOUR_STEP_SEQUENCE_NTSC: [u64; 4] = [7457, 14913, 22371, 29829];
+const FIVE_STEP_SEQUENCE_NTSC: [u64; 4] = [7457, 14913, 22371, 37281];
+const FOUR_STEP_SEQUENCE_PAL: [u64; 4] = [8313, 16627, 24939, 33253];
+const FIVE_STEP_SEQUENCE_PAL: [u64; 4] = [8313, 16627, 24939, 41565];
+
+/// Time constant of the high pass filter applied to the output, roughly the 90Hz filter on the real hardware.
+const HIGH_PASS_FACTOR: f32 = 0.996;
+
+#[derive(Serialize, Deserialize)]
+struct FrameCounter {
+    five_step: bool,
+    irq_inhibit: bool,
+    irq: bool,
+    cycle: u64,
+    /// Left out of states with the other region tables.
+    #[serde(skip, default = "ntsc_four_step_sequence")]
+    four_step_sequence: &'static [u64; 4],
+    #[serde(skip, default = "ntsc_five_step_sequence")]
+    five_step_sequence: &'static [u64; 4],
+}
+
+fn ntsc_four_step_sequence() -> &'static [u64; 4] {
+    &FOUR_STEP_SEQUENCE_NTSC
+}
+
+fn ntsc_five_step_sequence() -> &'static [u64; 4] {
+    &FIVE_STEP_SEQUENCE_NTSC
+}
+
+struct FrameEvents {
+    quarter: bool,
+    half: bool,
+}
+
+impl FrameCounter {
+    fn new(region: Region) -> Self {
+        let (four_step_sequence, five_step_sequence) = if region.has_pal_apu() {
+            (&FOUR_STEP_SEQUENCE_PAL, &FIVE_STEP_SEQUENCE_PAL)
+        } else {
+            (&FOUR_STEP_SEQUENCE_NTSC, &FIVE_STEP_SEQUENCE_NTSC)
+        };
+        Self {
+            five_step: false,
+            irq_inhibit: false,
+            irq: false,
+            cycle: 0,
+            four_step_sequence,
+            five_step_sequence,
+        }
+    }
+
+    fn load_state(&mut self, state: Self) {
+        *self = Self {
+            four_step_sequence: self.four_step_sequence,
+            five_step_sequence: self.five_step_sequence,
+            ..state
+        };
+    }
+
+    fn write8(&mut self, value: u8) -> FrameEvents {
+        self.five_step = (value & 0b1000_0000) != 0;
+        self.irq_inhibit = (value & 0b0100_0000) != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        self.cycle = 0;
+        // 5-step mode clocks everything immediately
+        FrameEvents {
+            quarter: self.five_step,
+            half: self.five_step,
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    fn clock(&mut self) -> FrameEvents {
+        self.cycle += 1;
+        let sequence = if self.five_step {
+            self.five_step_sequence
+        } else {
+            self.four_step_sequence
+        };
+        let result = match sequence.iter().position(|x| *x == self.cycle) {
+            Some(step) => FrameEvents {
+                quarter: true,
+                half: step % 2 == 1,
+            },
+            None => FrameEvents {
+                quarter: false,
+                half: false,
+            },
+        };
+        if self.cycle == sequence[3] {
+            if !self.five_step && !self.irq_inhibit {
+                self.irq = true;
+            }
+            self.cycle = 0;
+        }
+        result
+    }
+}
+
+/// Averages the per-cycle output down to the requested sample rate.
+#[derive(Serialize, Deserialize)]
+struct Sampler {
+    /// Set by whoever's listening rather than the machine, so left out of states along with what's been produced.
+    #[serde(skip)]
+    cycles_per_sample: f64,
+    cycles_until_sample: f64,
+    sum: f32,
+    count: u32,
+    previous_input: f32,
+    previous_output: f32,
+    #[serde(skip)]
+    samples: Vec<f32>,
+}
+
+impl Sampler {
+    fn new(sample_rate: u32, cpu_clock_rate: f64) -> Self {
+        let cycles_per_sample = cpu_clock_rate / (sample_rate as f64);
+        Self {
+            cycles_per_sample,
+            cycles_until_sample: cycles_per_sample,
+            sum: 0.0,
+            count: 0,
+            previous_input: 0.0,
+            previous_output: 0.0,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Keeps the sample rate, and the samples not taken yet.
+    fn load_state(&mut self, state: Self) {
+        *self = Self {
+            cycles_per_sample: self.cycles_per_sample,
+            samples: std::mem::take(&mut self.samples),
+            ..state
+        };
+    }
+
+    fn push(&mut self, value: f32) {
+        self.sum += value;
+        self.count += 1;
+        self.cycles_until_sample -= 1.0;
+        if self.cycles_until_sample <= 0.0 {
+            self.cycles_until_sample += self.cycles_per_sample;
+            let input = self.sum / (self.count as f32);
+            let output = HIGH_PASS_FACTOR * (self.previous_output + input - self.previous_input);
+            self.previous_input = input;
+            self.previous_output = output;
+            self.samples.push(output);
+            self.sum = 0.0;
+            self.count = 0;
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct APU {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: DMC,
+    frame_counter: FrameCounter,
+    /// Mutes, solos and gains are the listener's, not the machine's.
+    #[serde(skip, default = "Mixer::new")]
+    mixer: Mixer,
+    /// Set from outside every step, so there's nothing to save.
+    #[serde(skip)]
+    expansion: f32,
+    sampler: Sampler,
+}
+
+impl APU {
+    pub fn new(sample_rate: u32, region: Region) -> Self {
+        Self {
+            pulse_1: Pulse::new(true),
+            pulse_2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(region),
+            dmc: DMC::new(region),
+            frame_counter: FrameCounter::new(region),
+            mixer: Mixer::new(),
+            expansion: 0.0,
+            sampler: Sampler::new(sample_rate, region.cpu_clock_rate()),
+        }
+    }
+
+    /// Takes everything from the state but the region and the mixer settings.
+    pub fn load_state(&mut self, state: Self) {
+        let APU {
+            pulse_1,
+            pulse_2,
+            triangle,
+            noise,
+            dmc,
+            frame_counter,
+            mixer: _,
+            expansion: _,
+            sampler,
+        } = state;
+        self.pulse_1 = pulse_1;
+        self.pulse_2 = pulse_2;
+        self.triangle = triangle;
+        self.noise.load_state(noise);
+        self.dmc.load_state(dmc);
+        self.frame_counter.load_state(frame_counter);
+        self.sampler.load_state(sampler);
+    }
+
+    /// Whether the frame counter or the DMC is asserting the CPU's IRQ line.
+    pub fn irq(&self) -> bool {
+        self.frame_counter.irq || self.dmc.irq()
+    }
+
+    /// Reading status acknowledges the frame interrupt, but not the DMC's.
+    pub fn read8(&mut self, address: u16) -> u8 {
+        let result = self.peek8(address);
+        if address == STATUS_ADDRESS {
+            self.frame_counter.irq = false;
+        }
+        result
+    }
+
+    /// Only $4015 is readable, everything else is open bus.
+    pub fn peek8(&self, address: u16) -> u8 {
+        match address {
+            STATUS_ADDRESS => {
+                (if self.dmc.irq() { 0b1000_0000 } else { 0 })
+                    | (if self.frame_counter.irq {
+                        0b0100_0000
+                    } else {
+                        0
+                    })
+                    | (if self.dmc.is_active() { 0b0001_0000 } else { 0 })
+                    | (if self.noise.is_active() {
+                        0b0000_1000
+                    } else {
+                        0
+                    })
+                    | (if self.triangle.is_active() {
+                        0b0000_0100
+                    } else {
+                        0
+                    })
+                    | (if self.pulse_2.is_active() {
+                        0b0000_0010
+                    } else {
+                        0
+                    })
+                    | (if self.pulse_1.is_active() {
+                        0b0000_0001
+                    } else {
+                        0
+                    })
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn write8(&mut self, address: u16, value: u8) {
+        match address {
+            REGISTERS_START..PULSE_1_END => self.pulse_1.write8(address - REGISTERS_START, value),
+            PULSE_1_END..PULSE_2_END => self.pulse_2.write8(address - PULSE_1_END, value),
+            PULSE_2_END..TRIANGLE_END => self.triangle.write8(address - PULSE_2_END, value),
+            TRIANGLE_END..NOISE_END => self.noise.write8(address - TRIANGLE_END, value),
+            NOISE_END..DMC_END => self.dmc.write8(address - NOISE_END, value),
+            // OAM DMA doesn't belong to us
+            OAM_DMA_ADDRESS => (),
+            STATUS_ADDRESS => {
+                self.pulse_1.set_enabled((value & 0b0000_0001) != 0);
+                self.pulse_2.set_enabled((value & 0b0000_0010) != 0);
+                self.triangle.set_enabled((value & 0b0000_0100) != 0);
+                self.noise.set_enabled((value & 0b0000_1000) != 0);
+                self.dmc.set_enabled((value & 0b0001_0000) != 0);
+            }
+            FRAME_COUNTER_ADDRESS => {
+                let events = self.frame_counter.write8(value);
+                self.handle_frame_events(events);
+            }
+            // $4016 is the controller strobe, anything else isn't ours
+            _ => (),
+        }
+    }
+
+    /// Advances by the given number of CPU cycles. The DMC reads sample bytes through the given function, which will only
+    /// ever be asked for addresses in $8000..=$FFFF.
+    pub fn step<F>(&mut self, cycles: u64, mut read: F)
+    where
+        F: FnMut(u16) -> u8,
+    {
+        for _ in 0..cycles {
+            if let Some(address) = self.dmc.pending_read() {
+                self.dmc.fill_sample_buffer(read(address));
+            }
+
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+            self.triangle.clock_timer();
+            self.noise.clock_timer();
+            self.dmc.clock_timer();
+
+            let events = self.frame_counter.clock();
+            self.handle_frame_events(events);
+
+            self.sampler.push(self.output());
+        }
+    }
+
+    /// The cartridge's own sound in 0..1, mixed in from the next step on.
+    pub fn set_expansion(&mut self, level: f32) {
+        self.expansion = level;
+    }
+
+    /// Silences a channel in the output. The channel keeps running, so status reads are unaffected.
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.mixer.set_muted(channel, muted);
+    }
+
+    /// While any channel is soloed only soloed channels are heard.
+    pub fn set_soloed(&mut self, channel: Channel, soloed: bool) {
+        self.mixer.set_soloed(channel, soloed);
+    }
+
+    /// Scales a channel's level before mixing, 1 being the hardware's level.
+    pub fn set_gain(&mut self, channel: Channel, gain: f32) {
+        self.mixer.set_gain(channel, gain);
+    }
+
+    /// Everything produced since the last call, in -1..1 at the sample rate given on creation.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.sampler.samples)
+    }
+
+    fn handle_frame_events(&mut self, events: FrameEvents) {
+        if events.quarter {
+            self.pulse_1.clock_quarter_frame();
+            self.pulse_2.clock_quarter_frame();
+            self.triangle.clock_quarter_frame();
+            self.noise.clock_quarter_frame();
+        }
+        if events.half {
+            self.pulse_1.clock_half_frame();
+            self.pulse_2.clock_half_frame();
+            self.triangle.clock_half_frame();
+            self.noise.clock_half_frame();
+        }
+    }
+
+    fn output(&self) -> f32 {
+        self.mixer.mix(
+            self.pulse_1.output(),
+            self.pulse_2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+            self.expansion,
+        )
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+use super::{envelope::Envelope, length_counter::LengthCounter};
+use crate::region::Region;
+
+/// Timer periods in CPU cycles.
+const PERIODS_NTSC: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PERIODS_PAL: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+#[derive(Serialize, Deserialize)]
+pub struct Noise {
+    envelope: Envelope,
+    length_counter: LengthCounter,
+    short_mode: bool,
+    shift_register: u16,
+    timer_period: u16,
+    timer: u16,
+    /// Left out of states, the region doesn't change.
+    #[serde(skip, default = "ntsc_periods")]
+    periods: &'static [u16; 16],
+}
+
+fn ntsc_periods() -> &'static [u16; 16] {
+    &PERIODS_NTSC
+}
+
+impl Noise {
+    pub fn new(region: Region) -> Self {
+        let periods = if region.has_pal_apu() {
+            &PERIODS_PAL
+        } else {
+            &PERIODS_NTSC
+        };
+        Self {
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+            short_mode: false,
+            shift_register: 1,
+            timer_period: periods[0],
+            timer: 0,
+            periods,
+        }
+    }
+
+    /// Takes everything but the region's periods from the state.
+    pub fn load_state(&mut self, state: Self) {
+        *self = Self {
+            periods: self.periods,
+            ..state
+        };
+    }
+
+    /// Register is 0..4, relative to $400C.
+    pub fn write8(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.length_counter.set_halted((value & 0b0010_0000) != 0);
+                self.envelope.write_control(value);
+            }
+            // $400D is unused
+            1 => (),
+            2 => {
+                self.short_mode = (value & 0b1000_0000) != 0;
+                self.timer_period = self.periods[(value & 0b0000_1111) as usize];
+            }
+            _ => {
+                self.length_counter.load(value >> 3);
+                self.envelope.restart();
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
+    /// Clocked every CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let other_bit = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> other_bit)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.is_active() || (self.shift_register & 1) != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+use super::{envelope::Envelope, length_counter::LengthCounter};
+
+const DUTY_CYCLES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+#[derive(Serialize, Deserialize)]
+pub struct Pulse {
+    /// Pulse 1 negates with ones' complement, pulse 2 with two's complement.
+    ones_complement: bool,
+    envelope: Envelope,
+    length_counter: LengthCounter,
+    duty: u8,
+    sequence_step: u8,
+    timer_period: u16,
+    timer: u16,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Self {
+            ones_complement,
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+            duty: 0,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    /// Register is 0..4, relative to $4000 or $4004.
+    pub fn write8(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.length_counter.set_halted((value & 0b0010_0000) != 0);
+                self.envelope.write_control(value);
+            }
+            1 => {
+                self.sweep_enabled = (value & 0b1000_0000) != 0;
+                self.sweep_period = (value & 0b0111_0000) >> 4;
+                self.sweep_negate = (value & 0b0000_1000) != 0;
+                self.sweep_shift = value & 0b0000_0111;
+                self.sweep_reload = true;
+            }
+            2 => {
+                self.timer_period = (self.timer_period & 0xff00) | (value as u16);
+            }
+            _ => {
+                self.timer_period = (self.timer_period & 0x00ff) | (((value & 0b111) as u16) << 8);
+                self.length_counter.load(value >> 3);
+                self.envelope.restart();
+                self.sequence_step = 0;
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
+    /// Clocked every CPU cycle. The pulse timer really runs at half that rate, so the period is doubled.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = (self.timer_period + 1) * 2 - 1;
+            self.sequence_step = (self.sequence_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+
+        if self.sweep_divider == 0
+            && self.sweep_enabled
+            && self.sweep_shift > 0
+            && !self.is_sweep_muting()
+        {
+            self.timer_period = self.sweep_target_period();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.is_active()
+            || self.is_sweep_muting()
+            || DUTY_CYCLES[self.duty as usize][self.sequence_step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    fn sweep_target_period(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if self.sweep_negate {
+            let negated = if self.ones_complement {
+                change + 1
+            } else {
+                change
+            };
+            self.timer_period.saturating_sub(negated)
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    /// The sweep unit silences the channel even when it isn't enabled.
+    fn is_sweep_muting(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target_period() > 0x7ff
+    }
+}
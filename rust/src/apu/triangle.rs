@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+
+use super::length_counter::LengthCounter;
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+#[derive(Serialize, Deserialize)]
+pub struct Triangle {
+    length_counter: LengthCounter,
+    control: bool,
+    linear_counter_period: u8,
+    linear_counter: u8,
+    linear_counter_reload: bool,
+    sequence_step: u8,
+    timer_period: u16,
+    timer: u16,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Self {
+            length_counter: LengthCounter::new(),
+            control: false,
+            linear_counter_period: 0,
+            linear_counter: 0,
+            linear_counter_reload: false,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+        }
+    }
+
+    /// Register is 0..4, relative to $4008.
+    pub fn write8(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.control = (value & 0b1000_0000) != 0;
+                self.length_counter.set_halted(self.control);
+                self.linear_counter_period = value & 0b0111_1111;
+            }
+            // $4009 is unused
+            1 => (),
+            2 => {
+                self.timer_period = (self.timer_period & 0xff00) | (value as u16);
+            }
+            _ => {
+                self.timer_period = (self.timer_period & 0x00ff) | (((value & 0b111) as u16) << 8);
+                self.length_counter.load(value >> 3);
+                self.linear_counter_reload = true;
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
+    /// Clocked every CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.linear_counter > 0 && self.length_counter.is_active() {
+                self.sequence_step = (self.sequence_step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_counter_reload {
+            self.linear_counter = self.linear_counter_period;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_counter_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    /// The triangle keeps outputting its last value when silenced, it just stops stepping.
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.sequence_step as usize]
+    }
+}
@@ -0,0 +1,144 @@
+// Compressed ROMs, recognized by magic number so the file extension doesn't matter.
+
+use flate2::read::GzDecoder;
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{Cursor, Read},
+    path::Path,
+};
+use zip::{result::ZipError, ZipArchive};
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Gzip(std::io::Error),
+    Zip(ZipError),
+    /// Nothing in the zip has one of `ROM_EXTENSIONS`.
+    NoRomInZip,
+    MissingEntry(String),
+    /// Decompresses to more than `MAX_SIZE`.
+    TooLarge,
+}
+
+impl Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for ArchiveError {}
+
+impl From<ZipError> for ArchiveError {
+    fn from(value: ZipError) -> Self {
+        ArchiveError::Zip(value)
+    }
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+/// An empty zip is just the end of central directory record.
+const EMPTY_ZIP_MAGIC: &[u8] = b"PK\x05\x06";
+
+/// Far bigger than any real ROM or disk image, so a corrupt or malicious archive can't claim gigabytes.
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// What's looked for in a zip when no entry is named, case insensitively.
+pub const ROM_EXTENSIONS: [&str; 6] = ["nes", "fds", "nsf", "nsfe", "unf", "unif"];
+
+pub fn is_archive(data: &[u8]) -> bool {
+    data.starts_with(GZIP_MAGIC) || data.starts_with(ZIP_MAGIC) || data.starts_with(EMPTY_ZIP_MAGIC)
+}
+
+/// The decompressed contents for a gzip stream, the named entry or the first ROM for a zip, and anything else
+/// unchanged.
+pub fn extract(data: Vec<u8>, entry: Option<&str>) -> Result<Vec<u8>, ArchiveError> {
+    if data.starts_with(GZIP_MAGIC) {
+        read_limited(GzDecoder::new(data.as_slice()), ArchiveError::Gzip)
+    } else if data.starts_with(ZIP_MAGIC) || data.starts_with(EMPTY_ZIP_MAGIC) {
+        extract_zip(data, entry)
+    } else {
+        Ok(data)
+    }
+}
+
+fn extract_zip(data: Vec<u8>, entry: Option<&str>) -> Result<Vec<u8>, ArchiveError> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let index = match entry {
+        Some(name) => archive
+            .index_for_name(name)
+            .ok_or_else(|| ArchiveError::MissingEntry(name.to_string()))?,
+        // going by the index keeps this in the order they're stored in, which file_names doesn't
+        None => (0..archive.len())
+            .find(|i| archive.name_for_index(*i).is_some_and(is_rom_name))
+            .ok_or(ArchiveError::NoRomInZip)?,
+    };
+    // the size in the zip is only what the file claims, so it's no use for preallocating
+    let file = archive.by_index(index)?;
+    read_limited(file, |e| ArchiveError::Zip(e.into()))
+}
+
+/// Fails with TooLarge rather than reading more than `MAX_SIZE`.
+fn read_limited<R>(r: R, error: fn(std::io::Error) -> ArchiveError) -> Result<Vec<u8>, ArchiveError>
+where
+    R: Read,
+{
+    let mut result = Vec::new();
+    r.take(MAX_SIZE + 1)
+        .read_to_end(&mut result)
+        .map_err(error)?;
+    if result.len() as u64 > MAX_SIZE {
+        return Err(ArchiveError::TooLarge);
+    }
+    Ok(result)
+}
+
+fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|x| x.to_str())
+        .is_some_and(|x| ROM_EXTENSIONS.iter().any(|y| x.eq_ignore_ascii_case(y)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{extract, ArchiveError};
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::{Cursor, Write};
+    use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+    #[test]
+    pub fn extracts() {
+        let rom = b"NES\x1a not really a rom".to_vec();
+        assert_eq!(extract(rom.clone(), None).unwrap(), rom);
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&rom).unwrap();
+        assert_eq!(extract(encoder.finish().unwrap(), None).unwrap(), rom);
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        writer.start_file("readme.txt", options).unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.start_file("Game (USA).NES", options).unwrap();
+        writer.write_all(&rom).unwrap();
+        writer.start_file("Game (USA) [Hack].nes", options).unwrap();
+        writer.write_all(b"hacked").unwrap();
+        let zip = writer.finish().unwrap().into_inner();
+
+        assert_eq!(extract(zip.clone(), None).unwrap(), rom);
+        assert_eq!(
+            extract(zip.clone(), Some("Game (USA) [Hack].nes")).unwrap(),
+            b"hacked"
+        );
+        assert!(matches!(
+            extract(zip, Some("nope.nes")),
+            Err(ArchiveError::MissingEntry(_))
+        ));
+
+        let zip = ZipWriter::new(Cursor::new(Vec::new()))
+            .finish()
+            .unwrap()
+            .into_inner();
+        assert!(matches!(extract(zip, None), Err(ArchiveError::NoRomInZip)));
+    }
+}
@@ -0,0 +1,246 @@
+// Runs test ROMs that report through $6000, as blargg's and many written since do, see
+// https://github.com/christopherpow/nes-test-roms/blob/master/instr_test-v5/readme.txt
+//
+// $6000 is $80 while the test runs, $81 when it wants the reset button pressed and the result code once it's done, 0
+// for a pass. None of it means anything until $6001-$6003 hold DE B0 61. From $6004 is the text it printed, ending
+// with a 0.
+
+use std::{
+    fmt::Display,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cartridge_file::{Cartridge, CartridgeError},
+    console::Console,
+};
+
+const STATUS_ADDRESS: u16 = 0x6000;
+const SIGNATURE_ADDRESS: u16 = 0x6001;
+const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const MESSAGE_ADDRESS: u16 = 0x6004;
+const MESSAGE_END: u16 = 0x8000;
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_RESET: u8 = 0x81;
+const STATUS_PASSED: u8 = 0;
+/// The ROMs ask for at least 100ms between asking for a reset and getting one.
+const RESET_DELAY_SECONDS: f64 = 0.1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed(String),
+    Failed {
+        code: u8,
+        message: String,
+    },
+    /// With whatever it had printed so far.
+    TimedOut(String),
+    /// The emulator panicked, with the panic's message.
+    Crashed(String),
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        matches!(self, Outcome::Passed(_))
+    }
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Passed(message) => write!(f, "passed: {}", message.trim()),
+            Outcome::Failed { code, message } => {
+                write!(f, "failed with code {}: {}", code, message.trim())
+            }
+            Outcome::TimedOut(message) => write!(f, "timed out: {}", message.trim()),
+            Outcome::Crashed(message) => write!(f, "crashed: {}", message),
+        }
+    }
+}
+
+/// None until the signature's there.
+fn status(console: &Console) -> Option<u8> {
+    let signature = [0, 1, 2].map(|i| console.peek8(SIGNATURE_ADDRESS + i));
+    (signature == SIGNATURE).then(|| console.peek8(STATUS_ADDRESS))
+}
+
+fn message(console: &Console) -> String {
+    (MESSAGE_ADDRESS..MESSAGE_END)
+        .map(|address| console.peek8(address))
+        .take_while(|&x| x != 0)
+        .map(|x| x as char)
+        .collect()
+}
+
+/// Runs until the ROM reports a result or the given number of seconds of emulated time have passed. Fails if the
+/// mapper isn't emulated. A panic while running is caught and reported as a crash, so one ROM can't stop a batch.
+pub fn run(cartridge: &Cartridge, timeout_seconds: f64) -> Result<Outcome, CartridgeError> {
+    // the samples are thrown away
+    let mut console = Console::new(cartridge, 1, None)?;
+    Ok(catch_crash(|| run_console(&mut console, timeout_seconds)))
+}
+
+fn catch_crash<F>(f: F) -> Outcome
+where
+    F: FnOnce() -> Outcome,
+{
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|x| x.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Outcome::Crashed(message)
+    })
+}
+
+fn run_console(console: &mut Console, timeout_seconds: f64) -> Outcome {
+    let clock_rate = console.region().cpu_clock_rate();
+    let timeout = (timeout_seconds * clock_rate) as u64;
+    let mut reset_at = None;
+    while console.cpu_clock() < timeout {
+        console.step();
+        match status(console) {
+            None | Some(STATUS_RUNNING) => (),
+            Some(STATUS_RESET) => match reset_at {
+                None => {
+                    reset_at = Some(console.cpu_clock() + (RESET_DELAY_SECONDS * clock_rate) as u64)
+                }
+                Some(x) if console.cpu_clock() >= x => {
+                    console.reset();
+                    // not again until it asks again
+                    reset_at = Some(u64::MAX);
+                }
+                Some(_) => (),
+            },
+            Some(STATUS_PASSED) => return Outcome::Passed(message(console)),
+            Some(code) => {
+                return Outcome::Failed {
+                    code,
+                    message: message(console),
+                }
+            }
+        }
+        // the ROM clears the reset request itself once it's back up
+        if status(console) != Some(STATUS_RESET) {
+            reset_at = None;
+        }
+    }
+    Outcome::TimedOut(message(console))
+}
+
+/// Every .nes file in the directory, sorted by name.
+pub fn rom_paths(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths = std::fs::read_dir(path)?
+        .map(|x| x.map(|x| x.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.retain(|x| x.extension().is_some_and(|x| x.eq_ignore_ascii_case("nes")));
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod test {
+    use log::*;
+    use std::path::Path;
+
+    use super::Outcome;
+    use crate::{cartridge_file::Cartridge, test_utils};
+
+    const ROMS_PATH: &str = "../submodules/nes-test-roms";
+    /// Directories of ROMs to run, and whether they're expected to pass. The rest need timing closer than an
+    /// instruction at a time.
+    const ROM_DIRECTORIES: &[(&str, bool)] = &[
+        ("instr_test-v5/rom_singles", true),
+        ("instr_misc/rom_singles", false),
+        ("apu_test/rom_singles", false),
+        ("ppu_vbl_nmi/rom_singles", false),
+        ("mmc3_test_2/rom_singles", false),
+    ];
+    const TIMEOUT_SECONDS: f64 = 60.0;
+
+    /// An NROM cartridge that prints "hi", asks for a reset, then reports the given result.
+    fn cartridge(result: u8) -> Cartridge {
+        let mut prg = vec![0xea; 0x4000];
+        let mut program = vec![
+            0xad, 0x00, 0x60, // LDA $6000
+            0xc9, 0x81, // CMP #$81
+            0xf0, 0x21, // BEQ done
+            0xa9, 0x68, // LDA #'h'
+            0x8d, 0x04, 0x60, // STA $6004
+            0xa9, 0x69, // LDA #'i'
+            0x8d, 0x05, 0x60, // STA $6005
+            0xa9, 0x81, // LDA #$81
+            0x8d, 0x00, 0x60, // STA $6000
+        ];
+        for (i, x) in super::SIGNATURE.iter().enumerate() {
+            program.extend_from_slice(&[0xa9, *x, 0x8d, 0x01 + (i as u8), 0x60]);
+        }
+        program.extend_from_slice(&[0x4c, 0x25, 0x80]); // JMP self
+        program.extend_from_slice(&[
+            // done
+            0xa9, result, // LDA #result
+            0x8d, 0x00, 0x60, // STA $6000
+            0x4c, 0x2d, 0x80, // JMP self
+        ]);
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3ffc..0x3ffe].copy_from_slice(&0x8000u16.to_le_bytes());
+
+        let mut data = b"NES\x1a\x01\x01\0\0\0\0\0\0\0\0\0\0".to_vec();
+        data.extend_from_slice(&prg);
+        data.resize(data.len() + 0x2000, 0);
+        Cartridge::from_bytes(data).unwrap()
+    }
+
+    #[test]
+    pub fn protocol() {
+        assert_eq!(
+            super::run(&cartridge(0), 1.0).unwrap(),
+            Outcome::Passed("hi".to_string())
+        );
+        assert_eq!(
+            super::run(&cartridge(3), 1.0).unwrap(),
+            Outcome::Failed {
+                code: 3,
+                message: "hi".to_string()
+            }
+        );
+        // not long enough for the reset
+        assert_eq!(
+            super::run(&cartridge(0), 0.05).unwrap(),
+            Outcome::TimedOut("hi".to_string())
+        );
+        assert_eq!(
+            super::catch_crash(|| panic!("bad opcode {}", 2)),
+            Outcome::Crashed("bad opcode 2".to_string())
+        );
+    }
+
+    #[test]
+    #[ignore = "needs submodules/nes-test-roms, run with --ignored once it's checked out"]
+    pub fn roms() {
+        test_utils::test::init();
+
+        let mut failures = Vec::new();
+        for (directory, expected) in ROM_DIRECTORIES {
+            let path = Path::new(ROMS_PATH).join(directory);
+            let paths =
+                super::rom_paths(&path).unwrap_or_else(|e| panic!("reading {:?}: {}", path, e));
+            for path in paths {
+                let outcome = Cartridge::from_bytes(std::fs::read(&path).unwrap())
+                    .and_then(|cartridge| super::run(&cartridge, TIMEOUT_SECONDS));
+                match outcome {
+                    Ok(outcome) => {
+                        info!("{:?} {}", path, outcome);
+                        if *expected && !outcome.passed() {
+                            failures.push(format!("{:?} {}", path, outcome));
+                        }
+                    }
+                    Err(e) => info!("{:?} can't be loaded: {}", path, e),
+                }
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
@@ -1,37 +1,133 @@
 // see "INES - NESdev Wiki.html"
 
-use std::{error::Error, fmt::Display};
+use log::*;
+use std::{error::Error, fmt::Display, str::FromStr};
 
 #[derive(Debug, Clone, Copy)]
 pub enum CartridgeError {
-    BadHeader,
-    UnrecognizedMemoryMapper(u8),
-    MissingTrainer,
+    /// The file doesn't start with "NES<EOF>".
+    BadMagic([u8; 4]),
+    /// The file ends partway through a section, `offset` is where that section starts.
+    Truncated {
+        section: Section,
+        offset: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// The header says there's no PRG ROM at all.
     MissingPRGROM,
-    MissingCHRROM,
+    UnrecognizedMemoryMapper {
+        number: u16,
+        format: HeaderFormat,
+    },
+    TrailingData {
+        offset: usize,
+        size: usize,
+    },
+    /// `Cartridge::set_header` was given a header that disagrees about the size or presence of a section.
+    HeaderMismatch(Section),
+    /// HeaderBuilder was asked for an iNES 1.0 header with something only NES 2.0 can hold.
+    NotRepresentableInINES(&'static str),
 }
 
 impl Display for CartridgeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            CartridgeError::BadMagic(magic) => {
+                write!(f, "not an iNES file, starts with {:02x?}", magic)
+            }
+            CartridgeError::Truncated {
+                section,
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} at offset {:#x} is truncated, expected {} bytes but only {} are left",
+                section, offset, expected, actual
+            ),
+            CartridgeError::MissingPRGROM => write!(f, "header says there's no PRG ROM"),
+            CartridgeError::UnrecognizedMemoryMapper { number, format } => {
+                write!(f, "unsupported mapper {} ({} header)", number, format)
+            }
+            CartridgeError::TrailingData { offset, size } => write!(
+                f,
+                "{} bytes of unexpected data at offset {:#x}",
+                size, offset
+            ),
+            CartridgeError::HeaderMismatch(section) => {
+                write!(f, "new header doesn't agree with the loaded {}", section)
+            }
+            CartridgeError::NotRepresentableInINES(what) => {
+                write!(f, "{} can't be represented in an iNES 1.0 header", what)
+            }
+        }
     }
 }
 
 impl Error for CartridgeError {}
 
+/// The parts of a .nes file, in the order they appear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Header,
+    Trainer,
+    PRGROM,
+    CHRROM,
+    PlayChoiceINSTROM,
+    MiscROM,
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Section::Header => "header",
+            Section::Trainer => "trainer",
+            Section::PRGROM => "PRG ROM",
+            Section::CHRROM => "CHR ROM",
+            Section::PlayChoiceINSTROM => "PlayChoice-10 INST-ROM",
+            Section::MiscROM => "misc ROM",
+        })
+    }
+}
+
+/// Which flavour of header a file has, see https://www.nesdev.org/wiki/INES#Variant_comparison
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderFormat {
+    /// Bytes 7-15 hold something other than flags, so only bytes 4-6 can be trusted.
+    ArchaicINES,
+    /// The most common kind of archaic iNES header, with "DiskDude!" written over bytes 7-15 by an old ripping tool.
+    DiskDude,
+    INES,
+    NES2_0,
+}
+
+impl Display for HeaderFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HeaderFormat::ArchaicINES => "archaic iNES",
+            HeaderFormat::DiskDude => "DiskDude! polluted iNES",
+            HeaderFormat::INES => "iNES 1.0",
+            HeaderFormat::NES2_0 => "NES 2.0",
+        })
+    }
+}
+
 pub mod pgr_rom {
     pub const BLOCK_SIZE: usize = 1024 * 16;
 
+    /// In bytes, which for NES 2.0 files isn't necessarily a whole number of blocks.
     #[derive(Debug, Clone)]
-    pub struct Size(pub u8);
+    pub struct Size(pub usize);
 
     impl Size {
-        pub fn in_blocks(&self) -> u8 {
-            self.0
+        /// Rounded up.
+        pub fn in_blocks(&self) -> usize {
+            self.0.div_ceil(BLOCK_SIZE)
         }
 
         pub fn in_bytes(&self) -> usize {
-            (self.in_blocks() as usize) * BLOCK_SIZE
+            self.0
         }
     }
 
@@ -41,16 +137,18 @@ pub mod pgr_rom {
 pub mod chr_rom {
     pub const BLOCK_SIZE: usize = 1024 * 8;
 
+    /// In bytes, which for NES 2.0 files isn't necessarily a whole number of blocks.
     #[derive(Debug, Clone)]
-    pub struct Size(pub u8);
+    pub struct Size(pub usize);
 
     impl Size {
-        pub fn in_blocks(&self) -> u8 {
-            self.0
+        /// Rounded up.
+        pub fn in_blocks(&self) -> usize {
+            self.0.div_ceil(BLOCK_SIZE)
         }
 
         pub fn in_bytes(&self) -> usize {
-            (self.in_blocks() as usize) * BLOCK_SIZE
+            self.0
         }
     }
 
@@ -60,23 +158,54 @@ pub mod chr_rom {
 pub mod pgr_ram {
     pub const BLOCK_SIZE: usize = 1024 * 8;
 
+    /// In bytes, NES 2.0 files can have any power of 2 from 128 bytes up.
     #[derive(Debug, Clone)]
-    pub struct Size(pub u8);
+    pub struct Size(pub usize);
 
     impl Size {
-        pub fn in_blocks(&self) -> u8 {
+        pub fn in_bytes(&self) -> usize {
             self.0
         }
+    }
+}
+
+pub mod chr_ram {
+    pub const BLOCK_SIZE: usize = 1024 * 8;
 
+    /// In bytes, NES 2.0 files can have any power of 2 from 128 bytes up.
+    #[derive(Debug, Clone)]
+    pub struct Size(pub usize);
+
+    impl Size {
         pub fn in_bytes(&self) -> usize {
-            (self.in_blocks() as usize) * BLOCK_SIZE
+            self.0
         }
     }
+}
 
-    pub type Block = [u8; BLOCK_SIZE];
+pub mod playchoice {
+    pub const INST_ROM_SIZE: usize = 1024 * 8;
+    pub const PROM_SIZE: usize = 16;
+
+    pub type InstROM = [u8; INST_ROM_SIZE];
+
+    #[derive(Debug, Clone)]
+    pub struct PROM {
+        pub data: [u8; PROM_SIZE],
+        pub counter_out: [u8; PROM_SIZE],
+    }
 }
 
+/// What to do with bytes left over after everything the header says should be there.
 #[derive(Debug, Clone, Copy)]
+pub enum Strictness {
+    /// Log a warning and keep going.
+    Lenient,
+    /// Fail with `CartridgeError::TrailingData`.
+    Strict,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NametableArrangement {
     Vertical,
     Horizontal,
@@ -84,70 +213,239 @@ pub enum NametableArrangement {
     FourScreenMirroring,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Parses the short names used in the ROM database and on the command line.
+impl FromStr for NametableArrangement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "h" | "horizontal" => Ok(NametableArrangement::Horizontal),
+            "v" | "vertical" => Ok(NametableArrangement::Vertical),
+            "4" | "four-screen" => Ok(NametableArrangement::FourScreenMirroring),
+            _ => Err(format!("unknown mirroring {:?}, expected h, v or 4", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TVSystem {
     NTSC,
     PAL,
     Both,
+    Dendy,
+}
+
+impl FromStr for TVSystem {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ntsc" => Ok(TVSystem::NTSC),
+            "pal" => Ok(TVSystem::PAL),
+            "both" => Ok(TVSystem::Both),
+            "dendy" => Ok(TVSystem::Dendy),
+            _ => Err(format!(
+                "unknown tv system {:?}, expected ntsc, pal, both or dendy",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ConsoleType {
+    NES,
+    VsSystem,
+    Playchoice10,
+    /// NES 2.0 only, the extended console type from byte 13.
+    Extended(u8),
+}
+
+/// NES 2.0 only, see https://www.nesdev.org/wiki/NES_2.0#Vs._System_Type
+#[derive(Debug, Clone, Copy)]
+pub enum VsPPUType {
+    RP2C03B,
+    RP2C03G,
+    RP2C04_0001,
+    RP2C04_0002,
+    RP2C04_0003,
+    RP2C04_0004,
+    RC2C03B,
+    RC2C03C,
+    RC2C05_01,
+    RC2C05_02,
+    RC2C05_03,
+    RC2C05_04,
+    RC2C05_05,
+    Unknown(u8),
+}
+
+impl VsPPUType {
+    /// The RC2C05s have PPUCTRL at $2001 and PPUMASK at $2000.
+    pub fn swaps_ctrl_and_mask(&self) -> bool {
+        matches!(
+            self,
+            VsPPUType::RC2C05_01
+                | VsPPUType::RC2C05_02
+                | VsPPUType::RC2C05_03
+                | VsPPUType::RC2C05_04
+                | VsPPUType::RC2C05_05
+        )
+    }
+
+    /// What the RC2C05s return in the low 5 bits of PPUSTATUS, which some games check to make sure they're on the
+    /// right board.
+    pub fn status_id(&self) -> Option<u8> {
+        match self {
+            VsPPUType::RC2C05_01 | VsPPUType::RC2C05_04 => Some(0x1b),
+            VsPPUType::RC2C05_02 => Some(0x3d),
+            VsPPUType::RC2C05_03 => Some(0x1c),
+            _ => None,
+        }
+    }
+}
+
+/// NES 2.0 only, see https://www.nesdev.org/wiki/NES_2.0#Vs._System_Type
+#[derive(Debug, Clone, Copy)]
+pub enum VsHardwareType {
+    UniSystem,
+    UniSystemRBIBaseballProtection,
+    UniSystemTKOBoxingProtection,
+    UniSystemSuperXeviousProtection,
+    UniSystemIceClimberProtection,
+    DualSystem,
+    DualSystemRaidOnBungelingBayProtection,
+    Unknown(u8),
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum MemoryMapper {
     NROM,
+    /// Mapper 99, the Vs. System's own board.
+    VsSystem,
+    /// Any other number. The header can still be read and repaired, there's just nothing to emulate it with.
+    Other(u16),
 }
 
+pub const HEADER_SIZE: usize = 16;
+const MAGIC: [u8; 4] = [b'N', b'E', b'S', 0x1a];
+const DISK_DUDE: &[u8] = b"DiskDude!";
 const PRG_ROM_INDEX: usize = 4;
+const CHR_ROM_INDEX: usize = 5;
 
 pub struct Header {
-    data: [u8; 16],
+    /// With the untrustworthy bytes of archaic headers cleared, so everything else can read it as iNES.
+    data: [u8; HEADER_SIZE],
+    format: HeaderFormat,
     mapper: MemoryMapper,
 }
 
-pub struct Trainer([u8; 512]);
+pub const TRAINER_SIZE: usize = 512;
+
+pub struct Trainer([u8; TRAINER_SIZE]);
+
+impl Trainer {
+    pub fn data(&self) -> &[u8; TRAINER_SIZE] {
+        &self.0
+    }
+}
 
 impl Header {
-    pub fn new(data: [u8; 16]) -> Result<Self, CartridgeError> {
-        if &data[0..=3] != &['N' as u8, 'E' as u8, 'S' as u8, 0x1a] {
-            return Err(CartridgeError::BadHeader);
+    pub fn new(mut data: [u8; HEADER_SIZE]) -> Result<Self, CartridgeError> {
+        if data[0..4] != MAGIC {
+            return Err(CartridgeError::BadMagic(data[0..4].try_into().unwrap()));
         }
 
-        if data[PRG_ROM_INDEX] == 0 {
-            return Err(CartridgeError::MissingPRGROM);
+        let format = header_format(&data);
+        if matches!(format, HeaderFormat::ArchaicINES | HeaderFormat::DiskDude) {
+            warn!(
+                "ignoring bytes 7-15 of the {} header: {:02x?}",
+                format,
+                &data[7..]
+            );
+            data[7..].fill(0);
         }
 
-        let mapper = {
-            let low = (data[6] & 0b1111_0000) >> 4;
-            let high = data[7] & 0b1111_0000;
-            let mapper = low | high;
-            match mapper {
-                0 => MemoryMapper::NROM,
-                _ => Err(CartridgeError::UnrecognizedMemoryMapper(mapper))?,
-            }
-        };
+        let mapper = memory_mapper(mapper_number(&data));
 
-        Ok(Self { data, mapper })
+        let result = Self {
+            data,
+            format,
+            mapper,
+        };
+        if result.prg_rom_size().in_bytes() == 0 {
+            return Err(CartridgeError::MissingPRGROM);
+        }
+        Ok(result)
     }
 
     pub fn prg_rom_size(&self) -> pgr_rom::Size {
-        pgr_rom::Size(self.data[PRG_ROM_INDEX])
+        pgr_rom::Size(self.rom_size(
+            PRG_ROM_INDEX,
+            self.data[9] & 0b0000_1111,
+            pgr_rom::BLOCK_SIZE,
+        ))
     }
 
     pub fn chr_rom_size(&self) -> chr_rom::Size {
-        chr_rom::Size(self.data[5])
+        chr_rom::Size(self.rom_size(CHR_ROM_INDEX, self.data[9] >> 4, chr_rom::BLOCK_SIZE))
+    }
+
+    /// NES 2.0 puts the high bits of the size in byte 9. If those are all set the low byte is instead an exponent and
+    /// multiplier.
+    fn rom_size(&self, index: usize, high: u8, block_size: usize) -> usize {
+        let low = self.data[index] as usize;
+        if !self.is_nes_2_0() {
+            low * block_size
+        } else if high == 0b1111 {
+            let exponent = low >> 2;
+            let multiplier = (low & 0b11) * 2 + 1;
+            // anything this big couldn't possibly be in the file anyway
+            1usize
+                .checked_shl(exponent as u32)
+                .and_then(|x| x.checked_mul(multiplier))
+                .unwrap_or(usize::MAX)
+        } else {
+            (((high as usize) << 8) | low) * block_size
+        }
     }
 
     pub fn nametable_arrangement(&self) -> NametableArrangement {
         let alt_layout = (self.data[6] & 0b0000_1000) != 0;
         let layout = (self.data[6] & 0b0000_0001) != 0;
-        match (self.memory_mapper(), layout, alt_layout) {
-            (MemoryMapper::NROM, _, _) => NametableArrangement::Vertical,
+        match (layout, alt_layout) {
+            (_, true) => NametableArrangement::FourScreenMirroring,
+            (true, false) => NametableArrangement::Vertical,
+            (false, false) => NametableArrangement::Horizontal,
         }
     }
 
+    /// Single screen mirroring is always mapper controlled, so there's nothing to set for it.
+    pub fn set_nametable_arrangement(&mut self, arrangement: NametableArrangement) {
+        let bits = match arrangement {
+            NametableArrangement::Vertical => 0b0000_0001,
+            NametableArrangement::Horizontal => 0b0000_0000,
+            NametableArrangement::FourScreenMirroring => 0b0000_1000,
+            NametableArrangement::SingleScreenMirroring => return,
+        };
+        self.data[6] = (self.data[6] & 0b1111_0110) | bits;
+    }
+
     pub fn has_battery_backed_prg_ram(&self) -> bool {
         (self.data[6] & 0b0000_0010) != 0
     }
 
+    /// For NES 2.0 any PRG RAM becomes PRG NVRAM or the reverse, so the total stays the same.
+    pub fn set_battery_backed_prg_ram(&mut self, battery: bool) {
+        if self.is_nes_2_0() {
+            let ram = self.data[10] & 0b0000_1111;
+            let nvram = self.data[10] >> 4;
+            let total = ram.max(nvram);
+            self.data[10] = if battery { total << 4 } else { total };
+        }
+        self.data[6] = (self.data[6] & 0b1111_1101) | if battery { 0b0000_0010 } else { 0 };
+    }
+
     pub fn has_trainer(&self) -> bool {
         (self.data[6] & 0b0000_0100) != 0
     }
@@ -156,38 +454,621 @@ impl Header {
         self.mapper
     }
 
+    /// 8 bits for iNES, 12 for NES 2.0.
+    pub fn mapper_number(&self) -> u16 {
+        mapper_number(&self.data)
+    }
+
+    /// Converts to NES 2.0 if the header can't otherwise hold the mapper or submapper.
+    pub fn set_mapper(&mut self, number: u16, submapper: u8) {
+        let mapper = memory_mapper(number);
+        if (number > 0xff || submapper != 0) && !self.is_nes_2_0() {
+            self.convert_to_nes_2_0();
+        }
+        self.data[6] = (self.data[6] & 0b0000_1111) | (((number & 0x0f) as u8) << 4);
+        self.data[7] = (self.data[7] & 0b0000_1111) | ((number & 0xf0) as u8);
+        if self.is_nes_2_0() {
+            self.data[8] = ((number >> 8) as u8 & 0b0000_1111) | (submapper << 4);
+        }
+        self.mapper = mapper;
+    }
+
+    /// Always 0 for iNES.
+    pub fn submapper(&self) -> u8 {
+        if self.is_nes_2_0() {
+            self.data[8] >> 4
+        } else {
+            0
+        }
+    }
+
+    pub fn console_type(&self) -> ConsoleType {
+        match self.data[7] & 0b0000_0011 {
+            0 => ConsoleType::NES,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            // iNES has these as independent flags, but nothing is both
+            _ if !self.is_nes_2_0() => ConsoleType::VsSystem,
+            _ => ConsoleType::Extended(self.data[13] & 0b0000_1111),
+        }
+    }
+
     pub fn is_vs_unisystem(&self) -> bool {
-        (self.data[7] & 0b0000_0001) != 0
+        matches!(self.console_type(), ConsoleType::VsSystem)
     }
 
     pub fn is_playchoice_10(&self) -> bool {
-        (self.data[7] & 0b0000_0010) != 0
+        matches!(self.console_type(), ConsoleType::Playchoice10)
     }
 
-    /// flags from byte 8 through 15 are in NES 2.0 foramt
+    /// flags from byte 8 through 15 are in NES 2.0 format
     pub fn is_nes_2_0(&self) -> bool {
-        (self.data[7] & 0b0000_1100) == 0b0000_1100
+        self.format == HeaderFormat::NES2_0
+    }
+
+    /// Archaic headers are otherwise read as iNES, with the junk cleared.
+    pub fn format(&self) -> HeaderFormat {
+        self.format
     }
 
+    /// Volatile PRG RAM. For iNES this is all the PRG RAM there is, unless it's battery backed.
     pub fn prg_ram_size(&self) -> pgr_ram::Size {
-        let x = self.data[8];
-        pgr_ram::Size(if x == 0 { 1 } else { x })
+        if self.is_nes_2_0() {
+            pgr_ram::Size(shift_size(self.data[10] & 0b0000_1111))
+        } else if self.has_battery_backed_prg_ram() {
+            pgr_ram::Size(0)
+        } else {
+            self.ines_prg_ram_size()
+        }
+    }
+
+    /// Battery backed PRG RAM, or EEPROM.
+    pub fn prg_nvram_size(&self) -> pgr_ram::Size {
+        if self.is_nes_2_0() {
+            pgr_ram::Size(shift_size(self.data[10] >> 4))
+        } else if self.has_battery_backed_prg_ram() {
+            self.ines_prg_ram_size()
+        } else {
+            pgr_ram::Size(0)
+        }
+    }
+
+    /// iNES 1.0 doesn't really say, but 0 is taken to mean 8k for compatibility
+    fn ines_prg_ram_size(&self) -> pgr_ram::Size {
+        let x = self.data[8] as usize;
+        pgr_ram::Size(if x == 0 { 1 } else { x } * pgr_ram::BLOCK_SIZE)
+    }
+
+    /// Volatile CHR RAM. For iNES there's 8k exactly when there's no CHR ROM.
+    pub fn chr_ram_size(&self) -> chr_ram::Size {
+        if self.is_nes_2_0() {
+            chr_ram::Size(shift_size(self.data[11] & 0b0000_1111))
+        } else if self.data[CHR_ROM_INDEX] == 0 {
+            chr_ram::Size(chr_ram::BLOCK_SIZE)
+        } else {
+            chr_ram::Size(0)
+        }
+    }
+
+    /// Battery backed CHR RAM, only NES 2.0 can have this.
+    pub fn chr_nvram_size(&self) -> chr_ram::Size {
+        if self.is_nes_2_0() {
+            chr_ram::Size(shift_size(self.data[11] >> 4))
+        } else {
+            chr_ram::Size(0)
+        }
     }
 
     pub fn tv_system(&self) -> TVSystem {
-        match self.data[10] & 0b0000_0011 {
-            0 => TVSystem::NTSC,
-            2 => TVSystem::PAL,
-            _ => TVSystem::Both,
+        if self.is_nes_2_0() {
+            match self.data[12] & 0b0000_0011 {
+                0 => TVSystem::NTSC,
+                1 => TVSystem::PAL,
+                2 => TVSystem::Both,
+                _ => TVSystem::Dendy,
+            }
+        } else {
+            // byte 9 is the official flag but hardly anything uses it, byte 10 is unofficial but more common
+            match self.data[10] & 0b0000_0011 {
+                0 if (self.data[9] & 0b0000_0001) != 0 => TVSystem::PAL,
+                0 => TVSystem::NTSC,
+                2 => TVSystem::PAL,
+                _ => TVSystem::Both,
+            }
+        }
+    }
+
+    /// Converts to NES 2.0 for Dendy, which iNES can't express.
+    pub fn set_tv_system(&mut self, tv_system: TVSystem) {
+        if matches!(tv_system, TVSystem::Dendy) && !self.is_nes_2_0() {
+            self.convert_to_nes_2_0();
+        }
+        if self.is_nes_2_0() {
+            self.data[12] = (self.data[12] & 0b1111_1100)
+                | match tv_system {
+                    TVSystem::NTSC => 0,
+                    TVSystem::PAL => 1,
+                    TVSystem::Both => 2,
+                    TVSystem::Dendy => 3,
+                };
+        } else {
+            let (byte_9, byte_10) = ines_tv_system(tv_system);
+            self.data[9] = (self.data[9] & 0b1111_1110) | byte_9;
+            self.data[10] = (self.data[10] & 0b1111_1100) | byte_10;
+        }
+    }
+
+    /// Rewrites bytes 8-15 in NES 2.0 format, keeping everything they meant as iNES.
+    fn convert_to_nes_2_0(&mut self) {
+        if self.is_nes_2_0() {
+            return;
+        }
+        let prg_ram = shift_for_size(self.prg_ram_size().in_bytes());
+        let prg_nvram = shift_for_size(self.prg_nvram_size().in_bytes());
+        let chr_ram = shift_for_size(self.chr_ram_size().in_bytes());
+        let tv_system = self.tv_system();
+        let mapper_number = self.mapper_number();
+
+        self.format = HeaderFormat::NES2_0;
+        self.data[7] = (self.data[7] & 0b1111_0011) | 0b0000_1000;
+        self.data[8] = (mapper_number >> 8) as u8;
+        self.data[9] = 0;
+        self.data[10] = prg_ram | (prg_nvram << 4);
+        self.data[11] = chr_ram;
+        self.data[12] = 0;
+        self.data[13] = 0;
+        self.data[14] = 0;
+        self.data[15] = 0;
+        self.set_tv_system(tv_system);
+    }
+
+    pub fn vs_ppu_type(&self) -> Option<VsPPUType> {
+        if !self.is_nes_2_0() || !self.is_vs_unisystem() {
+            return None;
+        }
+        Some(match self.data[13] & 0b0000_1111 {
+            0x0 => VsPPUType::RP2C03B,
+            0x1 => VsPPUType::RP2C03G,
+            0x2 => VsPPUType::RP2C04_0001,
+            0x3 => VsPPUType::RP2C04_0002,
+            0x4 => VsPPUType::RP2C04_0003,
+            0x5 => VsPPUType::RP2C04_0004,
+            0x6 => VsPPUType::RC2C03B,
+            0x7 => VsPPUType::RC2C03C,
+            0x8 => VsPPUType::RC2C05_01,
+            0x9 => VsPPUType::RC2C05_02,
+            0xa => VsPPUType::RC2C05_03,
+            0xb => VsPPUType::RC2C05_04,
+            0xc => VsPPUType::RC2C05_05,
+            x => VsPPUType::Unknown(x),
+        })
+    }
+
+    pub fn vs_hardware_type(&self) -> Option<VsHardwareType> {
+        if !self.is_nes_2_0() || !self.is_vs_unisystem() {
+            return None;
+        }
+        Some(match self.data[13] >> 4 {
+            0x0 => VsHardwareType::UniSystem,
+            0x1 => VsHardwareType::UniSystemRBIBaseballProtection,
+            0x2 => VsHardwareType::UniSystemTKOBoxingProtection,
+            0x3 => VsHardwareType::UniSystemSuperXeviousProtection,
+            0x4 => VsHardwareType::UniSystemIceClimberProtection,
+            0x5 => VsHardwareType::DualSystem,
+            0x6 => VsHardwareType::DualSystemRaidOnBungelingBayProtection,
+            x => VsHardwareType::Unknown(x),
+        })
+    }
+
+    /// Number of miscellaneous ROMs following CHR ROM, NES 2.0 only.
+    pub fn misc_rom_count(&self) -> u8 {
+        if self.is_nes_2_0() {
+            self.data[14] & 0b0000_0011
+        } else {
+            0
+        }
+    }
+
+    /// NES 2.0 only, see https://www.nesdev.org/wiki/NES_2.0#Default_Expansion_Device for values. 0 is unspecified.
+    pub fn default_expansion_device(&self) -> u8 {
+        if self.is_nes_2_0() {
+            self.data[15] & 0b0011_1111
+        } else {
+            0
         }
     }
 
     pub fn has_prg_ram(&self) -> bool {
-        (self.data[10] & 0b0001_0000) == 0
+        if self.is_nes_2_0() {
+            self.prg_ram_size().in_bytes() > 0 || self.prg_nvram_size().in_bytes() > 0
+        } else {
+            (self.data[10] & 0b0001_0000) == 0
+        }
     }
 
+    /// Only iNES has a flag for this, NES 2.0 uses submappers instead.
     pub fn has_bus_conflicts(&self) -> bool {
-        (self.data[10] & 0b0010_0000) != 0
+        !self.is_nes_2_0() && (self.data[10] & 0b0010_0000) != 0
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        self.data
+    }
+}
+
+/// Builds a header from its parts, for writing out files. Anything not set is the same as an all zero NES 2.0 header.
+#[derive(Debug, Clone)]
+pub struct HeaderBuilder {
+    nes_2_0: bool,
+    prg_rom_size: usize,
+    chr_rom_size: usize,
+    mapper: u16,
+    submapper: u8,
+    nametable_arrangement: NametableArrangement,
+    battery: bool,
+    trainer: bool,
+    console_type: ConsoleType,
+    tv_system: TVSystem,
+    prg_ram_size: usize,
+    prg_nvram_size: usize,
+    chr_ram_size: usize,
+    chr_nvram_size: usize,
+    vs_ppu_type: VsPPUType,
+    vs_hardware_type: VsHardwareType,
+    misc_rom_count: u8,
+    default_expansion_device: u8,
+    has_prg_ram: bool,
+    bus_conflicts: bool,
+}
+
+impl HeaderBuilder {
+    pub fn new() -> Self {
+        Self {
+            nes_2_0: true,
+            prg_rom_size: 0,
+            chr_rom_size: 0,
+            mapper: 0,
+            submapper: 0,
+            nametable_arrangement: NametableArrangement::Horizontal,
+            battery: false,
+            trainer: false,
+            console_type: ConsoleType::NES,
+            tv_system: TVSystem::NTSC,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            vs_ppu_type: VsPPUType::RP2C03B,
+            vs_hardware_type: VsHardwareType::UniSystem,
+            misc_rom_count: 0,
+            default_expansion_device: 0,
+            has_prg_ram: true,
+            bus_conflicts: false,
+        }
+    }
+
+    /// Everything the header says, in the same format. Building this gives back the same bytes for any header without
+    /// junk in the unused bits.
+    pub fn from_header(header: &Header) -> Self {
+        Self {
+            nes_2_0: header.is_nes_2_0(),
+            prg_rom_size: header.prg_rom_size().in_bytes(),
+            chr_rom_size: header.chr_rom_size().in_bytes(),
+            mapper: header.mapper_number(),
+            submapper: header.submapper(),
+            nametable_arrangement: header.nametable_arrangement(),
+            battery: header.has_battery_backed_prg_ram(),
+            trainer: header.has_trainer(),
+            console_type: header.console_type(),
+            tv_system: header.tv_system(),
+            prg_ram_size: header.prg_ram_size().in_bytes(),
+            prg_nvram_size: header.prg_nvram_size().in_bytes(),
+            chr_ram_size: header.chr_ram_size().in_bytes(),
+            chr_nvram_size: header.chr_nvram_size().in_bytes(),
+            vs_ppu_type: header.vs_ppu_type().unwrap_or(VsPPUType::RP2C03B),
+            vs_hardware_type: header
+                .vs_hardware_type()
+                .unwrap_or(VsHardwareType::UniSystem),
+            misc_rom_count: header.misc_rom_count(),
+            default_expansion_device: header.default_expansion_device(),
+            has_prg_ram: header.has_prg_ram(),
+            bus_conflicts: header.has_bus_conflicts(),
+        }
+    }
+
+    /// iNES 1.0 when false.
+    pub fn nes_2_0(mut self, nes_2_0: bool) -> Self {
+        self.nes_2_0 = nes_2_0;
+        self
+    }
+
+    /// In bytes.
+    pub fn prg_rom_size(mut self, size: usize) -> Self {
+        self.prg_rom_size = size;
+        self
+    }
+
+    /// In bytes.
+    pub fn chr_rom_size(mut self, size: usize) -> Self {
+        self.chr_rom_size = size;
+        self
+    }
+
+    pub fn mapper(mut self, mapper: u16, submapper: u8) -> Self {
+        self.mapper = mapper;
+        self.submapper = submapper;
+        self
+    }
+
+    pub fn nametable_arrangement(mut self, arrangement: NametableArrangement) -> Self {
+        self.nametable_arrangement = arrangement;
+        self
+    }
+
+    pub fn battery(mut self, battery: bool) -> Self {
+        self.battery = battery;
+        self
+    }
+
+    pub fn trainer(mut self, trainer: bool) -> Self {
+        self.trainer = trainer;
+        self
+    }
+
+    pub fn console_type(mut self, console_type: ConsoleType) -> Self {
+        self.console_type = console_type;
+        self
+    }
+
+    pub fn tv_system(mut self, tv_system: TVSystem) -> Self {
+        self.tv_system = tv_system;
+        self
+    }
+
+    /// In bytes. For iNES only the larger of the two is kept, and the battery flag says which it is.
+    pub fn prg_ram_size(mut self, ram: usize, nvram: usize) -> Self {
+        self.prg_ram_size = ram;
+        self.prg_nvram_size = nvram;
+        self.has_prg_ram = ram > 0 || nvram > 0;
+        self
+    }
+
+    /// In bytes. iNES always has 8k of CHR RAM when there's no CHR ROM and never otherwise.
+    pub fn chr_ram_size(mut self, ram: usize, nvram: usize) -> Self {
+        self.chr_ram_size = ram;
+        self.chr_nvram_size = nvram;
+        self
+    }
+
+    pub fn vs_system(mut self, ppu_type: VsPPUType, hardware_type: VsHardwareType) -> Self {
+        self.vs_ppu_type = ppu_type;
+        self.vs_hardware_type = hardware_type;
+        self
+    }
+
+    pub fn misc_rom_count(mut self, count: u8) -> Self {
+        self.misc_rom_count = count;
+        self
+    }
+
+    pub fn default_expansion_device(mut self, device: u8) -> Self {
+        self.default_expansion_device = device;
+        self
+    }
+
+    /// iNES only.
+    pub fn bus_conflicts(mut self, bus_conflicts: bool) -> Self {
+        self.bus_conflicts = bus_conflicts;
+        self
+    }
+
+    pub fn build(&self) -> Result<Header, CartridgeError> {
+        Header::new(self.to_bytes()?)
+    }
+
+    fn to_bytes(&self) -> Result<[u8; 16], CartridgeError> {
+        let mut data = [b'N', b'E', b'S', 0x1a, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data[6] = (((self.mapper & 0x0f) as u8) << 4)
+            | match self.nametable_arrangement {
+                NametableArrangement::Vertical => 0b0000_0001,
+                NametableArrangement::FourScreenMirroring => 0b0000_1000,
+                _ => 0,
+            }
+            | if self.battery { 0b0000_0010 } else { 0 }
+            | if self.trainer { 0b0000_0100 } else { 0 };
+        data[7] = ((self.mapper & 0xf0) as u8)
+            | match self.console_type {
+                ConsoleType::NES => 0,
+                ConsoleType::VsSystem => 1,
+                ConsoleType::Playchoice10 => 2,
+                ConsoleType::Extended(_) => 3,
+            };
+
+        if self.nes_2_0 {
+            let (prg_low, prg_high) = encode_rom_size(self.prg_rom_size, pgr_rom::BLOCK_SIZE);
+            let (chr_low, chr_high) = encode_rom_size(self.chr_rom_size, chr_rom::BLOCK_SIZE);
+            data[4] = prg_low;
+            data[5] = chr_low;
+            data[7] |= 0b0000_1000;
+            data[8] = (((self.mapper >> 8) as u8) & 0b0000_1111) | (self.submapper << 4);
+            data[9] = prg_high | (chr_high << 4);
+            data[10] =
+                shift_for_size(self.prg_ram_size) | (shift_for_size(self.prg_nvram_size) << 4);
+            data[11] =
+                shift_for_size(self.chr_ram_size) | (shift_for_size(self.chr_nvram_size) << 4);
+            data[12] = match self.tv_system {
+                TVSystem::NTSC => 0,
+                TVSystem::PAL => 1,
+                TVSystem::Both => 2,
+                TVSystem::Dendy => 3,
+            };
+            data[13] = match self.console_type {
+                ConsoleType::VsSystem => {
+                    vs_ppu_type_bits(self.vs_ppu_type)
+                        | (vs_hardware_type_bits(self.vs_hardware_type) << 4)
+                }
+                ConsoleType::Extended(x) => x & 0b0000_1111,
+                _ => 0,
+            };
+            data[14] = self.misc_rom_count & 0b0000_0011;
+            data[15] = self.default_expansion_device & 0b0011_1111;
+        } else {
+            if self.mapper > 0xff {
+                return Err(CartridgeError::NotRepresentableInINES("mapper"));
+            }
+            if self.submapper != 0 {
+                return Err(CartridgeError::NotRepresentableInINES("submapper"));
+            }
+            if let ConsoleType::Extended(_) = self.console_type {
+                return Err(CartridgeError::NotRepresentableInINES("console type"));
+            }
+            if let TVSystem::Dendy = self.tv_system {
+                return Err(CartridgeError::NotRepresentableInINES("tv system"));
+            }
+            if self.chr_nvram_size > 0 || self.misc_rom_count > 0 {
+                return Err(CartridgeError::NotRepresentableInINES(
+                    "nes 2.0 only memory",
+                ));
+            }
+            if !self.prg_rom_size.is_multiple_of(pgr_rom::BLOCK_SIZE)
+                || self.prg_rom_size / pgr_rom::BLOCK_SIZE > 0xff
+            {
+                return Err(CartridgeError::NotRepresentableInINES("prg rom size"));
+            }
+            if !self.chr_rom_size.is_multiple_of(chr_rom::BLOCK_SIZE)
+                || self.chr_rom_size / chr_rom::BLOCK_SIZE > 0xff
+            {
+                return Err(CartridgeError::NotRepresentableInINES("chr rom size"));
+            }
+            data[4] = (self.prg_rom_size / pgr_rom::BLOCK_SIZE) as u8;
+            data[5] = (self.chr_rom_size / chr_rom::BLOCK_SIZE) as u8;
+            // 0 is 8k for compatibility, so that's what 8k or less gets written as
+            let prg_ram_size = self.prg_ram_size.max(self.prg_nvram_size);
+            data[8] = if prg_ram_size <= pgr_ram::BLOCK_SIZE {
+                0
+            } else {
+                prg_ram_size.div_ceil(pgr_ram::BLOCK_SIZE).min(0xff) as u8
+            };
+            let (byte_9, byte_10) = ines_tv_system(self.tv_system);
+            data[9] = byte_9;
+            data[10] = byte_10
+                | if self.has_prg_ram { 0 } else { 0b0001_0000 }
+                | if self.bus_conflicts { 0b0010_0000 } else { 0 };
+        }
+        Ok(data)
+    }
+}
+
+impl Default for HeaderBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// NES 2.0 size encoding, the low byte and the high nibble. Sizes that aren't a whole number of blocks use the
+/// exponent-multiplier form if they can, and otherwise get rounded up to a whole block.
+fn encode_rom_size(len: usize, block_size: usize) -> (u8, u8) {
+    if !len.is_multiple_of(block_size) || len / block_size > 0xeff {
+        for multiplier in 0..4 {
+            let factor = multiplier * 2 + 1;
+            if len.is_multiple_of(factor) && (len / factor).is_power_of_two() {
+                let exponent = (len / factor).trailing_zeros() as usize;
+                if exponent < 64 {
+                    return (((exponent << 2) | multiplier) as u8, 0x0f);
+                }
+            }
+        }
+    }
+    let blocks = len.div_ceil(block_size).min(0xeff);
+    ((blocks & 0xff) as u8, ((blocks >> 8) & 0x0f) as u8)
+}
+
+fn vs_ppu_type_bits(ppu_type: VsPPUType) -> u8 {
+    match ppu_type {
+        VsPPUType::RP2C03B => 0x0,
+        VsPPUType::RP2C03G => 0x1,
+        VsPPUType::RP2C04_0001 => 0x2,
+        VsPPUType::RP2C04_0002 => 0x3,
+        VsPPUType::RP2C04_0003 => 0x4,
+        VsPPUType::RP2C04_0004 => 0x5,
+        VsPPUType::RC2C03B => 0x6,
+        VsPPUType::RC2C03C => 0x7,
+        VsPPUType::RC2C05_01 => 0x8,
+        VsPPUType::RC2C05_02 => 0x9,
+        VsPPUType::RC2C05_03 => 0xa,
+        VsPPUType::RC2C05_04 => 0xb,
+        VsPPUType::RC2C05_05 => 0xc,
+        VsPPUType::Unknown(x) => x & 0b0000_1111,
+    }
+}
+
+fn vs_hardware_type_bits(hardware_type: VsHardwareType) -> u8 {
+    match hardware_type {
+        VsHardwareType::UniSystem => 0x0,
+        VsHardwareType::UniSystemRBIBaseballProtection => 0x1,
+        VsHardwareType::UniSystemTKOBoxingProtection => 0x2,
+        VsHardwareType::UniSystemSuperXeviousProtection => 0x3,
+        VsHardwareType::UniSystemIceClimberProtection => 0x4,
+        VsHardwareType::DualSystem => 0x5,
+        VsHardwareType::DualSystemRaidOnBungelingBayProtection => 0x6,
+        VsHardwareType::Unknown(x) => x & 0b0000_1111,
+    }
+}
+
+/// NES 2.0 if byte 7 says so. Otherwise if bytes 12-15 aren't all 0, or byte 7 has the bits NES 2.0 uses to mark
+/// itself set the wrong way, something other than flags was written over the end of the header.
+fn header_format(data: &[u8; HEADER_SIZE]) -> HeaderFormat {
+    match data[7] & 0b0000_1100 {
+        0b0000_1000 => HeaderFormat::NES2_0,
+        0b0000_0000 if data[12..].iter().all(|x| *x == 0) => HeaderFormat::INES,
+        _ if data[7..].starts_with(DISK_DUDE) => HeaderFormat::DiskDude,
+        _ => HeaderFormat::ArchaicINES,
+    }
+}
+
+fn mapper_number(data: &[u8; HEADER_SIZE]) -> u16 {
+    let low = ((data[6] & 0b1111_0000) >> 4) as u16;
+    let middle = (data[7] & 0b1111_0000) as u16;
+    let high = if (data[7] & 0b0000_1100) == 0b0000_1000 {
+        ((data[8] & 0b0000_1111) as u16) << 8
+    } else {
+        0
+    };
+    low | middle | high
+}
+
+/// Bit 0 of byte 9 and bits 0-1 of byte 10.
+fn ines_tv_system(tv_system: TVSystem) -> (u8, u8) {
+    match tv_system {
+        TVSystem::NTSC => (0, 0),
+        TVSystem::PAL => (1, 2),
+        _ => (0, 3),
+    }
+}
+
+fn memory_mapper(number: u16) -> MemoryMapper {
+    match number {
+        0 => MemoryMapper::NROM,
+        99 => MemoryMapper::VsSystem,
+        _ => MemoryMapper::Other(number),
+    }
+}
+
+/// NES 2.0 RAM sizes are 64 << shift, with 0 meaning none at all.
+fn shift_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+/// The reverse of shift_size, rounding up.
+fn shift_for_size(size: usize) -> u8 {
+    if size == 0 {
+        0
+    } else {
+        (size.div_ceil(64).next_power_of_two().trailing_zeros() as u8).min(0b0000_1111)
     }
 }
 
@@ -196,86 +1077,117 @@ pub struct Cartridge {
     trainer: Option<Trainer>,
     prg_rom_data: Vec<pgr_rom::Block>,
     chr_rom_data: Vec<chr_rom::Block>,
+    playchoice_inst_rom: Option<Box<playchoice::InstROM>>,
+    playchoice_prom: Option<playchoice::PROM>,
+    misc_rom_data: Vec<u8>,
+    trailing_data: Vec<u8>,
 }
 
 impl Cartridge {
     pub fn from_bytes(data: Vec<u8>) -> Result<Self, CartridgeError> {
-        // let data = data.as_slice();
+        Self::from_bytes_with_strictness(data, Strictness::Lenient)
+    }
+
+    pub fn from_bytes_with_strictness(
+        data: Vec<u8>,
+        strictness: Strictness,
+    ) -> Result<Self, CartridgeError> {
+        if data.len() >= MAGIC.len() && !data.starts_with(&MAGIC) {
+            return Err(CartridgeError::BadMagic(data[0..4].try_into().unwrap()));
+        }
+        let truncated = |section, remaining_data: &[u8], expected| CartridgeError::Truncated {
+            section,
+            offset: data.len() - remaining_data.len(),
+            expected,
+            actual: remaining_data.len(),
+        };
 
+        let mut remaining_data = &data[..];
         let header = Header::new(
-            data[0..16]
-                .try_into()
-                .map_err(|_| CartridgeError::BadHeader)?,
+            take(&mut remaining_data)
+                .ok_or_else(|| truncated(Section::Header, remaining_data, HEADER_SIZE))?,
         )?;
-        let mut remaining_data = &data[16..];
 
         let trainer = if header.has_trainer() {
-            let len = 512;
-            if remaining_data.len() < len {
-                Err(CartridgeError::MissingTrainer)
-            } else {
-                let result = Trainer(
-                    remaining_data[0..len]
-                        .try_into()
-                        .map_err(|_| CartridgeError::MissingTrainer)?,
-                );
-                remaining_data = &remaining_data[len..];
-                Ok(Some(result))
-            }
+            Some(Trainer(take(&mut remaining_data).ok_or_else(|| {
+                truncated(Section::Trainer, remaining_data, TRAINER_SIZE)
+            })?))
         } else {
-            Ok(None)
-        }?;
+            None
+        };
+
+        let len = header.prg_rom_size().in_bytes();
+        let prg_rom_data = read_blocks(&mut remaining_data, len)
+            .ok_or_else(|| truncated(Section::PRGROM, remaining_data, len))?;
+
+        let len = header.chr_rom_size().in_bytes();
+        let chr_rom_data = read_blocks(&mut remaining_data, len)
+            .ok_or_else(|| truncated(Section::CHRROM, remaining_data, len))?;
 
-        let prg_rom_data = {
-            let size = header.prg_rom_size();
-            if remaining_data.len() < size.in_bytes() {
-                Err(CartridgeError::MissingPRGROM)
+        let (playchoice_inst_rom, playchoice_prom) = if header.is_playchoice_10() {
+            // lots of dumps leave these out entirely, so only a partial one is an error
+            let inst_rom = if remaining_data.is_empty() {
+                warn!("PlayChoice-10 INST-ROM is missing");
+                None
             } else {
-                let mut results = Vec::with_capacity(size.in_blocks() as usize);
-                for _ in 0..size.in_blocks() {
-                    const LEN: usize = pgr_rom::BLOCK_SIZE;
-                    results.push(
-                        remaining_data[0..LEN]
-                            .try_into()
-                            .map_err(|_| CartridgeError::MissingPRGROM)?,
-                    );
-                    remaining_data = &remaining_data[LEN..];
-                }
-                Ok(results)
-            }
-        }?;
+                Some(Box::new(take(&mut remaining_data).ok_or_else(|| {
+                    truncated(
+                        Section::PlayChoiceINSTROM,
+                        remaining_data,
+                        playchoice::INST_ROM_SIZE,
+                    )
+                })?))
+            };
 
-        let chr_rom_data = {
-            let size = header.chr_rom_size();
-            if remaining_data.len() < size.in_bytes() {
-                Err(CartridgeError::MissingCHRROM)
+            let prom = if inst_rom.is_some() && remaining_data.len() >= playchoice::PROM_SIZE * 2 {
+                let result = playchoice::PROM {
+                    data: remaining_data[0..playchoice::PROM_SIZE].try_into().unwrap(),
+                    counter_out: remaining_data[playchoice::PROM_SIZE..(playchoice::PROM_SIZE * 2)]
+                        .try_into()
+                        .unwrap(),
+                };
+                remaining_data = &remaining_data[(playchoice::PROM_SIZE * 2)..];
+                Some(result)
             } else {
-                let size = header.chr_rom_size();
-                let mut results = Vec::with_capacity(size.in_blocks() as usize);
-                for _ in 0..size.in_blocks() {
-                    const LEN: usize = chr_rom::BLOCK_SIZE;
-                    results.push(
-                        remaining_data[0..LEN]
-                            .try_into()
-                            .map_err(|_| CartridgeError::MissingCHRROM)?,
-                    );
-                    remaining_data = &remaining_data[LEN..];
-                }
-                Ok(results)
-            }
-        }?;
+                None
+            };
+
+            (inst_rom, prom)
+        } else {
+            (None, None)
+        };
 
-        /*
-        TODO remaining data is playchoice stuff
-            PlayChoice INST-ROM, if present (0 or 8192 bytes)
-            PlayChoice PROM, if present (16 bytes Data, 16 bytes CounterOut) (this is often missing; see PC10 ROM-Images for details)
-        */
+        // the header doesn't give sizes for these, so it's just everything that's left
+        let misc_rom_data = if header.misc_rom_count() > 0 {
+            let result = remaining_data.to_vec();
+            remaining_data = &[];
+            result
+        } else {
+            Vec::new()
+        };
+
+        if !remaining_data.is_empty() {
+            match strictness {
+                Strictness::Lenient => warn!(
+                    "{} bytes of unexpected data at end of file",
+                    remaining_data.len()
+                ),
+                Strictness::Strict => Err(CartridgeError::TrailingData {
+                    offset: data.len() - remaining_data.len(),
+                    size: remaining_data.len(),
+                })?,
+            }
+        }
 
         let result = Self {
             header,
             trainer,
             prg_rom_data,
             chr_rom_data,
+            playchoice_inst_rom,
+            playchoice_prom,
+            misc_rom_data,
+            trailing_data: remaining_data.to_vec(),
         };
 
         Ok(result)
@@ -285,6 +1197,66 @@ impl Cartridge {
         &self.header
     }
 
+    /// For fixing up bad headers after loading, see `rom_database`.
+    pub fn header_mut(&mut self) -> &mut Header {
+        &mut self.header
+    }
+
+    /// Replaces the header, which has to agree with what's already loaded about where everything goes.
+    pub fn set_header(&mut self, header: Header) -> Result<(), CartridgeError> {
+        let mismatch = if header.has_trainer() != self.header.has_trainer() {
+            Some(Section::Trainer)
+        } else if header.prg_rom_size().in_bytes() != self.header.prg_rom_size().in_bytes() {
+            Some(Section::PRGROM)
+        } else if header.chr_rom_size().in_bytes() != self.header.chr_rom_size().in_bytes() {
+            Some(Section::CHRROM)
+        } else if header.is_playchoice_10() != self.header.is_playchoice_10() {
+            Some(Section::PlayChoiceINSTROM)
+        } else if header.misc_rom_count() != self.header.misc_rom_count() {
+            Some(Section::MiscROM)
+        } else {
+            None
+        };
+        if let Some(section) = mismatch {
+            return Err(CartridgeError::HeaderMismatch(section));
+        }
+        self.header = header;
+        Ok(())
+    }
+
+    /// The file as it would be read, with the header rebuilt from its parts by `HeaderBuilder`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CartridgeError> {
+        let mut result = HeaderBuilder::from_header(&self.header)
+            .build()?
+            .to_bytes()
+            .to_vec();
+        if let Some(trainer) = &self.trainer {
+            result.extend_from_slice(trainer.data());
+        }
+        // the last block is padded when the size isn't a whole number of them
+        let start = result.len();
+        result.extend(self.prg_rom_data.iter().flatten());
+        result.truncate(start + self.header.prg_rom_size().in_bytes());
+        let start = result.len();
+        result.extend(self.chr_rom_data.iter().flatten());
+        result.truncate(start + self.header.chr_rom_size().in_bytes());
+        if let Some(inst_rom) = &self.playchoice_inst_rom {
+            result.extend_from_slice(inst_rom.as_slice());
+        }
+        if let Some(prom) = &self.playchoice_prom {
+            result.extend_from_slice(&prom.data);
+            result.extend_from_slice(&prom.counter_out);
+        }
+        result.extend_from_slice(&self.misc_rom_data);
+        result.extend_from_slice(&self.trailing_data);
+        Ok(result)
+    }
+
+    /// Meant to be loaded at $7000 on power on.
+    pub fn trainer(&self) -> Option<&Trainer> {
+        self.trainer.as_ref()
+    }
+
     pub fn pgr_rom(&self) -> &[pgr_rom::Block] {
         self.prg_rom_data.as_slice()
     }
@@ -292,4 +1264,371 @@ impl Cartridge {
     pub fn chr_rom(&self) -> &[chr_rom::Block] {
         self.chr_rom_data.as_slice()
     }
+
+    /// PRG ROM without the padding on the end of its last block.
+    pub fn prg_rom_bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.prg_rom_data
+            .iter()
+            .flatten()
+            .take(self.header.prg_rom_size().in_bytes())
+            .copied()
+    }
+
+    /// CHR ROM without the padding on the end of its last block.
+    pub fn chr_rom_bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.chr_rom_data
+            .iter()
+            .flatten()
+            .take(self.header.chr_rom_size().in_bytes())
+            .copied()
+    }
+
+    pub fn playchoice_inst_rom(&self) -> Option<&playchoice::InstROM> {
+        self.playchoice_inst_rom.as_deref()
+    }
+
+    pub fn playchoice_prom(&self) -> Option<&playchoice::PROM> {
+        self.playchoice_prom.as_ref()
+    }
+
+    /// The NES 2.0 miscellaneous ROM area, how it's divided up depends on the mapper.
+    pub fn misc_rom(&self) -> &[u8] {
+        self.misc_rom_data.as_slice()
+    }
+
+    /// Anything past where the file should have ended. Always empty when loaded strictly.
+    pub fn trailing_data(&self) -> &[u8] {
+        self.trailing_data.as_slice()
+    }
+}
+
+/// Splits off a fixed size array, if there's enough left.
+fn take<const N: usize>(remaining_data: &mut &[u8]) -> Option<[u8; N]> {
+    let (data, rest) = remaining_data.split_first_chunk()?;
+    *remaining_data = rest;
+    Some(*data)
+}
+
+/// Splits off the given number of bytes into whole blocks, zero padding the last one when NES 2.0 gives us a size that
+/// isn't a multiple of the block size.
+fn read_blocks<const N: usize>(remaining_data: &mut &[u8], len: usize) -> Option<Vec<[u8; N]>> {
+    if remaining_data.len() < len {
+        return None;
+    }
+    let (data, rest) = remaining_data.split_at(len);
+    *remaining_data = rest;
+    Some(
+        data.chunks(N)
+            .map(|chunk| {
+                let mut block = [0; N];
+                block[0..chunk.len()].copy_from_slice(chunk);
+                block
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Cartridge, CartridgeError, ConsoleType, Header, HeaderBuilder, HeaderFormat, MemoryMapper,
+        NametableArrangement, Section, Strictness, TVSystem, VsPPUType,
+    };
+
+    #[test]
+    pub fn nes_2_0_header() {
+        let data = [
+            b'N',
+            b'E',
+            b'S',
+            0x1a,
+            0x02,
+            0x01,
+            0x02,
+            0b0000_1001,
+            0x50,
+            0x00,
+            0x97,
+            0x07,
+            0x03,
+            0x14,
+            0x01,
+            0x01,
+        ];
+        let header = Header::new(data).unwrap();
+        assert!(header.is_nes_2_0());
+        assert_eq!(header.mapper_number(), 0);
+        assert_eq!(header.submapper(), 5);
+        assert_eq!(header.prg_rom_size().in_bytes(), 2 * 16 * 1024);
+        assert_eq!(header.chr_rom_size().in_bytes(), 8 * 1024);
+        assert_eq!(header.prg_ram_size().in_bytes(), 64 << 7);
+        assert_eq!(header.prg_nvram_size().in_bytes(), 64 << 9);
+        assert_eq!(header.chr_ram_size().in_bytes(), 64 << 7);
+        assert_eq!(header.chr_nvram_size().in_bytes(), 0);
+        assert!(header.has_battery_backed_prg_ram());
+        assert!(matches!(header.tv_system(), TVSystem::Dendy));
+        assert!(matches!(header.console_type(), ConsoleType::VsSystem));
+        assert!(matches!(header.vs_ppu_type(), Some(VsPPUType::RP2C04_0003)));
+        assert_eq!(header.misc_rom_count(), 1);
+        assert_eq!(header.default_expansion_device(), 1);
+
+        // exponent-multiplier sizes, 2^6 * 3 = 192 bytes of PRG ROM
+        let mut data = data;
+        data[4] = 0b0001_1001;
+        data[9] = 0x0f;
+        let header = Header::new(data).unwrap();
+        assert_eq!(header.prg_rom_size().in_bytes(), 192);
+        assert_eq!(header.prg_rom_size().in_blocks(), 1);
+
+        // 12-bit mappers, which load whether or not they're emulated
+        data[8] = 0x01;
+        let header = Header::new(data).unwrap();
+        assert_eq!(header.mapper_number(), 0x100);
+        assert!(matches!(header.memory_mapper(), MemoryMapper::Other(0x100)));
+    }
+
+    #[test]
+    pub fn ines_header() {
+        let data = [
+            b'N', b'E', b'S', 0x1a, 0x02, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        let header = Header::new(data).unwrap();
+        assert!(!header.is_nes_2_0());
+        assert_eq!(header.submapper(), 0);
+        assert_eq!(header.prg_ram_size().in_bytes(), 0);
+        assert_eq!(header.prg_nvram_size().in_bytes(), 8 * 1024);
+        assert_eq!(header.chr_ram_size().in_bytes(), 8 * 1024);
+        assert!(matches!(header.console_type(), ConsoleType::NES));
+    }
+
+    #[test]
+    pub fn data_after_chr_rom() {
+        let mut data = vec![
+            b'N',
+            b'E',
+            b'S',
+            0x1a,
+            0x01,
+            0x00,
+            0x00,
+            0b0000_0010,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        data.resize(data.len() + 16 * 1024, 0xea);
+        data.resize(data.len() + 8 * 1024, 0x01);
+        data.resize(data.len() + 32, 0x02);
+
+        let cartridge =
+            Cartridge::from_bytes_with_strictness(data.clone(), Strictness::Strict).unwrap();
+        assert_eq!(cartridge.playchoice_inst_rom().unwrap()[0], 0x01);
+        assert_eq!(cartridge.playchoice_prom().unwrap().counter_out[15], 0x02);
+        assert!(cartridge.trailing_data().is_empty());
+
+        data.push(0x03);
+        assert!(matches!(
+            Cartridge::from_bytes_with_strictness(data.clone(), Strictness::Strict),
+            Err(CartridgeError::TrailingData {
+                offset: 0x6030,
+                size: 1
+            })
+        ));
+        let cartridge = Cartridge::from_bytes(data).unwrap();
+        assert_eq!(cartridge.trailing_data(), &[0x03]);
+    }
+
+    #[test]
+    pub fn to_bytes_round_trip() {
+        // NES 2.0 Vs. System with misc ROM
+        let mut data = vec![
+            b'N',
+            b'E',
+            b'S',
+            0x1a,
+            0x02,
+            0x01,
+            0x03,
+            0b0000_1001,
+            0x50,
+            0x00,
+            0x97,
+            0x07,
+            0x03,
+            0x14,
+            0x01,
+            0x01,
+        ];
+        data.extend((0..(2 * 16 * 1024 + 8 * 1024 + 4)).map(|x| x as u8));
+        let cartridge = Cartridge::from_bytes(data.clone()).unwrap();
+        assert_eq!(cartridge.to_bytes().unwrap(), data);
+
+        // exponent-multiplier PRG ROM size
+        let mut data = vec![
+            b'N',
+            b'E',
+            b'S',
+            0x1a,
+            0b0001_1001,
+            0x00,
+            0x00,
+            0b0000_1000,
+            0x00,
+            0x0f,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        data.extend((0..192).map(|x| x as u8));
+        let cartridge = Cartridge::from_bytes(data.clone()).unwrap();
+        assert_eq!(cartridge.to_bytes().unwrap(), data);
+
+        // iNES with a trainer, and junk that gets cleaned out of the unused byte
+        let mut data = vec![
+            b'N', b'E', b'S', 0x1a, 0x01, 0x01, 0x07, 0x00, 0x00, 0x01, 0x00, b'j', 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        data.extend((0..(512 + 16 * 1024 + 8 * 1024)).map(|x| x as u8));
+        let mut cartridge = Cartridge::from_bytes(data.clone()).unwrap();
+        assert!(matches!(cartridge.header().tv_system(), TVSystem::PAL));
+        let mut expected = data.clone();
+        expected[10] = 0x02;
+        expected[11] = 0;
+        assert_eq!(cartridge.to_bytes().unwrap(), expected);
+
+        let header = HeaderBuilder::from_header(cartridge.header())
+            .nes_2_0(true)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            HeaderBuilder::from_header(&header)
+                .tv_system(TVSystem::Dendy)
+                .nes_2_0(false)
+                .build(),
+            Err(CartridgeError::NotRepresentableInINES(_))
+        ));
+        cartridge.set_header(header).unwrap();
+        let bytes = cartridge.to_bytes().unwrap();
+        assert_eq!(
+            &bytes[4..16],
+            &[0x01, 0x01, 0x07, 0x08, 0x00, 0x00, 0x70, 0x00, 0x01, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(&bytes[16..], &data[16..]);
+
+        // the Vs. System's mirroring bits are kept, even though its VRAM is always four screens
+        let mut data = vec![
+            b'N', b'E', b'S', 0x1a, 0x01, 0x01, 0x31, 0x61, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        data.extend((0..(16 * 1024 + 8 * 1024)).map(|x| x as u8));
+        let mut cartridge = Cartridge::from_bytes(data.clone()).unwrap();
+        assert!(matches!(
+            cartridge.header().nametable_arrangement(),
+            NametableArrangement::Vertical
+        ));
+        let header = HeaderBuilder::from_header(cartridge.header())
+            .build()
+            .unwrap();
+        cartridge.set_header(header).unwrap();
+        assert_eq!(cartridge.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    pub fn archaic_headers() {
+        let mut data = vec![b'N', b'E', b'S', 0x1a, 0x01, 0x01, 0x01];
+        data.extend_from_slice(b"DiskDude!");
+        data.extend((0..(16 * 1024 + 8 * 1024)).map(|x| x as u8));
+        // read as iNES this would be mapper 64
+        let cartridge = Cartridge::from_bytes(data.clone()).unwrap();
+        assert_eq!(cartridge.header().format(), HeaderFormat::DiskDude);
+        assert_eq!(cartridge.header().mapper_number(), 0);
+        let mut expected = data.clone();
+        expected[7..16].fill(0);
+        assert_eq!(cartridge.to_bytes().unwrap(), expected);
+
+        data[7..16].copy_from_slice(&[0x04, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let header = Header::new(data[0..16].try_into().unwrap()).unwrap();
+        assert_eq!(header.format(), HeaderFormat::ArchaicINES);
+        data[7] = 0;
+        data[15] = 0xff;
+        let header = Header::new(data[0..16].try_into().unwrap()).unwrap();
+        assert_eq!(header.format(), HeaderFormat::ArchaicINES);
+        data[15] = 0;
+        let header = Header::new(data[0..16].try_into().unwrap()).unwrap();
+        assert_eq!(header.format(), HeaderFormat::INES);
+    }
+
+    #[test]
+    pub fn truncated_files() {
+        // PlayChoice-10 with a trainer, so there's every section that has to be complete
+        let mut data = vec![
+            b'N', b'E', b'S', 0x1a, 0x01, 0x01, 0x04, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        let sections = [
+            (Section::Trainer, 512),
+            (Section::PRGROM, 16 * 1024),
+            (Section::CHRROM, 8 * 1024),
+            (Section::PlayChoiceINSTROM, 8 * 1024),
+        ];
+        for (_, size) in sections {
+            data.resize(data.len() + size, 0xea);
+        }
+        assert!(Cartridge::from_bytes(data.clone()).is_ok());
+
+        for len in [0, 3, 15] {
+            assert!(matches!(
+                Cartridge::from_bytes(data[..len].to_vec()),
+                Err(CartridgeError::Truncated {
+                    section: Section::Header,
+                    offset: 0,
+                    expected: 16,
+                    actual,
+                }) if actual == len
+            ));
+        }
+        let mut offset = 16;
+        for (section, size) in sections {
+            let result = Cartridge::from_bytes(data[..(offset + size - 1)].to_vec());
+            match result {
+                Err(CartridgeError::Truncated {
+                    section: s,
+                    offset: o,
+                    expected,
+                    actual,
+                }) => {
+                    assert_eq!((s, o, expected, actual), (section, offset, size, size - 1));
+                }
+                _ => panic!("{:?} wasn't truncated", section),
+            }
+            offset += size;
+        }
+        assert_eq!(
+            Cartridge::from_bytes(data[..20].to_vec())
+                .err()
+                .unwrap()
+                .to_string(),
+            "trainer at offset 0x10 is truncated, expected 512 bytes but only 4 are left"
+        );
+
+        // any length at all gives an error or a cartridge, not a panic
+        for len in (0..data.len()).step_by(61) {
+            let _ = Cartridge::from_bytes(data[..len].to_vec());
+        }
+
+        data[2] = b'Z';
+        assert!(matches!(
+            Cartridge::from_bytes(data),
+            Err(CartridgeError::BadMagic([b'N', b'E', b'Z', 0x1a]))
+        ));
+    }
 }
@@ -0,0 +1,283 @@
+// Everything we know about a cartridge, for the info command.
+
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::fmt::Display;
+
+use crate::{
+    cartridge_file::{Cartridge, ConsoleType},
+    rom_database::RomDatabase,
+};
+
+#[derive(Serialize)]
+pub struct Hashes {
+    crc32: String,
+    sha1: String,
+}
+
+impl Hashes {
+    fn new(data: &[u8]) -> Self {
+        Self {
+            crc32: format!("{:08x}", crc32fast::hash(data)),
+            sha1: Sha1::digest(data)
+                .iter()
+                .map(|x| format!("{:02x}", x))
+                .collect(),
+        }
+    }
+}
+
+impl Display for Hashes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "crc32 {}, sha1 {}", self.crc32, self.sha1)
+    }
+}
+
+#[derive(Serialize)]
+pub struct DatabaseMatch {
+    name: String,
+    /// What the database would change in the header, empty if it's already right.
+    corrections: Vec<String>,
+}
+
+/// Enums are kept as their Debug names, so they read the same in text and JSON.
+#[derive(Serialize)]
+pub struct CartridgeInfo {
+    format: String,
+    mapper: u16,
+    submapper: u8,
+    mirroring: String,
+    battery: bool,
+    trainer: bool,
+    console_type: String,
+    region: String,
+    vs_ppu_type: Option<String>,
+    vs_hardware_type: Option<String>,
+    prg_rom_size: usize,
+    chr_rom_size: usize,
+    /// Whether there's any PRG RAM at all, for iNES this is a separate flag from the size.
+    prg_ram: bool,
+    prg_ram_size: usize,
+    prg_nvram_size: usize,
+    chr_ram_size: usize,
+    chr_nvram_size: usize,
+    bus_conflicts: bool,
+    misc_rom_count: u8,
+    misc_rom_size: usize,
+    /// Only for PlayChoice-10 cartridges, none when the dump leaves them out.
+    playchoice_inst_rom: Option<Hashes>,
+    /// Both halves, data then counter out.
+    playchoice_prom: Option<Hashes>,
+    trailing_data_size: usize,
+    default_expansion_device: u8,
+    prg_rom: Hashes,
+    chr_rom: Hashes,
+    /// PRG ROM followed by CHR ROM, which is what the ROM database goes by.
+    rom: Hashes,
+    database: Option<DatabaseMatch>,
+}
+
+impl CartridgeInfo {
+    /// Describes the header as loaded, with anything the database disagrees with listed separately.
+    pub fn new(mut cartridge: Cartridge, database: &RomDatabase) -> Self {
+        let header = cartridge.header();
+        let prg_rom = cartridge.prg_rom_bytes().collect::<Vec<_>>();
+        let chr_rom = cartridge.chr_rom_bytes().collect::<Vec<_>>();
+
+        let mut result = Self {
+            format: header.format().to_string(),
+            mapper: header.mapper_number(),
+            submapper: header.submapper(),
+            mirroring: format!("{:?}", header.nametable_arrangement()),
+            battery: header.has_battery_backed_prg_ram(),
+            trainer: header.has_trainer(),
+            console_type: format!("{:?}", header.console_type()),
+            region: format!("{:?}", header.tv_system()),
+            vs_ppu_type: header.vs_ppu_type().map(|x| format!("{:?}", x)),
+            vs_hardware_type: header.vs_hardware_type().map(|x| format!("{:?}", x)),
+            prg_rom_size: header.prg_rom_size().in_bytes(),
+            chr_rom_size: header.chr_rom_size().in_bytes(),
+            prg_ram: header.has_prg_ram(),
+            prg_ram_size: header.prg_ram_size().in_bytes(),
+            prg_nvram_size: header.prg_nvram_size().in_bytes(),
+            chr_ram_size: header.chr_ram_size().in_bytes(),
+            chr_nvram_size: header.chr_nvram_size().in_bytes(),
+            bus_conflicts: header.has_bus_conflicts(),
+            misc_rom_count: header.misc_rom_count(),
+            misc_rom_size: cartridge.misc_rom().len(),
+            playchoice_inst_rom: cartridge.playchoice_inst_rom().map(|x| Hashes::new(x)),
+            playchoice_prom: cartridge
+                .playchoice_prom()
+                .map(|x| Hashes::new(&[x.data, x.counter_out].concat())),
+            trailing_data_size: cartridge.trailing_data().len(),
+            default_expansion_device: header.default_expansion_device(),
+            prg_rom: Hashes::new(&prg_rom),
+            chr_rom: Hashes::new(&chr_rom),
+            rom: Hashes::new(&[prg_rom, chr_rom].concat()),
+            database: None,
+        };
+
+        if let (Some(entry), corrections) = database.correct(&mut cartridge) {
+            result.database = Some(DatabaseMatch {
+                name: entry.name().to_string(),
+                corrections: corrections.iter().map(|x| x.to_string()).collect(),
+            });
+        }
+        result
+    }
+}
+
+impl Display for CartridgeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "format = {} header", self.format)?;
+        writeln!(
+            f,
+            "mapper = {}, submapper = {}",
+            self.mapper, self.submapper
+        )?;
+        writeln!(f, "mirroring = {}", self.mirroring)?;
+        writeln!(f, "battery = {}, trainer = {}", self.battery, self.trainer)?;
+        writeln!(f, "console type = {}", self.console_type)?;
+        if let (Some(ppu_type), Some(hardware_type)) = (&self.vs_ppu_type, &self.vs_hardware_type) {
+            writeln!(
+                f,
+                "vs ppu type = {}, vs hardware type = {}",
+                ppu_type, hardware_type
+            )?;
+        }
+        writeln!(f, "region = {}", self.region)?;
+        writeln!(f, "prg rom = {} bytes, {}", self.prg_rom_size, self.prg_rom)?;
+        writeln!(f, "chr rom = {} bytes, {}", self.chr_rom_size, self.chr_rom)?;
+        writeln!(f, "prg + chr rom = {}", self.rom)?;
+        writeln!(
+            f,
+            "prg ram = {}, {} bytes, nvram = {} bytes",
+            if self.prg_ram { "present" } else { "absent" },
+            self.prg_ram_size,
+            self.prg_nvram_size
+        )?;
+        writeln!(
+            f,
+            "chr ram = {} bytes, nvram = {} bytes",
+            self.chr_ram_size, self.chr_nvram_size
+        )?;
+        if self.console_type == format!("{:?}", ConsoleType::Playchoice10) {
+            match &self.playchoice_inst_rom {
+                Some(hashes) => writeln!(f, "playchoice inst rom = {}", hashes)?,
+                None => writeln!(f, "playchoice inst rom = missing")?,
+            }
+            match &self.playchoice_prom {
+                Some(hashes) => writeln!(f, "playchoice prom = {}", hashes)?,
+                None => writeln!(f, "playchoice prom = missing")?,
+            }
+        }
+        writeln!(f, "bus conflicts = {}", self.bus_conflicts)?;
+        writeln!(
+            f,
+            "misc rom = {} roms, {} bytes, trailing data = {} bytes",
+            self.misc_rom_count, self.misc_rom_size, self.trailing_data_size
+        )?;
+        writeln!(
+            f,
+            "default expansion device = {}",
+            self.default_expansion_device
+        )?;
+        match &self.database {
+            Some(entry) => {
+                write!(f, "rom database = {:?}", entry.name)?;
+                if entry.corrections.is_empty() {
+                    write!(f, ", header agrees")
+                } else {
+                    write!(f, ", would correct {}", entry.corrections.join(", "))
+                }
+            }
+            None => write!(f, "rom database = no match"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CartridgeInfo;
+    use crate::{
+        cartridge_file::{Cartridge, ConsoleType, HeaderBuilder},
+        rom_database::RomDatabase,
+    };
+
+    #[test]
+    pub fn playchoice_10() {
+        let header = HeaderBuilder::new()
+            .prg_rom_size(16 * 1024)
+            .chr_rom_size(8 * 1024)
+            .console_type(ConsoleType::Playchoice10)
+            .build()
+            .unwrap();
+        let mut data = header.to_bytes().to_vec();
+        data.resize(data.len() + 16 * 1024 + 8 * 1024, 0);
+        let database = RomDatabase::parse("").unwrap();
+
+        let info = CartridgeInfo::new(Cartridge::from_bytes(data.clone()).unwrap(), &database);
+        assert!(info.playchoice_inst_rom.is_none());
+        assert!(info.playchoice_prom.is_none());
+        let text = info.to_string();
+        assert!(text.contains("playchoice inst rom = missing\n"));
+        assert!(text.contains("playchoice prom = missing\n"));
+
+        data.resize(data.len() + 8 * 1024 + 32, 0);
+        let info = CartridgeInfo::new(Cartridge::from_bytes(data).unwrap(), &database);
+        let inst_rom = info.playchoice_inst_rom.as_ref().unwrap();
+        assert_eq!(
+            inst_rom.crc32,
+            format!("{:08x}", crc32fast::hash(&[0; 8 * 1024]))
+        );
+        let prom = info.playchoice_prom.as_ref().unwrap();
+        assert_eq!(prom.crc32, format!("{:08x}", crc32fast::hash(&[0; 32])));
+        assert_eq!(info.trailing_data_size, 0);
+        assert!(info
+            .to_string()
+            .contains(&format!("playchoice prom = {}\n", prom)));
+    }
+
+    #[test]
+    pub fn memory_and_extras() {
+        let database = RomDatabase::parse("").unwrap();
+        let header = HeaderBuilder::new()
+            .prg_rom_size(16 * 1024)
+            .chr_rom_size(8 * 1024)
+            .prg_ram_size(8 * 1024, 0)
+            .misc_rom_count(1)
+            .build()
+            .unwrap();
+        let mut data = header.to_bytes().to_vec();
+        data.resize(data.len() + 16 * 1024 + 8 * 1024 + 100, 0);
+        let info = CartridgeInfo::new(Cartridge::from_bytes(data).unwrap(), &database);
+        assert!(info.prg_ram);
+        assert_eq!(info.prg_ram_size, 8 * 1024);
+        assert!(!info.bus_conflicts);
+        assert_eq!(info.misc_rom_count, 1);
+        assert_eq!(info.misc_rom_size, 100);
+        let text = info.to_string();
+        assert!(text.contains("prg ram = present, 8192 bytes, nvram = 0 bytes\n"));
+        assert!(text.contains("bus conflicts = false\n"));
+        assert!(text.contains("misc rom = 1 roms, 100 bytes, trailing data = 0 bytes\n"));
+
+        let header = HeaderBuilder::new()
+            .nes_2_0(false)
+            .prg_rom_size(16 * 1024)
+            .chr_rom_size(8 * 1024)
+            .bus_conflicts(true)
+            .build()
+            .unwrap();
+        let mut data = header.to_bytes().to_vec();
+        data.resize(data.len() + 16 * 1024 + 8 * 1024, 0);
+        let info = CartridgeInfo::new(Cartridge::from_bytes(data).unwrap(), &database);
+        // iNES has PRG RAM unless it's flagged as absent
+        assert!(info.prg_ram);
+        assert!(info.bus_conflicts);
+        assert_eq!(info.misc_rom_count, 0);
+        let report = serde_json::to_value(&info).unwrap();
+        assert_eq!(report["prg_ram"], true);
+        assert_eq!(report["bus_conflicts"], true);
+        assert_eq!(report["misc_rom_count"], 0);
+    }
+}
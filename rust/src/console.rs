@@ -0,0 +1,794 @@
+// A cartridge in a console: the CPU, PPU and APU run together a frame at a time.
+//
+// The CPU runs an instruction at a time and everything else catches up on the cycles it took, so writes land at the
+// end of the instruction that made them rather than on the exact cycle.
+
+use std::{cell::RefCell, rc::Rc};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    apu::APU,
+    cartridge_file::{Cartridge, CartridgeError, TVSystem, VsPPUType},
+    controller::Buttons,
+    cpu::{Interrupt, CPU},
+    debugger,
+    fds_file::{Bios, DiskImage},
+    framebuffer::Framebuffer,
+    memory::{
+        self, main,
+        mappers::{fds::DiskSystem, vs_system::VsSystem},
+        observe::{Access, AccessKind, Accessor, Bus, Observed, Observers},
+        video, Memory,
+    },
+    movie,
+    ppu::{self, PPU},
+    region::Region,
+    rewind,
+    save_state::{self, SaveStateError},
+};
+
+const APU_REGISTERS_START: u16 = 0x4000;
+const OAM_DMA_ADDRESS: u16 = 0x4014;
+/// $4016 and up read from the controllers, then open bus. Writes to $4016 strobe the controllers.
+const CONTROLLER_PORT_1: u16 = 0x4016;
+const FRAME_COUNTER_ADDRESS: u16 = 0x4017;
+const OAM_DMA_CYCLES: u64 = 513;
+
+pub struct Console {
+    region: Region,
+    /// Of PRG and CHR ROM, to check states are for the same cartridge.
+    crc32: u32,
+    cpu: CPU,
+    memory: ConsoleMemory,
+    /// Taken at the next instruction boundary.
+    nmi: bool,
+    /// Shared with the memory, which reports the PPU bus.
+    observers: Rc<RefCell<Observers>>,
+    vs_system: Option<VsSystem>,
+    disk_system: Option<DiskSystem>,
+}
+
+/// What a state is saved from. The memory's parts are behind trait objects, which hand their state over as a Value.
+/// Rewind snapshots have them as JSON strings instead, as bincode can't do Values, and last so the rest stays put.
+#[derive(Serialize)]
+struct StateRef<'a, T> {
+    region: Region,
+    cpu: &'a CPU,
+    nmi: bool,
+    ppu: &'a PPU,
+    apu: &'a APU,
+    ppu_debt: u64,
+    main: T,
+    video: T,
+}
+
+/// What a state is loaded into, the same fields as StateRef.
+#[derive(Deserialize)]
+struct State<T> {
+    region: Region,
+    cpu: CPU,
+    nmi: bool,
+    ppu: PPU,
+    apu: APU,
+    ppu_debt: u64,
+    main: T,
+    video: T,
+}
+
+/// The CPU's bus, with the PPU and APU registers on it.
+pub struct ConsoleMemory {
+    main: main::Memory,
+    video: video::Memory,
+    ppu: PPU,
+    apu: APU,
+    /// Page a write to $4014 asked to copy to OAM, done once the instruction's finished.
+    oam_dma: Option<u8>,
+    cpu_divider: u64,
+    ppu_divider: u64,
+    /// Master clocks the CPU has run that the PPU hasn't caught up on, as PAL has 3.2 dots a CPU cycle.
+    ppu_debt: u64,
+    observers: Rc<RefCell<Observers>>,
+    /// The CPU cycle the current instruction started on, to stamp accesses with.
+    cycle: u64,
+}
+
+impl Console {
+    /// Fails for mappers that aren't emulated. Sound comes out at the given sample rate. The region defaults to the
+    /// one in the header.
+    pub fn new(
+        cartridge: &Cartridge,
+        sample_rate: u32,
+        region: Option<Region>,
+    ) -> Result<Self, CartridgeError> {
+        let (main, video, vs_system) = memory::new(cartridge)?;
+        let tv_system = cartridge.header().tv_system();
+        let region = region.unwrap_or(Region::from_tv_system(tv_system));
+        if region == Region::NTSC && tv_system == TVSystem::PAL {
+            warn!("PAL only cartridge, running on an NTSC console");
+        }
+        let rom = cartridge
+            .prg_rom_bytes()
+            .chain(cartridge.chr_rom_bytes())
+            .collect::<Vec<_>>();
+        let mut result = Self::from_memory(
+            main,
+            video,
+            crc32fast::hash(&rom),
+            sample_rate,
+            region,
+            cartridge.header().vs_ppu_type(),
+        );
+        result.vs_system = vs_system;
+        Ok(result)
+    }
+
+    /// A Famicom with the Disk System plugged in, booting the BIOS with the image's first side in the drive. The region
+    /// defaults to NTSC, it was never sold anywhere else.
+    pub fn new_fds(image: DiskImage, bios: Bios, sample_rate: u32, region: Option<Region>) -> Self {
+        let mut crc32 = crc32fast::Hasher::new();
+        crc32.update(bios.data());
+        for side in 0..image.side_count() {
+            crc32.update(image.side(side));
+        }
+        let (main, video, disk_system) = memory::new_fds(image, bios);
+        let mut result = Self::from_memory(
+            main,
+            video,
+            crc32.finalize(),
+            sample_rate,
+            region.unwrap_or(Region::NTSC),
+            None,
+        );
+        result.disk_system = Some(disk_system);
+        result
+    }
+
+    /// Powered on and through the reset vector.
+    fn from_memory(
+        main: main::Memory,
+        video: video::Memory,
+        crc32: u32,
+        sample_rate: u32,
+        region: Region,
+        vs_ppu_type: Option<VsPPUType>,
+    ) -> Self {
+        let observers = Rc::new(RefCell::new(Observers::new()));
+        let mut result = Self {
+            region,
+            crc32,
+            cpu: CPU::new(),
+            memory: ConsoleMemory {
+                main,
+                video,
+                ppu: PPU::new(region, vs_ppu_type),
+                apu: APU::new(sample_rate, region),
+                oam_dma: None,
+                cpu_divider: region.cpu_divider(),
+                ppu_divider: region.ppu_divider(),
+                ppu_debt: 0,
+                observers: observers.clone(),
+                cycle: 0,
+            },
+            nmi: false,
+            observers,
+            vs_system: None,
+            disk_system: None,
+        };
+        result.cpu.reset(&mut result.memory);
+        result
+    }
+
+    /// Runs until the PPU starts on the next frame, which leaves the last one whole in the framebuffer.
+    pub fn run_frame(&mut self) {
+        let frame = self.memory.ppu.frame();
+        while self.memory.ppu.frame() == frame {
+            self.step();
+        }
+        if let Some(vs_system) = &self.vs_system {
+            vs_system.step_frame();
+        }
+    }
+
+    /// Runs an instruction, or enters an interrupt handler instead of one and returns which, and catches everything
+    /// else up. Only goes through the observers when there are any, it's a lot slower.
+    pub fn step(&mut self) -> Option<Interrupt> {
+        let before = self.cpu.clock;
+        self.memory.cycle = before;
+        let nmi = std::mem::take(&mut self.nmi);
+        let irq = self.irq();
+        let interrupt = if self.observers.borrow().is_empty() {
+            let interrupt = Self::interrupt(&mut self.cpu, &mut self.memory, nmi, irq);
+            if interrupt.is_none() {
+                self.cpu.step(&mut self.memory);
+            }
+            interrupt
+        } else {
+            let mut memory = Observed::new(
+                &mut self.memory,
+                &self.observers,
+                Bus::CPU,
+                Accessor::CPU,
+                before,
+            );
+            let interrupt = Self::interrupt(&mut self.cpu, &mut memory, nmi, irq);
+            if interrupt.is_none() {
+                memory.execute(self.cpu.pc);
+                self.cpu.step(&mut memory);
+            }
+            interrupt
+        };
+        if let Some(page) = self.memory.oam_dma.take() {
+            self.oam_dma(page);
+        }
+        self.memory.step(self.cpu.clock - before);
+        self.nmi |= self.memory.ppu.take_nmi();
+        interrupt
+    }
+
+    /// The reset button. The CPU goes through its reset vector and the APU goes quiet, RAM is left alone.
+    pub fn reset(&mut self) {
+        self.memory.apu.write8(crate::apu::STATUS_ADDRESS, 0);
+        self.memory.ppu.reset();
+        self.nmi = false;
+        self.cpu.reset(&mut self.memory);
+    }
+
+    /// What's held on the controller in the given 0-based port.
+    pub fn set_buttons(&mut self, port: usize, buttons: Buttons) {
+        self.memory.main.set_buttons(port, buttons);
+    }
+
+    /// The CPU, PPU, APU, RAM, sram, CHR-RAM, nametables, palette, OAM, mapper registers and controllers. The
+    /// framebuffer isn't included, so it's stale until the next frame's drawn, and nor are the mixer settings.
+    pub fn save_state(&self) -> Vec<u8> {
+        save_state::encode(self.crc32, &self.state(|x| x))
+    }
+
+    /// Only takes states saved from the same cartridge on the same region.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        self.set_state(save_state::decode(self.crc32, data)?)
+    }
+
+    fn state<T, F>(&self, memory_state: F) -> StateRef<'_, T>
+    where
+        F: Fn(Value) -> T,
+    {
+        StateRef {
+            region: self.region,
+            cpu: &self.cpu,
+            nmi: self.nmi,
+            ppu: &self.memory.ppu,
+            apu: &self.memory.apu,
+            ppu_debt: self.memory.ppu_debt,
+            main: memory_state(self.memory.main.save_state()),
+            video: memory_state(self.memory.video.save_state()),
+        }
+    }
+
+    fn set_state(&mut self, state: State<Value>) -> Result<(), SaveStateError> {
+        if state.region != self.region {
+            return Err(SaveStateError::WrongRegion {
+                expected: self.region,
+                actual: state.region,
+            });
+        }
+        self.memory.main.load_state(state.main)?;
+        self.memory.video.load_state(state.video)?;
+        self.cpu = state.cpu;
+        self.nmi = state.nmi;
+        self.memory.ppu.load_state(state.ppu);
+        self.memory.apu.load_state(state.apu);
+        self.memory.ppu_debt = state.ppu_debt;
+        self.memory.oam_dma = None;
+        Ok(())
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// The coin slots, DIP switches and service button, for Vs. System cartridges.
+    pub fn vs_system(&self) -> Option<&VsSystem> {
+        self.vs_system.as_ref()
+    }
+
+    /// The drive, for swapping sides and saving what's been written, when running a disk.
+    pub fn disk_system(&self) -> Option<&DiskSystem> {
+        self.disk_system.as_ref()
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        self.memory.ppu.framebuffer()
+    }
+
+    /// Watching the CPU and PPU buses. The CPU drives the PPU's through $2007, the PPU itself while rendering, and
+    /// sprite and DMC DMA read the CPU's.
+    pub fn observers(&self) -> &RefCell<Observers> {
+        &self.observers
+    }
+
+    pub fn cpu_clock(&self) -> u64 {
+        self.cpu.clock
+    }
+
+    /// The CPU's view of memory, without side effects.
+    pub fn peek8(&self, address: u16) -> u8 {
+        self.memory.peek8(address)
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.memory.apu.take_samples()
+    }
+
+    fn irq(&self) -> bool {
+        self.memory.apu.irq() || self.memory.main.mapper_irq()
+    }
+
+    /// Enters the handler of the interrupt that's due, if it isn't masked. Returns which it entered.
+    fn interrupt<M>(cpu: &mut CPU, memory: &mut M, nmi: bool, irq: bool) -> Option<Interrupt>
+    where
+        M: Memory,
+    {
+        if nmi && cpu.interrupt(memory, Interrupt::NMI) {
+            Some(Interrupt::NMI)
+        } else if irq && cpu.interrupt(memory, Interrupt::IRQ) {
+            Some(Interrupt::IRQ)
+        } else {
+            None
+        }
+    }
+
+    /// Copies a page to OAM through $2004, holding the CPU up while it does. An extra cycle is spent lining up with
+    /// the APU's clock on odd cycles.
+    fn oam_dma(&mut self, page: u8) {
+        let start = (page as u16) << 8;
+        let observed = !self.observers.borrow().is_empty();
+        for i in 0..(ppu::OAM_SIZE as u16) {
+            let value = if observed {
+                Observed::new(
+                    &mut self.memory,
+                    &self.observers,
+                    Bus::CPU,
+                    Accessor::DMA,
+                    self.cpu.clock,
+                )
+                .read8(start + i)
+            } else {
+                self.memory.read8(start + i)
+            };
+            self.memory.ppu.write_oam(value);
+        }
+        self.cpu.clock += OAM_DMA_CYCLES + self.cpu.clock % 2;
+    }
+}
+
+/// Input is a movie's frame, what's held on each controller and the reset button and coin slot.
+impl rewind::Machine for Console {
+    type Input = movie::Frame;
+
+    fn snapshot(&self) -> Vec<u8> {
+        bincode::serialize(&self.state(|x| x.to_string())).unwrap()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        let state: State<String> =
+            bincode::deserialize(snapshot).expect("a snapshot from this console");
+        let memory_state =
+            |x: String| serde_json::from_str(&x).expect("a snapshot from this console");
+        let state = State {
+            region: state.region,
+            cpu: state.cpu,
+            nmi: state.nmi,
+            ppu: state.ppu,
+            apu: state.apu,
+            ppu_debt: state.ppu_debt,
+            main: memory_state(state.main),
+            video: memory_state(state.video),
+        };
+        self.set_state(state).expect("a snapshot from this console");
+    }
+
+    fn run_frame(&mut self, input: &movie::Frame) -> std::io::Result<()> {
+        input.apply(self);
+        Console::run_frame(self);
+        Ok(())
+    }
+}
+
+/// Watchpoints see the PPU's bus and DMA as well as the CPU. Memory edits go through the CPU's bus, so they hit
+/// registers like the CPU writing them would.
+impl debugger::Target for Console {
+    type Memory = ConsoleMemory;
+
+    fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    fn memory(&self) -> &ConsoleMemory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut ConsoleMemory {
+        &mut self.memory
+    }
+
+    fn observers(&self) -> &RefCell<Observers> {
+        &self.observers
+    }
+
+    fn step(&mut self) -> Option<Interrupt> {
+        Console::step(self)
+    }
+}
+
+impl ConsoleMemory {
+    /// Catches the cartridge, APU and PPU up on the given number of CPU cycles.
+    fn step(&mut self, cycles: u64) {
+        let Self {
+            main,
+            video,
+            ppu,
+            apu,
+            ppu_debt,
+            observers,
+            cycle,
+            ..
+        } = self;
+        let observed = !observers.borrow().is_empty();
+        main.step_mapper(cycles);
+        apu.set_expansion(main.expansion_audio());
+        apu.step(cycles, |address| {
+            let value = main.peek8(address);
+            if observed {
+                observers.borrow_mut().notify(&Access {
+                    bus: Bus::CPU,
+                    accessor: Accessor::DMA,
+                    kind: AccessKind::Read,
+                    address,
+                    value,
+                    cycle: *cycle,
+                });
+            }
+            value
+        });
+        *ppu_debt += cycles * self.cpu_divider;
+        if observed {
+            let mut video = Observed::new(video, observers, Bus::PPU, Accessor::PPU, *cycle);
+            while *ppu_debt >= self.ppu_divider {
+                ppu.step(&mut video);
+                *ppu_debt -= self.ppu_divider;
+            }
+        } else {
+            while *ppu_debt >= self.ppu_divider {
+                ppu.step(video);
+                *ppu_debt -= self.ppu_divider;
+            }
+        }
+    }
+}
+
+impl Memory for ConsoleMemory {
+    fn read8(&mut self, address: u16) -> u8 {
+        match address {
+            ppu::REGISTERS_START..ppu::REGISTERS_END if self.observers.borrow().is_empty() => {
+                self.ppu.read8(address, &mut self.video)
+            }
+            ppu::REGISTERS_START..ppu::REGISTERS_END => {
+                let mut video = Observed::new(
+                    &mut self.video,
+                    &self.observers,
+                    Bus::PPU,
+                    Accessor::CPU,
+                    self.cycle,
+                );
+                self.ppu.read8(address, &mut video)
+            }
+            APU_REGISTERS_START..CONTROLLER_PORT_1 => self.apu.read8(address),
+            _ => self.main.read8(address),
+        }
+    }
+
+    fn peek8(&self, address: u16) -> u8 {
+        match address {
+            ppu::REGISTERS_START..ppu::REGISTERS_END => self.ppu.peek8(address, &self.video),
+            APU_REGISTERS_START..CONTROLLER_PORT_1 => self.apu.peek8(address),
+            _ => self.main.peek8(address),
+        }
+    }
+
+    fn write8(&mut self, address: u16, value: u8) {
+        match address {
+            ppu::REGISTERS_START..ppu::REGISTERS_END if self.observers.borrow().is_empty() => {
+                self.ppu.write8(address, value, &mut self.video)
+            }
+            ppu::REGISTERS_START..ppu::REGISTERS_END => {
+                let mut video = Observed::new(
+                    &mut self.video,
+                    &self.observers,
+                    Bus::PPU,
+                    Accessor::CPU,
+                    self.cycle,
+                );
+                self.ppu.write8(address, value, &mut video)
+            }
+            OAM_DMA_ADDRESS => self.oam_dma = Some(value),
+            CONTROLLER_PORT_1 => self.main.write8(address, value),
+            APU_REGISTERS_START..=FRAME_COUNTER_ADDRESS => self.apu.write8(address, value),
+            _ => self.main.write8(address, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Console;
+    use crate::{
+        cartridge_file::Cartridge,
+        controller::Buttons,
+        debugger::Debugger,
+        fds_file::{Bios, DiskImage, BIOS_SIZE, SIDE_SIZE},
+        memory::observe::{Access, AccessKind, Accessor, Bus},
+        movie::{Commands, Frame},
+        region::Region,
+        rewind::{Machine, Rewind},
+        save_state::SaveStateError,
+        test_utils::test::{nrom, picture},
+    };
+
+    #[test]
+    pub fn picture_frame() {
+        let mut console = Console::new(&picture(), 44100, None).unwrap();
+        // two frames waiting for the PPU to warm up, one to draw the palette and tiles and one for OAM to be copied
+        for _ in 0..5 {
+            console.run_frame();
+        }
+        let frame = console.framebuffer();
+        assert_eq!(frame.get(0, 0), 0x30);
+        assert_eq!(frame.get(255, 7), 0x30);
+        assert_eq!(frame.get(0, 8), 0x0f);
+        assert_eq!(frame.get(16, 1), 0x38);
+        assert_eq!(frame.get(23, 8), 0x38);
+        assert_eq!(frame.get(24, 8), 0x0f);
+        assert_eq!(frame.get(16, 9), 0x0f);
+        assert_eq!(frame.hash(), 2212948002);
+    }
+
+    #[test]
+    pub fn regions() {
+        for region in [Region::NTSC, Region::PAL, Region::Dendy] {
+            let mut console = Console::new(&picture(), 44100, Some(region)).unwrap();
+            for _ in 0..5 {
+                console.run_frame();
+            }
+            // frames end on the instruction after the PPU gets there, and NTSC ones alternate in length
+            let start = console.cpu_clock();
+            console.run_frame();
+            console.run_frame();
+            let cycles = (console.cpu_clock() - start) as f64 / 2.0;
+            let expected = region.cpu_cycles_per_frame();
+            assert!(
+                (cycles - expected).abs() < 8.0,
+                "{:?}: {} {}",
+                region,
+                cycles,
+                expected
+            );
+            // the picture's the same everywhere
+            assert_eq!(console.framebuffer().hash(), 2212948002, "{:?}", region);
+        }
+    }
+
+    #[test]
+    pub fn save_state() {
+        let mut console = Console::new(&picture(), 44100, None).unwrap();
+        for _ in 0..5 {
+            console.run_frame();
+        }
+        let state = console.save_state();
+        console.run_frame();
+        console.run_frame();
+
+        // the picture's only written once, so it has to come from the state
+        let mut loaded = Console::new(&picture(), 44100, None).unwrap();
+        loaded.load_state(&state).unwrap();
+        loaded.run_frame();
+        loaded.run_frame();
+        assert_eq!(loaded.cpu_clock(), console.cpu_clock());
+        assert_eq!(loaded.framebuffer().hash(), 2212948002);
+
+        let mut pal = Console::new(&picture(), 44100, Some(Region::PAL)).unwrap();
+        assert!(matches!(
+            pal.load_state(&state),
+            Err(SaveStateError::WrongRegion { .. })
+        ));
+        let mut other = Console::new(&nrom(&[0x4c, 0x00, 0x80], 0x8000, &[]), 44100, None).unwrap();
+        assert!(matches!(
+            other.load_state(&state),
+            Err(SaveStateError::WrongRom { .. })
+        ));
+    }
+
+    #[test]
+    pub fn vs_system() {
+        // LDA $4016, STA $00, LDA $4017, STA $01, JMP $8000
+        let program = [
+            0xad, 0x16, 0x40, 0x85, 0x00, 0xad, 0x17, 0x40, 0x85, 0x01, 0x4c, 0x00, 0x80,
+        ];
+        let mut data = nrom(&program, 0x8000, &[]).to_bytes().unwrap();
+        // mapper 99 and the Vs. System flag
+        data[6] |= 0x30;
+        data[7] |= 0x61;
+        let mut console = Console::new(&Cartridge::from_bytes(data).unwrap(), 44100, None).unwrap();
+        let vs_system = console.vs_system().unwrap();
+        vs_system.set_dip_switches(0b1000_0011);
+        vs_system.set_service_button(true);
+        console.run_frame();
+        assert_eq!(console.peek8(0x00) & 0b0111_1100, 0b0001_1100);
+        assert_eq!(console.peek8(0x01) & 0b1111_1100, 0b1000_0000);
+
+        // a movie's coin is held for a few frames then let go
+        let coin = Frame {
+            commands: Commands::VS_INSERT_COIN,
+            ..Default::default()
+        };
+        coin.apply(&mut console);
+        console.run_frame();
+        assert_eq!(console.peek8(0x00) & 0b0110_0000, 0b0010_0000);
+        for _ in 0..10 {
+            console.run_frame();
+        }
+        assert_eq!(console.peek8(0x00) & 0b0110_0000, 0);
+        assert_eq!(console.vs_system().unwrap().coins_counted(), 0);
+    }
+
+    #[test]
+    pub fn fds() {
+        // SEI, then LDA $4032, STA $00, JMP $E001 from $E000
+        let mut bios = vec![0; BIOS_SIZE];
+        bios[..9].copy_from_slice(&[0x78, 0xad, 0x32, 0x40, 0x85, 0x00, 0x4c, 0x01, 0xe0]);
+        bios[BIOS_SIZE - 4] = 0x00;
+        bios[BIOS_SIZE - 3] = 0xe0;
+        let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+        side.resize(SIDE_SIZE, 0);
+        let image = DiskImage::from_bytes(&side.repeat(2)).unwrap();
+        let mut console = Console::new_fds(image, Bios::from_bytes(&bios).unwrap(), 44100, None);
+        assert_eq!(console.region(), Region::NTSC);
+
+        // the drive status' bit 0 is set with no disk in
+        console.run_frame();
+        assert_eq!(console.peek8(0x00) & 1, 0);
+        let disk_system = console.disk_system().unwrap();
+        assert_eq!(disk_system.side_count(), 2);
+        disk_system.eject();
+        console.run_frame();
+        assert_eq!(console.peek8(0x00) & 1, 1);
+        let state = console.save_state();
+        console.disk_system().unwrap().insert(1);
+        console.run_frame();
+        assert_eq!(console.peek8(0x00) & 1, 0);
+        assert_eq!(console.disk_system().unwrap().inserted_side(), Some(1));
+
+        // states are for the same BIOS and disk
+        console.load_state(&state).unwrap();
+        bios[0] = 0xea;
+        let side = DiskImage::from_bytes(&side).unwrap();
+        let mut other = Console::new_fds(side, Bios::from_bytes(&bios).unwrap(), 44100, None);
+        assert!(matches!(
+            other.load_state(&state),
+            Err(SaveStateError::WrongRom { .. })
+        ));
+    }
+
+    #[test]
+    pub fn rewind() {
+        let mut console = Console::new(&picture(), 44100, None).unwrap();
+        let mut rewind = Rewind::new(60, 4, 1 << 20);
+        let mut history = Vec::new();
+        for _ in 0..20 {
+            history.push(console.snapshot());
+            let frame = Frame {
+                ports: [Buttons::START, Buttons::empty()],
+                ..Default::default()
+            };
+            rewind.run_frame(&mut console, frame).unwrap();
+        }
+
+        // back to between snapshots, which runs forward from the one before
+        assert_eq!(rewind.rewind(&mut console, 7).unwrap(), 7);
+        assert_eq!(console.snapshot(), history[13]);
+        console.run_frame();
+        assert_eq!(console.framebuffer().hash(), 2212948002);
+    }
+
+    #[test]
+    pub fn observers() {
+        let mut console = Console::new(&picture(), 44100, None).unwrap();
+        let (_, executes) = console.observers().borrow_mut().add_channel(
+            Bus::CPU,
+            &[AccessKind::Execute],
+            0x8000..=0xffff,
+        );
+        let (_, palette) = console.observers().borrow_mut().add_channel(
+            Bus::PPU,
+            &[AccessKind::Write],
+            0x3f00..=0x3f1f,
+        );
+        let (_, nametable) = console.observers().borrow_mut().add_channel(
+            Bus::PPU,
+            &[AccessKind::Read],
+            0x2000..=0x23bf,
+        );
+        let (_, oam) = console.observers().borrow_mut().add_channel(
+            Bus::CPU,
+            &[AccessKind::Read],
+            0x0200..=0x02ff,
+        );
+        for _ in 0..5 {
+            console.run_frame();
+        }
+        assert_eq!(console.framebuffer().hash(), 2212948002);
+
+        assert_eq!(executes.try_iter().next().unwrap().address, 0x8000);
+        // written through $2007 by the CPU
+        let palette = palette.try_iter().collect::<Vec<_>>();
+        assert_eq!(palette.len(), 0x20);
+        assert_eq!(
+            palette[1],
+            Access {
+                bus: Bus::PPU,
+                accessor: Accessor::CPU,
+                kind: AccessKind::Write,
+                address: 0x3f01,
+                value: 0x30,
+                cycle: palette[1].cycle,
+            }
+        );
+        // fetched by the PPU while rendering, the first row's tiles are 1
+        let nametable = nametable.try_iter().collect::<Vec<_>>();
+        assert!(nametable.iter().all(|x| x.accessor == Accessor::PPU));
+        assert!(nametable
+            .iter()
+            .any(|x| x.address == 0x2000 && x.value == 0x01));
+        // copied to OAM by sprite DMA, a page each frame after the first NMI
+        let oam = oam.try_iter().collect::<Vec<_>>();
+        assert!(oam.iter().all(|x| x.accessor == Accessor::DMA));
+        assert_eq!(oam.len() % 0x100, 0);
+        assert_eq!(oam[1].value, 0x02);
+    }
+
+    #[test]
+    pub fn debugger() {
+        let mut console = Console::new(&picture(), 44100, None).unwrap();
+        let mut debugger = Debugger::new();
+        let mut run = |console: &mut Console, line: &str| {
+            let result = debugger.execute(console, line);
+            assert!(!result.starts_with("error"), "{:?}: {}", line, result);
+            result
+        };
+
+        run(&mut console, "watch w ppu 3f00-3f1f");
+        assert!(run(&mut console, "c")
+            .starts_with("breakpoint 0: write ppu $3F00-$3F1F, $0F at $3F00\n"));
+        run(&mut console, "del 0");
+        run(&mut console, "bi nmi");
+        assert!(run(&mut console, "c").starts_with("breakpoint 0: NMI"));
+        assert_eq!(console.cpu.pc, 0x806d);
+        run(&mut console, "del 0");
+
+        run(&mut console, "watch r 0201");
+        assert!(
+            run(&mut console, "c").starts_with("breakpoint 0: read $0201, $02 at $0201 by DMA\n")
+        );
+        run(&mut console, "del 0");
+        run(&mut console, "watch r ppu 2000");
+        assert!(run(&mut console, "c")
+            .starts_with("breakpoint 0: read ppu $2000, $01 at $2000 by PPU\n"));
+    }
+}
@@ -0,0 +1,68 @@
+// The standard controller, see https://www.nesdev.org/wiki/Standard_controller
+//
+// It's a shift register of the buttons, reloaded for as long as bit 0 of the last write to $4016 is set. Reads
+// return the next button in bit 0, then 1s once all 8 are out.
+
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+
+bitflags! {
+    /// In the order they're read.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Buttons: u8 {
+        const A = 0b0000_0001;
+        const B = 0b0000_0010;
+        const SELECT = 0b0000_0100;
+        const START = 0b0000_1000;
+        const UP = 0b0001_0000;
+        const DOWN = 0b0010_0000;
+        const LEFT = 0b0100_0000;
+        const RIGHT = 0b1000_0000;
+    }
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Controller {
+    /// Input rather than state, whoever's driving sets it every frame.
+    #[serde(skip)]
+    buttons: Buttons,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Controller {
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons.bits();
+        }
+    }
+
+    /// Keeps what's held.
+    pub fn load_state(&mut self, state: Self) {
+        *self = Self {
+            buttons: self.buttons,
+            ..state
+        };
+    }
+
+    pub fn write_strobe(&mut self, value: u8) {
+        self.strobe = value & 1 != 0;
+        if self.strobe {
+            self.shift = self.buttons.bits();
+        }
+    }
+
+    /// Bit 0 only, the rest of the port is open bus or the cartridge's.
+    pub fn read(&mut self) -> u8 {
+        let result = self.peek();
+        if !self.strobe {
+            self.shift = (self.shift >> 1) | 0x80;
+        }
+        result
+    }
+
+    pub fn peek(&self) -> u8 {
+        self.shift & 1
+    }
+}
@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{endians::Word, flags::Flags, memory::Memory};
 
 const STACK_ADDRESS: u16 = 0x0100;
@@ -5,6 +7,7 @@ const NON_MASKABLE_INTERRUPT_ADDRESS: u16 = 0xfffa;
 const RESET_INTERRUPT_ADDRESS: u16 = 0xfffc;
 const INTERRUPT_REQUEST_INTERRUPT_ADDRESS: u16 = 0xfffe;
 
+#[derive(Serialize, Deserialize)]
 pub struct CPU {
     pub pc: u16,
     pub sp: u8,
@@ -15,6 +18,12 @@ pub struct CPU {
     pub clock: u64,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    NMI,
+    IRQ,
+}
+
 struct AddrValue {
     address: u16,
     value: u8,
@@ -309,6 +318,41 @@ impl CPU {
         }
     }
 
+    /// What the reset button does, and power on if the registers are still zero. The stack pointer goes down as if
+    /// for an interrupt, but nothing is written.
+    pub fn reset<M>(&mut self, m: &mut M)
+    where
+        M: Memory,
+    {
+        self.sp = self.sp.wrapping_sub(3);
+        self.flags.set(Flags::INTERRUPT_DISABLE, true);
+        self.pc = m.read16(RESET_INTERRUPT_ADDRESS);
+        self.clock += 7;
+    }
+
+    /// Pushes PC and the flags and jumps through the interrupt's vector. IRQs are ignored while interrupts are
+    /// disabled, returns whether it was taken.
+    pub fn interrupt<M>(&mut self, m: &mut M, interrupt: Interrupt) -> bool
+    where
+        M: Memory,
+    {
+        if interrupt == Interrupt::IRQ && self.flags.contains(Flags::INTERRUPT_DISABLE) {
+            return false;
+        }
+        self.push16(m, self.pc);
+        self.push8(
+            m,
+            ((self.flags - Flags::BREAK_COMMAND) | Flags::UNUSED).bits(),
+        );
+        self.flags.set(Flags::INTERRUPT_DISABLE, true);
+        self.pc = m.read16(match interrupt {
+            Interrupt::NMI => NON_MASKABLE_INTERRUPT_ADDRESS,
+            Interrupt::IRQ => INTERRUPT_REQUEST_INTERRUPT_ADDRESS,
+        });
+        self.clock += 7;
+        true
+    }
+
     fn brk<M>(&mut self, m: &mut M)
     where
         M: Memory,
@@ -1575,7 +1619,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.absolute(m);
+        let address = self.read_next_u16(m);
         let new_value = self.sta_common(4);
         m.write8(address, new_value);
     }
@@ -1584,11 +1628,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValueClock {
-            address,
-            value: _,
-            extra_clock: _,
-        } = self.absolute_x(m);
+        let address = self.absolute_indexed_address(m, self.x).0;
         let new_value = self.sta_common(5);
         m.write8(address, new_value);
     }
@@ -1597,11 +1637,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValueClock {
-            address,
-            value: _,
-            extra_clock: _,
-        } = self.absolute_y(m);
+        let address = self.absolute_indexed_address(m, self.y).0;
         let new_value = self.sta_common(5);
         m.write8(address, new_value);
     }
@@ -1610,7 +1646,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.zero_page_fixed(m);
+        let address = self.read_next_u8(m) as u16;
         let new_value = self.sta_common(3);
         m.write8(address, new_value);
     }
@@ -1619,7 +1655,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.zero_page_x(m);
+        let address = self.zero_page_indexed_address(m, self.x);
         let new_value = self.sta_common(4);
         m.write8(address, new_value);
     }
@@ -1628,7 +1664,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.zero_page_indirect_x(m);
+        let address = self.zero_page_indirect_x_address(m);
         let new_value = self.sta_common(6);
         m.write8(address, new_value);
     }
@@ -1637,11 +1673,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValueClock {
-            address,
-            value: _,
-            extra_clock: _,
-        } = self.zero_page_indirect_y(m);
+        let address = self.zero_page_indirect_y_address(m).0;
         let new_value = self.sta_common(6);
         m.write8(address, new_value);
     }
@@ -1655,7 +1687,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.absolute(m);
+        let address = self.read_next_u16(m);
         let new_value = self.sax_common(4);
         m.write8(address, new_value);
     }
@@ -1664,7 +1696,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.zero_page_fixed(m);
+        let address = self.read_next_u8(m) as u16;
         let new_value = self.sax_common(3);
         m.write8(address, new_value);
     }
@@ -1673,7 +1705,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.zero_page_y(m);
+        let address = self.zero_page_indexed_address(m, self.y);
         let new_value = self.sax_common(4);
         m.write8(address, new_value);
     }
@@ -1682,7 +1714,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.zero_page_indirect_x(m);
+        let address = self.zero_page_indirect_x_address(m);
         let new_value = self.sax_common(6);
         m.write8(address, new_value);
     }
@@ -1696,7 +1728,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.absolute(m);
+        let address = self.read_next_u16(m);
         let new_value = self.stx_common(4);
         m.write8(address, new_value);
     }
@@ -1705,7 +1737,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.zero_page_fixed(m);
+        let address = self.read_next_u8(m) as u16;
         let new_value = self.stx_common(3);
         m.write8(address, new_value);
     }
@@ -1714,7 +1746,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.zero_page_y(m);
+        let address = self.zero_page_indexed_address(m, self.y);
         let new_value = self.stx_common(4);
         m.write8(address, new_value);
     }
@@ -1728,7 +1760,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.absolute(m);
+        let address = self.read_next_u16(m);
         let new_value = self.sty_common(4);
         m.write8(address, new_value);
     }
@@ -1737,7 +1769,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.zero_page_fixed(m);
+        let address = self.read_next_u8(m) as u16;
         let new_value = self.sty_common(3);
         m.write8(address, new_value);
     }
@@ -1746,7 +1778,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let AddrValue { address, value: _ } = self.zero_page_x(m);
+        let address = self.zero_page_indexed_address(m, self.x);
         let new_value = self.sty_common(4);
         m.write8(address, new_value);
     }
@@ -2788,13 +2820,8 @@ impl CPU {
     where
         M: Memory,
     {
-        let address = self.read_next_u16(m);
-        let high1 = address & 0xff00;
-        let address = address.wrapping_add(offset as u16);
+        let (address, extra_clock) = self.absolute_indexed_address(m, offset);
         let value = m.read8(address);
-        let high2 = address & 0xff00;
-        // if adding Y pushes us into a new page it will take an extra clock cycle to resolve
-        let extra_clock = if high1 == high2 { 0 } else { 1 };
         AddrValueClock {
             address,
             value,
@@ -2802,6 +2829,20 @@ impl CPU {
         }
     }
 
+    /// The address and the extra clock cycle for crossing a page. Stores use this alone, as they don't read first.
+    fn absolute_indexed_address<M>(&mut self, m: &mut M, offset: u8) -> (u16, u64)
+    where
+        M: Memory,
+    {
+        let address = self.read_next_u16(m);
+        let high1 = address & 0xff00;
+        let address = address.wrapping_add(offset as u16);
+        let high2 = address & 0xff00;
+        // if adding Y pushes us into a new page it will take an extra clock cycle to resolve
+        let extra_clock = if high1 == high2 { 0 } else { 1 };
+        (address, extra_clock)
+    }
+
     fn zero_page_fixed<M>(&mut self, m: &mut M) -> AddrValue
     where
         M: Memory,
@@ -2815,7 +2856,7 @@ impl CPU {
     where
         M: Memory,
     {
-        let address = ((self.read_next_u8(m) as u16) + (self.x as u16)) & 0xff;
+        let address = self.zero_page_indexed_address(m, self.x);
         let value = m.read8(address);
         AddrValue { address, value }
     }
@@ -2824,22 +2865,49 @@ impl CPU {
     where
         M: Memory,
     {
-        let address = ((self.read_next_u8(m) as u16) + (self.y as u16)) & 0xff;
+        let address = self.zero_page_indexed_address(m, self.y);
         let value = m.read8(address);
         AddrValue { address, value }
     }
 
+    fn zero_page_indexed_address<M>(&mut self, m: &mut M, offset: u8) -> u16
+    where
+        M: Memory,
+    {
+        ((self.read_next_u8(m) as u16) + (offset as u16)) & 0xff
+    }
+
     fn zero_page_indirect_x<M>(&mut self, m: &mut M) -> AddrValue
     where
         M: Memory,
     {
-        let offset = self.read_next_u8(m);
-        let address = self.zero_page_indirect(m, offset, self.x);
+        let address = self.zero_page_indirect_x_address(m);
         let value = m.read8(address);
         AddrValue { address, value }
     }
 
+    fn zero_page_indirect_x_address<M>(&mut self, m: &mut M) -> u16
+    where
+        M: Memory,
+    {
+        let offset = self.read_next_u8(m);
+        self.zero_page_indirect(m, offset, self.x)
+    }
+
     fn zero_page_indirect_y<M>(&mut self, m: &mut M) -> AddrValueClock
+    where
+        M: Memory,
+    {
+        let (address, extra_clock) = self.zero_page_indirect_y_address(m);
+        let value = m.read8(address);
+        AddrValueClock {
+            address,
+            value,
+            extra_clock,
+        }
+    }
+
+    fn zero_page_indirect_y_address<M>(&mut self, m: &mut M) -> (u16, u64)
     where
         M: Memory,
     {
@@ -2847,15 +2915,10 @@ impl CPU {
         let address = self.zero_page_indirect(m, offset, 0);
         let high1 = address & 0xff00;
         let address = address.wrapping_add(self.y as u16);
-        let value = m.read8(address);
         let high2 = address & 0xff00;
         // if adding Y pushes us into a new page it will take an extra clock cycle to resolve
         let extra_clock = if high1 == high2 { 0 } else { 1 };
-        AddrValueClock {
-            address,
-            value,
-            extra_clock,
-        }
+        (address, extra_clock)
     }
 
     fn zero_page_indirect<M>(&mut self, m: &mut M, offset1: u8, offset2: u8) -> u16
@@ -0,0 +1,740 @@
+// An interactive debugger for anything with a CPU, driven by text commands.
+
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    io::{self, BufRead, Write},
+    ops::RangeInclusive,
+    sync::mpsc::{self, Receiver},
+};
+
+use crate::{
+    cpu::{Interrupt, CPU},
+    disasm::{self, Instruction},
+    flags::Flags,
+    memory::{
+        observe::{Access, AccessKind, Accessor, Bus, Observers},
+        Memory,
+    },
+};
+
+/// Running stops here even without hitting a breakpoint, so a missing RTS can't hang the prompt.
+const MAX_INSTRUCTIONS: u64 = 10_000_000;
+const JSR: u8 = 0x20;
+const RTI: u8 = 0x40;
+const RTS: u8 = 0x60;
+
+const DEFAULT_DISASSEMBLY_LINES: usize = 10;
+/// How many of the lines are before PC when disassembling around it.
+const DISASSEMBLY_LINES_BEFORE: usize = 3;
+const DEFAULT_MEMORY_LENGTH: u16 = 0x40;
+const MEMORY_BYTES_PER_LINE: u16 = 0x10;
+
+const HELP: &str = "\
+Addresses and values are hex, with or without $ or 0x, counts are decimal. An empty line repeats the last command.
+  s, step [count]               run one instruction, or count of them
+  n, next                       step over a JSR
+  f, finish                     run until the current subroutine or interrupt handler returns
+  c, continue                   run until a breakpoint
+  b, break <address>            stop before executing at address
+  w, watch r|w|rw [ppu] <address>[-<end>]
+                                stop after reading and/or writing anywhere in the range, on the PPU's bus with ppu
+  bo, break-opcode <opcode>     stop before executing the opcode
+  bi, break-interrupt nmi|irq   stop on entering the interrupt handler
+  bl, breakpoints               list breakpoints
+  del, delete <index>           remove a breakpoint
+  r, registers                  show registers
+  set a|x|y|sp|p|pc <value>     change a register
+  m, memory <address> [length]  dump memory
+  e, edit <address> <value>...  write bytes to memory
+  d, disassemble [address] [count]
+                                disassemble, around PC if no address is given
+  q, quit
+  h, help";
+
+/// What the debugger drives, one instruction at a time.
+pub trait Target {
+    type Memory: Memory;
+
+    fn cpu(&self) -> &CPU;
+    fn cpu_mut(&mut self) -> &mut CPU;
+    fn memory(&self) -> &Self::Memory;
+    fn memory_mut(&mut self) -> &mut Self::Memory;
+
+    /// Watchpoints are observers on the CPU or PPU bus while running.
+    fn observers(&self) -> &RefCell<Observers>;
+
+    /// Runs one instruction, or enters an interrupt handler instead of one and returns which.
+    fn step(&mut self) -> Option<Interrupt>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    Execute(u16),
+    Read(Bus, RangeInclusive<u16>),
+    Write(Bus, RangeInclusive<u16>),
+    Opcode(u8),
+    Interrupt(Interrupt),
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let range = |f: &mut std::fmt::Formatter<'_>, bus: &Bus, range: &RangeInclusive<u16>| {
+            if *bus == Bus::PPU {
+                write!(f, "ppu ")?;
+            }
+            if range.start() == range.end() {
+                write!(f, "${:04X}", range.start())
+            } else {
+                write!(f, "${:04X}-${:04X}", range.start(), range.end())
+            }
+        };
+        match self {
+            Breakpoint::Execute(address) => write!(f, "execute ${:04X}", address),
+            Breakpoint::Read(bus, x) => {
+                write!(f, "read ")?;
+                range(f, bus, x)
+            }
+            Breakpoint::Write(bus, x) => {
+                write!(f, "write ")?;
+                range(f, bus, x)
+            }
+            Breakpoint::Opcode(opcode) => write!(
+                f,
+                "opcode ${:02X} ({})",
+                opcode,
+                disasm::OPCODES[*opcode as usize].mnemonic
+            ),
+            Breakpoint::Interrupt(interrupt) => write!(f, "{:?}", interrupt),
+        }
+    }
+}
+
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    last_command: String,
+    quit: bool,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            last_command: String::new(),
+            quit: false,
+        }
+    }
+
+    pub fn has_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Prompts for and runs commands until told to quit or the input ends.
+    pub fn repl<T, R, W>(&mut self, target: &mut T, input: R, mut output: W) -> io::Result<()>
+    where
+        T: Target,
+        R: BufRead,
+        W: Write,
+    {
+        writeln!(output, "{}", location(target))?;
+        let mut lines = input.lines();
+        while !self.quit {
+            write!(output, "> ")?;
+            output.flush()?;
+            let Some(line) = lines.next() else {
+                break;
+            };
+            let result = self.execute(target, &line?);
+            if !result.is_empty() {
+                writeln!(output, "{}", result)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs one command line, returning what to show for it.
+    pub fn execute<T>(&mut self, target: &mut T, line: &str) -> String
+    where
+        T: Target,
+    {
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            line.to_string()
+        };
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let Some((command, args)) = words.split_first() else {
+            return String::new();
+        };
+        self.last_command = line.clone();
+        self.command(target, command, args)
+            .unwrap_or_else(|e| format!("error: {}", e))
+    }
+
+    fn command<T>(&mut self, target: &mut T, command: &str, args: &[&str]) -> Result<String, String>
+    where
+        T: Target,
+    {
+        match (command, args) {
+            ("s" | "step", []) => Ok(self.step(target, 1)),
+            ("s" | "step", [count]) => Ok(self.step(target, parse_steps(count)?)),
+            ("n" | "next", []) => Ok(self.next(target)),
+            ("f" | "finish", []) => Ok(self.finish(target)),
+            ("c" | "continue", []) => Ok(self.run(target, |_, _| false)),
+            ("b" | "break", [address]) => self.add(Breakpoint::Execute(parse_hex(address)?)),
+            ("w" | "watch", [kind, range]) => self.watch(kind, Bus::CPU, range),
+            ("w" | "watch", [kind, "ppu", range]) => self.watch(kind, Bus::PPU, range),
+            ("bo" | "break-opcode", [opcode]) => self.add(Breakpoint::Opcode(parse_byte(opcode)?)),
+            ("bi" | "break-interrupt", [interrupt]) => match *interrupt {
+                "nmi" => self.add(Breakpoint::Interrupt(Interrupt::NMI)),
+                "irq" => self.add(Breakpoint::Interrupt(Interrupt::IRQ)),
+                _ => Err(format!("expected nmi or irq, got {:?}", interrupt)),
+            },
+            ("bl" | "breakpoints", []) => Ok(self
+                .breakpoints
+                .iter()
+                .enumerate()
+                .map(|(i, x)| format!("{}: {}", i, x))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            ("del" | "delete", [index]) => {
+                let index = parse_count(index)? as usize;
+                if index >= self.breakpoints.len() {
+                    return Err(format!("no breakpoint {}", index));
+                }
+                Ok(format!("deleted {}", self.breakpoints.remove(index)))
+            }
+            ("r" | "registers", []) => Ok(registers(target.cpu())),
+            ("set", [register, value]) => {
+                let cpu = target.cpu_mut();
+                match *register {
+                    "pc" => cpu.pc = parse_hex(value)?,
+                    "a" => cpu.a = parse_byte(value)?,
+                    "x" => cpu.x = parse_byte(value)?,
+                    "y" => cpu.y = parse_byte(value)?,
+                    "sp" => cpu.sp = parse_byte(value)?,
+                    "p" => cpu.flags = Flags::from_bits_retain(parse_byte(value)?),
+                    _ => return Err(format!("unknown register {:?}", register)),
+                }
+                Ok(registers(target.cpu()))
+            }
+            ("m" | "memory", [address]) => Ok(dump(
+                target.memory(),
+                parse_hex(address)?,
+                DEFAULT_MEMORY_LENGTH,
+            )),
+            ("m" | "memory", [address, length]) => Ok(dump(
+                target.memory(),
+                parse_hex(address)?,
+                parse_hex(length)?,
+            )),
+            ("e" | "edit", [address, values @ ..]) if !values.is_empty() => {
+                let address = parse_hex(address)?;
+                let values = values
+                    .iter()
+                    .map(|x| parse_byte(x))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for (i, value) in values.iter().enumerate() {
+                    target
+                        .memory_mut()
+                        .write8(address.wrapping_add(i as u16), *value);
+                }
+                Ok(dump(target.memory(), address, values.len() as u16))
+            }
+            ("d" | "disassemble", []) => Ok(disassemble_around_pc(target)),
+            ("d" | "disassemble", [address]) => Ok(disassemble(
+                target,
+                parse_hex(address)?,
+                DEFAULT_DISASSEMBLY_LINES,
+            )),
+            ("d" | "disassemble", [address, count]) => Ok(disassemble(
+                target,
+                parse_hex(address)?,
+                parse_count(count)? as usize,
+            )),
+            ("q" | "quit", []) => {
+                self.quit = true;
+                Ok(String::new())
+            }
+            ("h" | "help", []) => Ok(HELP.to_string()),
+            _ => Err(format!(
+                "don't know {:?}, try help",
+                [&[command], args].concat().join(" ")
+            )),
+        }
+    }
+
+    fn watch(&mut self, kind: &str, bus: Bus, range: &str) -> Result<String, String> {
+        let range = parse_range(range)?;
+        match kind {
+            "r" => self.add(Breakpoint::Read(bus, range)),
+            "w" => self.add(Breakpoint::Write(bus, range)),
+            "rw" => Ok(format!(
+                "{}\n{}",
+                self.add(Breakpoint::Read(bus, range.clone()))?,
+                self.add(Breakpoint::Write(bus, range))?
+            )),
+            _ => Err(format!("expected r, w or rw, got {:?}", kind)),
+        }
+    }
+
+    fn add(&mut self, breakpoint: Breakpoint) -> Result<String, String> {
+        let result = format!("{}: {}", self.breakpoints.len(), breakpoint);
+        self.breakpoints.push(breakpoint);
+        Ok(result)
+    }
+
+    fn step<T>(&self, target: &mut T, count: u64) -> String
+    where
+        T: Target,
+    {
+        let mut remaining = count;
+        self.run(target, |_, _| {
+            remaining -= 1;
+            remaining == 0
+        })
+    }
+
+    /// Steps, unless it's a JSR in which case it runs until that returns.
+    fn next<T>(&self, target: &mut T) -> String
+    where
+        T: Target,
+    {
+        let instruction = disasm::disassemble(target.memory(), target.cpu().pc);
+        if instruction.bytes[0] != JSR {
+            return self.step(target, 1);
+        }
+        let return_address = instruction.next_address();
+        let sp = target.cpu().sp;
+        self.run(target, |target, _| {
+            target.cpu().pc == return_address && target.cpu().sp == sp
+        })
+    }
+
+    /// Runs until an RTS or RTI pops the stack past where it is now.
+    fn finish<T>(&self, target: &mut T) -> String
+    where
+        T: Target,
+    {
+        let sp = target.cpu().sp;
+        self.run(target, |target, opcode| {
+            matches!(opcode, Some(RTS | RTI)) && target.cpu().sp > sp
+        })
+    }
+
+    /// Steps until done says so or a breakpoint is hit, done gets the opcode that was just run or none for entering
+    /// an interrupt. Breakpoints on the instruction it starts at are skipped, so it can continue from one.
+    fn run<T, F>(&self, target: &mut T, done: F) -> String
+    where
+        T: Target,
+        F: FnMut(&T, Option<u8>) -> bool,
+    {
+        // each watchpoint says which breakpoint it is
+        let (sender, hits) = mpsc::channel();
+        let ids = self
+            .breakpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(index, breakpoint)| {
+                let (kind, bus, range) = match breakpoint {
+                    Breakpoint::Read(bus, range) => (AccessKind::Read, bus, range),
+                    Breakpoint::Write(bus, range) => (AccessKind::Write, bus, range),
+                    _ => return None,
+                };
+                let sender = sender.clone();
+                Some(target.observers().borrow_mut().add(
+                    *bus,
+                    &[kind],
+                    range.clone(),
+                    move |access| {
+                        let _ = sender.send((index, *access));
+                    },
+                ))
+            })
+            .collect::<Vec<_>>();
+        let result = self.run_watched(target, &hits, done);
+        for id in ids {
+            target.observers().borrow_mut().remove(id);
+        }
+        result
+    }
+
+    fn run_watched<T, F>(
+        &self,
+        target: &mut T,
+        hits: &Receiver<(usize, Access)>,
+        mut done: F,
+    ) -> String
+    where
+        T: Target,
+        F: FnMut(&T, Option<u8>) -> bool,
+    {
+        for i in 0..MAX_INSTRUCTIONS {
+            let opcode = target.memory().peek8(target.cpu().pc);
+            if i != 0 {
+                if let Some(index) = self.breakpoints.iter().position(|x| match x {
+                    Breakpoint::Execute(address) => *address == target.cpu().pc,
+                    Breakpoint::Opcode(x) => *x == opcode,
+                    _ => false,
+                }) {
+                    return self.stopped(target, index, None);
+                }
+            }
+
+            let interrupt = target.step();
+            if let Ok((index, access)) = hits.try_recv() {
+                return self.stopped(target, index, Some(&access));
+            }
+            if let Some(interrupt) = interrupt {
+                if let Some(index) = self
+                    .breakpoints
+                    .iter()
+                    .position(|x| *x == Breakpoint::Interrupt(interrupt))
+                {
+                    return self.stopped(target, index, None);
+                }
+            }
+
+            let opcode = if interrupt.is_some() {
+                None
+            } else {
+                Some(opcode)
+            };
+            if done(target, opcode) {
+                return location(target);
+            }
+        }
+        format!(
+            "stopped after {} instructions\n{}",
+            MAX_INSTRUCTIONS,
+            location(target)
+        )
+    }
+
+    fn stopped<T>(&self, target: &T, index: usize, access: Option<&Access>) -> String
+    where
+        T: Target,
+    {
+        // the CPU is the one being stepped, so only say who made the access when it wasn't
+        let access = match access {
+            Some(access) if access.accessor != Accessor::CPU => format!(
+                ", ${:02X} at ${:04X} by {:?}",
+                access.value, access.address, access.accessor
+            ),
+            Some(access) => format!(", ${:02X} at ${:04X}", access.value, access.address),
+            None => String::new(),
+        };
+        format!(
+            "breakpoint {}: {}{}\n{}",
+            index,
+            self.breakpoints[index],
+            access,
+            location(target)
+        )
+    }
+}
+
+/// The instruction at PC and the registers.
+fn location<T>(target: &T) -> String
+where
+    T: Target,
+{
+    let instruction = disasm::disassemble(target.memory(), target.cpu().pc);
+    format!("{}\n{}", line(&instruction), registers(target.cpu()))
+}
+
+fn line(instruction: &Instruction) -> String {
+    let bytes = instruction
+        .bytes
+        .iter()
+        .map(|x| format!("{:02X}", x))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("{:04X}  {:<8}  {}", instruction.address, bytes, instruction)
+}
+
+fn registers(cpu: &CPU) -> String {
+    let flags = [
+        (Flags::NEGATIVE, 'N'),
+        (Flags::OVERFLOW, 'V'),
+        (Flags::UNUSED, '-'),
+        (Flags::BREAK_COMMAND, 'B'),
+        (Flags::DECIMAL_MODE, 'D'),
+        (Flags::INTERRUPT_DISABLE, 'I'),
+        (Flags::ZERO, 'Z'),
+        (Flags::CARRY, 'C'),
+    ]
+    .iter()
+    .map(|(flag, c)| {
+        if cpu.flags.contains(*flag) {
+            *c
+        } else {
+            c.to_ascii_lowercase()
+        }
+    })
+    .collect::<String>();
+    format!(
+        "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X} {} CYC:{}",
+        cpu.pc,
+        cpu.a,
+        cpu.x,
+        cpu.y,
+        cpu.sp,
+        cpu.flags.bits(),
+        flags,
+        cpu.clock
+    )
+}
+
+fn dump<M>(m: &M, address: u16, length: u16) -> String
+where
+    M: Memory,
+{
+    (0..length)
+        .step_by(MEMORY_BYTES_PER_LINE as usize)
+        .map(|offset| {
+            let start = address.wrapping_add(offset);
+            let bytes = (0..MEMORY_BYTES_PER_LINE.min(length - offset))
+                .map(|i| format!("{:02X}", m.peek8(start.wrapping_add(i))))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("{:04X}: {}", start, bytes)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn disassemble<T>(target: &T, address: u16, count: usize) -> String
+where
+    T: Target,
+{
+    let pc = target.cpu().pc;
+    let mut address = address;
+    (0..count)
+        .map(|_| {
+            let instruction = disasm::disassemble(target.memory(), address);
+            address = instruction.next_address();
+            let marker = if instruction.address == pc { ">" } else { " " };
+            format!("{} {}", marker, line(&instruction))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// There's no telling where instructions start going backwards, so this looks for the furthest back start that
+/// decodes into a run of instructions landing exactly on PC.
+fn disassemble_around_pc<T>(target: &T) -> String
+where
+    T: Target,
+{
+    let pc = target.cpu().pc;
+    let start = (1..=(DISASSEMBLY_LINES_BEFORE as u16 * 3))
+        .rev()
+        .map(|offset| pc.wrapping_sub(offset))
+        .find(|start| {
+            let mut address = *start;
+            let mut lines = 0;
+            while lines < DISASSEMBLY_LINES_BEFORE && address != pc {
+                address = disasm::disassemble(target.memory(), address).next_address();
+                lines += 1;
+            }
+            address == pc && lines == DISASSEMBLY_LINES_BEFORE
+        })
+        .unwrap_or(pc);
+    disassemble(target, start, DEFAULT_DISASSEMBLY_LINES)
+}
+
+fn parse_hex(s: &str) -> Result<u16, String> {
+    let digits = s
+        .strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("expected a hex number, got {:?}", s))
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+    let value = parse_hex(s)?;
+    u8::try_from(value).map_err(|_| format!("${:X} doesn't fit in a byte", value))
+}
+
+fn parse_count(s: &str) -> Result<u64, String> {
+    s.parse()
+        .map_err(|_| format!("expected a decimal number, got {:?}", s))
+}
+
+/// As parse_count, but at least one.
+fn parse_steps(s: &str) -> Result<u64, String> {
+    match parse_count(s)? {
+        0 => Err("can't step 0 instructions".to_string()),
+        count => Ok(count),
+    }
+}
+
+/// A single address or start-end, inclusive.
+fn parse_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    match s.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (parse_hex(start)?, parse_hex(end)?);
+            if start > end {
+                return Err(format!("range {:?} ends before it starts", s));
+            }
+            Ok(start..=end)
+        }
+        None => {
+            let address = parse_hex(s)?;
+            Ok(address..=address)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use super::{Debugger, Target};
+    use crate::{
+        cpu::{Interrupt, CPU},
+        memory::{
+            observe::{Accessor, Bus, Observed, Observers},
+            Memory,
+        },
+        test_utils::test::TestMemory,
+    };
+
+    /// A CPU and RAM, with an NMI once the clock gets to next_nmi.
+    struct TestTarget {
+        cpu: CPU,
+        memory: TestMemory,
+        observers: RefCell<Observers>,
+        next_nmi: u64,
+    }
+
+    impl Target for TestTarget {
+        type Memory = TestMemory;
+
+        fn cpu(&self) -> &CPU {
+            &self.cpu
+        }
+
+        fn cpu_mut(&mut self) -> &mut CPU {
+            &mut self.cpu
+        }
+
+        fn memory(&self) -> &TestMemory {
+            &self.memory
+        }
+
+        fn memory_mut(&mut self) -> &mut TestMemory {
+            &mut self.memory
+        }
+
+        fn observers(&self) -> &RefCell<Observers> {
+            &self.observers
+        }
+
+        fn step(&mut self) -> Option<Interrupt> {
+            let mut memory = Observed::new(
+                &mut self.memory,
+                &self.observers,
+                Bus::CPU,
+                Accessor::CPU,
+                self.cpu.clock,
+            );
+            if self.cpu.clock >= self.next_nmi {
+                self.next_nmi = u64::MAX;
+                self.cpu.interrupt(&mut memory, Interrupt::NMI);
+                Some(Interrupt::NMI)
+            } else {
+                self.cpu.step(&mut memory);
+                None
+            }
+        }
+    }
+
+    #[test]
+    pub fn debugger() {
+        let mut memory = TestMemory::new();
+        let program: &[u8] = &[
+            0x20, 0x07, 0x80, // $8000 JSR $8007
+            0x4c, 0x00, 0x80, // $8003 JMP $8000
+            0xea, // $8006 NOP
+            0xa5, 0x10, // $8007 LDA $10
+            0x8d, 0x00, 0x02, // $8009 STA $0200
+            0x60, // $800C RTS
+            0x40, // $800D RTI
+        ];
+        for (i, value) in program.iter().enumerate() {
+            memory.write8(0x8000 + i as u16, *value);
+        }
+        memory.write16(0xfffa, 0x800d);
+        memory.write8(0x10, 0x5a);
+        let mut cpu = CPU::new();
+        cpu.pc = 0x8000;
+        cpu.sp = 0xfd;
+        let mut target = TestTarget {
+            cpu,
+            memory,
+            observers: RefCell::new(Observers::new()),
+            next_nmi: u64::MAX,
+        };
+        let mut debugger = Debugger::new();
+        let mut run = |target: &mut TestTarget, line: &str| {
+            let result = debugger.execute(target, line);
+            assert!(!result.starts_with("error"), "{:?}: {}", line, result);
+            result
+        };
+
+        run(&mut target, "next");
+        assert_eq!((target.cpu.pc, target.cpu.a), (0x8003, 0x5a));
+        run(&mut target, "step 2");
+        assert_eq!(target.cpu.pc, 0x8007);
+        run(&mut target, "finish");
+        assert_eq!(target.cpu.pc, 0x8003);
+
+        run(&mut target, "watch w 0200-02ff");
+        run(&mut target, "set a 42");
+        let result = run(&mut target, "continue");
+        assert!(result.starts_with("breakpoint 0: write $0200-$02FF, $5A at $0200"));
+        assert_eq!(target.cpu.pc, 0x800c);
+
+        run(&mut target, "delete 0");
+        run(&mut target, "break $8006");
+        run(&mut target, "bo 60");
+        assert!(run(&mut target, "c").starts_with("breakpoint 1: opcode $60 (RTS)"));
+        run(&mut target, "del 1");
+        run(&mut target, "watch r 10");
+        // an empty line repeats the last command
+        assert!(run(&mut target, "c").starts_with("breakpoint 1: read $0010, $5A at $0010"));
+        assert!(run(&mut target, "").starts_with("breakpoint 1"));
+        assert_eq!(target.cpu.pc, 0x8009);
+
+        run(&mut target, "del 1");
+        run(&mut target, "edit 8000 ea ea ea ea ea ea");
+        run(&mut target, "set pc 8000");
+        assert!(run(&mut target, "c").starts_with("breakpoint 0: execute $8006"));
+        assert!(run(&mut target, "disassemble").contains("> 8006  EA        NOP"));
+
+        target.next_nmi = target.cpu.clock;
+        run(&mut target, "bi nmi");
+        assert!(run(&mut target, "c").starts_with("breakpoint 1: NMI"));
+        assert_eq!(target.cpu.pc, 0x800d);
+        assert_eq!(run(&mut target, "bl").lines().count(), 2);
+        assert_eq!(
+            run(&mut target, "watch rw ppu 2000-23ff"),
+            "2: read ppu $2000-$23FF\n3: write ppu $2000-$23FF"
+        );
+
+        assert!(debugger
+            .execute(&mut target, "set a 100")
+            .starts_with("error"));
+        assert!(debugger.execute(&mut target, "s 0").starts_with("error"));
+        assert!(debugger
+            .execute(&mut target, "watch r apu 4000")
+            .starts_with("error"));
+        assert!(debugger
+            .execute(&mut target, "frobnicate")
+            .starts_with("error"));
+        debugger.execute(&mut target, "q");
+        assert!(debugger.has_quit());
+    }
+}
@@ -0,0 +1,535 @@
+// see https://www.nesdev.org/wiki/CPU_unofficial_opcodes and https://www.nesdev.org/6502_cpu.txt
+
+use std::fmt::Display;
+
+use crate::memory::Memory;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    /// Branches, the operand is a signed offset from the next instruction.
+    Relative,
+}
+
+impl AddressingMode {
+    /// Bytes after the opcode.
+    pub fn operand_size(&self) -> u16 {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 0,
+            AddressingMode::Immediate
+            | AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY
+            | AddressingMode::IndirectX
+            | AddressingMode::IndirectY
+            | AddressingMode::Relative => 1,
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    /// Before any page crossing or taken branch. KIL is the cycles before the CPU locks up.
+    pub cycles: u8,
+    /// One more cycle when indexing crosses a page. Branches take one more when taken and another when the target is
+    /// on a different page.
+    pub page_cross_penalty: bool,
+    pub official: bool,
+}
+
+impl Opcode {
+    pub fn size(&self) -> u16 {
+        1 + self.mode.operand_size()
+    }
+
+    const fn with_page_cross_penalty(self) -> Self {
+        Self {
+            page_cross_penalty: true,
+            ..self
+        }
+    }
+}
+
+const fn official(mnemonic: &'static str, mode: AddressingMode, cycles: u8) -> Opcode {
+    Opcode {
+        mnemonic,
+        mode,
+        cycles,
+        page_cross_penalty: false,
+        official: true,
+    }
+}
+
+const fn unofficial(mnemonic: &'static str, mode: AddressingMode, cycles: u8) -> Opcode {
+    Opcode {
+        official: false,
+        ..official(mnemonic, mode, cycles)
+    }
+}
+
+use AddressingMode::*;
+
+/// Every opcode, unofficial ones named as in `CPU`.
+pub const OPCODES: [Opcode; 256] = [
+    official("BRK", Implied, 7),                               // 00
+    official("ORA", IndirectX, 6),                             // 01
+    unofficial("KIL", Implied, 2),                             // 02
+    unofficial("SLO", IndirectX, 8),                           // 03
+    unofficial("NOP", ZeroPage, 3),                            // 04
+    official("ORA", ZeroPage, 3),                              // 05
+    official("ASL", ZeroPage, 5),                              // 06
+    unofficial("SLO", ZeroPage, 5),                            // 07
+    official("PHP", Implied, 3),                               // 08
+    official("ORA", Immediate, 2),                             // 09
+    official("ASL", Accumulator, 2),                           // 0a
+    unofficial("ANC", Immediate, 2),                           // 0b
+    unofficial("NOP", Absolute, 4),                            // 0c
+    official("ORA", Absolute, 4),                              // 0d
+    official("ASL", Absolute, 6),                              // 0e
+    unofficial("SLO", Absolute, 6),                            // 0f
+    official("BPL", Relative, 2).with_page_cross_penalty(),    // 10
+    official("ORA", IndirectY, 5).with_page_cross_penalty(),   // 11
+    unofficial("KIL", Implied, 2),                             // 12
+    unofficial("SLO", IndirectY, 8),                           // 13
+    unofficial("NOP", ZeroPageX, 4),                           // 14
+    official("ORA", ZeroPageX, 4),                             // 15
+    official("ASL", ZeroPageX, 6),                             // 16
+    unofficial("SLO", ZeroPageX, 6),                           // 17
+    official("CLC", Implied, 2),                               // 18
+    official("ORA", AbsoluteY, 4).with_page_cross_penalty(),   // 19
+    unofficial("NOP", Implied, 2),                             // 1a
+    unofficial("SLO", AbsoluteY, 7),                           // 1b
+    unofficial("NOP", AbsoluteX, 4).with_page_cross_penalty(), // 1c
+    official("ORA", AbsoluteX, 4).with_page_cross_penalty(),   // 1d
+    official("ASL", AbsoluteX, 7),                             // 1e
+    unofficial("SLO", AbsoluteX, 7),                           // 1f
+    official("JSR", Absolute, 6),                              // 20
+    official("AND", IndirectX, 6),                             // 21
+    unofficial("KIL", Implied, 2),                             // 22
+    unofficial("RLA", IndirectX, 8),                           // 23
+    official("BIT", ZeroPage, 3),                              // 24
+    official("AND", ZeroPage, 3),                              // 25
+    official("ROL", ZeroPage, 5),                              // 26
+    unofficial("RLA", ZeroPage, 5),                            // 27
+    official("PLP", Implied, 4),                               // 28
+    official("AND", Immediate, 2),                             // 29
+    official("ROL", Accumulator, 2),                           // 2a
+    unofficial("ANC", Immediate, 2),                           // 2b
+    official("BIT", Absolute, 4),                              // 2c
+    official("AND", Absolute, 4),                              // 2d
+    official("ROL", Absolute, 6),                              // 2e
+    unofficial("RLA", Absolute, 6),                            // 2f
+    official("BMI", Relative, 2).with_page_cross_penalty(),    // 30
+    official("AND", IndirectY, 5).with_page_cross_penalty(),   // 31
+    unofficial("KIL", Implied, 2),                             // 32
+    unofficial("RLA", IndirectY, 8),                           // 33
+    unofficial("NOP", ZeroPageX, 4),                           // 34
+    official("AND", ZeroPageX, 4),                             // 35
+    official("ROL", ZeroPageX, 6),                             // 36
+    unofficial("RLA", ZeroPageX, 6),                           // 37
+    official("SEC", Implied, 2),                               // 38
+    official("AND", AbsoluteY, 4).with_page_cross_penalty(),   // 39
+    unofficial("NOP", Implied, 2),                             // 3a
+    unofficial("RLA", AbsoluteY, 7),                           // 3b
+    unofficial("NOP", AbsoluteX, 4).with_page_cross_penalty(), // 3c
+    official("AND", AbsoluteX, 4).with_page_cross_penalty(),   // 3d
+    official("ROL", AbsoluteX, 7),                             // 3e
+    unofficial("RLA", AbsoluteX, 7),                           // 3f
+    official("RTI", Implied, 6),                               // 40
+    official("EOR", IndirectX, 6),                             // 41
+    unofficial("KIL", Implied, 2),                             // 42
+    unofficial("SRE", IndirectX, 8),                           // 43
+    unofficial("NOP", ZeroPage, 3),                            // 44
+    official("EOR", ZeroPage, 3),                              // 45
+    official("LSR", ZeroPage, 5),                              // 46
+    unofficial("SRE", ZeroPage, 5),                            // 47
+    official("PHA", Implied, 3),                               // 48
+    official("EOR", Immediate, 2),                             // 49
+    official("LSR", Accumulator, 2),                           // 4a
+    unofficial("ALR", Immediate, 2),                           // 4b
+    official("JMP", Absolute, 3),                              // 4c
+    official("EOR", Absolute, 4),                              // 4d
+    official("LSR", Absolute, 6),                              // 4e
+    unofficial("SRE", Absolute, 6),                            // 4f
+    official("BVC", Relative, 2).with_page_cross_penalty(),    // 50
+    official("EOR", IndirectY, 5).with_page_cross_penalty(),   // 51
+    unofficial("KIL", Implied, 2),                             // 52
+    unofficial("SRE", IndirectY, 8),                           // 53
+    unofficial("NOP", ZeroPageX, 4),                           // 54
+    official("EOR", ZeroPageX, 4),                             // 55
+    official("LSR", ZeroPageX, 6),                             // 56
+    unofficial("SRE", ZeroPageX, 6),                           // 57
+    official("CLI", Implied, 2),                               // 58
+    official("EOR", AbsoluteY, 4).with_page_cross_penalty(),   // 59
+    unofficial("NOP", Implied, 2),                             // 5a
+    unofficial("SRE", AbsoluteY, 7),                           // 5b
+    unofficial("NOP", AbsoluteX, 4).with_page_cross_penalty(), // 5c
+    official("EOR", AbsoluteX, 4).with_page_cross_penalty(),   // 5d
+    official("LSR", AbsoluteX, 7),                             // 5e
+    unofficial("SRE", AbsoluteX, 7),                           // 5f
+    official("RTS", Implied, 6),                               // 60
+    official("ADC", IndirectX, 6),                             // 61
+    unofficial("KIL", Implied, 2),                             // 62
+    unofficial("RRA", IndirectX, 8),                           // 63
+    unofficial("NOP", ZeroPage, 3),                            // 64
+    official("ADC", ZeroPage, 3),                              // 65
+    official("ROR", ZeroPage, 5),                              // 66
+    unofficial("RRA", ZeroPage, 5),                            // 67
+    official("PLA", Implied, 4),                               // 68
+    official("ADC", Immediate, 2),                             // 69
+    official("ROR", Accumulator, 2),                           // 6a
+    unofficial("ARR", Immediate, 2),                           // 6b
+    official("JMP", Indirect, 5),                              // 6c
+    official("ADC", Absolute, 4),                              // 6d
+    official("ROR", Absolute, 6),                              // 6e
+    unofficial("RRA", Absolute, 6),                            // 6f
+    official("BVS", Relative, 2).with_page_cross_penalty(),    // 70
+    official("ADC", IndirectY, 5).with_page_cross_penalty(),   // 71
+    unofficial("KIL", Implied, 2),                             // 72
+    unofficial("RRA", IndirectY, 8),                           // 73
+    unofficial("NOP", ZeroPageX, 4),                           // 74
+    official("ADC", ZeroPageX, 4),                             // 75
+    official("ROR", ZeroPageX, 6),                             // 76
+    unofficial("RRA", ZeroPageX, 6),                           // 77
+    official("SEI", Implied, 2),                               // 78
+    official("ADC", AbsoluteY, 4).with_page_cross_penalty(),   // 79
+    unofficial("NOP", Implied, 2),                             // 7a
+    unofficial("RRA", AbsoluteY, 7),                           // 7b
+    unofficial("NOP", AbsoluteX, 4).with_page_cross_penalty(), // 7c
+    official("ADC", AbsoluteX, 4).with_page_cross_penalty(),   // 7d
+    official("ROR", AbsoluteX, 7),                             // 7e
+    unofficial("RRA", AbsoluteX, 7),                           // 7f
+    unofficial("NOP", Immediate, 2),                           // 80
+    official("STA", IndirectX, 6),                             // 81
+    unofficial("NOP", Immediate, 2),                           // 82
+    unofficial("SAX", IndirectX, 6),                           // 83
+    official("STY", ZeroPage, 3),                              // 84
+    official("STA", ZeroPage, 3),                              // 85
+    official("STX", ZeroPage, 3),                              // 86
+    unofficial("SAX", ZeroPage, 3),                            // 87
+    official("DEY", Implied, 2),                               // 88
+    unofficial("NOP", Immediate, 2),                           // 89
+    official("TXA", Implied, 2),                               // 8a
+    unofficial("XAA", Immediate, 2),                           // 8b
+    official("STY", Absolute, 4),                              // 8c
+    official("STA", Absolute, 4),                              // 8d
+    official("STX", Absolute, 4),                              // 8e
+    unofficial("SAX", Absolute, 4),                            // 8f
+    official("BCC", Relative, 2).with_page_cross_penalty(),    // 90
+    official("STA", IndirectY, 6),                             // 91
+    unofficial("KIL", Implied, 2),                             // 92
+    unofficial("AHX", IndirectY, 6),                           // 93
+    official("STY", ZeroPageX, 4),                             // 94
+    official("STA", ZeroPageX, 4),                             // 95
+    official("STX", ZeroPageY, 4),                             // 96
+    unofficial("SAX", ZeroPageY, 4),                           // 97
+    official("TYA", Implied, 2),                               // 98
+    official("STA", AbsoluteY, 5),                             // 99
+    official("TXS", Implied, 2),                               // 9a
+    unofficial("TAS", AbsoluteY, 5),                           // 9b
+    unofficial("SHY", AbsoluteX, 5),                           // 9c
+    official("STA", AbsoluteX, 5),                             // 9d
+    unofficial("SHX", AbsoluteY, 5),                           // 9e
+    unofficial("AHX", AbsoluteY, 5),                           // 9f
+    official("LDY", Immediate, 2),                             // a0
+    official("LDA", IndirectX, 6),                             // a1
+    official("LDX", Immediate, 2),                             // a2
+    unofficial("LAX", IndirectX, 6),                           // a3
+    official("LDY", ZeroPage, 3),                              // a4
+    official("LDA", ZeroPage, 3),                              // a5
+    official("LDX", ZeroPage, 3),                              // a6
+    unofficial("LAX", ZeroPage, 3),                            // a7
+    official("TAY", Implied, 2),                               // a8
+    official("LDA", Immediate, 2),                             // a9
+    official("TAX", Implied, 2),                               // aa
+    unofficial("LAX", Immediate, 2),                           // ab
+    official("LDY", Absolute, 4),                              // ac
+    official("LDA", Absolute, 4),                              // ad
+    official("LDX", Absolute, 4),                              // ae
+    unofficial("LAX", Absolute, 4),                            // af
+    official("BCS", Relative, 2).with_page_cross_penalty(),    // b0
+    official("LDA", IndirectY, 5).with_page_cross_penalty(),   // b1
+    unofficial("KIL", Implied, 2),                             // b2
+    unofficial("LAX", IndirectY, 5).with_page_cross_penalty(), // b3
+    official("LDY", ZeroPageX, 4),                             // b4
+    official("LDA", ZeroPageX, 4),                             // b5
+    official("LDX", ZeroPageY, 4),                             // b6
+    unofficial("LAX", ZeroPageY, 4),                           // b7
+    official("CLV", Implied, 2),                               // b8
+    official("LDA", AbsoluteY, 4).with_page_cross_penalty(),   // b9
+    official("TSX", Implied, 2),                               // ba
+    unofficial("LAS", AbsoluteY, 4).with_page_cross_penalty(), // bb
+    official("LDY", AbsoluteX, 4).with_page_cross_penalty(),   // bc
+    official("LDA", AbsoluteX, 4).with_page_cross_penalty(),   // bd
+    official("LDX", AbsoluteY, 4).with_page_cross_penalty(),   // be
+    unofficial("LAX", AbsoluteY, 4).with_page_cross_penalty(), // bf
+    official("CPY", Immediate, 2),                             // c0
+    official("CMP", IndirectX, 6),                             // c1
+    unofficial("NOP", Immediate, 2),                           // c2
+    unofficial("DCP", IndirectX, 8),                           // c3
+    official("CPY", ZeroPage, 3),                              // c4
+    official("CMP", ZeroPage, 3),                              // c5
+    official("DEC", ZeroPage, 5),                              // c6
+    unofficial("DCP", ZeroPage, 5),                            // c7
+    official("INY", Implied, 2),                               // c8
+    official("CMP", Immediate, 2),                             // c9
+    official("DEX", Implied, 2),                               // ca
+    unofficial("AXS", Immediate, 2),                           // cb
+    official("CPY", Absolute, 4),                              // cc
+    official("CMP", Absolute, 4),                              // cd
+    official("DEC", Absolute, 6),                              // ce
+    unofficial("DCP", Absolute, 6),                            // cf
+    official("BNE", Relative, 2).with_page_cross_penalty(),    // d0
+    official("CMP", IndirectY, 5).with_page_cross_penalty(),   // d1
+    unofficial("KIL", Implied, 2),                             // d2
+    unofficial("DCP", IndirectY, 8),                           // d3
+    unofficial("NOP", ZeroPageX, 4),                           // d4
+    official("CMP", ZeroPageX, 4),                             // d5
+    official("DEC", ZeroPageX, 6),                             // d6
+    unofficial("DCP", ZeroPageX, 6),                           // d7
+    official("CLD", Implied, 2),                               // d8
+    official("CMP", AbsoluteY, 4).with_page_cross_penalty(),   // d9
+    unofficial("NOP", Implied, 2),                             // da
+    unofficial("DCP", AbsoluteY, 7),                           // db
+    unofficial("NOP", AbsoluteX, 4).with_page_cross_penalty(), // dc
+    official("CMP", AbsoluteX, 4).with_page_cross_penalty(),   // dd
+    official("DEC", AbsoluteX, 7),                             // de
+    unofficial("DCP", AbsoluteX, 7),                           // df
+    official("CPX", Immediate, 2),                             // e0
+    official("SBC", IndirectX, 6),                             // e1
+    unofficial("NOP", Immediate, 2),                           // e2
+    unofficial("ISC", IndirectX, 8),                           // e3
+    official("CPX", ZeroPage, 3),                              // e4
+    official("SBC", ZeroPage, 3),                              // e5
+    official("INC", ZeroPage, 5),                              // e6
+    unofficial("ISC", ZeroPage, 5),                            // e7
+    official("INX", Implied, 2),                               // e8
+    official("SBC", Immediate, 2),                             // e9
+    official("NOP", Implied, 2),                               // ea
+    unofficial("SBC", Immediate, 2),                           // eb
+    official("CPX", Absolute, 4),                              // ec
+    official("SBC", Absolute, 4),                              // ed
+    official("INC", Absolute, 6),                              // ee
+    unofficial("ISC", Absolute, 6),                            // ef
+    official("BEQ", Relative, 2).with_page_cross_penalty(),    // f0
+    official("SBC", IndirectY, 5).with_page_cross_penalty(),   // f1
+    unofficial("KIL", Implied, 2),                             // f2
+    unofficial("ISC", IndirectY, 8),                           // f3
+    unofficial("NOP", ZeroPageX, 4),                           // f4
+    official("SBC", ZeroPageX, 4),                             // f5
+    official("INC", ZeroPageX, 6),                             // f6
+    unofficial("ISC", ZeroPageX, 6),                           // f7
+    official("SED", Implied, 2),                               // f8
+    official("SBC", AbsoluteY, 4).with_page_cross_penalty(),   // f9
+    unofficial("NOP", Implied, 2),                             // fa
+    unofficial("ISC", AbsoluteY, 7),                           // fb
+    unofficial("NOP", AbsoluteX, 4).with_page_cross_penalty(), // fc
+    official("SBC", AbsoluteX, 4).with_page_cross_penalty(),   // fd
+    official("INC", AbsoluteX, 7),                             // fe
+    unofficial("ISC", AbsoluteX, 7),                           // ff
+];
+
+pub struct Instruction {
+    pub address: u16,
+    /// The opcode and its operand.
+    pub bytes: Vec<u8>,
+    pub opcode: &'static Opcode,
+}
+
+impl Instruction {
+    /// The operand as written, except for branches where it's the target address.
+    pub fn operand(&self) -> u16 {
+        match self.opcode.mode {
+            Implied | Accumulator => 0,
+            Relative => self
+                .address
+                .wrapping_add(self.opcode.size())
+                .wrapping_add(self.bytes[1] as i8 as u16),
+            _ => self.bytes[1..]
+                .iter()
+                .rev()
+                .fold(0, |result, x| (result << 8) | (*x as u16)),
+        }
+    }
+
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.opcode.size())
+    }
+}
+
+/// ca65 syntax, with unofficial opcodes as 6502X would have them.
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mnemonic = self.opcode.mnemonic;
+        let operand = self.operand();
+        match self.opcode.mode {
+            Implied => write!(f, "{}", mnemonic),
+            Accumulator => write!(f, "{} A", mnemonic),
+            Immediate => write!(f, "{} #${:02X}", mnemonic, operand),
+            ZeroPage => write!(f, "{} ${:02X}", mnemonic, operand),
+            ZeroPageX => write!(f, "{} ${:02X},X", mnemonic, operand),
+            ZeroPageY => write!(f, "{} ${:02X},Y", mnemonic, operand),
+            Absolute | Relative => write!(f, "{} ${:04X}", mnemonic, operand),
+            AbsoluteX => write!(f, "{} ${:04X},X", mnemonic, operand),
+            AbsoluteY => write!(f, "{} ${:04X},Y", mnemonic, operand),
+            Indirect => write!(f, "{} (${:04X})", mnemonic, operand),
+            IndirectX => write!(f, "{} (${:02X},X)", mnemonic, operand),
+            IndirectY => write!(f, "{} (${:02X}),Y", mnemonic, operand),
+        }
+    }
+}
+
+/// Decodes the instruction at the given address, reading past the end of memory wraps around.
+pub fn disassemble<M>(m: &M, address: u16) -> Instruction
+where
+    M: Memory,
+{
+    let opcode = &OPCODES[m.peek8(address) as usize];
+    Instruction {
+        address,
+        bytes: (0..opcode.size())
+            .map(|i| m.peek8(address.wrapping_add(i)))
+            .collect(),
+        opcode,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{disassemble, AddressingMode, OPCODES};
+    use crate::{cpu::CPU, flags::Flags, memory::Memory, test_utils::test::TestMemory};
+
+    const INSTRUCTION_ADDRESS: u16 = 0x0200;
+
+    /// Runs one instruction on a blank machine and returns the cycles CPU charged for it.
+    fn cycles(bytes: &[u8], address: u16, x: u8, y: u8, flags: Flags, setup: &[(u16, u8)]) -> u64 {
+        let mut m = TestMemory::new();
+        for (i, value) in bytes.iter().enumerate() {
+            m.write8(address + i as u16, *value);
+        }
+        for (address, value) in setup {
+            m.write8(*address, *value);
+        }
+        let mut cpu = CPU::new();
+        cpu.pc = address;
+        cpu.sp = 0xfd;
+        cpu.x = x;
+        cpu.y = y;
+        cpu.flags = flags;
+        cpu.step(&mut m);
+        cpu.clock
+    }
+
+    #[test]
+    pub fn cycles_match_cpu() {
+        for (value, opcode) in OPCODES.iter().enumerate() {
+            // the CPU spins in place instead of locking up
+            if opcode.mnemonic == "KIL" {
+                continue;
+            }
+            let name = format!("{:02x} {}", value, opcode.mnemonic);
+            let base = opcode.cycles as u64;
+            let penalty = if opcode.page_cross_penalty { 1 } else { 0 };
+
+            if opcode.mode == AddressingMode::Relative {
+                // branch on a flag is in bits 6-7, and whether it has to be set in bit 5
+                let flag = match value >> 6 {
+                    0 => Flags::NEGATIVE,
+                    1 => Flags::OVERFLOW,
+                    2 => Flags::CARRY,
+                    _ => Flags::ZERO,
+                };
+                let (taken, not_taken) = if (value & 0b0010_0000) != 0 {
+                    (flag, Flags::empty())
+                } else {
+                    (Flags::empty(), flag)
+                };
+                let op = value as u8;
+                let not_taken = cycles(&[op, 0x10], INSTRUCTION_ADDRESS, 0, 0, not_taken, &[]);
+                assert_eq!(not_taken, base, "{}, not taken", name);
+                let same_page = cycles(&[op, 0x10], INSTRUCTION_ADDRESS, 0, 0, taken, &[]);
+                assert_eq!(same_page, base + penalty, "{}, taken", name);
+                let next_page = cycles(&[op, 0x7f], 0x02f0, 0, 0, taken, &[]);
+                assert_eq!(
+                    next_page,
+                    base + 2 * penalty,
+                    "{}, taken to next page",
+                    name
+                );
+                continue;
+            }
+
+            // (zp),Y points at $0210 through $10, so crossing a page needs Y to be big enough
+            let bytes = [value as u8, 0x10, 0x02];
+            let pointer = [(0x0010, 0x10), (0x0011, 0x02)];
+            let no_crossing = cycles(&bytes, INSTRUCTION_ADDRESS, 0, 0, Flags::empty(), &pointer);
+            assert_eq!(no_crossing, base, "{}", name);
+            let crossing = cycles(
+                &bytes,
+                INSTRUCTION_ADDRESS,
+                0xff,
+                0xff,
+                Flags::empty(),
+                &pointer,
+            );
+            assert_eq!(crossing, base + penalty, "{}, crossing a page", name);
+        }
+    }
+
+    #[test]
+    pub fn ca65_syntax() {
+        let mut m = TestMemory::new();
+        let program: &[u8] = &[
+            0xa9, 0x12, // LDA #$12
+            0x0a, // ASL A
+            0xbd, 0x34, 0x12, // LDA $1234,X
+            0xb1, 0x80, // LDA ($80),Y
+            0x6c, 0xfc, 0xff, // JMP ($FFFC)
+            0xd0, 0xf3, // BNE back to the start
+            0x07, 0x10, // SLO $10
+            0x02, // KIL
+        ];
+        for (i, value) in program.iter().enumerate() {
+            m.write8(0x8000 + i as u16, *value);
+        }
+
+        let mut address = 0x8000;
+        let mut lines = Vec::new();
+        while address < 0x8000 + program.len() as u16 {
+            let instruction = disassemble(&m, address);
+            lines.push(instruction.to_string());
+            address = instruction.next_address();
+        }
+        assert_eq!(
+            lines,
+            [
+                "LDA #$12",
+                "ASL A",
+                "LDA $1234,X",
+                "LDA ($80),Y",
+                "JMP ($FFFC)",
+                "BNE $8000",
+                "SLO $10",
+                "KIL",
+            ]
+        );
+        assert!(!disassemble(&m, 0x800d).opcode.official);
+    }
+}
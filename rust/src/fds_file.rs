@@ -0,0 +1,299 @@
+// see https://www.nesdev.org/wiki/FDS_file_format and https://www.nesdev.org/wiki/FDS_disk_format
+
+use std::{error::Error, fmt::Display};
+
+#[derive(Debug, Clone, Copy)]
+pub enum FdsError {
+    BadHeader,
+    MissingData,
+    BadBios,
+    BadDiff,
+}
+
+impl Display for FdsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for FdsError {}
+
+/// Sides in .fds files are always this big, regardless of how much is actually on them.
+pub const SIDE_SIZE: usize = 65500;
+const HEADER_SIZE: usize = 16;
+const MAGIC: &[u8] = b"FDS\x1a";
+/// Every side starts with the disk info block, so this is how we recognize headerless images.
+const DISK_INFO_MAGIC: &[u8] = b"\x01*NINTENDO-HVC*";
+
+pub const BIOS_SIZE: usize = 0x2000;
+
+pub const DIFF_MAGIC: &[u8] = b"FDSD";
+
+/// On the real disk blocks are separated by gaps and end with a CRC, neither of which are in .fds files.
+const LEADING_GAP_SIZE: usize = 28300 / 8;
+const BLOCK_GAP_SIZE: usize = 976 / 8;
+/// The byte that ends a gap and starts a block.
+pub const GAP_END: u8 = 0x80;
+const CRC_SIZE: usize = 2;
+
+const DISK_INFO_BLOCK: u8 = 1;
+const FILE_AMOUNT_BLOCK: u8 = 2;
+const FILE_HEADER_BLOCK: u8 = 3;
+const FILE_DATA_BLOCK: u8 = 4;
+const DISK_INFO_BLOCK_SIZE: usize = 56;
+const FILE_AMOUNT_BLOCK_SIZE: usize = 2;
+const FILE_HEADER_BLOCK_SIZE: usize = 16;
+const FILE_HEADER_FILE_SIZE_OFFSET: usize = 13;
+
+#[derive(Clone, Default)]
+pub struct DiskImage {
+    sides: Vec<Vec<u8>>,
+}
+
+impl DiskImage {
+    /// Reads either a .fds file, or a raw dump of the sides with no header.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, FdsError> {
+        let (side_count, data) = if data.starts_with(MAGIC) {
+            if data.len() < HEADER_SIZE {
+                return Err(FdsError::BadHeader);
+            }
+            (data[4] as usize, &data[HEADER_SIZE..])
+        } else if data.starts_with(DISK_INFO_MAGIC) {
+            (data.len() / SIDE_SIZE, data)
+        } else {
+            return Err(FdsError::BadHeader);
+        };
+        if side_count == 0 || data.len() < side_count * SIDE_SIZE {
+            return Err(FdsError::MissingData);
+        }
+
+        let sides = data
+            .chunks_exact(SIDE_SIZE)
+            .take(side_count)
+            .map(|x| x.to_vec())
+            .collect::<Vec<_>>();
+        if !sides.iter().all(|x| x.starts_with(DISK_INFO_MAGIC)) {
+            return Err(FdsError::BadHeader);
+        }
+        Ok(Self { sides })
+    }
+
+    pub fn side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    pub fn side(&self, side: usize) -> &[u8] {
+        &self.sides[side]
+    }
+
+    /// A side the way the drive sees it, with gaps between the blocks and CRCs after them. Free space on the side comes
+    /// after the last block.
+    pub fn raw_side(&self, side: usize) -> Vec<u8> {
+        let data = &self.sides[side];
+        let mut result = vec![0; LEADING_GAP_SIZE];
+        let mut position = 0;
+        let mut file_size = 0;
+        while let Some(length) = block_length(&data[position..], file_size) {
+            let block = &data[position..(position + length)];
+            if block[0] == FILE_HEADER_BLOCK {
+                file_size = u16::from_le_bytes([
+                    block[FILE_HEADER_FILE_SIZE_OFFSET],
+                    block[FILE_HEADER_FILE_SIZE_OFFSET + 1],
+                ]) as usize;
+            }
+            result.push(GAP_END);
+            result.extend_from_slice(block);
+            let crc = [GAP_END]
+                .iter()
+                .chain(block)
+                .chain(&[0, 0])
+                .fold(0, |crc, x| update_crc(crc, *x));
+            result.extend_from_slice(&crc.to_le_bytes());
+            result.resize(result.len() + BLOCK_GAP_SIZE, 0);
+            position += length;
+        }
+        result.resize(result.len() + SIDE_SIZE - position, 0);
+        result
+    }
+
+    /// Reverses `raw_side`, dropping the gaps and CRCs.
+    pub fn set_raw_side(&mut self, side: usize, raw: &[u8]) {
+        let mut result = Vec::with_capacity(SIDE_SIZE);
+        let mut position = 0;
+        let mut file_size = 0;
+        loop {
+            while position < raw.len() && raw[position] == 0 {
+                position += 1;
+            }
+            if position >= raw.len() || raw[position] != GAP_END {
+                break;
+            }
+            position += 1;
+            let Some(length) = block_length(&raw[position..], file_size) else {
+                break;
+            };
+            let block = &raw[position..(position + length)];
+            if block[0] == FILE_HEADER_BLOCK {
+                file_size = u16::from_le_bytes([
+                    block[FILE_HEADER_FILE_SIZE_OFFSET],
+                    block[FILE_HEADER_FILE_SIZE_OFFSET + 1],
+                ]) as usize;
+            }
+            result.extend_from_slice(block);
+            position += length + CRC_SIZE;
+        }
+        result.resize(SIDE_SIZE, 0);
+        self.sides[side] = result;
+    }
+
+    /// Every difference from `original`, for saving next to it so the original image never needs to be modified.
+    pub fn diff(&self, original: &DiskImage) -> Vec<u8> {
+        let mut result = DIFF_MAGIC.to_vec();
+        let current = self.sides.concat();
+        let original = original.sides.concat();
+        let mut offset = 0;
+        while offset < current.len() {
+            if original.get(offset) == Some(&current[offset]) {
+                offset += 1;
+                continue;
+            }
+            let start = offset;
+            while offset < current.len()
+                && offset - start < u16::MAX as usize
+                && original.get(offset) != Some(&current[offset])
+            {
+                offset += 1;
+            }
+            result.extend_from_slice(&(start as u32).to_le_bytes());
+            result.extend_from_slice(&((offset - start) as u16).to_le_bytes());
+            result.extend_from_slice(&current[start..offset]);
+        }
+        result
+    }
+
+    /// Applies what `diff` produced.
+    pub fn apply_diff(&mut self, diff: &[u8]) -> Result<(), FdsError> {
+        if !diff.starts_with(DIFF_MAGIC) {
+            return Err(FdsError::BadDiff);
+        }
+        let mut data = self.sides.concat();
+        let mut remaining_data = &diff[DIFF_MAGIC.len()..];
+        while !remaining_data.is_empty() {
+            if remaining_data.len() < 6 {
+                return Err(FdsError::BadDiff);
+            }
+            let offset = u32::from_le_bytes(remaining_data[0..4].try_into().unwrap()) as usize;
+            let length = u16::from_le_bytes(remaining_data[4..6].try_into().unwrap()) as usize;
+            remaining_data = &remaining_data[6..];
+            if remaining_data.len() < length || offset + length > data.len() {
+                return Err(FdsError::BadDiff);
+            }
+            data[offset..(offset + length)].copy_from_slice(&remaining_data[..length]);
+            remaining_data = &remaining_data[length..];
+        }
+        self.sides = data.chunks_exact(SIDE_SIZE).map(|x| x.to_vec()).collect();
+        Ok(())
+    }
+}
+
+/// How long the block at the start of data is, if there is a valid one there. File data blocks don't say how long they
+/// are, that comes from the preceding file header block.
+fn block_length(data: &[u8], file_size: usize) -> Option<usize> {
+    let length = match *data.first()? {
+        DISK_INFO_BLOCK => DISK_INFO_BLOCK_SIZE,
+        FILE_AMOUNT_BLOCK => FILE_AMOUNT_BLOCK_SIZE,
+        FILE_HEADER_BLOCK => FILE_HEADER_BLOCK_SIZE,
+        FILE_DATA_BLOCK => 1 + file_size,
+        _ => return None,
+    };
+    if data.len() < length {
+        None
+    } else {
+        Some(length)
+    }
+}
+
+/// The drive's CRC-16. Running it over a block including its gap end byte and CRC gives 0.
+pub fn update_crc(mut crc: u16, value: u8) -> u16 {
+    for bit in 0..8 {
+        let carry = (crc & 1) != 0;
+        crc >>= 1;
+        if carry {
+            crc ^= 0x8408;
+        }
+        if (value & (1 << bit)) != 0 {
+            crc ^= 0x8000;
+        }
+    }
+    crc
+}
+
+/// disksys.rom, which has to be supplied by the user.
+pub struct Bios(Box<[u8; BIOS_SIZE]>);
+
+impl Bios {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, FdsError> {
+        Ok(Self(Box::new(
+            data.try_into().map_err(|_| FdsError::BadBios)?,
+        )))
+    }
+
+    pub fn data(&self) -> &[u8; BIOS_SIZE] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{update_crc, DiskImage, DISK_INFO_MAGIC, GAP_END, SIDE_SIZE};
+
+    fn side() -> Vec<u8> {
+        let mut side = DISK_INFO_MAGIC.to_vec();
+        side.resize(56, 0x11);
+        side.extend_from_slice(&[2, 1]);
+        let mut header = vec![
+            3, 0, 0, b'F', b'I', b'L', b'E', b'N', b'A', b'M', b'E', 0, 0,
+        ];
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.push(0);
+        side.extend(header);
+        side.extend_from_slice(&[4, 0xde, 0xad, 0xbe, 0xef]);
+        side.resize(SIDE_SIZE, 0);
+        side
+    }
+
+    #[test]
+    pub fn raw_side_round_trip() {
+        let mut data = b"FDS\x1a\x01".to_vec();
+        data.resize(16, 0);
+        data.extend(side());
+        let mut image = DiskImage::from_bytes(&data).unwrap();
+        let original = image.clone();
+        assert_eq!(image.side_count(), 1);
+
+        let mut raw = image.raw_side(0);
+        // every block's CRC checks out
+        let mut position = 0;
+        for length in [56, 2, 16, 5] {
+            position += raw[position..].iter().position(|x| *x == GAP_END).unwrap();
+            let crc = raw[position..(position + 1 + length + 2)]
+                .iter()
+                .fold(0, |crc, x| update_crc(crc, *x));
+            assert_eq!(crc, 0);
+            position += 1 + length + 2;
+        }
+
+        image.set_raw_side(0, &raw);
+        assert_eq!(image.side(0), &side()[..]);
+        assert_eq!(image.diff(&original), b"FDSD");
+
+        let file_data = raw.iter().position(|x| *x == 0xde).unwrap();
+        raw[file_data] = 0x12;
+        image.set_raw_side(0, &raw);
+        let diff = image.diff(&original);
+        let mut patched = original.clone();
+        patched.apply_diff(&diff).unwrap();
+        assert_eq!(patched.side(0), image.side(0));
+        assert_ne!(patched.side(0), original.side(0));
+    }
+}
@@ -0,0 +1,144 @@
+// What the PPU draws, as palette indices so it can be compared without caring which palette shows it.
+//
+// The Vs. System's RP2C04s show the same colors as the others in a scrambled order, so their games can't be moved to
+// another cabinet, see https://www.nesdev.org/wiki/PPU_palettes#2C04
+
+use crate::cartridge_file::VsPPUType;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+/// RGB for each of the 64 colors, approximating a 2C02's composite output.
+#[rustfmt::skip]
+const PALETTE: [u32; 64] = [
+    0x666666, 0x002a88, 0x1412a7, 0x3b00a4, 0x5c007e, 0x6e0040, 0x6c0600, 0x561d00,
+    0x333500, 0x0b4800, 0x005200, 0x004f08, 0x00404d, 0x000000, 0x000000, 0x000000,
+    0xadadad, 0x155fd9, 0x4240ff, 0x7527fe, 0xa01acc, 0xb71e7b, 0xb53120, 0x994e00,
+    0x6b6d00, 0x388700, 0x0c9300, 0x008f32, 0x007c8d, 0x000000, 0x000000, 0x000000,
+    0xfffeff, 0x64b0ff, 0x9290ff, 0xc676ff, 0xf36aff, 0xfe6ecc, 0xfe8170, 0xea9e22,
+    0xbcbe00, 0x88d800, 0x5ce430, 0x45e082, 0x48cdde, 0x4f4f4f, 0x000000, 0x000000,
+    0xfffeff, 0xc0dfff, 0xd3d2ff, 0xe8c8ff, 0xfbc2ff, 0xfec4ea, 0xfeccc5, 0xf7d8a5,
+    0xe4e594, 0xcfef96, 0xbdf4ab, 0xb3f3cc, 0xb5ebf2, 0xb8b8b8, 0x000000, 0x000000,
+];
+
+/// Each RP2C04 color's index in PALETTE.
+#[rustfmt::skip]
+const RP2C04_0001: [u8; 64] = [
+    0x35, 0x23, 0x16, 0x22, 0x1c, 0x09, 0x1d, 0x15, 0x20, 0x00, 0x27, 0x05, 0x04, 0x28, 0x08, 0x20,
+    0x21, 0x3e, 0x1f, 0x29, 0x3c, 0x32, 0x36, 0x12, 0x3f, 0x2b, 0x2e, 0x1e, 0x3d, 0x2d, 0x24, 0x01,
+    0x0e, 0x31, 0x33, 0x2a, 0x2c, 0x0c, 0x1b, 0x14, 0x2e, 0x07, 0x34, 0x06, 0x13, 0x02, 0x26, 0x2e,
+    0x2e, 0x19, 0x10, 0x0a, 0x39, 0x03, 0x37, 0x17, 0x0f, 0x11, 0x0b, 0x0d, 0x38, 0x25, 0x18, 0x3a,
+];
+
+#[rustfmt::skip]
+const RP2C04_0002: [u8; 64] = [
+    0x2e, 0x27, 0x18, 0x39, 0x3a, 0x25, 0x1c, 0x31, 0x16, 0x13, 0x38, 0x34, 0x20, 0x23, 0x3c, 0x0b,
+    0x0f, 0x21, 0x06, 0x3d, 0x1b, 0x29, 0x1e, 0x22, 0x1d, 0x24, 0x0e, 0x2b, 0x32, 0x08, 0x2e, 0x03,
+    0x04, 0x36, 0x26, 0x33, 0x11, 0x1f, 0x10, 0x02, 0x14, 0x3f, 0x00, 0x09, 0x12, 0x2e, 0x28, 0x20,
+    0x3e, 0x0d, 0x2a, 0x17, 0x0c, 0x01, 0x15, 0x19, 0x2e, 0x2c, 0x07, 0x37, 0x35, 0x05, 0x0a, 0x2d,
+];
+
+#[rustfmt::skip]
+const RP2C04_0003: [u8; 64] = [
+    0x14, 0x25, 0x3a, 0x10, 0x0b, 0x20, 0x31, 0x09, 0x01, 0x2e, 0x36, 0x08, 0x15, 0x3d, 0x3e, 0x3c,
+    0x22, 0x1c, 0x05, 0x12, 0x19, 0x18, 0x17, 0x1b, 0x00, 0x03, 0x2e, 0x02, 0x16, 0x06, 0x34, 0x35,
+    0x23, 0x0f, 0x0e, 0x37, 0x0d, 0x27, 0x26, 0x20, 0x29, 0x04, 0x21, 0x24, 0x11, 0x2d, 0x2e, 0x1f,
+    0x2c, 0x1e, 0x39, 0x33, 0x07, 0x2a, 0x28, 0x1d, 0x0a, 0x2e, 0x32, 0x38, 0x13, 0x2b, 0x3f, 0x0c,
+];
+
+#[rustfmt::skip]
+const RP2C04_0004: [u8; 64] = [
+    0x18, 0x03, 0x1c, 0x28, 0x2e, 0x35, 0x01, 0x17, 0x10, 0x1f, 0x2a, 0x0e, 0x36, 0x37, 0x0b, 0x39,
+    0x25, 0x1e, 0x12, 0x34, 0x2e, 0x1d, 0x06, 0x26, 0x3e, 0x1b, 0x22, 0x19, 0x04, 0x2e, 0x3a, 0x21,
+    0x05, 0x0a, 0x07, 0x02, 0x13, 0x14, 0x00, 0x15, 0x0c, 0x3d, 0x11, 0x0f, 0x0d, 0x38, 0x2d, 0x24,
+    0x33, 0x20, 0x08, 0x16, 0x3f, 0x2b, 0x20, 0x3c, 0x2e, 0x27, 0x23, 0x31, 0x29, 0x32, 0x2c, 0x09,
+];
+
+pub struct Framebuffer {
+    pixels: Box<[u8; WIDTH * HEIGHT]>,
+    /// For PPUs with their own order of colors, where each is in PALETTE.
+    order: Option<&'static [u8; 64]>,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self {
+            pixels: Box::new([0; WIDTH * HEIGHT]),
+            order: None,
+        }
+    }
+
+    /// As new, but with the colors in the order the Vs. System PPU has them.
+    pub fn with_vs_ppu_type(vs_ppu_type: Option<VsPPUType>) -> Self {
+        Self {
+            order: match vs_ppu_type {
+                Some(VsPPUType::RP2C04_0001) => Some(&RP2C04_0001),
+                Some(VsPPUType::RP2C04_0002) => Some(&RP2C04_0002),
+                Some(VsPPUType::RP2C04_0003) => Some(&RP2C04_0003),
+                Some(VsPPUType::RP2C04_0004) => Some(&RP2C04_0004),
+                _ => None,
+            },
+            ..Self::new()
+        }
+    }
+
+    /// Only the low 6 bits of the color are used.
+    pub fn set(&mut self, x: usize, y: usize, color: u8) {
+        self.pixels[y * WIDTH + x] = color & 0x3f;
+    }
+
+    /// Tests look at pixels, everything else goes through the whole frame.
+    #[cfg(test)]
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * WIDTH + x]
+    }
+
+    /// 3 bytes a pixel, a row at a time from the top.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&x| {
+                let x = self.order.map_or(x, |order| order[x as usize]);
+                let [_, r, g, b] = PALETTE[x as usize].to_be_bytes();
+                [r, g, b]
+            })
+            .collect()
+    }
+
+    /// CRC32 of the palette indices, the same from one run, build or palette to the next.
+    pub fn hash(&self) -> u32 {
+        crc32fast::hash(self.pixels.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Framebuffer, PALETTE};
+    use crate::cartridge_file::VsPPUType;
+
+    #[test]
+    pub fn vs_ppu_colors() {
+        let rgb = |color: u32| color.to_be_bytes()[1..].to_vec();
+        for (ppu_type, expected) in [
+            (None, 0x11),
+            (Some(VsPPUType::RC2C05_01), 0x11),
+            (Some(VsPPUType::RP2C04_0001), 0x3e),
+            (Some(VsPPUType::RP2C04_0004), 0x1e),
+        ] {
+            let mut frame = Framebuffer::with_vs_ppu_type(ppu_type);
+            frame.set(0, 0, 0x11);
+            assert_eq!(
+                frame.to_rgb()[..3],
+                rgb(PALETTE[expected]),
+                "{:?}",
+                ppu_type
+            );
+            // the hash is of the indices, whatever colors they are
+            assert_eq!(frame.hash(), {
+                let mut frame = Framebuffer::new();
+                frame.set(0, 0, 0x11);
+                frame.hash()
+            });
+        }
+    }
+}
@@ -9,7 +9,10 @@ mod test {
         cpu::CPU,
         flags::Flags,
         memory::Memory,
-        test_utils::{self, test::TestResults},
+        test_utils::{
+            self,
+            test::{TestMemory, TestResults},
+        },
     };
 
     #[derive(Debug, Deserialize)]
@@ -54,26 +57,6 @@ mod test {
         Write,
     }
 
-    struct TestMemory {
-        data: [u8; 0x10000],
-    }
-
-    impl TestMemory {
-        pub fn new() -> Self {
-            Self { data: [0; 0x10000] }
-        }
-    }
-
-    impl Memory for TestMemory {
-        fn read8(&self, address: u16) -> u8 {
-            self.data[address as usize]
-        }
-
-        fn write8(&mut self, address: u16, value: u8) {
-            self.data[address as usize] = value
-        }
-    }
-
     #[test]
     pub fn test() {
         test_utils::test::init();
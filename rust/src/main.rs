@@ -1,46 +1,884 @@
+mod apu;
+mod archive;
+mod blargg;
 mod cartridge_file;
+mod cartridge_info;
+mod console;
+mod controller;
 mod cpu;
+mod debugger;
+mod disasm;
 mod endians;
+mod fds_file;
 mod flags;
+mod framebuffer;
 mod instruction_set_test_cases;
 mod logging_utils;
 mod memory;
+mod movie;
+mod nsf_file;
+mod nsf_player;
+mod patch;
+mod ppu;
+mod region;
+mod rewind;
+mod rom_database;
+mod rom_loader;
+mod save_state;
+mod screenshot;
 mod test_utils;
+mod trace;
+mod unif_file;
+mod wav;
 
-use cartridge_file::Cartridge;
+use anyhow::Context;
+use apu::Channel;
+use cartridge_file::{Cartridge, HeaderBuilder, NametableArrangement, Strictness, TVSystem};
+use cartridge_info::CartridgeInfo;
+use clap::{Args, Parser, Subcommand};
+use console::Console;
+use debugger::Debugger;
+use fds_file::{Bios, DiskImage};
 use log::*;
 use logging_utils::logger_builder;
-use std::{fs::File, io::Read};
+use movie::Movie;
+use nsf_file::Nsf;
+use nsf_player::Player;
+use region::Region;
+use rewind::Rewind;
+use rom_database::RomDatabase;
+use rom_loader::{read_file, Loader};
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+const SAMPLE_RATE: u32 = 44100;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// Apply game.ips, game.bps or game.ups sitting next to the ROM when loading it, for every command
+    #[arg(long, global = true)]
+    auto_patch: bool,
+
+    /// Which file to load from a .zip, instead of the first .nes, .fds, .nsf or .unf in it
+    #[arg(long, global = true)]
+    zip_entry: Option<String>,
+
+    /// A ROM database to check before the built in one, either NES 2.0 DB's nes20db.xml or the same CSV format as
+    /// src/rom_database.csv
+    #[arg(long, global = true)]
+    rom_database: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prints everything in the header, the ROM hashes and what the ROM database knows about each file
+    Info(InfoArgs),
+    /// Plays a track from an NSF or NSFe music file
+    Nsf(NsfArgs),
+    /// Steps through a cartridge's or an NSF or NSFe file's code in an interactive debugger, type help at the prompt
+    /// for commands
+    Debug(DebugArgs),
+    /// Runs a Famicom Disk System image through the BIOS for a number of frames, printing a hash of each frame like
+    /// run. What the game writes to the disk is kept next to it as .fdsdiff
+    Fds(FdsArgs),
+    /// Rewrites iNES/NES 2.0 headers from the ROM database and/or the given values. Only reports what would change
+    /// unless --in-place or --output is given
+    FixHeader(FixHeaderArgs),
+    /// Runs a cartridge with nothing pressed for a number of frames, printing a hash of each frame
+    Run(RunArgs),
+    /// Checks an FCEUX .fm2 movie was recorded with the ROM and plays it, printing a hash of each frame like run
+    Movie(MovieArgs),
+    /// Runs every .nes file in a directory that reports its result at $6000, like blargg's test ROMs, and prints how
+    /// each did
+    TestRoms(TestRomsArgs),
+}
+
+#[derive(Args)]
+struct InfoArgs {
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Print a JSON array with an object per file instead
+    #[arg(long)]
+    json: bool,
+
+    /// Fail files with unexpected data after everything the header accounts for, instead of just reporting its size
+    #[arg(long)]
+    strict: bool,
+}
+
+#[derive(Args)]
+struct NsfArgs {
+    path: PathBuf,
+
+    /// 1-based track number, defaults to the file's starting track
+    #[arg(long)]
+    track: Option<u8>,
+
+    /// How long to play for
+    #[arg(long, default_value_t = 120.0)]
+    seconds: f64,
+
+    /// Where to render the audio to
+    #[arg(long)]
+    wav: Option<PathBuf>,
+
+    /// Channels to silence, comma separated (pulse1, pulse2, triangle, noise, dmc, expansion)
+    #[arg(long, value_delimiter = ',')]
+    mute: Vec<Channel>,
+
+    /// Channels to play on their own, comma separated
+    #[arg(long, value_delimiter = ',')]
+    solo: Vec<Channel>,
+
+    /// Per-channel volume as channel=gain, comma separated, e.g. triangle=2,noise=0.5
+    #[arg(long, value_delimiter = ',', value_parser = parse_gain)]
+    gain: Vec<(Channel, f32)>,
+
+    /// ntsc, pal or dendy, instead of what the file asks for
+    #[arg(long)]
+    region: Option<Region>,
+
+    /// Where to write a nestest.log style line for every instruction executed
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    /// Numbered save state to start from, kept next to the file as .ss<slot>
+    #[arg(long)]
+    load_state: Option<u8>,
+
+    /// Numbered save state to write once done playing
+    #[arg(long)]
+    save_state: Option<u8>,
+}
+
+#[derive(Args)]
+struct DebugArgs {
+    /// A ROM, or an NSF or NSFe file
+    path: PathBuf,
+
+    /// 1-based track number for NSF files, defaults to the file's starting track
+    #[arg(long)]
+    track: Option<u8>,
+
+    /// ntsc, pal or dendy, instead of what the file asks for
+    #[arg(long)]
+    region: Option<Region>,
+
+    /// Numbered save state to start from, kept next to the file as .ss<slot>
+    #[arg(long)]
+    load_state: Option<u8>,
+}
+
+#[derive(Args)]
+struct FdsArgs {
+    /// .fds file or headerless disk image
+    path: PathBuf,
+
+    /// The disk system's BIOS
+    #[arg(long, default_value = "disksys.rom")]
+    bios: PathBuf,
+
+    /// 0-based disk side to start with inserted
+    #[arg(long, default_value_t = 0)]
+    side: usize,
+
+    #[arg(long, default_value_t = 600)]
+    frames: u64,
+
+    /// Sides to flip to as frame=side, comma separated. The disk comes out on the frame and the side goes in a second
+    /// later, so the game sees the drive empty in between
+    #[arg(long, value_delimiter = ',', value_parser = parse_swap)]
+    swap: Vec<(u64, usize)>,
+
+    /// Where to save the last frame, as a PNG if it ends in .png and a PPM otherwise
+    #[arg(long)]
+    screenshot: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    rom: PathBuf,
+
+    #[arg(long, default_value_t = 60)]
+    frames: u64,
+
+    /// Where to save the last frame, as a PNG if it ends in .png and a PPM otherwise
+    #[arg(long)]
+    screenshot: Option<PathBuf>,
+
+    /// ntsc, pal or dendy, instead of what the header asks for
+    #[arg(long)]
+    region: Option<Region>,
+
+    /// Numbered save state to start from, kept next to the ROM as .ss<slot>
+    #[arg(long)]
+    load_state: Option<u8>,
+
+    /// Numbered save state to write once the frames have run
+    #[arg(long)]
+    save_state: Option<u8>,
+
+    /// Once the frames have run, go back this many with rewind and run them again, checking they come out the same
+    #[arg(long)]
+    rewind: Option<u64>,
+
+    /// Frames rewind keeps snapshots going back, at least
+    #[arg(long, default_value_t = 600)]
+    rewind_length: u64,
+
+    /// Frames between rewind snapshots, going back to between two runs forward from the one before
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    rewind_interval: u64,
+
+    /// MiB rewind snapshots can use, the oldest go first
+    #[arg(long, default_value_t = 64)]
+    rewind_budget: usize,
+
+    /// Write the run out as an .fm2 movie with nothing pressed, which the movie command should play back with the
+    /// same hashes
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Vs. System DIP switches as a number, switch 1 is bit 0
+    #[arg(long)]
+    dip: Option<u8>,
+
+    /// Vs. System frames to put a coin in slot 1 at the start of, comma separated
+    #[arg(long, value_delimiter = ',')]
+    coin: Vec<u64>,
+
+    /// Hold the Vs. System service button down the whole time
+    #[arg(long)]
+    service: bool,
+
+    /// Refuse ROMs with unexpected data after everything the header accounts for
+    #[arg(long)]
+    strict: bool,
+}
+
+#[derive(Args)]
+struct MovieArgs {
+    rom: PathBuf,
+
+    /// The .fm2 file
+    movie: PathBuf,
+
+    /// Play it even if it was recorded with a different ROM
+    #[arg(long)]
+    ignore_checksum: bool,
+
+    /// Where to save the last frame, as a PNG if it ends in .png and a PPM otherwise
+    #[arg(long)]
+    screenshot: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct TestRomsArgs {
+    path: PathBuf,
+
+    /// Emulated seconds to give each ROM before giving up on it
+    #[arg(long, default_value_t = 60.0)]
+    timeout: f64,
+}
+
+#[derive(Args)]
+struct FixHeaderArgs {
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Take corrections from the ROM database, before any of the values below
+    #[arg(long)]
+    database: bool,
+
+    #[arg(long)]
+    mapper: Option<u16>,
+
+    #[arg(long)]
+    submapper: Option<u8>,
+
+    /// h, v or 4
+    #[arg(long)]
+    mirroring: Option<NametableArrangement>,
+
+    #[arg(long)]
+    battery: Option<bool>,
+
+    /// ntsc, pal, both or dendy
+    #[arg(long)]
+    region: Option<TVSystem>,
+
+    /// Write an iNES 1.0 header rather than NES 2.0
+    #[arg(long, conflicts_with = "nes_2_0")]
+    ines: bool,
+
+    /// Write an NES 2.0 header even if the file has an iNES 1.0 one
+    #[arg(long)]
+    nes_2_0: bool,
+
+    /// Overwrite the files
+    #[arg(long, conflicts_with = "output")]
+    in_place: bool,
+
+    /// Where to write the fixed file, only for a single file
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
 
 fn main() -> anyhow::Result<()> {
     logger_builder().init();
 
-    let mut f = File::open("/home/jeff/scratch/emulation/nes/Super Mario Bros. (Japan, USA).nes")?;
-    let mut buffer = Vec::new();
-    f.read_to_end(&mut buffer)?;
-    let cartridge = Cartridge::from_bytes(buffer)?;
-    info!("memory mapper = {:?}", cartridge.header().memory_mapper());
+    let cli = Cli::parse();
+    let mut database = RomDatabase::embedded();
+    if let Some(path) = &cli.rom_database {
+        database = RomDatabase::parse_any(&std::fs::read_to_string(path)?)
+            .with_context(|| format!("reading {:?}", path))?
+            .with_fallback(database);
+    }
+    let loader = Loader::new(cli.zip_entry, cli.auto_patch, database);
+    match cli.command {
+        Command::Info(args) => info(args, &loader),
+        Command::Nsf(args) => nsf(args, &loader),
+        Command::Debug(args) => debug(args, &loader),
+        Command::Fds(args) => fds(args, &loader),
+        Command::Run(args) => run(args, &loader),
+        Command::Movie(args) => movie(args, &loader),
+        Command::TestRoms(args) => test_roms(args, &loader),
+        Command::FixHeader(args) => fix_header(args, &loader),
+    }
+}
+
+fn nsf(args: NsfArgs, loader: &Loader) -> anyhow::Result<()> {
+    let mut player = nsf_player(&args.path, args.track, args.region, loader)?;
+    for channel in args.mute {
+        player.apu_mut().set_muted(channel, true);
+    }
+    for channel in args.solo {
+        player.apu_mut().set_soloed(channel, true);
+    }
+    for (channel, gain) in args.gain {
+        player.apu_mut().set_gain(channel, gain);
+    }
+    if let Some(path) = &args.trace {
+        player.set_trace(Box::new(BufWriter::new(File::create(path)?)));
+    }
+    if let Some(slot) = args.load_state {
+        load_state(&args.path, slot, |x| player.load_state(x))?;
+    }
+    player.run((args.seconds * player.region().cpu_clock_rate()) as u64)?;
+    let samples = player.take_samples();
+    if let Some(slot) = args.save_state {
+        save_state(&args.path, slot, &player.save_state())?;
+    }
+
+    if let Some(path) = args.wav {
+        let mut w = BufWriter::new(File::create(&path)?);
+        wav::write(&mut w, SAMPLE_RATE, &samples)?;
+        info!("wrote {} samples to {:?}", samples.len(), path);
+    }
+
+    Ok(())
+}
+
+/// Cartridges get watchpoints on the PPU's bus too, and see sprite and DMC DMA reads on the CPU's.
+fn debug(args: DebugArgs, loader: &Loader) -> anyhow::Result<()> {
+    if nsf_file::is_nsf(&loader.read(&args.path)?) {
+        let mut player = nsf_player(&args.path, args.track, args.region, loader)?;
+        if let Some(slot) = args.load_state {
+            load_state(&args.path, slot, |x| player.load_state(x))?;
+        }
+        Debugger::new().repl(&mut player, std::io::stdin().lock(), std::io::stdout())?;
+        return Ok(());
+    }
+
+    if args.track.is_some() {
+        anyhow::bail!("--track is only for NSF files");
+    }
+    let cartridge = loader.cartridge(&args.path)?;
+    let mut console = Console::new(&cartridge, SAMPLE_RATE, args.region)?;
+    info!("region = {:?}", console.region());
+    if let Some(slot) = args.load_state {
+        load_state(&args.path, slot, |x| console.load_state(x))?;
+    }
+    Debugger::new().repl(&mut console, std::io::stdin().lock(), std::io::stdout())?;
+    Ok(())
+}
+
+/// Reads a slot and hands it to whatever's loading it.
+fn load_state<F>(path: &Path, slot: u8, load: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&[u8]) -> Result<(), save_state::SaveStateError>,
+{
+    let slot_path = save_state::slot_path(path, slot);
+    let state =
+        save_state::read_slot(path, slot).with_context(|| format!("reading {:?}", slot_path))?;
+    load(&state).with_context(|| format!("loading {:?}", slot_path))?;
+    info!("loaded state {}", slot);
+    Ok(())
+}
+
+fn save_state(path: &Path, slot: u8, state: &[u8]) -> anyhow::Result<()> {
+    save_state::write_slot(path, slot, state)
+        .with_context(|| format!("writing {:?}", save_state::slot_path(path, slot)))?;
+    info!("saved state {}", slot);
+    Ok(())
+}
+
+/// Loads the file and gets the player ready to run the track's INIT.
+fn nsf_player(
+    path: &Path,
+    track: Option<u8>,
+    region: Option<Region>,
+    loader: &Loader,
+) -> anyhow::Result<Player> {
+    let nsf = Nsf::from_bytes(loader.read(path)?)?;
+    info!("title = {:?}", nsf.title());
+    info!("artist = {:?}", nsf.artist());
+    info!("copyright = {:?}", nsf.copyright());
     info!(
-        "nametable arragement = {:?}",
-        cartridge.header().nametable_arrangement()
+        "songs = {}, starting song = {}",
+        nsf.total_songs(),
+        nsf.starting_song()
     );
     info!(
-        "pgr rom size = {:?}, {} bytes",
-        cartridge.header().prg_rom_size(),
-        cartridge.header().prg_rom_size().in_bytes()
+        "load = {:04x}, init = {:04x}, play = {:04x}, bankswitched = {}",
+        nsf.load_address(),
+        nsf.init_address(),
+        nsf.play_address(),
+        nsf.bankswitch_init().is_some()
     );
     info!(
-        "chr rom size = {:?}, {} bytes",
-        cartridge.header().chr_rom_size(),
-        cartridge.header().chr_rom_size().in_bytes()
+        "tv system = {:?}, ntsc play speed = {}us, pal play speed = {}us",
+        nsf.tv_system(),
+        nsf.ntsc_play_speed(),
+        nsf.pal_play_speed()
     );
+    info!("expansion audio = {:?}", nsf.expansion_audio());
+
+    let track = track.unwrap_or(nsf.starting_song());
+    if track == 0 || track > nsf.total_songs() {
+        anyhow::bail!(
+            "track {} out of range, file has {} tracks",
+            track,
+            nsf.total_songs()
+        );
+    }
+    match nsf.track_label(track) {
+        Some(label) => info!("playing track {}, {:?}", track, label),
+        None => info!("playing track {}", track),
+    }
+
+    let mut player = Player::new(&nsf, SAMPLE_RATE, region);
+    info!("region = {:?}", player.region());
+    player.start(track - 1);
+    Ok(player)
+}
+
+fn fds(args: FdsArgs, loader: &Loader) -> anyhow::Result<()> {
+    let image = DiskImage::from_bytes(&loader.read(&args.path)?)?;
+    let bios = Bios::from_bytes(&read_file(&args.bios)?)?;
+    let mut console = Console::new_fds(image, bios, SAMPLE_RATE, None);
+    let disk_system = console.disk_system().expect("a disk");
+    let side_count = disk_system.side_count();
+    info!("sides = {}", side_count);
+    for side in std::iter::once(args.side).chain(args.swap.iter().map(|(_, side)| *side)) {
+        if side >= side_count {
+            anyhow::bail!("side {} out of range, disk has {} sides", side, side_count);
+        }
+    }
+
+    // anything written to the disk lives next to it rather than in it
+    let diff_path = args.path.with_extension("fdsdiff");
+    if diff_path.exists() {
+        disk_system.apply_diff(&read_file(&diff_path)?)?;
+        info!("applied {:?}", diff_path);
+    }
+    disk_system.insert(args.side);
+
+    let swap_frames = console.region().frame_rate().round() as u64;
+    let mut pending = None;
+    for number in 0..args.frames {
+        let disk_system = console.disk_system().expect("a disk");
+        if let Some((_, side)) = args.swap.iter().find(|(frame, _)| *frame == number) {
+            disk_system.eject();
+            info!("frame {}: ejected the disk", number);
+            pending = Some((number + swap_frames, *side));
+        }
+        if let Some((_, side)) = pending.filter(|(frame, _)| *frame == number) {
+            disk_system.insert(side);
+            info!(
+                "frame {}: inserted side {:?}",
+                number,
+                disk_system.inserted_side()
+            );
+            pending = None;
+        }
+        console.run_frame();
+        print_frame(&mut console, number);
+    }
+    if let Some(path) = &args.screenshot {
+        write_screenshot(path, &console)?;
+    }
+
+    let diff = console.disk_system().expect("a disk").diff();
+    if diff.len() > fds_file::DIFF_MAGIC.len() {
+        std::fs::write(&diff_path, diff)?;
+        info!("wrote {:?}", diff_path);
+    }
+
+    Ok(())
+}
+
+fn run(args: RunArgs, loader: &Loader) -> anyhow::Result<()> {
+    let cartridge = loader.cartridge_with_strictness(&args.rom, strictness(args.strict))?;
+    let mut console = Console::new(&cartridge, SAMPLE_RATE, args.region)?;
+    info!("region = {:?}", console.region());
+    let mut start = None;
+    if let Some(slot) = args.load_state {
+        load_state(&args.rom, slot, |x| console.load_state(x))?;
+        start = Some(console.save_state());
+    }
+    let cabinet = args.dip.is_some() || args.service;
+    match console.vs_system() {
+        Some(vs_system) => {
+            if let Some(dip_switches) = args.dip {
+                vs_system.set_dip_switches(dip_switches);
+            }
+            if args.service {
+                vs_system.set_service_button(true);
+            }
+        }
+        None if cabinet || !args.coin.is_empty() => {
+            anyhow::bail!("--dip, --coin and --service are only for Vs. System cartridges")
+        }
+        None => {}
+    }
+    // .fm2 has nowhere for the DIP switches, so they go in a state too
+    if cabinet {
+        start = Some(console.save_state());
+    }
+    // starting from a state, the movie embeds it
+    let mut movie = Movie::new(
+        &args.rom.file_name().unwrap_or_default().to_string_lossy(),
+        movie::rom_checksum(&cartridge),
+        console.region(),
+        start,
+    );
+    let mut rewind = args.rewind.map(|_| {
+        Rewind::new(
+            args.rewind_length,
+            args.rewind_interval,
+            args.rewind_budget << 20,
+        )
+    });
+    let mut hashes = Vec::new();
+    for number in 0..args.frames {
+        let mut frame = movie::Frame::default();
+        if args.coin.contains(&number) {
+            frame.commands |= movie::Commands::VS_INSERT_COIN;
+        }
+        movie.record(frame);
+        match &mut rewind {
+            Some(rewind) => rewind.run_frame(&mut console, frame)?,
+            None => {
+                frame.apply(&mut console);
+                console.run_frame();
+            }
+        }
+        hashes.push(print_frame(&mut console, number));
+    }
+    if let (Some(frames), Some(rewind)) = (args.rewind, &mut rewind) {
+        if frames > rewind.available() {
+            warn!(
+                "only {} frames are kept, going back that far",
+                rewind.available()
+            );
+        }
+        let back = rewind.rewind(&mut console, frames)?;
+        for number in (args.frames - back)..args.frames {
+            rewind.run_frame(&mut console, movie.frames()[number as usize])?;
+            console.take_samples();
+            let hash = console.framebuffer().hash();
+            if hash != hashes[number as usize] {
+                anyhow::bail!(
+                    "frame {} came out as {:08x} after rewinding rather than {:08x}",
+                    number,
+                    hash,
+                    hashes[number as usize]
+                );
+            }
+        }
+        info!(
+            "went back {} frames and ran them again the same, {} bytes of snapshots",
+            back,
+            rewind.size()
+        );
+    }
+    if let Some(vs_system) = console.vs_system() {
+        info!("coins counted = {}", vs_system.coins_counted());
+    }
+    if let Some(path) = &args.screenshot {
+        write_screenshot(path, &console)?;
+    }
+    if let Some(slot) = args.save_state {
+        save_state(&args.rom, slot, &console.save_state())?;
+    }
+    if let Some(path) = &args.record {
+        std::fs::write(path, movie.to_fm2()).with_context(|| format!("writing {:?}", path))?;
+        info!("recorded {} frames to {:?}", args.frames, path);
+    }
+    Ok(())
+}
+
+/// Prints the hash of the frame just run, given its number as there's no telling how a power cycle numbers them.
+fn print_frame(console: &mut Console, number: u64) -> u32 {
+    // there's nowhere for the sound to go
+    console.take_samples();
+    let hash = console.framebuffer().hash();
+    println!("frame {}: {:08x}", number, hash);
+    hash
+}
+
+fn write_screenshot(path: &Path, console: &Console) -> anyhow::Result<()> {
+    screenshot::write_file(path, console.framebuffer())
+        .with_context(|| format!("writing {:?}", path))?;
+    info!("saved the last frame to {:?}", path);
+    Ok(())
+}
+
+fn movie(args: MovieArgs, loader: &Loader) -> anyhow::Result<()> {
+    let cartridge = loader.cartridge(&args.rom)?;
+    let movie = Movie::from_fm2(&std::fs::read_to_string(&args.movie)?)
+        .with_context(|| format!("reading {:?}", args.movie))?;
     info!(
-        "pgr ram size = {:?}, {} bytes",
-        cartridge.header().prg_ram_size(),
-        cartridge.header().prg_ram_size().in_bytes()
+        "{} frames recorded with {:?}",
+        movie.frames().len(),
+        movie.rom_filename()
     );
+    if let Err(e) = movie.check_rom(movie::rom_checksum(&cartridge)) {
+        if !args.ignore_checksum {
+            return Err(e)
+                .context("recorded with a different ROM, use --ignore-checksum to play it anyway");
+        }
+        warn!("{}, it will most likely desync", e);
+    }
 
-    let (main_memory, video_memory) = memory::new(&cartridge);
+    let region = movie.region();
+    info!("region = {:?}", region);
+    let mut console = Console::new(&cartridge, SAMPLE_RATE, Some(region))?;
+    if let Some(state) = movie.save_state() {
+        console
+            .load_state(state)
+            .context("loading the state the movie starts from")?;
+    }
+    for (number, frame) in movie.frames().iter().enumerate() {
+        if !frame.commands.is_empty() {
+            info!("frame {}: {:?}", number, frame.commands);
+        }
+        if frame.commands.contains(movie::Commands::POWER) {
+            console = Console::new(&cartridge, SAMPLE_RATE, Some(region))?;
+        }
+        let unsupported = frame.commands
+            - movie::Commands::RESET
+            - movie::Commands::POWER
+            - movie::Commands::VS_INSERT_COIN;
+        if !unsupported.is_empty() {
+            warn!("frame {}: {:?} isn't supported", number, unsupported);
+        }
+        frame.apply(&mut console);
+        console.run_frame();
+        print_frame(&mut console, number as u64);
+    }
+    if let Some(path) = &args.screenshot {
+        write_screenshot(path, &console)?;
+    }
+    Ok(())
+}
+
+fn test_roms(args: TestRomsArgs, loader: &Loader) -> anyhow::Result<()> {
+    let paths =
+        blargg::rom_paths(&args.path).with_context(|| format!("reading {:?}", args.path))?;
+    let mut failed = 0;
+    for path in &paths {
+        let outcome = loader
+            .cartridge(path)
+            .and_then(|cartridge| Ok(blargg::run(&cartridge, args.timeout)?));
+        match outcome {
+            Ok(outcome) => {
+                println!("{}\n  {}", path.display(), outcome);
+                if !outcome.passed() {
+                    failed += 1;
+                }
+            }
+            Err(e) => {
+                println!("{}\n  error = {}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} failed", failed, paths.len());
+    }
+    Ok(())
+}
+
+fn fix_header(args: FixHeaderArgs, loader: &Loader) -> anyhow::Result<()> {
+    if args.output.is_some() && args.paths.len() > 1 {
+        anyhow::bail!("--output only works with a single file");
+    }
+    if args.in_place && loader.auto_patch() {
+        anyhow::bail!("--auto-patch would write the patch in, use --output rather than --in-place");
+    }
+    for path in &args.paths {
+        if args.in_place && archive::is_archive(&read_file(path)?) {
+            anyhow::bail!(
+                "{:?} is compressed, use --output rather than --in-place",
+                path
+            );
+        }
+        let original = loader.read(path)?;
+        let mut cartridge = Cartridge::from_bytes(original.clone())
+            .with_context(|| format!("reading {:?}", path))?;
+
+        if args.database {
+            let (entry, corrections) = loader.database().correct(&mut cartridge);
+            match entry {
+                Some(entry) => info!("{:?}: found in rom database as {:?}", path, entry.name()),
+                None => info!("{:?}: not in rom database", path),
+            }
+            for correction in corrections {
+                info!("{:?}: {}", path, correction);
+            }
+        }
+
+        let mut builder = HeaderBuilder::from_header(cartridge.header());
+        if args.mapper.is_some() || args.submapper.is_some() {
+            builder = builder.mapper(
+                args.mapper.unwrap_or(cartridge.header().mapper_number()),
+                args.submapper.unwrap_or(cartridge.header().submapper()),
+            );
+        }
+        if let Some(mirroring) = args.mirroring {
+            builder = builder.nametable_arrangement(mirroring);
+        }
+        if let Some(battery) = args.battery {
+            builder = builder.battery(battery);
+        }
+        if let Some(region) = args.region {
+            builder = builder.tv_system(region);
+        }
+        if args.ines {
+            builder = builder.nes_2_0(false);
+        } else if args.nes_2_0 {
+            builder = builder.nes_2_0(true);
+        }
+        cartridge.set_header(builder.build()?)?;
+
+        let fixed = cartridge.to_bytes()?;
+        if fixed == original {
+            info!("{:?}: header is already clean", path);
+            continue;
+        }
+        info!(
+            "{:?}: header {:02x?} -> {:02x?}",
+            path,
+            &original[0..16],
+            &fixed[0..16]
+        );
+        if let Some(output) = &args.output {
+            std::fs::write(output, &fixed)?;
+            info!("wrote {:?}", output);
+        } else if args.in_place {
+            std::fs::write(path, &fixed)?;
+            info!("wrote {:?}", path);
+        }
+    }
+    Ok(())
+}
+
+fn parse_swap(s: &str) -> Result<(u64, usize), String> {
+    let (frame, side) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected frame=side, got {:?}", s))?;
+    let frame = frame
+        .parse::<u64>()
+        .map_err(|e| format!("bad frame {:?}: {}", frame, e))?;
+    let side = side
+        .parse::<usize>()
+        .map_err(|e| format!("bad side {:?}: {}", side, e))?;
+    Ok((frame, side))
+}
+
+fn parse_gain(s: &str) -> Result<(Channel, f32), String> {
+    let (channel, gain) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected channel=gain, got {:?}", s))?;
+    let gain = gain
+        .parse::<f32>()
+        .map_err(|e| format!("bad gain {:?}: {}", gain, e))?;
+    Ok((channel.parse()?, gain))
+}
+
+fn strictness(strict: bool) -> Strictness {
+    if strict {
+        Strictness::Strict
+    } else {
+        Strictness::Lenient
+    }
+}
 
+/// Keeps going past files that fail to load so a whole directory can be checked at once, but still fails at the end.
+fn info(args: InfoArgs, loader: &Loader) -> anyhow::Result<()> {
+    let mut reports = Vec::new();
+    let mut failures = 0;
+    for path in &args.paths {
+        // not loader.cartridge, the corrections are reported rather than made
+        let result = loader.read(path).and_then(|buffer| {
+            Ok(CartridgeInfo::new(
+                rom_loader::parse_cartridge(buffer, strictness(args.strict))?,
+                loader.database(),
+            ))
+        });
+        if result.is_err() {
+            failures += 1;
+        }
+        if args.json {
+            reports.push(match result {
+                Ok(info) => {
+                    let mut report = serde_json::to_value(info)?;
+                    report["path"] = serde_json::to_value(path)?;
+                    report
+                }
+                Err(e) => serde_json::json!({ "path": path, "error": format!("{:#}", e) }),
+            });
+        } else {
+            match result {
+                Ok(info) => {
+                    println!("{}", path.display());
+                    for line in info.to_string().lines() {
+                        println!("  {}", line);
+                    }
+                }
+                Err(e) => println!("{}\n  error = {:#}", path.display(), e),
+            }
+        }
+    }
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    }
+    if failures > 0 {
+        anyhow::bail!(
+            "{} of {} files couldn't be read",
+            failures,
+            args.paths.len()
+        );
+    }
     Ok(())
 }
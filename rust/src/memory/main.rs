@@ -1,4 +1,11 @@
-use crate::cartridge_file::pgr_rom;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    cartridge_file::{pgr_rom, Trainer, TRAINER_SIZE},
+    controller::{Buttons, Controller},
+    save_state::{self, SaveStateError},
+};
 
 use super::main_mapper::MainMemoryMapper;
 
@@ -13,6 +20,8 @@ const IO_REGISTER_LOWER_START: u16 = 0x2000;
 const IO_REGISTER_LOWER_END: u16 = 0x2008;
 const IO_REGISTER_LOWER_SIZE: u16 = IO_REGISTER_LOWER_END - IO_REGISTER_LOWER_START;
 const IO_REGISTER_MIRROR_END: u16 = 0x4000;
+const CONTROLLER_PORT_1: u16 = 0x4016;
+const CONTROLLER_PORT_2: u16 = 0x4017;
 const IO_REGISTER_UPPER_END: u16 = 0x4020;
 
 const EXPANSION_ROM_END: u16 = 0x6000;
@@ -21,14 +30,36 @@ const SRAM_START: u16 = EXPANSION_ROM_END;
 const SRAM_END: u16 = 0x8000;
 const SRAM_SIZE: u16 = SRAM_END - SRAM_START;
 
+const TRAINER_START: u16 = 0x7000;
+const TRAINER_END: u16 = TRAINER_START + TRAINER_SIZE as u16;
+
 pub const PRG_BANK_SIZE: u16 = pgr_rom::BLOCK_SIZE as u16;
 const PRG_LOWER_BANK_START: u16 = SRAM_END;
 const PRG_LOWER_BANK_END: u16 = PRG_LOWER_BANK_START + PRG_BANK_SIZE;
 
+#[derive(Serialize, Deserialize)]
+struct State {
+    #[serde(with = "save_state::hex")]
+    ram: [u8; TOTAL_RAM_SIZE as usize],
+    #[serde(with = "save_state::hex")]
+    sram: [u8; SRAM_SIZE as usize],
+    #[serde(default)]
+    mapper: Value,
+    #[serde(default)]
+    controllers: [Controller; 2],
+    #[serde(default)]
+    open_bus: u8,
+}
+
+/// The CPU's view of the console, less the PPU and APU registers. Nothing is attached to those here, so like the rest
+/// of open bus they read back whatever was last on the bus and ignore writes.
 pub struct Memory {
     ram: [u8; TOTAL_RAM_SIZE as usize],
     sram: [u8; SRAM_SIZE as usize],
     mapper: Box<dyn MainMemoryMapper>,
+    controllers: [Controller; 2],
+    /// The last value read or written.
+    open_bus: u8,
 }
 
 impl Memory {
@@ -37,28 +68,114 @@ impl Memory {
             ram: [0; TOTAL_RAM_SIZE as usize],
             sram: [0; SRAM_SIZE as usize],
             mapper,
+            controllers: Default::default(),
+            open_bus: 0,
         }
     }
+
+    /// What's held on the controller in the given 0-based port.
+    pub fn set_buttons(&mut self, port: usize, buttons: Buttons) {
+        self.controllers[port].set_buttons(buttons);
+    }
+
+    /// Copies a cartridge's trainer into sram, where it would have been put by the copier hardware.
+    pub fn load_trainer(&mut self, trainer: &Trainer) {
+        self.sram[((TRAINER_START - SRAM_START) as usize)..((TRAINER_END - SRAM_START) as usize)]
+            .copy_from_slice(trainer.data());
+    }
+
+    /// RAM, sram, the mapper's registers and where the controllers are up to.
+    pub fn save_state(&self) -> Value {
+        save_state::to_value(&State {
+            ram: self.ram,
+            sram: self.sram,
+            mapper: self.mapper.save_state(),
+            controllers: self.controllers.clone(),
+            open_bus: self.open_bus,
+        })
+    }
+
+    pub fn load_state(&mut self, state: Value) -> Result<(), SaveStateError> {
+        let state: State = save_state::from_value(state)?;
+        self.ram = state.ram;
+        self.sram = state.sram;
+        self.open_bus = state.open_bus;
+        for (controller, state) in self.controllers.iter_mut().zip(state.controllers) {
+            controller.load_state(state);
+        }
+        self.mapper.load_state(state.mapper)
+    }
+
+    /// Advances the mapper by the given number of CPU cycles.
+    pub fn step_mapper(&mut self, cycles: u64) {
+        self.mapper.step(cycles);
+    }
+
+    pub fn mapper_irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    /// Expansion audio to mix in with the APU's output, from 0 to 1.
+    pub fn expansion_audio(&self) -> f32 {
+        self.mapper.expansion_audio()
+    }
 }
 
 impl super::Memory for Memory {
-    fn read8(&self, address: u16) -> u8 {
+    fn read8(&mut self, address: u16) -> u8 {
+        let value = match address {
+            // ram has no side effects
+            ..RAM_MIRROR_END => self.peek8(address),
+            // io registers
+            ..IO_REGISTER_LOWER_END => self.open_bus,
+            // mirrors io registers
+            ..IO_REGISTER_MIRROR_END => self.read8(
+                (address - IO_REGISTER_LOWER_START) % IO_REGISTER_LOWER_SIZE
+                    + IO_REGISTER_LOWER_START,
+            ),
+            // the cartridge can drive the other bits
+            CONTROLLER_PORT_1 | CONTROLLER_PORT_2 => {
+                self.controllers[(address - CONTROLLER_PORT_1) as usize].read()
+                    | self.mapper.read8_input_port(address)
+            }
+            // io registers
+            ..IO_REGISTER_UPPER_END => self.open_bus,
+            // expansion rom
+            ..EXPANSION_ROM_END => self
+                .mapper
+                .read8_expansion(address)
+                .unwrap_or(self.open_bus),
+            // neither do sram or prg rom
+            _ => self.peek8(address),
+        };
+        self.open_bus = value;
+        value
+    }
+
+    fn peek8(&self, address: u16) -> u8 {
         match address {
             // zero page, stack, ram
             ..TOTAL_RAM_SIZE => self.ram[address as usize],
             // mirrors ram
-            ..RAM_MIRROR_END => self.read8(address % TOTAL_RAM_SIZE),
+            ..RAM_MIRROR_END => self.peek8(address % TOTAL_RAM_SIZE),
             // io registers
-            ..IO_REGISTER_LOWER_END => todo!(),
+            ..IO_REGISTER_LOWER_END => self.open_bus,
             // mirrors io registers
-            ..IO_REGISTER_MIRROR_END => self.read8(
+            ..IO_REGISTER_MIRROR_END => self.peek8(
                 (address - IO_REGISTER_LOWER_START) % IO_REGISTER_LOWER_SIZE
                     + IO_REGISTER_LOWER_START,
             ),
+            CONTROLLER_PORT_1 | CONTROLLER_PORT_2 => {
+                self.controllers[(address - CONTROLLER_PORT_1) as usize].peek()
+                    | self.mapper.peek8_input_port(address)
+            }
             // io registers
-            ..IO_REGISTER_UPPER_END => todo!(),
+            ..IO_REGISTER_UPPER_END => self.open_bus,
             // expansion rom
-            ..EXPANSION_ROM_END => todo!(),
+            ..EXPANSION_ROM_END => self
+                .mapper
+                .peek8_expansion(address)
+                .unwrap_or(self.open_bus),
             // sram = persistent ram for save games
             ..SRAM_END => self.sram[(address - SRAM_START) as usize],
             // prg rom lower bank
@@ -73,23 +190,30 @@ impl super::Memory for Memory {
     }
 
     fn write8(&mut self, address: u16, value: u8) {
+        self.open_bus = value;
         match address {
             // zero page, stack, ram
             ..TOTAL_RAM_SIZE => self.ram[address as usize] = value,
             // mirrors ram
             ..RAM_MIRROR_END => self.write8(address % TOTAL_RAM_SIZE, value),
             // io registers
-            ..IO_REGISTER_LOWER_END => todo!(),
+            ..IO_REGISTER_LOWER_END => (),
             // mirrors io registers
             ..IO_REGISTER_MIRROR_END => self.write8(
                 (address - IO_REGISTER_LOWER_START) % IO_REGISTER_LOWER_SIZE
                     + IO_REGISTER_LOWER_START,
                 value,
             ),
+            CONTROLLER_PORT_1 => {
+                for controller in &mut self.controllers {
+                    controller.write_strobe(value);
+                }
+                self.mapper.write8_input_port(value)
+            }
             // io registers
-            ..IO_REGISTER_UPPER_END => todo!(),
+            ..IO_REGISTER_UPPER_END => (),
             // expansion rom
-            ..EXPANSION_ROM_END => todo!(),
+            ..EXPANSION_ROM_END => self.mapper.write8_expansion(address, value),
             // sram = persistent ram for save games
             ..SRAM_END => self.sram[(address - SRAM_START) as usize] = value,
             // prg rom lower and upper banks
@@ -1,3 +1,7 @@
+use serde_json::Value;
+
+use crate::save_state::SaveStateError;
+
 pub trait MainMemoryMapper {
     /// Reads from whatever bank is set as the lower bank.
     /// Address will be already adjusted to be in 0..PRG_BANK_SIZE.
@@ -10,4 +14,55 @@ pub trait MainMemoryMapper {
     /// registers. Since they all behave differently and don't really have a banking mode just expose the whole range.
     /// Address will be already adjusted to be in 0..(PRG_BANK_SIZE*2).
     fn write8_main(&mut self, address: u16, value: u8);
+
+    /// Reads from $4020-$5FFF, where some mappers have registers or extra memory.
+    /// Returns None for open bus.
+    fn read8_expansion(&mut self, address: u16) -> Option<u8> {
+        self.peek8_expansion(address)
+    }
+
+    /// As read, but mustn't change anything. Only mappers whose registers have read side effects need both.
+    fn peek8_expansion(&self, _address: u16) -> Option<u8> {
+        None
+    }
+
+    /// As read.
+    fn write8_expansion(&mut self, _address: u16, _value: u8) {}
+
+    /// Bits the cartridge puts on $4016 or $4017 alongside the controllers', like the Vs. System's coin slots and DIP
+    /// switches.
+    fn read8_input_port(&mut self, address: u16) -> u8 {
+        self.peek8_input_port(address)
+    }
+
+    /// As read, but mustn't change anything.
+    fn peek8_input_port(&self, _address: u16) -> u8 {
+        0
+    }
+
+    /// Sees writes to $4016, which the Vs. System also uses for bank switching.
+    fn write8_input_port(&mut self, _value: u8) {}
+
+    /// Advances anything on the cartridge that runs off the CPU clock, like IRQ counters and expansion audio.
+    fn step(&mut self, _cycles: u64) {}
+
+    /// Whether the cartridge is asserting the CPU's IRQ line.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Level of any expansion audio on the cartridge, from 0 to 1.
+    fn expansion_audio(&self) -> f32 {
+        0.0
+    }
+
+    /// Registers and RAM on the cartridge, for save states. Boards without any can leave these alone.
+    fn save_state(&self) -> Value {
+        Value::Null
+    }
+
+    /// Given what save_state returned, possibly from an older or newer build.
+    fn load_state(&mut self, _state: Value) -> Result<(), SaveStateError> {
+        Ok(())
+    }
 }
@@ -0,0 +1,708 @@
+// see https://www.nesdev.org/wiki/Family_Computer_Disk_System
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    apu::fds::{self as audio, FdsAudio},
+    fds_file::{update_crc, Bios, DiskImage, FdsError, GAP_END},
+    memory::{
+        main_mapper::MainMemoryMapper, name_attr_tables_mapper::NameAndAttributeTablesMemoryMapper,
+        pattern_tables_mapper::PatternTableMemoryMapper, video,
+    },
+    save_state::{self, SaveStateError},
+};
+
+const TIMER_RELOAD_LOW_ADDRESS: u16 = 0x4020;
+const TIMER_RELOAD_HIGH_ADDRESS: u16 = 0x4021;
+const TIMER_CONTROL_ADDRESS: u16 = 0x4022;
+const MASTER_IO_ENABLE_ADDRESS: u16 = 0x4023;
+const WRITE_DATA_ADDRESS: u16 = 0x4024;
+const CONTROL_ADDRESS: u16 = 0x4025;
+const DISK_STATUS_ADDRESS: u16 = 0x4030;
+const READ_DATA_ADDRESS: u16 = 0x4031;
+const DRIVE_STATUS_ADDRESS: u16 = 0x4032;
+const EXTERNAL_CONNECTOR_ADDRESS: u16 = 0x4033;
+
+/// The drive moves about 96.4 kbit/s past the head, which works out to a byte about every 149 CPU cycles.
+const CYCLES_PER_BYTE: u32 = 150;
+/// Roughly how long the head takes to get back to the start of the disk.
+const HEAD_RETURN_CYCLES: u32 = 50000;
+
+/// The RAM adapter's 32k of PRG-RAM covers $6000-$DFFF, but $6000-$7FFF is already in main memory's sram.
+const RAM_SIZE: usize = 0x6000;
+/// Offset of the BIOS in the upper bank, the rest of the bank is RAM.
+const BIOS_START: u16 = 0x2000;
+const PATTERN_TABLE_SIZE: usize = 0x1000;
+
+/// Registers and disk drive, shared between the memory mappers and whoever is switching disks.
+#[derive(Serialize, Deserialize)]
+struct Adapter {
+    /// The disks are saved through DiskSystem::diff rather than in states, only the inserted side's contents are.
+    #[serde(skip)]
+    original: DiskImage,
+    #[serde(skip)]
+    image: DiskImage,
+    /// Inserted side, and its contents as the drive sees them.
+    inserted: Option<(usize, Vec<u8>)>,
+    disk_registers_enabled: bool,
+    sound_registers_enabled: bool,
+
+    timer_reload: u16,
+    timer_counter: u16,
+    timer_repeat: bool,
+    timer_enabled: bool,
+    timer_irq: bool,
+
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    horizontal_mirroring: bool,
+    crc_control: bool,
+    previous_crc_control: bool,
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+
+    position: usize,
+    delay: u32,
+    scanning: bool,
+    end_of_head: bool,
+    gap_ended: bool,
+    crc: u16,
+    read_data: u8,
+    write_data: u8,
+    transfer_complete: bool,
+    disk_irq: bool,
+
+    audio: FdsAudio,
+}
+
+impl Adapter {
+    fn new(image: DiskImage) -> Self {
+        Self {
+            original: image.clone(),
+            inserted: Some((0, image.raw_side(0))),
+            image,
+            disk_registers_enabled: false,
+            sound_registers_enabled: false,
+            timer_reload: 0,
+            timer_counter: 0,
+            timer_repeat: false,
+            timer_enabled: false,
+            timer_irq: false,
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            horizontal_mirroring: false,
+            crc_control: false,
+            previous_crc_control: false,
+            disk_ready: false,
+            disk_irq_enabled: false,
+            position: 0,
+            delay: 0,
+            scanning: false,
+            end_of_head: true,
+            gap_ended: false,
+            crc: 0,
+            read_data: 0,
+            write_data: 0,
+            transfer_complete: false,
+            disk_irq: false,
+            audio: FdsAudio::new(),
+        }
+    }
+
+    /// Takes everything from the state but the disks.
+    fn load_state(&mut self, state: Self) {
+        *self = Self {
+            original: std::mem::take(&mut self.original),
+            image: std::mem::take(&mut self.image),
+            ..state
+        };
+    }
+
+    /// Puts whatever was written to the inserted side back into the image.
+    fn flush(&mut self) {
+        if let Some((side, raw)) = &self.inserted {
+            self.image.set_raw_side(*side, raw);
+        }
+    }
+
+    /// Reading the status or the data acknowledges the IRQs.
+    fn read8(&mut self, address: u16) -> Option<u8> {
+        let result = self.peek8(address);
+        match address {
+            DISK_STATUS_ADDRESS => {
+                self.timer_irq = false;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            READ_DATA_ADDRESS => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            _ => (),
+        }
+        result
+    }
+
+    fn peek8(&self, address: u16) -> Option<u8> {
+        match address {
+            DISK_STATUS_ADDRESS => Some(
+                (if self.timer_irq { 0b0000_0001 } else { 0 })
+                    | (if self.transfer_complete {
+                        0b0000_0010
+                    } else {
+                        0
+                    })
+                    | (if self.crc != 0 { 0b0001_0000 } else { 0 })
+                    | (if self.end_of_head { 0b0100_0000 } else { 0 }),
+            ),
+            READ_DATA_ADDRESS => Some(self.read_data),
+            DRIVE_STATUS_ADDRESS => Some(match self.inserted {
+                // no disk, not ready, write protected
+                None => 0b0100_0111,
+                Some(_) if !self.scanning => 0b0100_0010,
+                Some(_) => 0b0100_0000,
+            }),
+            // battery is good
+            EXTERNAL_CONNECTOR_ADDRESS => Some(0b1000_0000),
+            audio::WAVETABLE_START..audio::REGISTERS_END => self.audio.read8(address),
+            _ => None,
+        }
+    }
+
+    fn write8(&mut self, address: u16, value: u8) {
+        match address {
+            TIMER_RELOAD_LOW_ADDRESS => {
+                self.timer_reload = (self.timer_reload & 0xff00) | (value as u16)
+            }
+            TIMER_RELOAD_HIGH_ADDRESS => {
+                self.timer_reload = (self.timer_reload & 0x00ff) | ((value as u16) << 8)
+            }
+            TIMER_CONTROL_ADDRESS if self.disk_registers_enabled => {
+                self.timer_repeat = (value & 0b0000_0001) != 0;
+                self.timer_enabled = (value & 0b0000_0010) != 0;
+                if self.timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            MASTER_IO_ENABLE_ADDRESS => {
+                self.disk_registers_enabled = (value & 0b0000_0001) != 0;
+                self.sound_registers_enabled = (value & 0b0000_0010) != 0;
+                if !self.disk_registers_enabled {
+                    self.timer_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            WRITE_DATA_ADDRESS if self.disk_registers_enabled => {
+                self.write_data = value;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            CONTROL_ADDRESS if self.disk_registers_enabled => {
+                self.motor_on = (value & 0b0000_0001) != 0;
+                self.reset_transfer = (value & 0b0000_0010) != 0;
+                self.read_mode = (value & 0b0000_0100) != 0;
+                self.horizontal_mirroring = (value & 0b0000_1000) != 0;
+                self.crc_control = (value & 0b0001_0000) != 0;
+                self.disk_ready = (value & 0b0100_0000) != 0;
+                self.disk_irq_enabled = (value & 0b1000_0000) != 0;
+                self.disk_irq = false;
+            }
+            audio::WAVETABLE_START..audio::REGISTERS_END if self.sound_registers_enabled => {
+                self.audio.write8(address, value);
+            }
+            // $4026 is the expansion port, which nothing is plugged into
+            _ => (),
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    fn clock(&mut self) {
+        if self.timer_enabled {
+            if self.timer_counter == 0 {
+                self.timer_irq = true;
+                self.timer_counter = self.timer_reload;
+                if !self.timer_repeat {
+                    self.timer_enabled = false;
+                }
+            } else {
+                self.timer_counter -= 1;
+            }
+        }
+        self.audio.clock();
+        self.clock_drive();
+    }
+
+    fn clock_drive(&mut self) {
+        let Some((_, raw)) = &mut self.inserted else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+        if !self.motor_on {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = HEAD_RETURN_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        if self.read_mode {
+            let value = raw[self.position];
+            self.crc = update_crc(self.crc, value);
+            let mut irq = self.disk_irq_enabled;
+            if !self.disk_ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if value == GAP_END && !self.gap_ended {
+                // the gap end mark comes through, but without an interrupt
+                self.gap_ended = true;
+                irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = value;
+                self.disk_irq |= irq;
+            }
+        } else {
+            let mut value = self.write_data;
+            if !self.crc_control {
+                self.transfer_complete = true;
+                self.disk_irq |= self.disk_irq_enabled;
+            }
+            if !self.disk_ready {
+                value = 0;
+            }
+            if !self.crc_control {
+                self.crc = update_crc(self.crc, value);
+            } else {
+                if !self.previous_crc_control {
+                    self.crc = update_crc(update_crc(self.crc, 0), 0);
+                }
+                value = self.crc as u8;
+                self.crc >>= 8;
+            }
+            raw[self.position] = value;
+            self.gap_ended = false;
+        }
+        self.previous_crc_control = self.crc_control;
+
+        self.position += 1;
+        if self.position >= raw.len() {
+            self.end_of_head = true;
+            self.scanning = false;
+        } else {
+            self.delay = CYCLES_PER_BYTE - 1;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+}
+
+/// For swapping disks and saving what's been written to them.
+pub struct DiskSystem {
+    adapter: Rc<RefCell<Adapter>>,
+}
+
+impl DiskSystem {
+    pub fn side_count(&self) -> usize {
+        self.adapter.borrow().image.side_count()
+    }
+
+    pub fn inserted_side(&self) -> Option<usize> {
+        self.adapter
+            .borrow()
+            .inserted
+            .as_ref()
+            .map(|(side, _)| *side)
+    }
+
+    /// Games only notice a side change if they see the drive empty first, so eject and give them a moment before
+    /// inserting the next side.
+    pub fn insert(&self, side: usize) {
+        let mut adapter = self.adapter.borrow_mut();
+        adapter.flush();
+        let raw = adapter.image.raw_side(side);
+        adapter.inserted = Some((side, raw));
+        adapter.end_of_head = true;
+        adapter.scanning = false;
+    }
+
+    /// Applies a diff saved from an earlier run, before the drive's read anything. Diffs are still taken against the
+    /// image as it was loaded, so they cover every run's writes.
+    pub fn apply_diff(&self, diff: &[u8]) -> Result<(), FdsError> {
+        let mut adapter = self.adapter.borrow_mut();
+        let Adapter {
+            image, inserted, ..
+        } = &mut *adapter;
+        image.apply_diff(diff)?;
+        if let Some((side, raw)) = inserted {
+            *raw = image.raw_side(*side);
+        }
+        Ok(())
+    }
+
+    pub fn eject(&self) {
+        let mut adapter = self.adapter.borrow_mut();
+        adapter.flush();
+        adapter.inserted = None;
+    }
+
+    /// Everything written to the disk since it was loaded, see `DiskImage::diff`.
+    pub fn diff(&self) -> Vec<u8> {
+        let mut adapter = self.adapter.borrow_mut();
+        adapter.flush();
+        adapter.image.diff(&adapter.original)
+    }
+}
+
+pub struct Main {
+    ram: Box<[u8; RAM_SIZE]>,
+    bios: Bios,
+    adapter: Rc<RefCell<Adapter>>,
+}
+
+impl MainMemoryMapper for Main {
+    fn read8_main_lower_bank(&self, address: u16) -> u8 {
+        self.ram[address as usize]
+    }
+
+    fn read8_main_upper_bank(&self, address: u16) -> u8 {
+        if address < BIOS_START {
+            self.ram[(address as usize) + RAM_SIZE - (BIOS_START as usize)]
+        } else {
+            self.bios.data()[(address - BIOS_START) as usize]
+        }
+    }
+
+    fn write8_main(&mut self, address: u16, value: u8) {
+        // the BIOS is ROM
+        if (address as usize) < RAM_SIZE {
+            self.ram[address as usize] = value;
+        }
+    }
+
+    fn read8_expansion(&mut self, address: u16) -> Option<u8> {
+        self.adapter.borrow_mut().read8(address)
+    }
+
+    fn peek8_expansion(&self, address: u16) -> Option<u8> {
+        self.adapter.borrow().peek8(address)
+    }
+
+    fn write8_expansion(&mut self, address: u16, value: u8) {
+        self.adapter.borrow_mut().write8(address, value);
+    }
+
+    fn step(&mut self, cycles: u64) {
+        let mut adapter = self.adapter.borrow_mut();
+        for _ in 0..cycles {
+            adapter.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.adapter.borrow().irq()
+    }
+
+    fn expansion_audio(&self) -> f32 {
+        self.adapter.borrow().audio.output()
+    }
+
+    fn save_state(&self) -> Value {
+        serde_json::json!({
+            "ram": save_state::hex::encode(&self.ram[..]),
+            "adapter": save_state::to_value(&*self.adapter.borrow()),
+        })
+    }
+
+    fn load_state(&mut self, state: Value) -> Result<(), SaveStateError> {
+        #[derive(Deserialize)]
+        struct State {
+            #[serde(with = "save_state::hex")]
+            ram: Box<[u8; RAM_SIZE]>,
+            adapter: Adapter,
+        }
+
+        let state: State = save_state::from_value(state)?;
+        self.ram = state.ram;
+        self.adapter.borrow_mut().load_state(state.adapter);
+        Ok(())
+    }
+}
+
+/// 8k of CHR-RAM.
+#[derive(Serialize, Deserialize)]
+pub struct PatternTable {
+    #[serde(with = "save_state::hex")]
+    pattern_table_0: [u8; PATTERN_TABLE_SIZE],
+    #[serde(with = "save_state::hex")]
+    pattern_table_1: [u8; PATTERN_TABLE_SIZE],
+}
+
+impl PatternTableMemoryMapper for PatternTable {
+    fn save_state(&self) -> Value {
+        save_state::to_value(self)
+    }
+
+    fn load_state(&mut self, state: Value) -> Result<(), SaveStateError> {
+        *self = save_state::from_value(state)?;
+        Ok(())
+    }
+
+    fn peek8_pattern_table_0(&self, address: u16) -> u8 {
+        self.pattern_table_0[address as usize]
+    }
+
+    fn write8_pattern_table_0(&mut self, address: u16, value: u8) {
+        self.pattern_table_0[address as usize] = value;
+    }
+
+    fn peek8_pattern_table_1(&self, address: u16) -> u8 {
+        self.pattern_table_1[address as usize]
+    }
+
+    fn write8_pattern_table_1(&mut self, address: u16, value: u8) {
+        self.pattern_table_1[address as usize] = value;
+    }
+}
+
+/// Mirroring is set through $4025 rather than being wired on the board.
+pub struct NameAndAttributeTable {
+    name_tables: [[u8; video::NAME_TABLE_SIZE as usize]; 2],
+    attribute_tables: [[u8; video::ATTRIBUTE_TABLE_SIZE as usize]; 2],
+    adapter: Rc<RefCell<Adapter>>,
+}
+
+impl NameAndAttributeTable {
+    /// Which of the two physical tables a logical one maps to.
+    fn table(&self, table: usize) -> usize {
+        if self.adapter.borrow().horizontal_mirroring {
+            table >> 1
+        } else {
+            table & 1
+        }
+    }
+
+    fn read8_name_table(&self, table: usize, address: u16) -> u8 {
+        self.name_tables[self.table(table)][address as usize]
+    }
+
+    fn write8_name_table(&mut self, table: usize, address: u16, value: u8) {
+        let table = self.table(table);
+        self.name_tables[table][address as usize] = value;
+    }
+
+    fn read8_attribute_table(&self, table: usize, address: u16) -> u8 {
+        self.attribute_tables[self.table(table)][address as usize]
+    }
+
+    fn write8_attribute_table(&mut self, table: usize, address: u16, value: u8) {
+        let table = self.table(table);
+        self.attribute_tables[table][address as usize] = value;
+    }
+}
+
+/// Both physical tables one after the other, mirroring is the adapter's.
+#[derive(Serialize, Deserialize)]
+struct NameAndAttributeTableState {
+    #[serde(with = "save_state::hex")]
+    name_tables: Vec<u8>,
+    #[serde(with = "save_state::hex")]
+    attribute_tables: Vec<u8>,
+}
+
+impl NameAndAttributeTablesMemoryMapper for NameAndAttributeTable {
+    fn save_state(&self) -> Value {
+        save_state::to_value(&NameAndAttributeTableState {
+            name_tables: self.name_tables.concat(),
+            attribute_tables: self.attribute_tables.concat(),
+        })
+    }
+
+    fn load_state(&mut self, state: Value) -> Result<(), SaveStateError> {
+        let state: NameAndAttributeTableState = save_state::from_value(state)?;
+        if state.name_tables.len() != 2 * video::NAME_TABLE_SIZE as usize
+            || state.attribute_tables.len() != 2 * video::ATTRIBUTE_TABLE_SIZE as usize
+        {
+            return Err(SaveStateError::Corrupt(
+                "wrong name or attribute table size".to_string(),
+            ));
+        }
+        for (i, chunk) in state
+            .name_tables
+            .chunks(video::NAME_TABLE_SIZE as usize)
+            .enumerate()
+        {
+            self.name_tables[i].copy_from_slice(chunk);
+        }
+        for (i, chunk) in state
+            .attribute_tables
+            .chunks(video::ATTRIBUTE_TABLE_SIZE as usize)
+            .enumerate()
+        {
+            self.attribute_tables[i].copy_from_slice(chunk);
+        }
+        Ok(())
+    }
+
+    fn read8_name_table_0(&self, address: u16) -> u8 {
+        self.read8_name_table(0, address)
+    }
+
+    fn write8_name_table_0(&mut self, address: u16, value: u8) {
+        self.write8_name_table(0, address, value);
+    }
+
+    fn read8_attribute_table_0(&self, address: u16) -> u8 {
+        self.read8_attribute_table(0, address)
+    }
+
+    fn write8_attribute_table_0(&mut self, address: u16, value: u8) {
+        self.write8_attribute_table(0, address, value);
+    }
+
+    fn read8_name_table_1(&self, address: u16) -> u8 {
+        self.read8_name_table(1, address)
+    }
+
+    fn write8_name_table_1(&mut self, address: u16, value: u8) {
+        self.write8_name_table(1, address, value);
+    }
+
+    fn read8_attribute_table_1(&self, address: u16) -> u8 {
+        self.read8_attribute_table(1, address)
+    }
+
+    fn write8_attribute_table_1(&mut self, address: u16, value: u8) {
+        self.write8_attribute_table(1, address, value);
+    }
+
+    fn read8_name_table_2(&self, address: u16) -> u8 {
+        self.read8_name_table(2, address)
+    }
+
+    fn write8_name_table_2(&mut self, address: u16, value: u8) {
+        self.write8_name_table(2, address, value);
+    }
+
+    fn read8_attribute_table_2(&self, address: u16) -> u8 {
+        self.read8_attribute_table(2, address)
+    }
+
+    fn write8_attribute_table_2(&mut self, address: u16, value: u8) {
+        self.write8_attribute_table(2, address, value);
+    }
+
+    fn read8_name_table_3(&self, address: u16) -> u8 {
+        self.read8_name_table(3, address)
+    }
+
+    fn write8_name_table_3(&mut self, address: u16, value: u8) {
+        self.write8_name_table(3, address, value);
+    }
+
+    fn read8_attribute_table_3(&self, address: u16) -> u8 {
+        self.read8_attribute_table(3, address)
+    }
+
+    fn write8_attribute_table_3(&mut self, address: u16, value: u8) {
+        self.write8_attribute_table(3, address, value);
+    }
+}
+
+type Mappers = (
+    Box<dyn MainMemoryMapper>,
+    Box<dyn PatternTableMemoryMapper>,
+    Box<dyn NameAndAttributeTablesMemoryMapper>,
+    DiskSystem,
+);
+
+/// Side 0 starts off inserted.
+pub fn new(image: DiskImage, bios: Bios) -> Mappers {
+    let adapter = Rc::new(RefCell::new(Adapter::new(image)));
+    (
+        Box::new(Main {
+            ram: Box::new([0; RAM_SIZE]),
+            bios,
+            adapter: adapter.clone(),
+        }),
+        Box::new(PatternTable {
+            pattern_table_0: [0; PATTERN_TABLE_SIZE],
+            pattern_table_1: [0; PATTERN_TABLE_SIZE],
+        }),
+        Box::new(NameAndAttributeTable {
+            name_tables: [[0; video::NAME_TABLE_SIZE as usize]; 2],
+            attribute_tables: [[0; video::ATTRIBUTE_TABLE_SIZE as usize]; 2],
+            adapter: adapter.clone(),
+        }),
+        DiskSystem { adapter },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        fds_file::{Bios, DiskImage, BIOS_SIZE, SIDE_SIZE},
+        memory::{self, Memory},
+    };
+
+    #[test]
+    pub fn reads_disk() {
+        let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+        side.resize(56, 0);
+        side.resize(SIDE_SIZE, 0);
+        let mut bios = vec![0; BIOS_SIZE];
+        bios[BIOS_SIZE - 4] = 0x24;
+        let (mut main, _, disk_system) = memory::new_fds(
+            DiskImage::from_bytes(&side).unwrap(),
+            Bios::from_bytes(&bios).unwrap(),
+        );
+        assert_eq!(main.read8(0xfffc), 0x24);
+        assert_eq!(disk_system.inserted_side(), Some(0));
+
+        // enable disk registers, motor on, read mode, waiting for the gap to end
+        main.write8(0x4023, 0b0000_0001);
+        main.write8(0x4025, 0b0110_0101);
+        let mut bytes = Vec::new();
+        while bytes.len() < 3 {
+            main.step_mapper(150);
+            if (main.read8(0x4030) & 0b0000_0010) != 0 {
+                bytes.push(main.read8(0x4031));
+            }
+        }
+        assert_eq!(bytes, [0x80, 0x01, b'*']);
+
+        disk_system.eject();
+        main.step_mapper(1);
+        assert_eq!(main.read8(0x4032) & 0b0000_0001, 1);
+        assert_eq!(disk_system.diff(), b"FDSD");
+
+        // an earlier run's writes stay in the diff
+        let diff = b"FDSD\x10\0\0\0\x01\0\x5a";
+        disk_system.apply_diff(diff).unwrap();
+        disk_system.insert(0);
+        assert_eq!(disk_system.diff(), diff);
+    }
+}
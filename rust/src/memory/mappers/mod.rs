@@ -1,16 +1,34 @@
-use crate::cartridge_file::{self, Cartridge};
+use crate::cartridge_file::{self, Cartridge, CartridgeError};
 
 use super::{main_mapper::MainMemoryMapper, pattern_tables_mapper::PatternTableMemoryMapper};
 
+pub mod fds;
 mod nrom;
+pub mod vs_system;
 
-pub fn new(
-    cartridge: &Cartridge,
-) -> (Box<dyn MainMemoryMapper>, Box<dyn PatternTableMemoryMapper>) {
+/// Vs. System boards come with the cabinet's coin slots and DIP switches.
+type Mappers = (
+    Box<dyn MainMemoryMapper>,
+    Box<dyn PatternTableMemoryMapper>,
+    Option<vs_system::VsSystem>,
+);
+
+pub fn new(cartridge: &Cartridge) -> Result<Mappers, CartridgeError> {
     match cartridge.header().memory_mapper() {
-        cartridge_file::MemoryMapper::NROM => (
+        cartridge_file::MemoryMapper::NROM => Ok((
             Box::new(nrom::Main::new(cartridge)),
             Box::new(nrom::PatternTable::new(cartridge)),
-        ),
+            None,
+        )),
+        cartridge_file::MemoryMapper::VsSystem => {
+            let (main, pattern_table, vs_system) = vs_system::new(cartridge);
+            Ok((Box::new(main), Box::new(pattern_table), Some(vs_system)))
+        }
+        cartridge_file::MemoryMapper::Other(number) => {
+            Err(CartridgeError::UnrecognizedMemoryMapper {
+                number,
+                format: cartridge.header().format(),
+            })
+        }
     }
 }
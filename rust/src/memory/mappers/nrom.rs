@@ -1,8 +1,13 @@
+use serde_json::Value;
+
 use crate::{
     cartridge_file::{chr_rom, pgr_rom, Cartridge},
     memory::{main_mapper::MainMemoryMapper, pattern_tables_mapper::PatternTableMemoryMapper},
+    save_state::{self, SaveStateError},
 };
 
+const PATTERN_TABLE_SIZE: usize = chr_rom::BLOCK_SIZE / 2;
+
 pub struct Main {
     main_lower: pgr_rom::Block,
     main_upper: pgr_rom::Block,
@@ -10,7 +15,7 @@ pub struct Main {
 
 impl Main {
     pub fn new(cartridge: &Cartridge) -> Self {
-        let size = cartridge.header().prg_rom_size().in_blocks() as usize;
+        let size = cartridge.header().prg_rom_size().in_blocks();
         let lower = cartridge.pgr_rom()[0];
         let upper = cartridge.pgr_rom()[size - 1];
         Self {
@@ -34,44 +39,76 @@ impl MainMemoryMapper for Main {
     }
 }
 
+/// The first 8k of CHR ROM, or 8k of CHR RAM when there's no ROM.
 pub struct PatternTable {
-    pattern_table_0: chr_rom::Block,
-    pattern_table_1: Option<chr_rom::Block>,
+    chr: Vec<u8>,
+    is_ram: bool,
 }
 
 impl PatternTable {
     pub fn new(cartridge: &Cartridge) -> Self {
-        Self {
-            pattern_table_0: [0; chr_rom::BLOCK_SIZE],
-            pattern_table_1: if cartridge.header().chr_rom_size().in_blocks() >= 2 {
-                Some([0; chr_rom::BLOCK_SIZE])
-            } else {
-                None
-            },
+        let mut chr = cartridge
+            .chr_rom_bytes()
+            .take(chr_rom::BLOCK_SIZE)
+            .collect::<Vec<_>>();
+        let is_ram = chr.is_empty();
+        // anything short is mirrored
+        if !is_ram {
+            chr = chr
+                .iter()
+                .copied()
+                .cycle()
+                .take(chr_rom::BLOCK_SIZE)
+                .collect();
+        }
+        chr.resize(chr_rom::BLOCK_SIZE, 0);
+        Self { chr, is_ram }
+    }
+
+    fn write8(&mut self, address: usize, value: u8) {
+        if self.is_ram {
+            self.chr[address] = value;
         }
     }
 }
 
 impl PatternTableMemoryMapper for PatternTable {
-    fn read8_pattern_table_0(&self, address: u16) -> u8 {
-        self.pattern_table_0[address as usize]
+    fn peek8_pattern_table_0(&self, address: u16) -> u8 {
+        self.chr[address as usize]
     }
 
     fn write8_pattern_table_0(&mut self, address: u16, value: u8) {
-        self.pattern_table_0[address as usize] = value;
+        self.write8(address as usize, value);
     }
 
-    fn read8_pattern_table_1(&self, address: u16) -> u8 {
-        (match self.pattern_table_1 {
-            Some(x) => x,
-            None => self.pattern_table_0,
-        })[address as usize]
+    fn peek8_pattern_table_1(&self, address: u16) -> u8 {
+        self.chr[PATTERN_TABLE_SIZE + address as usize]
     }
 
     fn write8_pattern_table_1(&mut self, address: u16, value: u8) {
-        (match self.pattern_table_1 {
-            Some(x) => x,
-            None => self.pattern_table_0,
-        })[address as usize] = value
+        self.write8(PATTERN_TABLE_SIZE + address as usize, value);
+    }
+
+    /// Only CHR RAM changes.
+    fn save_state(&self) -> Value {
+        if self.is_ram {
+            Value::String(save_state::hex::encode(&self.chr))
+        } else {
+            Value::Null
+        }
+    }
+
+    fn load_state(&mut self, state: Value) -> Result<(), SaveStateError> {
+        if let (true, Value::String(chr)) = (self.is_ram, &state) {
+            let chr = save_state::hex::decode(chr).map_err(SaveStateError::Corrupt)?;
+            if chr.len() != self.chr.len() {
+                return Err(SaveStateError::Corrupt(format!(
+                    "{} bytes of CHR RAM",
+                    chr.len()
+                )));
+            }
+            self.chr = chr;
+        }
+        Ok(())
     }
 }
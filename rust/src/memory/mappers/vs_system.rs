@@ -0,0 +1,388 @@
+// see https://www.nesdev.org/wiki/Vs._System and https://www.nesdev.org/wiki/INES_Mapper_099
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    cartridge_file::{chr_rom, Cartridge, VsHardwareType},
+    memory::{main_mapper::MainMemoryMapper, pattern_tables_mapper::PatternTableMemoryMapper},
+    save_state::{self, SaveStateError},
+};
+
+const CONTROLLER_PORT_1_ADDRESS: u16 = 0x4016;
+const COIN_COUNTER_ADDRESS: u16 = 0x4020;
+
+/// Set in $4016 writes to switch both the CHR bank and, for 40k games, the PRG bank at $8000.
+const BANK_SELECT: u8 = 0b0000_0100;
+/// $4016 read bits.
+const SERVICE_BUTTON: u8 = 0b0000_0100;
+const COIN_1: u8 = 0b0010_0000;
+const COIN_2: u8 = 0b0100_0000;
+/// Games look for the slot going high for a few frames, this is what FCEUX holds it for.
+const COIN_FRAMES: u8 = 6;
+
+/// Games with more than 32k of PRG ROM swap their first 8k for the last one.
+const SWITCHABLE_PRG_SIZE: usize = 0x2000;
+const FIXED_PRG_SIZE: usize = 0x8000;
+const PATTERN_TABLE_SIZE: usize = 0x1000;
+
+const RBI_BASEBALL_RESET_ADDRESS: u16 = 0x5e00;
+const RBI_BASEBALL_DATA_ADDRESS: u16 = 0x5e01;
+/// TKO Boxing reads its check values from the same place RBI Baseball does.
+const TKO_BOXING_DATA: [u8; 32] = [
+    0xff, 0xbf, 0xb7, 0x97, 0x97, 0x17, 0x57, 0x4f, 0x6f, 0x6b, 0xeb, 0xa9, 0xb1, 0x90, 0x94, 0x14,
+    0x56, 0x4e, 0x6f, 0x6b, 0xeb, 0xa9, 0xb1, 0x90, 0xd4, 0x5c, 0x3e, 0x26, 0x87, 0x83, 0x13, 0x00,
+];
+
+/// The coin box, operator settings and protection chip, shared between the memory mappers and whoever is running the
+/// cabinet.
+#[derive(Serialize, Deserialize)]
+struct Cabinet {
+    /// DIP switch 1 is bit 0.
+    dip_switches: u8,
+    /// Frames left for each slot to say a coin's going through it.
+    coin_frames: [u8; 2],
+    service_button: bool,
+    coin_counter: bool,
+    coins_counted: u32,
+    bank: bool,
+    /// Comes from the cartridge, so it's left out of states.
+    #[serde(skip)]
+    protection: Option<VsHardwareType>,
+    protection_counter: usize,
+}
+
+impl Cabinet {
+    /// Only called for $4016 and $4017, the controller bits are left for the caller to fill in.
+    fn peek8_input_port(&self, address: u16) -> u8 {
+        if address == CONTROLLER_PORT_1_ADDRESS {
+            (if self.service_button { SERVICE_BUTTON } else { 0 })
+                | ((self.dip_switches & 0b0000_0011) << 3)
+                | if self.coin_frames[0] > 0 { COIN_1 } else { 0 }
+                | if self.coin_frames[1] > 0 { COIN_2 } else { 0 }
+        } else {
+            self.dip_switches & 0b1111_1100
+        }
+    }
+
+    /// Takes everything but the protection chip's type from the state.
+    fn load_state(&mut self, state: Self) {
+        *self = Self {
+            protection: self.protection,
+            ..state
+        };
+    }
+
+    /// What the protection chip answers with, without moving on to its next value.
+    fn peek8_protection(&self, address: u16) -> Option<u8> {
+        match (self.protection?, address) {
+            (VsHardwareType::UniSystemRBIBaseballProtection, RBI_BASEBALL_DATA_ADDRESS) => {
+                Some(if self.protection_counter + 1 == 10 {
+                    0x6f
+                } else {
+                    0xb4
+                })
+            }
+            (VsHardwareType::UniSystemTKOBoxingProtection, RBI_BASEBALL_DATA_ADDRESS) => {
+                Some(TKO_BOXING_DATA[self.protection_counter % TKO_BOXING_DATA.len()])
+            }
+            // Super Xevious checks a handful of addresses, one of which flips what two of the others return
+            (VsHardwareType::UniSystemSuperXeviousProtection, 0x54ff) => Some(0x05),
+            (VsHardwareType::UniSystemSuperXeviousProtection, 0x5678) => {
+                Some(if self.protection_counter != 0 { 0x00 } else { 0x01 })
+            }
+            (VsHardwareType::UniSystemSuperXeviousProtection, 0x578f) => {
+                Some(if self.protection_counter != 0 { 0xd1 } else { 0x89 })
+            }
+            (VsHardwareType::UniSystemSuperXeviousProtection, 0x5567) => {
+                Some(if self.protection_counter == 0 { 0x37 } else { 0x3e })
+            }
+            _ => None,
+        }
+    }
+
+    fn read8_protection(&mut self, address: u16) -> Option<u8> {
+        let result = self.peek8_protection(address);
+        match (self.protection?, address) {
+            (
+                VsHardwareType::UniSystemRBIBaseballProtection
+                | VsHardwareType::UniSystemTKOBoxingProtection,
+                RBI_BASEBALL_RESET_ADDRESS,
+            ) => self.protection_counter = 0,
+            (
+                VsHardwareType::UniSystemRBIBaseballProtection
+                | VsHardwareType::UniSystemTKOBoxingProtection,
+                RBI_BASEBALL_DATA_ADDRESS,
+            ) => self.protection_counter += 1,
+            (VsHardwareType::UniSystemSuperXeviousProtection, 0x5567) => {
+                self.protection_counter ^= 1
+            }
+            _ => (),
+        }
+        result
+    }
+}
+
+/// For the operator and the player, everything on the cabinet that isn't a controller.
+pub struct VsSystem {
+    cabinet: Rc<RefCell<Cabinet>>,
+}
+
+impl VsSystem {
+    /// Bit 0 is switch 1. What they do is different for every game.
+    pub fn set_dip_switches(&self, dip_switches: u8) {
+        self.cabinet.borrow_mut().dip_switches = dip_switches;
+    }
+
+    /// The slot says there's a coin going through it for the next few frames.
+    pub fn insert_coin(&self, slot: usize) {
+        self.cabinet.borrow_mut().coin_frames[slot] = COIN_FRAMES;
+    }
+
+    /// For whoever's running the cabinet to call at the end of each frame, to time the coins.
+    pub fn step_frame(&self) {
+        for frames in self.cabinet.borrow_mut().coin_frames.iter_mut() {
+            *frames = frames.saturating_sub(1);
+        }
+    }
+
+    pub fn set_service_button(&self, pressed: bool) {
+        self.cabinet.borrow_mut().service_button = pressed;
+    }
+
+    /// How many times the game has ticked the mechanical coin counter.
+    pub fn coins_counted(&self) -> u32 {
+        self.cabinet.borrow().coins_counted
+    }
+}
+
+pub struct Main {
+    prg_rom: Vec<u8>,
+    cabinet: Rc<RefCell<Cabinet>>,
+}
+
+impl Main {
+    fn read8_prg(&self, address: usize) -> u8 {
+        let address = if address < SWITCHABLE_PRG_SIZE
+            && self.prg_rom.len() > FIXED_PRG_SIZE
+            && self.cabinet.borrow().bank
+        {
+            address + FIXED_PRG_SIZE
+        } else {
+            address
+        };
+        self.prg_rom[address % self.prg_rom.len()]
+    }
+}
+
+impl MainMemoryMapper for Main {
+    fn read8_main_lower_bank(&self, address: u16) -> u8 {
+        self.read8_prg(address as usize)
+    }
+
+    fn read8_main_upper_bank(&self, address: u16) -> u8 {
+        self.read8_prg(address as usize + crate::memory::main::PRG_BANK_SIZE as usize)
+    }
+
+    fn write8_main(&mut self, _address: u16, _value: u8) {
+        // banks are switched through $4016
+    }
+
+    fn read8_expansion(&mut self, address: u16) -> Option<u8> {
+        self.cabinet.borrow_mut().read8_protection(address)
+    }
+
+    fn peek8_expansion(&self, address: u16) -> Option<u8> {
+        self.cabinet.borrow().peek8_protection(address)
+    }
+
+    fn write8_expansion(&mut self, address: u16, value: u8) {
+        if address == COIN_COUNTER_ADDRESS {
+            let mut cabinet = self.cabinet.borrow_mut();
+            let coin_counter = (value & 0b0000_0001) != 0;
+            if coin_counter && !cabinet.coin_counter {
+                cabinet.coins_counted += 1;
+            }
+            cabinet.coin_counter = coin_counter;
+        }
+    }
+
+    fn peek8_input_port(&self, address: u16) -> u8 {
+        self.cabinet.borrow().peek8_input_port(address)
+    }
+
+    fn write8_input_port(&mut self, value: u8) {
+        self.cabinet.borrow_mut().bank = (value & BANK_SELECT) != 0;
+    }
+
+    /// The pattern table shares the cabinet, so it's all saved from here.
+    fn save_state(&self) -> Value {
+        save_state::to_value(&*self.cabinet.borrow())
+    }
+
+    fn load_state(&mut self, state: Value) -> Result<(), SaveStateError> {
+        let state = save_state::from_value(state)?;
+        self.cabinet.borrow_mut().load_state(state);
+        Ok(())
+    }
+}
+
+/// One or two 8k CHR ROM banks, switched along with PRG.
+pub struct PatternTable {
+    chr: Vec<u8>,
+    cabinet: Rc<RefCell<Cabinet>>,
+}
+
+impl PatternTable {
+    fn index(&self, address: usize) -> usize {
+        let bank = if self.cabinet.borrow().bank { 1 } else { 0 };
+        (bank * chr_rom::BLOCK_SIZE + address) % self.chr.len()
+    }
+}
+
+impl PatternTableMemoryMapper for PatternTable {
+    fn peek8_pattern_table_0(&self, address: u16) -> u8 {
+        self.chr[self.index(address as usize)]
+    }
+
+    fn write8_pattern_table_0(&mut self, _address: u16, _value: u8) {
+        // CHR ROM
+    }
+
+    fn peek8_pattern_table_1(&self, address: u16) -> u8 {
+        self.chr[self.index(address as usize + PATTERN_TABLE_SIZE)]
+    }
+
+    fn write8_pattern_table_1(&mut self, _address: u16, _value: u8) {
+        // CHR ROM
+    }
+}
+
+/// Protection comes from the NES 2.0 Vs. hardware type, iNES files don't have anywhere to say.
+pub fn new(cartridge: &Cartridge) -> (Main, PatternTable, VsSystem) {
+    let header = cartridge.header();
+    let prg_rom = cartridge
+        .pgr_rom()
+        .iter()
+        .flatten()
+        .take(header.prg_rom_size().in_bytes())
+        .copied()
+        .collect();
+    let mut chr = cartridge
+        .chr_rom()
+        .iter()
+        .flatten()
+        .take(header.chr_rom_size().in_bytes())
+        .copied()
+        .collect::<Vec<_>>();
+    if chr.is_empty() {
+        chr.resize(chr_rom::BLOCK_SIZE, 0);
+    }
+
+    let cabinet = Rc::new(RefCell::new(Cabinet {
+        dip_switches: 0,
+        coin_frames: [0; 2],
+        service_button: false,
+        coin_counter: false,
+        coins_counted: 0,
+        bank: false,
+        protection: header.vs_hardware_type(),
+        protection_counter: 0,
+    }));
+    (
+        Main {
+            prg_rom,
+            cabinet: cabinet.clone(),
+        },
+        PatternTable {
+            chr,
+            cabinet: cabinet.clone(),
+        },
+        VsSystem { cabinet },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        cartridge_file::Cartridge,
+        memory::{self, Memory},
+    };
+
+    #[test]
+    pub fn cabinet() {
+        // NES 2.0, mapper 99, Vs. System with RBI Baseball protection, 40k PRG ROM and 16k CHR ROM
+        let mut data = vec![
+            b'N',
+            b'E',
+            b'S',
+            0x1a,
+            // 5 * 2^13
+            0b0011_0110,
+            0x02,
+            0x30,
+            0b0110_1001,
+            0x00,
+            0x0f,
+            0x00,
+            0x00,
+            0x00,
+            0x10,
+            0x00,
+            0x00,
+        ];
+        for bank in 0..5 {
+            data.resize(data.len() + 0x2000, bank);
+        }
+        data.resize(data.len() + 0x2000, 0xc0);
+        data.resize(data.len() + 0x2000, 0xc1);
+        let cartridge = Cartridge::from_bytes(data).unwrap();
+        assert_eq!(cartridge.header().prg_rom_size().in_bytes(), 0xa000);
+        let (mut main, mut video, vs_system) = memory::new(&cartridge).unwrap();
+        let vs_system = vs_system.unwrap();
+
+        vs_system.set_dip_switches(0b1010_0110);
+        vs_system.insert_coin(1);
+        vs_system.set_service_button(true);
+        assert_eq!(main.read8(0x4016), 0b0101_0100);
+        assert_eq!(main.read8(0x4017), 0b1010_0100);
+        for _ in 0..super::COIN_FRAMES {
+            vs_system.step_frame();
+        }
+        assert_eq!(main.read8(0x4016), 0b0001_0100);
+
+        main.write8(0x4020, 1);
+        main.write8(0x4020, 1);
+        main.write8(0x4020, 0);
+        main.write8(0x4020, 1);
+        assert_eq!(vs_system.coins_counted(), 2);
+
+        assert_eq!((main.read8(0x8000), main.read8(0xa000)), (0, 1));
+        assert_eq!(video.read8(0x0000), 0xc0);
+        main.write8(0x4016, 0b0000_0100);
+        assert_eq!((main.read8(0x8000), main.read8(0xa000)), (4, 1));
+        assert_eq!(main.read8(0xffff), 3);
+        assert_eq!(video.read8(0x1fff), 0xc1);
+
+        main.read8(0x5e00);
+        let reads = (0..10).map(|_| main.read8(0x5e01)).collect::<Vec<_>>();
+        assert_eq!(reads[8..], [0xb4, 0x6f]);
+
+        // peeking doesn't move the protection chip on
+        main.read8(0x5e00);
+        let peeks = (0..10).map(|_| main.peek8(0x5e01)).collect::<Vec<_>>();
+        assert_eq!(peeks, [0xb4; 10]);
+        assert_eq!(main.read8(0x5e01), 0xb4);
+
+        // states cover the bank and the protection chip
+        let state = main.save_state();
+        for _ in 0..8 {
+            main.read8(0x5e01);
+        }
+        main.write8(0x4016, 0);
+        assert_eq!((main.peek8(0x8000), main.peek8(0x5e01)), (0, 0x6f));
+        main.load_state(state).unwrap();
+        assert_eq!((main.peek8(0x8000), main.peek8(0x5e01)), (4, 0xb4));
+    }
+}
@@ -2,10 +2,13 @@ pub mod main;
 pub mod main_mapper;
 pub mod mappers;
 pub mod name_attr_tables_mapper;
+pub mod nsf;
+pub mod observe;
 pub mod pattern_tables_mapper;
 pub mod video;
 
 use main_mapper::MainMemoryMapper;
+use mappers::vs_system::VsSystem;
 use name_attr_tables_mapper::{
     FourWayMirroringNameAndAttributeTable, HorizontalMirroringNameAndAttributeTable,
     NameAndAttributeTablesMemoryMapper, SingleNameAndAttributeTable,
@@ -14,20 +17,36 @@ use name_attr_tables_mapper::{
 use pattern_tables_mapper::PatternTableMemoryMapper;
 
 use crate::{
-    cartridge_file::{Cartridge, NametableArrangement},
+    cartridge_file::{Cartridge, CartridgeError, MemoryMapper, NametableArrangement},
     endians::Word,
+    fds_file::{Bios, DiskImage},
 };
 
 pub trait Memory {
-    fn read8(&self, address: u16) -> u8;
+    /// A read as the CPU or PPU does it, which can change state: reading PPUSTATUS clears vblank, $4015 acknowledges
+    /// the frame IRQ, mappers count or latch on reads and so on. Memory without any of that only needs peek8.
+    fn read8(&mut self, address: u16) -> u8 {
+        self.peek8(address)
+    }
+
+    /// What read would return, without any of its side effects. For debuggers, disassemblers and traces.
+    fn peek8(&self, address: u16) -> u8;
+
     fn write8(&mut self, address: u16, value: u8);
 
-    fn read16(&self, address: u16) -> u16 {
+    fn read16(&mut self, address: u16) -> u16 {
         let low = self.read8(address);
         let high = self.read8(address.wrapping_add(1));
         Word { low, high }.into()
     }
 
+    /// As read16, but with peek8.
+    fn peek16(&self, address: u16) -> u16 {
+        let low = self.peek8(address);
+        let high = self.peek8(address.wrapping_add(1));
+        Word { low, high }.into()
+    }
+
     fn write16(&mut self, address: u16, value: u16) {
         let value: Word = value.into();
         self.write8(address, value.low);
@@ -35,11 +54,18 @@ pub trait Memory {
     }
 }
 
-pub fn new(cartridge: &Cartridge) -> (main::Memory, video::Memory) {
-    let name_and_attributes: Box<dyn NameAndAttributeTablesMemoryMapper> = match cartridge
-        .header()
-        .nametable_arrangement()
-    {
+/// Fails for mappers that aren't emulated. Vs. System cartridges come with the VsSystem for the coin slots and DIP
+/// switches.
+pub fn new(
+    cartridge: &Cartridge,
+) -> Result<(main::Memory, video::Memory, Option<VsSystem>), CartridgeError> {
+    let header = cartridge.header();
+    // the Vs. System has 4k of VRAM, whatever the header says
+    let arrangement = match header.memory_mapper() {
+        MemoryMapper::VsSystem => NametableArrangement::FourScreenMirroring,
+        _ => header.nametable_arrangement(),
+    };
+    let name_and_attributes: Box<dyn NameAndAttributeTablesMemoryMapper> = match arrangement {
         NametableArrangement::Vertical => Box::new(VerticalMirroringNameAndAttributeTable::new()),
         NametableArrangement::Horizontal => {
             Box::new(HorizontalMirroringNameAndAttributeTable::new())
@@ -50,10 +76,84 @@ pub fn new(cartridge: &Cartridge) -> (main::Memory, video::Memory) {
         }
     };
 
-    let (main, pattern_table) = mappers::new(cartridge);
+    let (main, pattern_table, vs_system) = mappers::new(cartridge)?;
+
+    let mut main = main::Memory::new(main);
+    if let Some(trainer) = cartridge.trainer() {
+        main.load_trainer(trainer);
+    }
+
+    Ok((
+        main,
+        video::Memory::new(pattern_table, name_and_attributes),
+        vs_system,
+    ))
+}
 
+/// As new, but for a Famicom Disk System with the given disk. The DiskSystem is for swapping sides.
+pub fn new_fds(
+    image: DiskImage,
+    bios: Bios,
+) -> (main::Memory, video::Memory, mappers::fds::DiskSystem) {
+    let (main, pattern_table, name_and_attributes, disk_system) = mappers::fds::new(image, bios);
     (
         main::Memory::new(main),
         video::Memory::new(pattern_table, name_and_attributes),
+        disk_system,
     )
 }
+
+#[cfg(test)]
+mod test {
+    use super::Memory;
+    use crate::cartridge_file::Cartridge;
+
+    #[test]
+    pub fn trainer_is_loaded_at_7000() {
+        let mut data = vec![
+            b'N',
+            b'E',
+            b'S',
+            0x1a,
+            0x01,
+            0x01,
+            0b0000_0100,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        data.extend((0..512).map(|x| x as u8));
+        data.resize(data.len() + 16 * 1024 + 8 * 1024, 0);
+        let cartridge = Cartridge::from_bytes(data).unwrap();
+        assert_eq!(cartridge.trainer().unwrap().data()[3], 3);
+
+        let (mut main, _, _) = super::new(&cartridge).unwrap();
+        assert_eq!(main.read8(0x6fff), 0);
+        assert_eq!(main.read8(0x7000), 0);
+        assert_eq!(main.read8(0x7001), 1);
+        assert_eq!(main.read8(0x71ff), 0xff);
+        assert_eq!(main.read8(0x7200), 0);
+    }
+
+    #[test]
+    pub fn open_bus() {
+        let mut data = b"NES\x1a\x01\x01\0\0\0\0\0\0\0\0\0\0".to_vec();
+        data.resize(data.len() + 16 * 1024, 0xa5);
+        data.resize(data.len() + 8 * 1024, 0);
+        let cartridge = Cartridge::from_bytes(data).unwrap();
+        let (mut main, _, _) = super::new(&cartridge).unwrap();
+
+        assert_eq!(main.read8(0x8000), 0xa5);
+        assert_eq!(main.peek8(0x2002), 0xa5);
+        assert_eq!(main.read8(0x4000), 0xa5);
+        main.write8(0x2000, 0x5a);
+        assert_eq!(main.peek8(0x3ff7), 0x5a);
+        assert_eq!(main.read8(0x5000), 0x5a);
+    }
+}
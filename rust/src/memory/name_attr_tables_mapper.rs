@@ -1,4 +1,8 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
 use super::video;
+use crate::save_state::{self, SaveStateError};
 
 pub trait NameAndAttributeTablesMemoryMapper {
     /// Reads from name table 0.
@@ -56,12 +60,27 @@ pub trait NameAndAttributeTablesMemoryMapper {
 
     /// As read.
     fn write8_attribute_table_3(&mut self, address: u16, value: u8);
+
+    /// The RAM behind the tables, for save states. Boards without any can leave these alone.
+    fn save_state(&self) -> Value {
+        Value::Null
+    }
+
+    /// Given what save_state returned, possibly from an older or newer build.
+    fn load_state(&mut self, _state: Value) -> Result<(), SaveStateError> {
+        Ok(())
+    }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct HorizontalMirroringNameAndAttributeTable {
+    #[serde(with = "save_state::hex")]
     name_table_0: [u8; video::NAME_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     attribute_table_0: [u8; video::ATTRIBUTE_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     name_table_1: [u8; video::NAME_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     attribute_table_1: [u8; video::ATTRIBUTE_TABLE_SIZE as usize],
 }
 
@@ -77,6 +96,15 @@ impl HorizontalMirroringNameAndAttributeTable {
 }
 
 impl NameAndAttributeTablesMemoryMapper for HorizontalMirroringNameAndAttributeTable {
+    fn save_state(&self) -> Value {
+        save_state::to_value(self)
+    }
+
+    fn load_state(&mut self, state: Value) -> Result<(), SaveStateError> {
+        *self = save_state::from_value(state)?;
+        Ok(())
+    }
+
     fn read8_name_table_0(&self, address: u16) -> u8 {
         self.name_table_0[address as usize]
     }
@@ -142,10 +170,15 @@ impl NameAndAttributeTablesMemoryMapper for HorizontalMirroringNameAndAttributeT
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct VerticalMirroringNameAndAttributeTable {
+    #[serde(with = "save_state::hex")]
     name_table_0: [u8; video::NAME_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     attribute_table_0: [u8; video::ATTRIBUTE_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     name_table_1: [u8; video::NAME_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     attribute_table_1: [u8; video::ATTRIBUTE_TABLE_SIZE as usize],
 }
 
@@ -161,6 +194,15 @@ impl VerticalMirroringNameAndAttributeTable {
 }
 
 impl NameAndAttributeTablesMemoryMapper for VerticalMirroringNameAndAttributeTable {
+    fn save_state(&self) -> Value {
+        save_state::to_value(self)
+    }
+
+    fn load_state(&mut self, state: Value) -> Result<(), SaveStateError> {
+        *self = save_state::from_value(state)?;
+        Ok(())
+    }
+
     fn read8_name_table_0(&self, address: u16) -> u8 {
         self.name_table_0[address as usize]
     }
@@ -226,8 +268,11 @@ impl NameAndAttributeTablesMemoryMapper for VerticalMirroringNameAndAttributeTab
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct SingleNameAndAttributeTable {
+    #[serde(with = "save_state::hex")]
     name_table_0: [u8; video::NAME_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     attribute_table_0: [u8; video::ATTRIBUTE_TABLE_SIZE as usize],
 }
 
@@ -241,6 +286,15 @@ impl SingleNameAndAttributeTable {
 }
 
 impl NameAndAttributeTablesMemoryMapper for SingleNameAndAttributeTable {
+    fn save_state(&self) -> Value {
+        save_state::to_value(self)
+    }
+
+    fn load_state(&mut self, state: Value) -> Result<(), SaveStateError> {
+        *self = save_state::from_value(state)?;
+        Ok(())
+    }
+
     fn read8_name_table_0(&self, address: u16) -> u8 {
         self.name_table_0[address as usize]
     }
@@ -306,14 +360,23 @@ impl NameAndAttributeTablesMemoryMapper for SingleNameAndAttributeTable {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct FourWayMirroringNameAndAttributeTable {
+    #[serde(with = "save_state::hex")]
     name_table_0: [u8; video::NAME_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     attribute_table_0: [u8; video::ATTRIBUTE_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     name_table_1: [u8; video::NAME_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     attribute_table_1: [u8; video::ATTRIBUTE_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     name_table_2: [u8; video::NAME_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     attribute_table_2: [u8; video::ATTRIBUTE_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     name_table_3: [u8; video::NAME_TABLE_SIZE as usize],
+    #[serde(with = "save_state::hex")]
     attribute_table_3: [u8; video::ATTRIBUTE_TABLE_SIZE as usize],
 }
 
@@ -333,6 +396,15 @@ impl FourWayMirroringNameAndAttributeTable {
 }
 
 impl NameAndAttributeTablesMemoryMapper for FourWayMirroringNameAndAttributeTable {
+    fn save_state(&self) -> Value {
+        save_state::to_value(self)
+    }
+
+    fn load_state(&mut self, state: Value) -> Result<(), SaveStateError> {
+        *self = save_state::from_value(state)?;
+        Ok(())
+    }
+
     fn read8_name_table_0(&self, address: u16) -> u8 {
         self.name_table_0[address as usize]
     }
@@ -0,0 +1,230 @@
+// see https://www.nesdev.org/wiki/NSF
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    apu::{
+        fds::{self, FdsAudio},
+        APU,
+    },
+    nsf_file::{ExpansionAudio, Nsf},
+    save_state,
+};
+
+const RAM_SIZE: u16 = 0x0800;
+const RAM_MIRROR_END: u16 = 0x2000;
+
+const APU_REGISTERS_START: u16 = 0x4000;
+const APU_REGISTERS_END: u16 = 0x4018;
+
+/// The synthetic player lives in otherwise unused address space.
+pub const PLAYER_START: u16 = 0x4100;
+pub const PLAYER_INIT_ADDRESS: u16 = PLAYER_START;
+pub const PLAYER_PLAY_ADDRESS: u16 = PLAYER_START + 6;
+pub const PLAYER_IDLE_ADDRESS: u16 = PLAYER_START + 12;
+const PLAYER_SIZE: usize = 15;
+const PLAYER_END: u16 = PLAYER_START + PLAYER_SIZE as u16;
+
+/// FDS files also bankswitch $6000-$7FFF, everything else only $8000-$FFFF.
+const FDS_BANK_REGISTERS_START: u16 = 0x5ff6;
+const BANK_REGISTERS_START: u16 = 0x5ff8;
+const BANK_REGISTERS_END: u16 = 0x6000;
+
+const SRAM_START: u16 = 0x6000;
+const SRAM_END: u16 = 0x8000;
+const SRAM_SIZE: u16 = SRAM_END - SRAM_START;
+
+const PRG_START: u16 = SRAM_END;
+const BANK_SIZE: usize = 0x1000;
+
+/// Lowest address the FDS lets us write to, it's all ram from here up.
+const FDS_RAM_START: u16 = SRAM_START;
+const FDS_RAM_SIZE: usize = 0x10000 - FDS_RAM_START as usize;
+
+#[derive(Serialize, Deserialize)]
+pub struct Memory {
+    #[serde(with = "save_state::hex")]
+    ram: [u8; RAM_SIZE as usize],
+    #[serde(with = "save_state::hex")]
+    sram: [u8; SRAM_SIZE as usize],
+    /// This and prg come from the file, so they're left out of states.
+    #[serde(skip)]
+    player: [u8; PLAYER_SIZE],
+    /// The file's data padded out to whole banks.
+    #[serde(skip)]
+    prg: Vec<u8>,
+    banks: [u8; 8],
+    /// For FDS files $6000-$FFFF is writable and bank switches copy into it.
+    #[serde(with = "save_state::hex::option")]
+    fds_ram: Option<Vec<u8>>,
+    /// The FDS wavetable channel at $4040-$4097, for FDS files.
+    #[serde(default)]
+    fds_audio: Option<FdsAudio>,
+    apu: APU,
+}
+
+impl Memory {
+    pub fn new(nsf: &Nsf, apu: APU) -> Self {
+        let fds = nsf.expansion_audio().contains(ExpansionAudio::FDS);
+        let (padding, banks) = match nsf.bankswitch_init() {
+            Some(banks) => ((nsf.load_address() as usize) & (BANK_SIZE - 1), banks),
+            // copied to the load address below instead
+            None if fds => (0, [0, 1, 2, 3, 4, 5, 6, 7]),
+            None => (
+                (nsf.load_address() - PRG_START) as usize,
+                [0, 1, 2, 3, 4, 5, 6, 7],
+            ),
+        };
+        let mut prg = vec![0; padding];
+        prg.extend_from_slice(nsf.data());
+        prg.resize(prg.len().next_multiple_of(BANK_SIZE), 0);
+
+        let init = nsf.init_address().to_le_bytes();
+        let play = nsf.play_address().to_le_bytes();
+        let idle = PLAYER_IDLE_ADDRESS.to_le_bytes();
+        let player = [
+            // init: jsr init, jmp idle
+            0x20, init[0], init[1], 0x4c, idle[0], idle[1], // play: jsr play, jmp idle
+            0x20, play[0], play[1], 0x4c, idle[0], idle[1], // idle: jmp idle
+            0x4c, idle[0], idle[1],
+        ];
+
+        let mut result = Self {
+            ram: [0; RAM_SIZE as usize],
+            sram: [0; SRAM_SIZE as usize],
+            player,
+            prg,
+            banks,
+            fds_ram: fds.then(|| vec![0; FDS_RAM_SIZE]),
+            fds_audio: fds.then(FdsAudio::new),
+            apu,
+        };
+        if result.fds_ram.is_some() {
+            match nsf.bankswitch_init() {
+                Some(banks) => {
+                    // the first two banks for $6000-$7FFF are the same as those for $E000-$FFFF
+                    result.switch_fds_bank(0, banks[6]);
+                    result.switch_fds_bank(1, banks[7]);
+                    for (i, bank) in banks.iter().enumerate() {
+                        result.switch_fds_bank(i + 2, *bank);
+                    }
+                }
+                None => {
+                    let start = (nsf.load_address() - FDS_RAM_START) as usize;
+                    let fds_ram = result.fds_ram.as_mut().unwrap();
+                    let len = result.prg.len().min(fds_ram.len() - start);
+                    fds_ram[start..(start + len)].copy_from_slice(&result.prg[0..len]);
+                }
+            }
+        }
+        result
+    }
+
+    /// Takes everything but what came from the file from the state.
+    pub fn load_state(&mut self, state: Self) {
+        self.ram = state.ram;
+        self.sram = state.sram;
+        self.banks = state.banks;
+        self.fds_ram = state.fds_ram;
+        self.fds_audio = state.fds_audio;
+        self.apu.load_state(state.apu);
+    }
+
+    pub fn apu_mut(&mut self) -> &mut APU {
+        &mut self.apu
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.apu.take_samples()
+    }
+
+    /// Steps the APU and any expansion audio, feeding the DMC from the currently banked in data.
+    pub fn step_apu(&mut self, cycles: u64) {
+        let Self {
+            apu,
+            prg,
+            banks,
+            fds_ram,
+            fds_audio,
+            ..
+        } = self;
+        if let Some(fds_audio) = fds_audio {
+            for _ in 0..cycles {
+                fds_audio.clock();
+            }
+            apu.set_expansion(fds_audio.output());
+        }
+        apu.step(cycles, |address| match fds_ram {
+            Some(fds_ram) => fds_ram[(address - FDS_RAM_START) as usize],
+            None => read_prg(prg, banks, address),
+        });
+    }
+
+    /// Bank is the index of the 4k region starting from $6000.
+    fn switch_fds_bank(&mut self, bank: usize, value: u8) {
+        let start = (value as usize) * BANK_SIZE;
+        let fds_ram = self.fds_ram.as_mut().unwrap();
+        let dst = &mut fds_ram[(bank * BANK_SIZE)..((bank + 1) * BANK_SIZE)];
+        match self.prg.get(start..(start + BANK_SIZE)) {
+            Some(src) => dst.copy_from_slice(src),
+            None => dst.fill(0),
+        }
+    }
+}
+
+impl super::Memory for Memory {
+    fn read8(&mut self, address: u16) -> u8 {
+        match address {
+            APU_REGISTERS_START..APU_REGISTERS_END => self.apu.read8(address),
+            _ => self.peek8(address),
+        }
+    }
+
+    fn peek8(&self, address: u16) -> u8 {
+        match address {
+            ..RAM_MIRROR_END => self.ram[(address % RAM_SIZE) as usize],
+            APU_REGISTERS_START..APU_REGISTERS_END => self.apu.peek8(address),
+            fds::WAVETABLE_START..fds::REGISTERS_END if self.fds_audio.is_some() => {
+                self.fds_audio.as_ref().unwrap().read8(address).unwrap_or(0)
+            }
+            PLAYER_START..PLAYER_END => self.player[(address - PLAYER_START) as usize],
+            FDS_RAM_START.. if self.fds_ram.is_some() => {
+                self.fds_ram.as_ref().unwrap()[(address - FDS_RAM_START) as usize]
+            }
+            SRAM_START..SRAM_END => self.sram[(address - SRAM_START) as usize],
+            PRG_START.. => read_prg(&self.prg, &self.banks, address),
+            // open bus
+            _ => 0,
+        }
+    }
+
+    fn write8(&mut self, address: u16, value: u8) {
+        match address {
+            ..RAM_MIRROR_END => self.ram[(address % RAM_SIZE) as usize] = value,
+            APU_REGISTERS_START..APU_REGISTERS_END => self.apu.write8(address, value),
+            fds::WAVETABLE_START..fds::REGISTERS_END if self.fds_audio.is_some() => {
+                self.fds_audio.as_mut().unwrap().write8(address, value)
+            }
+            FDS_BANK_REGISTERS_START..BANK_REGISTERS_END if self.fds_ram.is_some() => {
+                self.switch_fds_bank((address - FDS_BANK_REGISTERS_START) as usize, value)
+            }
+            BANK_REGISTERS_START..BANK_REGISTERS_END => {
+                self.banks[(address - BANK_REGISTERS_START) as usize] = value
+            }
+            FDS_RAM_START.. if self.fds_ram.is_some() => {
+                self.fds_ram.as_mut().unwrap()[(address - FDS_RAM_START) as usize] = value
+            }
+            SRAM_START..SRAM_END => self.sram[(address - SRAM_START) as usize] = value,
+            // everything else is rom or unmapped
+            _ => (),
+        }
+    }
+}
+
+fn read_prg(prg: &[u8], banks: &[u8; 8], address: u16) -> u8 {
+    let offset = (address - PRG_START) as usize;
+    let bank = banks[offset / BANK_SIZE] as usize;
+    prg.get(bank * BANK_SIZE + offset % BANK_SIZE)
+        .copied()
+        .unwrap_or(0)
+}
@@ -0,0 +1,268 @@
+// Lets debuggers, cheat searches, code/data loggers and the like see bus traffic without the memory behind it knowing.
+
+use std::{
+    cell::RefCell,
+    ops::RangeInclusive,
+    sync::mpsc::{self, Receiver},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    CPU,
+    PPU,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    /// An opcode fetch, reported by whoever is stepping the CPU. The fetch is reported as a read too.
+    Execute,
+}
+
+/// Who is driving the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accessor {
+    CPU,
+    PPU,
+    /// Sprite and DMC DMA, which take the CPU bus over.
+    DMA,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub bus: Bus,
+    pub accessor: Accessor,
+    pub kind: AccessKind,
+    pub address: u16,
+    pub value: u8,
+    /// CPU cycle the instruction making the access started on, the CPU isn't stepped a cycle at a time.
+    pub cycle: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+struct Observer {
+    id: ObserverId,
+    bus: Bus,
+    kinds: Vec<AccessKind>,
+    range: RangeInclusive<u16>,
+    callback: Box<dyn FnMut(&Access)>,
+}
+
+/// Everyone watching a machine's buses, each with the accesses they care about.
+#[derive(Default)]
+pub struct Observers {
+    observers: Vec<Observer>,
+    next_id: u64,
+}
+
+impl Observers {
+    pub fn new() -> Self {
+        Self {
+            observers: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// The callback gets every access of one of the given kinds to the range on the bus. It mustn't touch the
+    /// `Observers` it's registered with.
+    pub fn add<F>(
+        &mut self,
+        bus: Bus,
+        kinds: &[AccessKind],
+        range: RangeInclusive<u16>,
+        callback: F,
+    ) -> ObserverId
+    where
+        F: FnMut(&Access) + 'static,
+    {
+        let id = ObserverId(self.next_id);
+        self.next_id += 1;
+        self.observers.push(Observer {
+            id,
+            bus,
+            kinds: kinds.to_vec(),
+            range,
+            callback: Box::new(callback),
+        });
+        id
+    }
+
+    /// As add, but the accesses queue up in a channel. The observer stays until removed, even if the receiver is
+    /// dropped.
+    pub fn add_channel(
+        &mut self,
+        bus: Bus,
+        kinds: &[AccessKind],
+        range: RangeInclusive<u16>,
+    ) -> (ObserverId, Receiver<Access>) {
+        let (sender, receiver) = mpsc::channel();
+        let id = self.add(bus, kinds, range, move |access| {
+            let _ = sender.send(*access);
+        });
+        (id, receiver)
+    }
+
+    /// Returns whether it was there to remove.
+    pub fn remove(&mut self, id: ObserverId) -> bool {
+        let len = self.observers.len();
+        self.observers.retain(|x| x.id != id);
+        self.observers.len() != len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    pub fn notify(&mut self, access: &Access) {
+        for observer in self.observers.iter_mut() {
+            if observer.bus == access.bus
+                && observer.kinds.contains(&access.kind)
+                && observer.range.contains(&access.address)
+            {
+                (observer.callback)(access);
+            }
+        }
+    }
+}
+
+/// Passes everything through to the wrapped memory, telling the observers about each access on the way.
+pub struct Observed<'a, M> {
+    memory: &'a mut M,
+    observers: &'a RefCell<Observers>,
+    bus: Bus,
+    accessor: Accessor,
+    cycle: u64,
+}
+
+impl<'a, M> Observed<'a, M>
+where
+    M: super::Memory,
+{
+    /// Accesses are stamped with the given cycle.
+    pub fn new(
+        memory: &'a mut M,
+        observers: &'a RefCell<Observers>,
+        bus: Bus,
+        accessor: Accessor,
+        cycle: u64,
+    ) -> Self {
+        Self {
+            memory,
+            observers,
+            bus,
+            accessor,
+            cycle,
+        }
+    }
+
+    /// For the CPU's driver to call before stepping it, as the bus can't tell opcode fetches from other reads. Peeks, the
+    /// CPU does the real read.
+    pub fn execute(&self, address: u16) {
+        let value = self.memory.peek8(address);
+        self.notify(AccessKind::Execute, address, value);
+    }
+
+    fn notify(&self, kind: AccessKind, address: u16, value: u8) {
+        self.observers.borrow_mut().notify(&Access {
+            bus: self.bus,
+            accessor: self.accessor,
+            kind,
+            address,
+            value,
+            cycle: self.cycle,
+        });
+    }
+}
+
+impl<M> super::Memory for Observed<'_, M>
+where
+    M: super::Memory,
+{
+    fn read8(&mut self, address: u16) -> u8 {
+        let value = self.memory.read8(address);
+        self.notify(AccessKind::Read, address, value);
+        value
+    }
+
+    /// Peeks aren't accesses, so nobody's told.
+    fn peek8(&self, address: u16) -> u8 {
+        self.memory.peek8(address)
+    }
+
+    fn write8(&mut self, address: u16, value: u8) {
+        self.memory.write8(address, value);
+        self.notify(AccessKind::Write, address, value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::{Access, AccessKind, Accessor, Bus, Observed, Observers};
+    use crate::{cpu::CPU, memory::Memory, test_utils::test::TestMemory};
+
+    #[test]
+    pub fn observers() {
+        let mut memory = TestMemory::new();
+        // LDA $10, STA $0200, STA $0300
+        for (i, value) in [0xa5, 0x10, 0x8d, 0x00, 0x02, 0x8d, 0x00, 0x03]
+            .iter()
+            .enumerate()
+        {
+            memory.write8(0x8000 + i as u16, *value);
+        }
+        memory.write8(0x10, 0x5a);
+
+        let observers = RefCell::new(Observers::new());
+        let (_, writes) =
+            observers
+                .borrow_mut()
+                .add_channel(Bus::CPU, &[AccessKind::Write], 0x0200..=0x02ff);
+        let executes = Rc::new(RefCell::new(Vec::new()));
+        let id = {
+            let executes = executes.clone();
+            observers.borrow_mut().add(
+                Bus::CPU,
+                &[AccessKind::Execute],
+                0x8000..=0xffff,
+                move |access| executes.borrow_mut().push(access.address),
+            )
+        };
+        let (_, ppu) = observers.borrow_mut().add_channel(
+            Bus::PPU,
+            &[AccessKind::Read, AccessKind::Write],
+            0..=0xffff,
+        );
+
+        let mut cpu = CPU::new();
+        cpu.pc = 0x8000;
+        for _ in 0..3 {
+            if cpu.pc == 0x8005 {
+                assert!(observers.borrow_mut().remove(id));
+            }
+            let mut memory =
+                Observed::new(&mut memory, &observers, Bus::CPU, Accessor::CPU, cpu.clock);
+            memory.execute(cpu.pc);
+            cpu.step(&mut memory);
+        }
+
+        assert_eq!(*executes.borrow(), [0x8000, 0x8002]);
+        assert_eq!(
+            writes.try_iter().collect::<Vec<_>>(),
+            [Access {
+                bus: Bus::CPU,
+                accessor: Accessor::CPU,
+                kind: AccessKind::Write,
+                address: 0x0200,
+                value: 0x5a,
+                cycle: 3,
+            }]
+        );
+        assert_eq!(memory.read8(0x0300), 0x5a);
+        assert!(ppu.try_iter().next().is_none());
+    }
+}
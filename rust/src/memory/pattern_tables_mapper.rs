@@ -1,15 +1,40 @@
+use serde_json::Value;
+
+use crate::save_state::SaveStateError;
+
 pub trait PatternTableMemoryMapper {
     /// Reads from pattern table 0.
     /// Address will be already adjusted to be in 0..PATTERN_TABLE_SIZE.
-    fn read8_pattern_table_0(&self, address: u16) -> u8;
+    /// Mappers like MMC2 switch banks on seeing the PPU fetch certain tiles, those override this.
+    fn read8_pattern_table_0(&mut self, address: u16) -> u8 {
+        self.peek8_pattern_table_0(address)
+    }
+
+    /// As read, but mustn't change anything.
+    fn peek8_pattern_table_0(&self, address: u16) -> u8;
 
     /// As read.
     fn write8_pattern_table_0(&mut self, address: u16, value: u8);
 
-    /// Reads from pattern table 0.
+    /// Reads from pattern table 1.
     /// Address will be already adjusted to be in 0..PATTERN_TABLE_SIZE.
-    fn read8_pattern_table_1(&self, address: u16) -> u8;
+    fn read8_pattern_table_1(&mut self, address: u16) -> u8 {
+        self.peek8_pattern_table_1(address)
+    }
+
+    /// As read, but mustn't change anything.
+    fn peek8_pattern_table_1(&self, address: u16) -> u8;
 
     /// As read.
     fn write8_pattern_table_1(&mut self, address: u16, value: u8);
+
+    /// CHR-RAM and bank registers, for save states. Boards without any can leave these alone.
+    fn save_state(&self) -> Value {
+        Value::Null
+    }
+
+    /// Given what save_state returned, possibly from an older or newer build.
+    fn load_state(&mut self, _state: Value) -> Result<(), SaveStateError> {
+        Ok(())
+    }
 }
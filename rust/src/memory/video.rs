@@ -1,7 +1,11 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
 use super::{
     name_attr_tables_mapper::NameAndAttributeTablesMemoryMapper,
     pattern_tables_mapper::PatternTableMemoryMapper,
 };
+use crate::save_state::{self, SaveStateError};
 
 const PATTERN_TABLE_0_START: u16 = 0x0000;
 const PATTERN_TABLE_0_END: u16 = 0x1000;
@@ -34,8 +38,9 @@ const NAME_AND_ATTRIBUTE_TABLES_TOTAL_SIZE: u16 = ATTRIBUTE_TABLE_3_END - NAME_T
 const NAME_AND_ATTRIBUTE_TABLE_MIRRORS_START: u16 = ATTRIBUTE_TABLE_3_END;
 const NAME_AND_ATTRIBUTE_TABLE_MIRRORS_END: u16 = 0x3f00;
 
-const IMAGE_PALETTE_START: u16 = NAME_AND_ATTRIBUTE_TABLE_MIRRORS_END;
+pub const IMAGE_PALETTE_START: u16 = NAME_AND_ATTRIBUTE_TABLE_MIRRORS_END;
 const IMAGE_PALETTE_END: u16 = 0x3f10;
+pub const SPRITE_PALETTE_START: u16 = IMAGE_PALETTE_END;
 const SPRITE_PALETTE_END: u16 = 0x3f20;
 const IMAGE_AND_SPRITE_PALETTE_TOTAL_SIZE: u16 = SPRITE_PALETTE_END - IMAGE_PALETTE_START;
 
@@ -45,9 +50,21 @@ const IMAGE_AND_SPRITE_PALETTE_MIRRORS_END: u16 = 0x4000;
 const MIRRORS_START: u16 = IMAGE_AND_SPRITE_PALETTE_MIRRORS_END;
 const MIRRORED_CONTENT_SIZE: u16 = IMAGE_AND_SPRITE_PALETTE_MIRRORS_END;
 
+#[derive(Serialize, Deserialize)]
+struct State {
+    #[serde(default)]
+    pattern_tables: Value,
+    #[serde(default)]
+    name_and_attribute_tables: Value,
+    #[serde(default, with = "save_state::hex")]
+    palette: [u8; IMAGE_AND_SPRITE_PALETTE_TOTAL_SIZE as usize],
+}
+
+/// The PPU's bus. The palette is inside the PPU rather than on the cartridge, but it's addressed like the rest.
 pub struct Memory {
     pattern_table_mapper: Box<dyn PatternTableMemoryMapper>,
     name_and_attribute_table_mapper: Box<dyn NameAndAttributeTablesMemoryMapper>,
+    palette: [u8; IMAGE_AND_SPRITE_PALETTE_TOTAL_SIZE as usize],
 }
 
 impl Memory {
@@ -58,8 +75,26 @@ impl Memory {
         Self {
             pattern_table_mapper,
             name_and_attribute_table_mapper,
+            palette: [0; IMAGE_AND_SPRITE_PALETTE_TOTAL_SIZE as usize],
         }
     }
+
+    /// CHR-RAM, nametables and the palette.
+    pub fn save_state(&self) -> Value {
+        save_state::to_value(&State {
+            pattern_tables: self.pattern_table_mapper.save_state(),
+            name_and_attribute_tables: self.name_and_attribute_table_mapper.save_state(),
+            palette: self.palette,
+        })
+    }
+
+    pub fn load_state(&mut self, state: Value) -> Result<(), SaveStateError> {
+        let state: State = save_state::from_value(state)?;
+        self.palette = state.palette;
+        self.pattern_table_mapper.load_state(state.pattern_tables)?;
+        self.name_and_attribute_table_mapper
+            .load_state(state.name_and_attribute_tables)
+    }
 }
 
 impl super::Memory for Memory {
@@ -81,7 +116,7 @@ impl super::Memory for Memory {
         there is actually a full 4 kb of ram backing these, they're all distinct
     */
 
-    fn read8(&self, address: u16) -> u8 {
+    fn read8(&mut self, address: u16) -> u8 {
         match address {
             ..PATTERN_TABLE_0_END => self
                 .pattern_table_mapper
@@ -89,6 +124,20 @@ impl super::Memory for Memory {
             ..PATTERN_TABLE_1_END => self
                 .pattern_table_mapper
                 .read8_pattern_table_1(address - PATTERN_TABLE_1_START),
+            // only the cartridge cares what the PPU reads, and it only sees the pattern tables
+            ..MIRRORS_START => self.peek8(address),
+            _ => self.read8(address % MIRRORED_CONTENT_SIZE),
+        }
+    }
+
+    fn peek8(&self, address: u16) -> u8 {
+        match address {
+            ..PATTERN_TABLE_0_END => self
+                .pattern_table_mapper
+                .peek8_pattern_table_0(address - PATTERN_TABLE_0_START),
+            ..PATTERN_TABLE_1_END => self
+                .pattern_table_mapper
+                .peek8_pattern_table_1(address - PATTERN_TABLE_1_START),
             ..NAME_TABLE_0_END => self
                 .name_and_attribute_table_mapper
                 .read8_name_table_0(address - NAME_TABLE_0_START),
@@ -113,17 +162,16 @@ impl super::Memory for Memory {
             ..ATTRIBUTE_TABLE_3_END => self
                 .name_and_attribute_table_mapper
                 .read8_attribute_table_3(address - ATTRIBUTE_TABLE_3_START),
-            ..NAME_AND_ATTRIBUTE_TABLE_MIRRORS_END => self.read8(
+            ..NAME_AND_ATTRIBUTE_TABLE_MIRRORS_END => self.peek8(
                 (address - NAME_TABLE_0_START) % NAME_AND_ATTRIBUTE_TABLES_TOTAL_SIZE
                     + NAME_TABLE_0_START,
             ),
-            ..IMAGE_PALETTE_END => todo!(),
-            ..SPRITE_PALETTE_END => todo!(),
-            ..IMAGE_AND_SPRITE_PALETTE_MIRRORS_END => self.read8(
+            ..SPRITE_PALETTE_END => self.palette[palette_index(address)],
+            ..IMAGE_AND_SPRITE_PALETTE_MIRRORS_END => self.peek8(
                 (address - IMAGE_PALETTE_START) % IMAGE_AND_SPRITE_PALETTE_TOTAL_SIZE
                     + IMAGE_PALETTE_START,
             ),
-            _ => self.read8(address % MIRRORED_CONTENT_SIZE),
+            _ => self.peek8(address % MIRRORED_CONTENT_SIZE),
         }
     }
 
@@ -164,8 +212,8 @@ impl super::Memory for Memory {
                     + NAME_TABLE_0_START,
                 value,
             ),
-            ..IMAGE_PALETTE_END => todo!(),
-            ..SPRITE_PALETTE_END => todo!(),
+            // only 6 bits are stored
+            ..SPRITE_PALETTE_END => self.palette[palette_index(address)] = value & 0b0011_1111,
             ..IMAGE_AND_SPRITE_PALETTE_MIRRORS_END => self.write8(
                 (address - IMAGE_PALETTE_START) % IMAGE_AND_SPRITE_PALETTE_TOTAL_SIZE
                     + IMAGE_PALETTE_START,
@@ -175,3 +223,13 @@ impl super::Memory for Memory {
         }
     }
 }
+
+/// Each sprite palette's first color is the image palette's, so $3F10 is $3F00 and so on.
+fn palette_index(address: u16) -> usize {
+    let index = (address - IMAGE_PALETTE_START) as usize;
+    if index & 0b1_0011 == 0b1_0000 {
+        index & 0b0_1111
+    } else {
+        index
+    }
+}
@@ -0,0 +1,487 @@
+// Input movies in FCEUX's .fm2 format, see https://fceux.com/web/help/fm2.html
+//
+// Only the text format with a controller or nothing in each port is supported, no Four Score, Zapper or Famicom
+// expansion port devices. A movie that starts from a save state embeds one of ours rather than FCEUX's, so neither
+// emulator can play the other's.
+
+use std::{error::Error, fmt::Display};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use bitflags::bitflags;
+use md5::{Digest, Md5};
+
+use crate::{cartridge_file::Cartridge, console::Console, controller::Buttons, region::Region};
+
+const VERSION: u32 = 3;
+/// SI_NONE and SI_GAMEPAD in FCEUX.
+const PORT_NONE: u8 = 0;
+const PORT_GAMEPAD: u8 = 1;
+/// Each button's character in an input log line, pressed ones are shown and the rest are spaces or dots.
+const BUTTON_CHARACTERS: [(char, Buttons); 8] = [
+    ('R', Buttons::RIGHT),
+    ('L', Buttons::LEFT),
+    ('D', Buttons::DOWN),
+    ('U', Buttons::UP),
+    ('T', Buttons::START),
+    ('S', Buttons::SELECT),
+    ('B', Buttons::B),
+    ('A', Buttons::A),
+];
+
+bitflags! {
+    /// What happens to the console at the start of a frame, besides the controllers.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Commands: u8 {
+        const RESET = 0b0000_0001;
+        const POWER = 0b0000_0010;
+        const FDS_INSERT = 0b0000_0100;
+        const FDS_SELECT = 0b0000_1000;
+        const VS_INSERT_COIN = 0b0001_0000;
+    }
+}
+
+#[derive(Debug)]
+pub enum MovieError {
+    /// Line number and what's wrong with it.
+    Syntax(usize, String),
+    Unsupported(String),
+    /// Recorded with a different ROM, MD5s given.
+    Desync {
+        expected: [u8; 16],
+        actual: [u8; 16],
+    },
+}
+
+impl Display for MovieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for MovieError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Frame {
+    pub commands: Commands,
+    pub ports: [Buttons; 2],
+}
+
+impl Frame {
+    /// Puts the buttons on the controllers, presses reset and puts a coin in slot 1 if asked to. Power cycling needs
+    /// the cartridge, so that's left to the caller, and the FDS commands aren't supported.
+    pub fn apply(&self, console: &mut Console) {
+        if self.commands.contains(Commands::RESET) {
+            console.reset();
+        }
+        if self.commands.contains(Commands::VS_INSERT_COIN) {
+            if let Some(vs_system) = console.vs_system() {
+                vs_system.insert_coin(0);
+            }
+        }
+        for (port, buttons) in self.ports.iter().enumerate() {
+            console.set_buttons(port, *buttons);
+        }
+    }
+}
+
+pub struct Movie {
+    rom_filename: String,
+    rom_checksum: [u8; 16],
+    guid: String,
+    region: Region,
+    rerecord_count: u32,
+    /// Whether each port has a controller.
+    ports: [bool; 2],
+    comments: Vec<String>,
+    /// Plays from here rather than power on.
+    save_state: Option<Vec<u8>>,
+    /// Header lines this doesn't use, like subtitles, kept as they were.
+    other: Vec<(String, String)>,
+    frames: Vec<Frame>,
+}
+
+impl Movie {
+    /// An empty movie to record into, with controllers in both ports.
+    pub fn new(
+        rom_filename: &str,
+        rom_checksum: [u8; 16],
+        region: Region,
+        save_state: Option<Vec<u8>>,
+    ) -> Self {
+        Self {
+            rom_filename: rom_filename.to_string(),
+            rom_checksum,
+            guid: new_guid(&rom_checksum),
+            region,
+            rerecord_count: 0,
+            ports: [true, true],
+            comments: Vec::new(),
+            save_state,
+            other: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn from_fm2(text: &str) -> Result<Self, MovieError> {
+        let mut result = Self::new("", [0; 16], Region::NTSC, None);
+        result.guid.clear();
+        let mut version = None;
+        let mut pal = false;
+        let mut region = None;
+        for (number, line) in text.lines().enumerate() {
+            let number = number + 1;
+            let syntax = |message: &str| MovieError::Syntax(number, message.to_string());
+            if line.starts_with('|') {
+                result.frames.push(
+                    result
+                        .parse_frame(line)
+                        .ok_or_else(|| syntax("bad input log line"))?,
+                );
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let number_value = || {
+                value
+                    .trim()
+                    .parse::<u32>()
+                    .map_err(|_| syntax("expected a number"))
+            };
+            match key {
+                "version" => version = Some(number_value()?),
+                "romFilename" => result.rom_filename = value.to_string(),
+                "romChecksum" => {
+                    result.rom_checksum = decode_binary(value)
+                        .and_then(|x| x.try_into().ok())
+                        .ok_or_else(|| syntax("expected a 16 byte checksum"))?
+                }
+                "guid" => result.guid = value.to_string(),
+                "palFlag" => pal = number_value()? != 0,
+                "region" => region = Some(value.trim().parse().map_err(|e: String| syntax(&e))?),
+                "rerecordCount" => result.rerecord_count = number_value()?,
+                "comment" => result.comments.push(value.to_string()),
+                "savestate" => {
+                    result.save_state =
+                        Some(decode_binary(value).ok_or_else(|| syntax("bad save state"))?)
+                }
+                "port0" | "port1" => {
+                    let port = if key == "port0" { 0 } else { 1 };
+                    result.ports[port] = match number_value()? as u8 {
+                        PORT_NONE => false,
+                        PORT_GAMEPAD => true,
+                        x => return Err(MovieError::Unsupported(format!("{} device {}", key, x))),
+                    };
+                }
+                "port2" if number_value()? != 0 => {
+                    return Err(MovieError::Unsupported(
+                        "expansion port devices".to_string(),
+                    ))
+                }
+                "fourscore" if number_value()? != 0 => {
+                    return Err(MovieError::Unsupported("the Four Score".to_string()))
+                }
+                "binary" if number_value()? != 0 => {
+                    return Err(MovieError::Unsupported("binary input logs".to_string()))
+                }
+                // emuVersion is whoever wrote the file's, the others are only supported as 0
+                "port2" | "fourscore" | "binary" | "emuVersion" => {}
+                _ => result.other.push((key.to_string(), value.to_string())),
+            }
+        }
+        result.region = region.unwrap_or(if pal { Region::PAL } else { Region::NTSC });
+        match version {
+            Some(VERSION) => Ok(result),
+            Some(x) => Err(MovieError::Unsupported(format!("version {}", x))),
+            None => Err(MovieError::Syntax(1, "no version".to_string())),
+        }
+    }
+
+    /// `|commands|port0|port1|port2|`, with the ports' buttons as in BUTTON_CHARACTERS or empty without a controller.
+    fn parse_frame(&self, line: &str) -> Option<Frame> {
+        let mut fields = line.strip_prefix('|')?.split('|');
+        let commands = Commands::from_bits_retain(fields.next()?.trim().parse().ok()?);
+        let mut ports = [Buttons::empty(); 2];
+        for (buttons, connected) in ports.iter_mut().zip(self.ports) {
+            let field = fields.next()?;
+            if !connected {
+                continue;
+            }
+            if field.chars().count() != BUTTON_CHARACTERS.len() {
+                return None;
+            }
+            for (c, (_, button)) in field.chars().zip(BUTTON_CHARACTERS) {
+                buttons.set(button, c != ' ' && c != '.');
+            }
+        }
+        Some(Frame { commands, ports })
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut result = format!("version {}\n", VERSION);
+        let mut header = |key: &str, value: &str| {
+            result.push_str(key);
+            result.push(' ');
+            result.push_str(value);
+            result.push('\n');
+        };
+        header("emuVersion", "0");
+        header("rerecordCount", &self.rerecord_count.to_string());
+        header(
+            "palFlag",
+            if self.region == Region::PAL { "1" } else { "0" },
+        );
+        // FCEUX has no flag for a Dendy, so that gets a line of its own which FCEUX skips over
+        if self.region == Region::Dendy {
+            header("region", "dendy");
+        }
+        header("romFilename", &self.rom_filename);
+        header("romChecksum", &encode_binary(&self.rom_checksum));
+        header("guid", &self.guid);
+        header("fourscore", "0");
+        for (i, connected) in self.ports.iter().enumerate() {
+            let device = if *connected { PORT_GAMEPAD } else { PORT_NONE };
+            header(&format!("port{}", i), &device.to_string());
+        }
+        header("port2", "0");
+        for comment in &self.comments {
+            header("comment", comment);
+        }
+        for (key, value) in &self.other {
+            header(key, value);
+        }
+        if let Some(save_state) = &self.save_state {
+            header("savestate", &encode_binary(save_state));
+        }
+
+        for frame in &self.frames {
+            result.push_str(&format!("|{}|", frame.commands.bits()));
+            for (buttons, connected) in frame.ports.iter().zip(self.ports) {
+                if connected {
+                    for (c, button) in BUTTON_CHARACTERS {
+                        result.push(if buttons.contains(button) { c } else { '.' });
+                    }
+                }
+                result.push('|');
+            }
+            result.push_str("|\n");
+        }
+        result
+    }
+
+    pub fn record(&mut self, frame: Frame) {
+        self.frames.push(frame);
+    }
+
+    /// Errors if the movie wasn't recorded with this ROM, as it would most likely desync.
+    pub fn check_rom(&self, checksum: [u8; 16]) -> Result<(), MovieError> {
+        if checksum != self.rom_checksum {
+            return Err(MovieError::Desync {
+                expected: self.rom_checksum,
+                actual: checksum,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn rom_filename(&self) -> &str {
+        &self.rom_filename
+    }
+
+    /// From palFlag, unless there's a region line saying it's a Dendy.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn save_state(&self) -> Option<&[u8]> {
+        self.save_state.as_deref()
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+}
+
+/// FCEUX's checksum, the MD5 of PRG ROM followed by CHR ROM.
+pub fn rom_checksum(cartridge: &Cartridge) -> [u8; 16] {
+    let rom = cartridge
+        .prg_rom_bytes()
+        .chain(cartridge.chr_rom_bytes())
+        .collect::<Vec<_>>();
+    Md5::digest(&rom).into()
+}
+
+/// FCEUX writes binary header values as base64 but reads hex too.
+fn decode_binary(value: &str) -> Option<Vec<u8>> {
+    if let Some(base64) = value.strip_prefix("base64:") {
+        BASE64_STANDARD.decode(base64.trim()).ok()
+    } else {
+        let hex = value.strip_prefix("0x").unwrap_or(value);
+        crate::save_state::hex::decode(&hex.to_ascii_lowercase()).ok()
+    }
+}
+
+fn encode_binary(value: &[u8]) -> String {
+    format!("base64:{}", BASE64_STANDARD.encode(value))
+}
+
+/// Made up from the time and the ROM, it only has to tell movies apart.
+fn new_guid(rom_checksum: &[u8; 16]) -> String {
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut md5 = Md5::new();
+    md5.update(time.to_le_bytes());
+    md5.update(rom_checksum);
+    let hex = md5
+        .finalize()
+        .iter()
+        .map(|x| format!("{:02X}", x))
+        .collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Commands, Frame, Movie, MovieError};
+    use crate::{console::Console, controller::Buttons, region::Region, test_utils::test::picture};
+
+    const FM2: &str = "version 3
+emuVersion 22020
+rerecordCount 4
+palFlag 0
+romFilename smb
+guid 6B8F4A2C-1D3E-4F5A-8B9C-0D1E2F3A4B5C
+romChecksum base64:2P9Sx0WdnuJPKi3wKkMtBA==
+fourscore 0
+port0 1
+port1 1
+port2 0
+comment author someone
+subtitle 2 hello
+|2|........|........||
+|0|....T...|........||
+|0|R......A| .....B.||
+|1|........|........||
+";
+
+    #[test]
+    pub fn fm2() {
+        let movie = Movie::from_fm2(FM2).unwrap();
+        assert_eq!(movie.rom_filename(), "smb");
+        assert_eq!(movie.region(), Region::NTSC);
+        assert_eq!(
+            movie.frames(),
+            &[
+                Frame {
+                    commands: Commands::POWER,
+                    ports: [Buttons::empty(); 2]
+                },
+                Frame {
+                    commands: Commands::empty(),
+                    ports: [Buttons::START, Buttons::empty()]
+                },
+                Frame {
+                    commands: Commands::empty(),
+                    ports: [Buttons::RIGHT | Buttons::A, Buttons::B]
+                },
+                Frame {
+                    commands: Commands::RESET,
+                    ports: [Buttons::empty(); 2]
+                },
+            ]
+        );
+
+        // everything but emuVersion survives, and the checksum has to match
+        let text = movie.to_fm2();
+        assert_eq!(
+            text.replace("emuVersion 0", "emuVersion 22020")
+                .lines()
+                .count(),
+            FM2.lines().count()
+        );
+        let again = Movie::from_fm2(&text).unwrap();
+        assert_eq!(again.frames(), movie.frames());
+        assert_eq!(again.to_fm2(), text);
+        let checksum = movie.rom_checksum;
+        movie.check_rom(checksum).unwrap();
+        assert!(matches!(
+            movie.check_rom([0; 16]),
+            Err(MovieError::Desync { .. })
+        ));
+
+        assert!(matches!(
+            Movie::from_fm2(&FM2.replace("port1 1", "port1 2")),
+            Err(MovieError::Unsupported(_))
+        ));
+        assert!(matches!(
+            Movie::from_fm2(&FM2.replace("|1|", "|x|")),
+            Err(MovieError::Syntax(17, _))
+        ));
+    }
+
+    #[test]
+    pub fn region() {
+        let pal = Movie::from_fm2(&FM2.replace("palFlag 0", "palFlag 1")).unwrap();
+        assert_eq!(pal.region(), Region::PAL);
+        assert!(pal.to_fm2().contains("palFlag 1\n"));
+        assert!(!pal.to_fm2().contains("region"));
+
+        let movie = Movie::new("smb", [0; 16], Region::Dendy, None);
+        let text = movie.to_fm2();
+        assert!(text.contains("palFlag 0\n"));
+        assert!(text.contains("region dendy\n"));
+        assert_eq!(Movie::from_fm2(&text).unwrap().region(), Region::Dendy);
+        assert!(matches!(
+            Movie::from_fm2(&text.replace("region dendy", "region secam")),
+            Err(MovieError::Syntax(_, _))
+        ));
+    }
+
+    #[test]
+    pub fn play() {
+        let cartridge = picture();
+        let mut movie = Movie::new(
+            "picture.nes",
+            super::rom_checksum(&cartridge),
+            Region::NTSC,
+            None,
+        );
+        for i in 0..10 {
+            movie.record(Frame {
+                commands: if i == 6 {
+                    Commands::RESET
+                } else {
+                    Commands::empty()
+                },
+                ports: [Buttons::A, Buttons::empty()],
+            });
+        }
+        let movie = Movie::from_fm2(&movie.to_fm2()).unwrap();
+        movie.check_rom(super::rom_checksum(&cartridge)).unwrap();
+
+        let mut console = Console::new(&cartridge, 44100, None).unwrap();
+        let hashes = movie
+            .frames()
+            .iter()
+            .map(|frame| {
+                frame.apply(&mut console);
+                console.run_frame();
+                console.framebuffer().hash()
+            })
+            .collect::<Vec<_>>();
+        // the picture's up by the fifth frame, gone while the reset waits for the PPU and back after
+        assert_eq!(hashes[4], 2212948002);
+        assert_ne!(hashes[6], 2212948002);
+        assert_eq!(hashes[9], 2212948002);
+    }
+}
@@ -0,0 +1,537 @@
+// see https://www.nesdev.org/wiki/NSF and https://www.nesdev.org/wiki/NSFe
+
+use bitflags::bitflags;
+use std::{error::Error, fmt::Display};
+
+use crate::cartridge_file::TVSystem;
+
+#[derive(Debug, Clone, Copy)]
+pub enum NsfError {
+    BadHeader,
+    MissingData,
+    NoSongs,
+    BadChunk([u8; 4]),
+    MissingChunk([u8; 4]),
+    UnrecognizedRequiredChunk([u8; 4]),
+    /// Below $8000 without bank switching, or $6000 for FDS files which have RAM there.
+    BadLoadAddress(u16),
+}
+
+impl Display for NsfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NsfError::BadHeader => write!(f, "not an NSF or NSFe file, or its header is cut short"),
+            NsfError::MissingData => write!(f, "the file has no program data"),
+            NsfError::NoSongs => write!(f, "the file says it has no songs"),
+            NsfError::BadChunk(id) => {
+                write!(f, "{} chunk is truncated or too short", chunk_name(id))
+            }
+            NsfError::MissingChunk(id) => write!(f, "missing {} chunk", chunk_name(id)),
+            NsfError::UnrecognizedRequiredChunk(id) => write!(
+                f,
+                "unsupported {} chunk, which is needed to play the file",
+                chunk_name(id)
+            ),
+            NsfError::BadLoadAddress(address) => write!(
+                f,
+                "load address ${:04X} is too low for a file without bank switching",
+                address
+            ),
+        }
+    }
+}
+
+fn chunk_name(id: &[u8; 4]) -> String {
+    String::from_utf8_lossy(id).into_owned()
+}
+
+impl Error for NsfError {}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ExpansionAudio: u8 {
+        const VRC6 = 0b0000_0001;
+        const VRC7 = 0b0000_0010;
+        const FDS = 0b0000_0100;
+        const MMC5 = 0b0000_1000;
+        const NAMCO_163 = 0b0001_0000;
+        const SUNSOFT_5B = 0b0010_0000;
+        const VT02 = 0b0100_0000;
+    }
+}
+
+const NSF_MAGIC: &[u8] = b"NESM\x1a";
+const NSFE_MAGIC: &[u8] = b"NSFE";
+const NSF_HEADER_SIZE: usize = 0x80;
+
+/// Play speeds in microseconds for NSFe files with no RATE chunk.
+const NSFE_DEFAULT_NTSC_PLAY_SPEED: u16 = 16639;
+const NSFE_DEFAULT_PAL_PLAY_SPEED: u16 = 19997;
+
+/// Whether the data starts like an NSF or NSFe file, to tell them apart from ROMs.
+pub fn is_nsf(data: &[u8]) -> bool {
+    data.starts_with(NSF_MAGIC) || data.starts_with(NSFE_MAGIC)
+}
+
+pub struct Nsf {
+    total_songs: u8,
+    starting_song: u8,
+    load_address: u16,
+    init_address: u16,
+    play_address: u16,
+    title: String,
+    artist: String,
+    copyright: String,
+    ntsc_play_speed: u16,
+    pal_play_speed: u16,
+    bankswitch_init: Option<[u8; 8]>,
+    tv_system: TVSystem,
+    expansion_audio: ExpansionAudio,
+    track_labels: Vec<String>,
+    data: Vec<u8>,
+}
+
+impl Nsf {
+    /// Accepts both NSF and NSFe, telling them apart by the magic number.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, NsfError> {
+        if data.starts_with(NSF_MAGIC) {
+            Self::from_nsf_bytes(data)
+        } else if data.starts_with(NSFE_MAGIC) {
+            Self::from_nsfe_bytes(&data)
+        } else {
+            Err(NsfError::BadHeader)
+        }
+    }
+
+    fn from_nsf_bytes(data: Vec<u8>) -> Result<Self, NsfError> {
+        if data.len() < NSF_HEADER_SIZE {
+            return Err(NsfError::BadHeader);
+        }
+        let header = &data[0..NSF_HEADER_SIZE];
+
+        // NSF2 can give an explicit data length, after which there's metadata we don't care about
+        let data_length = (header[0x7d] as usize)
+            | ((header[0x7e] as usize) << 8)
+            | ((header[0x7f] as usize) << 16);
+        let payload = &data[NSF_HEADER_SIZE..];
+        let payload = if header[5] >= 2 && data_length != 0 {
+            if payload.len() < data_length {
+                return Err(NsfError::MissingData);
+            }
+            &payload[0..data_length]
+        } else {
+            payload
+        };
+        if payload.is_empty() {
+            return Err(NsfError::MissingData);
+        }
+
+        let bankswitch_init: [u8; 8] = header[0x70..0x78].try_into().unwrap();
+
+        let result = Self {
+            total_songs: header[6],
+            starting_song: header[7],
+            load_address: read_u16(header, 0x08),
+            init_address: read_u16(header, 0x0a),
+            play_address: read_u16(header, 0x0c),
+            title: read_string(&header[0x0e..0x2e]),
+            artist: read_string(&header[0x2e..0x4e]),
+            copyright: read_string(&header[0x4e..0x6e]),
+            ntsc_play_speed: read_u16(header, 0x6e),
+            bankswitch_init: if bankswitch_init.iter().any(|x| *x != 0) {
+                Some(bankswitch_init)
+            } else {
+                None
+            },
+            pal_play_speed: read_u16(header, 0x78),
+            tv_system: tv_system(header[0x7a]),
+            expansion_audio: ExpansionAudio::from_bits_truncate(header[0x7b]),
+            track_labels: Vec::new(),
+            data: payload.to_vec(),
+        };
+        result.validate()
+    }
+
+    fn from_nsfe_bytes(data: &[u8]) -> Result<Self, NsfError> {
+        let mut remaining_data = &data[NSFE_MAGIC.len()..];
+
+        let mut info = None;
+        let mut payload = None;
+        let mut bankswitch_init = None;
+        let mut rate = None;
+        let mut auth = Vec::new();
+        let mut track_labels = Vec::new();
+
+        loop {
+            if remaining_data.len() < 8 {
+                return Err(NsfError::MissingChunk(*b"NEND"));
+            }
+            let length = u32::from_le_bytes(remaining_data[0..4].try_into().unwrap()) as usize;
+            let id: [u8; 4] = remaining_data[4..8].try_into().unwrap();
+            remaining_data = &remaining_data[8..];
+            if remaining_data.len() < length {
+                return Err(NsfError::BadChunk(id));
+            }
+            let chunk = &remaining_data[0..length];
+            remaining_data = &remaining_data[length..];
+
+            match &id {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        return Err(NsfError::BadChunk(id));
+                    }
+                    info = Some(chunk);
+                }
+                b"DATA" => payload = Some(chunk),
+                b"BANK" => {
+                    let mut banks = [0; 8];
+                    for (dst, src) in banks.iter_mut().zip(chunk.iter()) {
+                        *dst = *src;
+                    }
+                    bankswitch_init = Some(banks);
+                }
+                b"RATE" => {
+                    if chunk.len() < 2 {
+                        return Err(NsfError::BadChunk(id));
+                    }
+                    rate = Some(chunk);
+                }
+                b"auth" => {
+                    auth = chunk
+                        .split(|x| *x == 0)
+                        .map(read_string)
+                        .collect::<Vec<_>>();
+                }
+                b"tlbl" => {
+                    track_labels = chunk
+                        .split(|x| *x == 0)
+                        .map(read_string)
+                        .collect::<Vec<_>>();
+                }
+                b"NEND" => break,
+                // chunks starting with an upper case letter must be understood to play the file
+                _ if id[0].is_ascii_uppercase() => {
+                    return Err(NsfError::UnrecognizedRequiredChunk(id))
+                }
+                _ => (),
+            }
+        }
+
+        let info = info.ok_or(NsfError::MissingChunk(*b"INFO"))?;
+        let payload = payload.ok_or(NsfError::MissingChunk(*b"DATA"))?;
+        let mut auth = auth.into_iter();
+
+        let result = Self {
+            total_songs: info.get(8).copied().unwrap_or(1),
+            // NSFe counts from 0, NSF from 1
+            starting_song: info.get(9).copied().unwrap_or(0).saturating_add(1),
+            load_address: read_u16(info, 0),
+            init_address: read_u16(info, 2),
+            play_address: read_u16(info, 4),
+            title: auth.next().unwrap_or_default(),
+            artist: auth.next().unwrap_or_default(),
+            copyright: auth.next().unwrap_or_default(),
+            ntsc_play_speed: rate
+                .map(|x| read_u16(x, 0))
+                .unwrap_or(NSFE_DEFAULT_NTSC_PLAY_SPEED),
+            pal_play_speed: rate
+                .filter(|x| x.len() >= 4)
+                .map(|x| read_u16(x, 2))
+                .unwrap_or(NSFE_DEFAULT_PAL_PLAY_SPEED),
+            bankswitch_init,
+            tv_system: tv_system(info[6]),
+            expansion_audio: ExpansionAudio::from_bits_truncate(info[7]),
+            track_labels,
+            data: payload.to_vec(),
+        };
+        result.validate()
+    }
+
+    fn validate(self) -> Result<Self, NsfError> {
+        if self.total_songs == 0 {
+            return Err(NsfError::NoSongs);
+        }
+        if self.data.is_empty() {
+            return Err(NsfError::MissingData);
+        }
+        let lowest = if self.expansion_audio.contains(ExpansionAudio::FDS) {
+            0x6000
+        } else {
+            0x8000
+        };
+        if self.bankswitch_init.is_none() && self.load_address < lowest {
+            return Err(NsfError::BadLoadAddress(self.load_address));
+        }
+        Ok(self)
+    }
+
+    pub fn total_songs(&self) -> u8 {
+        self.total_songs
+    }
+
+    /// 1-based, as in the file.
+    pub fn starting_song(&self) -> u8 {
+        self.starting_song.clamp(1, self.total_songs)
+    }
+
+    pub fn load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    pub fn init_address(&self) -> u16 {
+        self.init_address
+    }
+
+    pub fn play_address(&self) -> u16 {
+        self.play_address
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn artist(&self) -> &str {
+        &self.artist
+    }
+
+    pub fn copyright(&self) -> &str {
+        &self.copyright
+    }
+
+    /// Microseconds between calls to the play routine.
+    pub fn ntsc_play_speed(&self) -> u16 {
+        self.ntsc_play_speed
+    }
+
+    /// Microseconds between calls to the play routine.
+    pub fn pal_play_speed(&self) -> u16 {
+        self.pal_play_speed
+    }
+
+    /// Initial values for $5FF8-$5FFF, or none if the file isn't bankswitched.
+    pub fn bankswitch_init(&self) -> Option<[u8; 8]> {
+        self.bankswitch_init
+    }
+
+    pub fn tv_system(&self) -> TVSystem {
+        self.tv_system
+    }
+
+    pub fn expansion_audio(&self) -> ExpansionAudio {
+        self.expansion_audio
+    }
+
+    /// Label for the given 1-based track, only NSFe files have these.
+    pub fn track_label(&self, track: u8) -> Option<&str> {
+        self.track_labels
+            .get((track as usize).wrapping_sub(1))
+            .map(|x| x.as_str())
+            .filter(|x| !x.is_empty())
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+}
+
+fn read_u16(data: &[u8], index: usize) -> u16 {
+    (data[index] as u16) | ((data[index + 1] as u16) << 8)
+}
+
+/// Strings are null terminated, or fill their whole field.
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|x| *x == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[0..end]).into_owned()
+}
+
+fn tv_system(value: u8) -> TVSystem {
+    match value & 0b0000_0011 {
+        0 => TVSystem::NTSC,
+        1 => TVSystem::PAL,
+        _ => TVSystem::Both,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ExpansionAudio, Nsf, NsfError};
+    use crate::cartridge_file::TVSystem;
+
+    fn nsf_header() -> Vec<u8> {
+        let mut data = b"NESM\x1a\x01\x03\x02".to_vec();
+        data.extend_from_slice(&0x8000u16.to_le_bytes());
+        data.extend_from_slice(&0x8003u16.to_le_bytes());
+        data.extend_from_slice(&0x8006u16.to_le_bytes());
+        data.extend_from_slice(b"Title\0");
+        data.resize(0x2e, 0);
+        data.extend_from_slice(b"Artist\0");
+        data.resize(0x4e, 0);
+        data.extend_from_slice(b"Copyright\0");
+        data.resize(0x6e, 0);
+        data.extend_from_slice(&16666u16.to_le_bytes());
+        data.resize(0x78, 0);
+        data.extend_from_slice(&20000u16.to_le_bytes());
+        data.resize(0x80, 0);
+        data
+    }
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut result = (data.len() as u32).to_le_bytes().to_vec();
+        result.extend_from_slice(id);
+        result.extend_from_slice(data);
+        result
+    }
+
+    /// Load at $8000, init at $8003 and play at $8006, NTSC with FDS audio, 3 songs starting at the second.
+    fn nsfe_info() -> Vec<u8> {
+        vec![0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0x00, 0x04, 0x03, 0x01]
+    }
+
+    #[test]
+    pub fn nsf() {
+        let mut data = nsf_header();
+        data.extend_from_slice(&[0x60; 16]);
+        let nsf = Nsf::from_bytes(data.clone()).unwrap();
+        assert_eq!(nsf.total_songs(), 3);
+        assert_eq!(nsf.starting_song(), 2);
+        assert_eq!(nsf.load_address(), 0x8000);
+        assert_eq!(nsf.init_address(), 0x8003);
+        assert_eq!(nsf.play_address(), 0x8006);
+        assert_eq!(nsf.title(), "Title");
+        assert_eq!(nsf.artist(), "Artist");
+        assert_eq!(nsf.copyright(), "Copyright");
+        assert_eq!(nsf.ntsc_play_speed(), 16666);
+        assert_eq!(nsf.pal_play_speed(), 20000);
+        assert_eq!(nsf.bankswitch_init(), None);
+        assert_eq!(nsf.tv_system(), TVSystem::NTSC);
+        assert_eq!(nsf.expansion_audio(), ExpansionAudio::empty());
+        assert_eq!(nsf.track_label(1), None);
+        assert_eq!(nsf.data(), &[0x60; 16]);
+
+        data[0x70..0x78].copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        data[0x7a] = 0b10;
+        data[0x7b] = (ExpansionAudio::FDS | ExpansionAudio::VRC6).bits();
+        let nsf = Nsf::from_bytes(data.clone()).unwrap();
+        assert_eq!(nsf.bankswitch_init(), Some([0, 1, 2, 3, 4, 5, 6, 7]));
+        assert_eq!(nsf.tv_system(), TVSystem::Both);
+        assert_eq!(
+            nsf.expansion_audio(),
+            ExpansionAudio::FDS | ExpansionAudio::VRC6
+        );
+
+        // NSF2 metadata after the data length isn't part of the program
+        data[5] = 2;
+        data[0x7d] = 8;
+        let nsf = Nsf::from_bytes(data.clone()).unwrap();
+        assert_eq!(nsf.data(), &[0x60; 8]);
+        data[0x7d] = 32;
+        assert!(matches!(Nsf::from_bytes(data), Err(NsfError::MissingData)));
+    }
+
+    #[test]
+    pub fn nsf_errors() {
+        assert!(matches!(
+            Nsf::from_bytes(b"NESN".to_vec()),
+            Err(NsfError::BadHeader)
+        ));
+        assert!(matches!(
+            Nsf::from_bytes(nsf_header()[0..0x40].to_vec()),
+            Err(NsfError::BadHeader)
+        ));
+        assert!(matches!(
+            Nsf::from_bytes(nsf_header()),
+            Err(NsfError::MissingData)
+        ));
+
+        let mut data = nsf_header();
+        data.push(0x60);
+        data[6] = 0;
+        assert!(matches!(
+            Nsf::from_bytes(data.clone()),
+            Err(NsfError::NoSongs)
+        ));
+        data[6] = 1;
+        data[0x09] = 0x70;
+        assert!(matches!(
+            Nsf::from_bytes(data.clone()),
+            Err(NsfError::BadLoadAddress(0x7000))
+        ));
+        // bank switched files can load anywhere
+        data[0x70] = 1;
+        assert!(Nsf::from_bytes(data).is_ok());
+    }
+
+    #[test]
+    pub fn nsfe() {
+        let mut data = b"NSFE".to_vec();
+        data.extend(chunk(b"INFO", &nsfe_info()));
+        data.extend(chunk(b"DATA", &[0x60; 16]));
+        data.extend(chunk(b"BANK", &[0, 1, 2]));
+        data.extend(chunk(b"RATE", &16639u16.to_le_bytes()));
+        data.extend(chunk(b"auth", b"Title\0Artist\0Copyright\0Ripper\0"));
+        data.extend(chunk(b"tlbl", b"First\0\0Third\0"));
+        data.extend(chunk(b"text", b"optional chunks are skipped"));
+        data.extend(chunk(b"NEND", &[]));
+
+        let nsf = Nsf::from_bytes(data).unwrap();
+        assert_eq!(nsf.total_songs(), 3);
+        assert_eq!(nsf.starting_song(), 2);
+        assert_eq!(nsf.load_address(), 0x8000);
+        assert_eq!(nsf.init_address(), 0x8003);
+        assert_eq!(nsf.play_address(), 0x8006);
+        assert_eq!(nsf.title(), "Title");
+        assert_eq!(nsf.artist(), "Artist");
+        assert_eq!(nsf.copyright(), "Copyright");
+        assert_eq!(nsf.ntsc_play_speed(), 16639);
+        // no PAL rate in the RATE chunk
+        assert_eq!(nsf.pal_play_speed(), 19997);
+        assert_eq!(nsf.bankswitch_init(), Some([0, 1, 2, 0, 0, 0, 0, 0]));
+        assert_eq!(nsf.tv_system(), TVSystem::NTSC);
+        assert_eq!(nsf.expansion_audio(), ExpansionAudio::FDS);
+        assert_eq!(nsf.track_label(1), Some("First"));
+        assert_eq!(nsf.track_label(2), None);
+        assert_eq!(nsf.track_label(3), Some("Third"));
+        assert_eq!(nsf.track_label(4), None);
+        assert_eq!(nsf.data(), &[0x60; 16]);
+    }
+
+    #[test]
+    pub fn nsfe_errors() {
+        let mut data = b"NSFE".to_vec();
+        data.extend(chunk(b"DATA", &[0x60]));
+        assert!(matches!(
+            Nsf::from_bytes(data.clone()),
+            Err(NsfError::MissingChunk(x)) if &x == b"NEND"
+        ));
+        let mut without_info = data.clone();
+        without_info.extend(chunk(b"NEND", &[]));
+        assert!(matches!(
+            Nsf::from_bytes(without_info),
+            Err(NsfError::MissingChunk(x)) if &x == b"INFO"
+        ));
+
+        let mut short_info = data.clone();
+        short_info.extend(chunk(b"INFO", &[0x00, 0x80]));
+        assert!(matches!(
+            Nsf::from_bytes(short_info),
+            Err(NsfError::BadChunk(x)) if &x == b"INFO"
+        ));
+
+        let mut truncated = data.clone();
+        truncated.extend(&chunk(b"RATE", &[0x00, 0x80])[0..9]);
+        assert!(matches!(
+            Nsf::from_bytes(truncated),
+            Err(NsfError::BadChunk(x)) if &x == b"RATE"
+        ));
+
+        data.extend(chunk(b"INFO", &nsfe_info()));
+        data.extend(chunk(b"VRC7", &[]));
+        data.extend(chunk(b"NEND", &[]));
+        let error = Nsf::from_bytes(data).err().unwrap();
+        assert!(matches!(error, NsfError::UnrecognizedRequiredChunk(x) if &x == b"VRC7"));
+        assert_eq!(
+            error.to_string(),
+            "unsupported VRC7 chunk, which is needed to play the file"
+        );
+    }
+}
@@ -0,0 +1,437 @@
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, io::Write};
+
+use crate::{
+    apu::{self, APU},
+    cartridge_file::TVSystem,
+    cpu::Interrupt,
+    cpu::CPU,
+    debugger,
+    memory::{
+        nsf::{self, PLAYER_IDLE_ADDRESS, PLAYER_INIT_ADDRESS, PLAYER_PLAY_ADDRESS},
+        observe::{Accessor, Bus, Observed, Observers},
+        Memory,
+    },
+    nsf_file::{ExpansionAudio, Nsf},
+    region::Region,
+    rewind,
+    save_state::{self, SaveStateError},
+    trace,
+};
+
+const INITIAL_STACK_POINTER: u8 = 0xfd;
+
+/// Drives the CPU through the synthetic player in `memory::nsf`, calling INIT once and then PLAY at the rate the file
+/// asks for.
+#[derive(Serialize, Deserialize)]
+pub struct Player {
+    cpu: CPU,
+    memory: nsf::Memory,
+    /// Saved so a state can't be loaded into a player for a different console.
+    region: Region,
+    /// Of the file's data, to check states are for the same file.
+    #[serde(skip)]
+    crc32: u32,
+    #[serde(skip)]
+    cycles_per_play: f64,
+    next_play: f64,
+    /// Frames run by run_frame, to keep them lined up with the clock.
+    #[serde(default)]
+    frame: u64,
+    #[serde(skip)]
+    trace: Option<Box<dyn Write>>,
+    #[serde(skip)]
+    observers: RefCell<Observers>,
+}
+
+impl Player {
+    /// Plays on the console the file asks for unless given one, files for both get NTSC.
+    pub fn new(nsf: &Nsf, sample_rate: u32, region: Option<Region>) -> Self {
+        let unemulated = nsf.expansion_audio() - ExpansionAudio::FDS;
+        if !unemulated.is_empty() {
+            warn!(
+                "expansion audio isn't emulated, those channels will be silent: {:?}",
+                unemulated
+            );
+        }
+        let region = region.unwrap_or(Region::from_tv_system(nsf.tv_system()));
+        if region == Region::NTSC && nsf.tv_system() == TVSystem::PAL {
+            warn!("PAL only file, playing on an NTSC console");
+        }
+        // a Dendy calls PLAY every 50Hz frame like PAL, even though it tells the tune it's NTSC
+        let play_speed = match region {
+            Region::NTSC => nsf.ntsc_play_speed(),
+            Region::PAL | Region::Dendy => nsf.pal_play_speed(),
+        };
+
+        let mut result = Self {
+            cpu: CPU::new(),
+            memory: nsf::Memory::new(nsf, APU::new(sample_rate, region)),
+            region,
+            crc32: crc32fast::hash(nsf.data()),
+            cycles_per_play: (play_speed as f64) * region.cpu_clock_rate() / 1_000_000.0,
+            next_play: 0.0,
+            frame: 0,
+            trace: None,
+            observers: RefCell::new(Observers::new()),
+        };
+        result.cpu.sp = INITIAL_STACK_POINTER;
+        result.cpu.pc = PLAYER_IDLE_ADDRESS;
+        result
+    }
+
+    /// Runs the file's INIT routine for the given 0-based song.
+    pub fn start(&mut self, song: u8) {
+        for address in 0x4000..=0x4013 {
+            self.memory.write8(address, 0);
+        }
+        self.memory.write8(apu::STATUS_ADDRESS, 0x0f);
+        self.memory.write8(apu::FRAME_COUNTER_ADDRESS, 0x40);
+
+        self.cpu.a = song;
+        self.cpu.x = match self.region {
+            Region::PAL => 1,
+            Region::NTSC | Region::Dendy => 0,
+        };
+        self.cpu.pc = PLAYER_INIT_ADDRESS;
+        self.next_play = (self.cpu.clock as f64) + self.cycles_per_play;
+    }
+
+    /// Runs for at least the given number of CPU cycles, calling PLAY whenever it's due and the previous call has
+    /// returned.
+    pub fn run(&mut self, cycles: u64) -> std::io::Result<()> {
+        self.run_until(self.cpu.clock + cycles)
+    }
+
+    /// Runs until the end of the next frame of the console it's playing on. There's no video, frames are just a
+    /// length of time here.
+    pub fn run_frame(&mut self) -> std::io::Result<()> {
+        self.frame += 1;
+        self.run_until(((self.frame as f64) * self.region.cpu_cycles_per_frame()) as u64)
+    }
+
+    fn run_until(&mut self, end: u64) -> std::io::Result<()> {
+        while self.cpu.clock < end {
+            self.play_if_due();
+            if let Some(trace) = &mut self.trace {
+                writeln!(
+                    trace,
+                    "{}",
+                    trace::line(&self.cpu, &self.memory, self.region)
+                )?;
+            }
+            self.step_instruction();
+        }
+        Ok(())
+    }
+
+    /// Only goes through the observers when there are any, it's a lot slower.
+    fn step_instruction(&mut self) {
+        let before = self.cpu.clock;
+        if self.observers.borrow().is_empty() {
+            self.cpu.step(&mut self.memory);
+        } else {
+            let mut memory = Observed::new(
+                &mut self.memory,
+                &self.observers,
+                Bus::CPU,
+                Accessor::CPU,
+                before,
+            );
+            memory.execute(self.cpu.pc);
+            self.cpu.step(&mut memory);
+        }
+        self.memory.step_apu(self.cpu.clock - before);
+    }
+
+    /// Returns whether it did.
+    fn play_if_due(&mut self) -> bool {
+        if self.cpu.pc == PLAYER_IDLE_ADDRESS && (self.cpu.clock as f64) >= self.next_play {
+            self.cpu.pc = PLAYER_PLAY_ADDRESS;
+            self.next_play += self.cycles_per_play;
+            return true;
+        }
+        false
+    }
+
+    /// The CPU, memory, APU and where PLAY is up to. Tracing, observers and the mixer settings aren't included.
+    pub fn save_state(&self) -> Vec<u8> {
+        save_state::encode(self.crc32, self)
+    }
+
+    /// Only takes states saved from the same file on the same region.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let state: Player = save_state::decode(self.crc32, data)?;
+        if state.region != self.region {
+            return Err(SaveStateError::WrongRegion {
+                expected: self.region,
+                actual: state.region,
+            });
+        }
+        self.set_state(state);
+        Ok(())
+    }
+
+    fn set_state(&mut self, state: Player) {
+        self.cpu = state.cpu;
+        self.memory.load_state(state.memory);
+        self.next_play = state.next_play;
+        self.frame = state.frame;
+    }
+
+    /// Writes a line for every instruction from here on, including the synthetic player's own.
+    pub fn set_trace(&mut self, trace: Box<dyn Write>) {
+        self.trace = Some(trace);
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// For the audio debug controls.
+    pub fn apu_mut(&mut self) -> &mut APU {
+        self.memory.apu_mut()
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.memory.take_samples()
+    }
+}
+
+/// Tunes don't take input.
+impl rewind::Machine for Player {
+    type Input = ();
+
+    fn snapshot(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        self.set_state(bincode::deserialize(snapshot).expect("a snapshot from this player"));
+    }
+
+    fn run_frame(&mut self, _input: &()) -> std::io::Result<()> {
+        Player::run_frame(self)
+    }
+}
+
+/// Calling PLAY stands in for the NMI a real player would call it from, so it's a step of its own reported as one.
+/// NSF files don't get IRQs.
+impl debugger::Target for Player {
+    type Memory = nsf::Memory;
+
+    fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    fn memory(&self) -> &nsf::Memory {
+        &self.memory
+    }
+
+    fn memory_mut(&mut self) -> &mut nsf::Memory {
+        &mut self.memory
+    }
+
+    fn observers(&self) -> &RefCell<Observers> {
+        &self.observers
+    }
+
+    fn step(&mut self) -> Option<Interrupt> {
+        if self.play_if_due() {
+            return Some(Interrupt::NMI);
+        }
+        self.step_instruction();
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Player;
+    use crate::{
+        apu::Channel,
+        debugger::Debugger,
+        memory::{nsf::PLAYER_PLAY_ADDRESS, Memory},
+        nsf_file::{ExpansionAudio, Nsf, NsfError},
+        region::Region,
+        rewind::{Machine, Rewind},
+        save_state::SaveStateError,
+    };
+
+    /// Counts PLAY calls in $00.
+    const COUNTER: [u8; 3] = [
+        0x60, // RTS
+        0xe6, 0x00, // INC $00
+    ];
+
+    fn nsf(program: &[u8]) -> Nsf {
+        Nsf::from_bytes(nsf_data(program)).unwrap()
+    }
+
+    /// Init at $8000, PLAY at $8001.
+    fn nsf_data(program: &[u8]) -> Vec<u8> {
+        let mut data = b"NESM\x1a\x01\x01\x01".to_vec();
+        data.extend_from_slice(&0x8000u16.to_le_bytes());
+        // init is just an RTS
+        data.extend_from_slice(&0x8000u16.to_le_bytes());
+        data.extend_from_slice(&0x8001u16.to_le_bytes());
+        data.resize(0x6e, 0);
+        data.extend_from_slice(&16666u16.to_le_bytes());
+        data.resize(0x78, 0);
+        data.extend_from_slice(&20000u16.to_le_bytes());
+        data.resize(0x80, 0);
+        data.extend_from_slice(program);
+        data
+    }
+
+    #[test]
+    pub fn save_state() {
+        let program = [
+            0x60, // RTS
+            0xe6, 0x00, // INC $00
+            0xa9, 0xbf, // LDA #$BF
+            0x8d, 0x00, 0x40, // STA $4000
+            0xa5, 0x00, // LDA $00
+            0x8d, 0x02, 0x40, // STA $4002
+            0xa9, 0x08, // LDA #$08
+            0x8d, 0x03, 0x40, // STA $4003
+            0x60, // RTS
+        ];
+        let mut player = Player::new(&nsf(&program), 44100, None);
+        player.start(0);
+        player.run(100_000).unwrap();
+        let state = player.save_state();
+        player.take_samples();
+
+        player.run(100_000).unwrap();
+        let expected = (
+            player.cpu.clock,
+            player.memory.peek8(0x0000),
+            player.take_samples(),
+        );
+        assert!(expected.1 > 3);
+
+        player.load_state(&state).unwrap();
+        player.run(100_000).unwrap();
+        assert_eq!(
+            (
+                player.cpu.clock,
+                player.memory.peek8(0x0000),
+                player.take_samples()
+            ),
+            expected
+        );
+
+        let mut other = Player::new(&nsf(&program[..(program.len() - 1)]), 44100, None);
+        assert!(matches!(
+            other.load_state(&state),
+            Err(SaveStateError::WrongRom { .. })
+        ));
+        let mut pal = Player::new(&nsf(&program), 44100, Some(Region::PAL));
+        assert!(matches!(
+            pal.load_state(&state),
+            Err(SaveStateError::WrongRegion { .. })
+        ));
+    }
+
+    #[test]
+    pub fn fds_audio() {
+        let program = [
+            0x60, // RTS
+            0xa9, 0x80, // LDA #$80
+            0x8d, 0x89, 0x40, // STA $4089
+            0xa9, 0x3f, // LDA #$3F
+            0x8d, 0x40, 0x40, // STA $4040
+            0xa9, 0x00, // LDA #$00
+            0x8d, 0x89, 0x40, // STA $4089
+            0xa9, 0xa0, // LDA #$A0
+            0x8d, 0x80, 0x40, // STA $4080
+            0x60, // RTS
+        ];
+        let mut data = nsf_data(&program);
+        let plain = Nsf::from_bytes(data.clone()).unwrap();
+        data[0x7b] = ExpansionAudio::FDS.bits();
+        let fds = Nsf::from_bytes(data).unwrap();
+
+        let loudest = |nsf: &Nsf, muted: bool| {
+            let mut player = Player::new(nsf, 44100, None);
+            // the other channels idle at a level that takes a while to filter out
+            player.apu_mut().set_soloed(Channel::Expansion, true);
+            player.apu_mut().set_muted(Channel::Expansion, muted);
+            player.start(0);
+            player.run(100_000).unwrap();
+            player
+                .take_samples()
+                .into_iter()
+                .fold(0.0f32, |a, x| a.max(x.abs()))
+        };
+        assert!(loudest(&fds, false) > 0.1);
+        assert_eq!(loudest(&fds, true), 0.0);
+        assert_eq!(loudest(&plain, false), 0.0);
+    }
+
+    #[test]
+    pub fn fds_load_address() {
+        let mut program = COUNTER.to_vec();
+        program.push(0x60);
+        let mut data = nsf_data(&program);
+        data[0x08..0x0e].copy_from_slice(&[0x00, 0x60, 0x00, 0x60, 0x01, 0x60]);
+        assert!(matches!(
+            Nsf::from_bytes(data.clone()),
+            Err(NsfError::BadLoadAddress(0x6000))
+        ));
+
+        // FDS files have RAM from $6000 to load into
+        data[0x7b] = ExpansionAudio::FDS.bits();
+        let mut player = Player::new(&Nsf::from_bytes(data).unwrap(), 44100, None);
+        assert_eq!(player.memory.peek8(0x6001), 0xe6);
+        assert_eq!(player.memory.peek8(0x8001), 0x00);
+        player.start(0);
+        player.run(300_000).unwrap();
+        assert!(player.memory.peek8(0x0000) > 5);
+    }
+
+    #[test]
+    pub fn rewind() {
+        let mut program = COUNTER.to_vec();
+        program.push(0x60);
+        let mut player = Player::new(&nsf(&program), 44100, None);
+        player.start(0);
+        let mut rewind = Rewind::new(600, 8, 1 << 20);
+        let mut snapshots = Vec::new();
+        for _ in 0..100 {
+            snapshots.push(player.snapshot());
+            rewind.run_frame(&mut player, ()).unwrap();
+        }
+        let plays = player.memory.peek8(0x0000);
+        assert!(plays > 90);
+
+        assert_eq!(rewind.rewind(&mut player, 13).unwrap(), 13);
+        assert_eq!(player.snapshot(), snapshots[87]);
+        for _ in 0..13 {
+            rewind.run_frame(&mut player, ()).unwrap();
+        }
+        assert_eq!(player.memory.peek8(0x0000), plays);
+    }
+
+    #[test]
+    pub fn debugger() {
+        let mut program = COUNTER.to_vec();
+        program.push(0x60);
+        let mut player = Player::new(&nsf(&program), 44100, None);
+        player.start(0);
+        let mut debugger = Debugger::new();
+        debugger.execute(&mut player, "bi nmi");
+        assert!(debugger
+            .execute(&mut player, "c")
+            .starts_with("breakpoint 0: NMI"));
+        assert_eq!(player.cpu.pc, PLAYER_PLAY_ADDRESS);
+        debugger.execute(&mut player, "s 2");
+        assert_eq!(player.memory.peek8(0x0000), 1);
+    }
+}
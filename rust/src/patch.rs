@@ -0,0 +1,429 @@
+// Soft patching, applied to a ROM's bytes before they're parsed.
+// see https://zerosoft.zophar.net/ips.php, https://www.romhacking.net/documents/746/ and
+// https://www.romhacking.net/documents/392/
+
+use std::{error::Error, fmt::Display};
+
+#[derive(Debug, Clone, Copy)]
+pub enum PatchError {
+    UnrecognizedFormat,
+    /// The patch ended in the middle of something.
+    Truncated,
+    /// A record reads or writes outside the source or target.
+    OutOfBounds,
+    /// The target would be bigger than `MAX_TARGET_SIZE`.
+    TooLarge,
+    SourceChecksumMismatch,
+    TargetChecksumMismatch,
+    PatchChecksumMismatch,
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for PatchError {}
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+const UPS_MAGIC: &[u8] = b"UPS1";
+/// BPS and UPS both end with CRC32s of the source, target and the patch up to that point.
+const FOOTER_SIZE: usize = 12;
+
+/// Far bigger than any real ROM, so a corrupt patch can't ask for gigabytes of target.
+const MAX_TARGET_SIZE: usize = 64 * 1024 * 1024;
+
+/// File extensions of the patch formats we know, in the order they're looked for next to a ROM.
+pub const EXTENSIONS: [&str; 3] = ["ips", "bps", "ups"];
+
+/// Works out the format from the patch's magic number.
+pub fn apply(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(patch, source)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(patch, source)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(patch, source)
+    } else {
+        Err(PatchError::UnrecognizedFormat)
+    }
+}
+
+/// IPS has no checksums, so any source is accepted.
+pub fn apply_ips(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut reader = Reader::new(&patch[IPS_MAGIC.len()..]);
+    let mut target = source.to_vec();
+    loop {
+        if reader.remaining().starts_with(IPS_EOF) {
+            reader.take(IPS_EOF.len())?;
+            break;
+        }
+        let offset = reader.u24_be()?;
+        let size = reader.u16_be()? as usize;
+        let (size, data) = if size == 0 {
+            // RLE record
+            let size = reader.u16_be()? as usize;
+            (size, vec![reader.u8()?; size])
+        } else {
+            (size, reader.take(size)?.to_vec())
+        };
+        if target.len() < offset + size {
+            target.resize(offset + size, 0);
+        }
+        target[offset..(offset + size)].copy_from_slice(&data);
+    }
+    // a size after EOF is the truncation extension
+    if reader.remaining().len() >= 3 {
+        target.truncate(reader.u24_be()?);
+    }
+    Ok(target)
+}
+
+pub fn apply_bps(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let body = verify_footer(patch, source)?;
+    let mut reader = Reader::new(&body[BPS_MAGIC.len()..]);
+    let source_size = reader.varint()?;
+    let target_size = target_size(&mut reader)?;
+    if source_size != source.len() {
+        return Err(PatchError::SourceChecksumMismatch);
+    }
+    let metadata_size = reader.varint()?;
+    reader.take(metadata_size)?;
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset = 0isize;
+    let mut target_offset = 0isize;
+    while !reader.remaining().is_empty() {
+        let action = reader.varint()?;
+        let length = (action >> 2) + 1;
+        if length > target_size - target.len() {
+            return Err(PatchError::OutOfBounds);
+        }
+        match action & 0b11 {
+            // source read
+            0 => {
+                let start = target.len();
+                target.extend_from_slice(
+                    source
+                        .get(start..(start + length))
+                        .ok_or(PatchError::OutOfBounds)?,
+                );
+            }
+            // target read
+            1 => target.extend_from_slice(reader.take(length)?),
+            // source copy
+            2 => {
+                source_offset = source_offset
+                    .checked_add(reader.signed_varint()?)
+                    .ok_or(PatchError::OutOfBounds)?;
+                let start = usize::try_from(source_offset).map_err(|_| PatchError::OutOfBounds)?;
+                target.extend_from_slice(
+                    start
+                        .checked_add(length)
+                        .and_then(|end| source.get(start..end))
+                        .ok_or(PatchError::OutOfBounds)?,
+                );
+                // both fit in the source, so this can't overflow
+                source_offset += length as isize;
+            }
+            // target copy, which can overlap what it's writing so has to go a byte at a time
+            _ => {
+                target_offset = target_offset
+                    .checked_add(reader.signed_varint()?)
+                    .ok_or(PatchError::OutOfBounds)?;
+                for _ in 0..length {
+                    let value = *usize::try_from(target_offset)
+                        .ok()
+                        .and_then(|x| target.get(x))
+                        .ok_or(PatchError::OutOfBounds)?;
+                    target.push(value);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(PatchError::OutOfBounds);
+    }
+    verify_target(patch, &target)?;
+    Ok(target)
+}
+
+pub fn apply_ups(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let body = verify_footer(patch, source)?;
+    let mut reader = Reader::new(&body[UPS_MAGIC.len()..]);
+    let source_size = reader.varint()?;
+    let target_size = target_size(&mut reader)?;
+    if source_size != source.len() {
+        return Err(PatchError::SourceChecksumMismatch);
+    }
+
+    let mut target = source.to_vec();
+    target.resize(target_size, 0);
+    let mut offset = 0usize;
+    while !reader.remaining().is_empty() {
+        offset = offset
+            .checked_add(reader.varint()?)
+            .filter(|x| *x <= target.len())
+            .ok_or(PatchError::OutOfBounds)?;
+        loop {
+            let value = reader.u8()?;
+            if value == 0 {
+                offset += 1;
+                break;
+            }
+            *target.get_mut(offset).ok_or(PatchError::OutOfBounds)? ^= value;
+            offset += 1;
+        }
+    }
+
+    verify_target(patch, &target)?;
+    Ok(target)
+}
+
+/// Only what the patch claims, so it's capped before anything is allocated for it.
+fn target_size(reader: &mut Reader) -> Result<usize, PatchError> {
+    let size = reader.varint()?;
+    if size > MAX_TARGET_SIZE {
+        return Err(PatchError::TooLarge);
+    }
+    Ok(size)
+}
+
+/// Checks the patch and source CRCs, returning the patch without its footer.
+fn verify_footer<'a>(patch: &'a [u8], source: &[u8]) -> Result<&'a [u8], PatchError> {
+    if patch.len() < BPS_MAGIC.len() + FOOTER_SIZE {
+        return Err(PatchError::Truncated);
+    }
+    let (body, footer) = patch.split_at(patch.len() - FOOTER_SIZE);
+    let patch_crc = u32::from_le_bytes(footer[8..12].try_into().unwrap());
+    if crc32fast::hash(&patch[..(patch.len() - 4)]) != patch_crc {
+        return Err(PatchError::PatchChecksumMismatch);
+    }
+    let source_crc = u32::from_le_bytes(footer[0..4].try_into().unwrap());
+    if crc32fast::hash(source) != source_crc {
+        return Err(PatchError::SourceChecksumMismatch);
+    }
+    Ok(body)
+}
+
+fn verify_target(patch: &[u8], target: &[u8]) -> Result<(), PatchError> {
+    let footer = &patch[(patch.len() - FOOTER_SIZE)..];
+    let target_crc = u32::from_le_bytes(footer[4..8].try_into().unwrap());
+    if crc32fast::hash(target) != target_crc {
+        return Err(PatchError::TargetChecksumMismatch);
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        if self.data.len() < len {
+            return Err(PatchError::Truncated);
+        }
+        let (result, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(result)
+    }
+
+    fn u8(&mut self) -> Result<u8, PatchError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16_be(&mut self) -> Result<u16, PatchError> {
+        let x = self.take(2)?;
+        Ok(u16::from_be_bytes([x[0], x[1]]))
+    }
+
+    fn u24_be(&mut self) -> Result<usize, PatchError> {
+        let x = self.take(3)?;
+        Ok(u32::from_be_bytes([0, x[0], x[1], x[2]]) as usize)
+    }
+
+    /// The variable length number BPS and UPS share, where each byte after the first also adds one to what's above it
+    /// so there's only one way to encode any value.
+    fn varint(&mut self) -> Result<usize, PatchError> {
+        let mut result = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let x = self.u8()?;
+            result = ((x & 0x7f) as usize)
+                .checked_mul(shift)
+                .and_then(|x| result.checked_add(x))
+                .ok_or(PatchError::OutOfBounds)?;
+            if (x & 0x80) != 0 {
+                return Ok(result);
+            }
+            shift = shift.checked_shl(7).ok_or(PatchError::OutOfBounds)?;
+            result = result.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+
+    /// Low bit is the sign.
+    fn signed_varint(&mut self) -> Result<isize, PatchError> {
+        let x = self.varint()?;
+        let magnitude = (x >> 1) as isize;
+        Ok(if (x & 1) != 0 { -magnitude } else { magnitude })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply, PatchError};
+
+    fn varint(mut x: usize) -> Vec<u8> {
+        let mut result = Vec::new();
+        loop {
+            let low = (x & 0x7f) as u8;
+            x >>= 7;
+            if x == 0 {
+                result.push(low | 0x80);
+                return result;
+            }
+            result.push(low);
+            x -= 1;
+        }
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    pub fn ips() {
+        let source = [0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        // 2 bytes at 1
+        patch.extend_from_slice(&[0, 0, 1, 0, 2, 0xaa, 0xbb]);
+        // RLE, 3 0xcc at 4
+        patch.extend_from_slice(&[0, 0, 4, 0, 0, 0, 3, 0xcc]);
+        patch.extend_from_slice(b"EOF");
+        assert_eq!(
+            apply(&patch, &source).unwrap(),
+            [0, 0xaa, 0xbb, 0, 0xcc, 0xcc, 0xcc, 0]
+        );
+
+        // truncated to 5
+        patch.extend_from_slice(&[0, 0, 5]);
+        assert_eq!(apply(&patch, &source).unwrap(), [0, 0xaa, 0xbb, 0, 0xcc]);
+    }
+
+    #[test]
+    pub fn bps() {
+        let source = b"abcdefgh";
+        let target = b"abcXYXYXYgh";
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(target.len()));
+        patch.extend(varint(0));
+        // source read 3
+        patch.extend(varint(2 << 2));
+        // target read 2
+        patch.extend(varint((1 << 2) | 1));
+        patch.extend_from_slice(b"XY");
+        // target copy 4 from 3, overlapping what it writes
+        patch.extend(varint((3 << 2) | 3));
+        patch.extend(varint(3 << 1));
+        // source copy 2 from 6
+        patch.extend(varint((1 << 2) | 2));
+        patch.extend(varint(6 << 1));
+        let patch = with_footer(patch, source, target);
+
+        assert_eq!(apply(&patch, source).unwrap(), target);
+        assert!(matches!(
+            apply(&patch, b"abcdefgi"),
+            Err(PatchError::SourceChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    pub fn ups() {
+        let source = b"abcdefgh";
+        let target = b"abXdefghij";
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(target.len()));
+        patch.extend(varint(2));
+        patch.extend_from_slice(&[b'c' ^ b'X', 0]);
+        // the terminator moved us along one too, so this skips to 8
+        patch.extend(varint(4));
+        patch.extend_from_slice(&[b'i', b'j', 0]);
+        let mut patch = with_footer(patch, source, target);
+
+        assert_eq!(apply(&patch, source).unwrap(), target);
+        patch[5] ^= 1;
+        assert!(matches!(
+            apply(&patch, source),
+            Err(PatchError::PatchChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    pub fn malformed() {
+        let source = b"abcdefgh";
+        let header = |magic: &[u8], target_size| {
+            let mut patch = magic.to_vec();
+            patch.extend(varint(source.len()));
+            patch.extend(varint(target_size));
+            patch
+        };
+
+        // sizes nothing could need
+        for magic in [b"BPS1", b"UPS1"] {
+            let patch = with_footer(header(magic, usize::MAX >> 8), source, b"");
+            assert!(matches!(apply(&patch, source), Err(PatchError::TooLarge)));
+        }
+
+        // a source copy from as far back as the offset goes
+        let mut patch = header(b"BPS1", 4);
+        patch.extend(varint(0));
+        patch.extend(varint(2));
+        patch.extend(varint(usize::MAX));
+        let patch = with_footer(patch, source, b"");
+        assert!(matches!(
+            apply(&patch, source),
+            Err(PatchError::OutOfBounds)
+        ));
+
+        // a target copy going on past the end of the target
+        let mut patch = header(b"BPS1", 4);
+        patch.extend(varint(0));
+        patch.extend(varint(0));
+        patch.extend(varint((99 << 2) | 3));
+        patch.extend(varint(0));
+        let patch = with_footer(patch, source, b"");
+        assert!(matches!(
+            apply(&patch, source),
+            Err(PatchError::OutOfBounds)
+        ));
+
+        // skipping as far as the offset goes
+        let mut patch = header(b"UPS1", 8);
+        patch.extend(varint(usize::MAX));
+        patch.extend_from_slice(&[0]);
+        let patch = with_footer(patch, source, b"");
+        assert!(matches!(
+            apply(&patch, source),
+            Err(PatchError::OutOfBounds)
+        ));
+    }
+}
@@ -0,0 +1,678 @@
+// see https://www.nesdev.org/wiki/PPU_rendering and https://www.nesdev.org/wiki/PPU_scrolling
+//
+// Stepped a dot at a time, fetching through the video memory the way the real thing does so mappers see the same
+// accesses. v, t, x and w are the scrolling registers as the wiki names them.
+//
+// Of the Vs. System PPUs, the RC2C05s' swapped PPUCTRL and PPUMASK and their ID in PPUSTATUS are emulated, and the
+// RP2C04s' scrambled palettes are left to the framebuffer.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cartridge_file::VsPPUType,
+    framebuffer::{Framebuffer, WIDTH},
+    memory::{video, Memory},
+    region::Region,
+    save_state,
+};
+
+pub const REGISTERS_START: u16 = 0x2000;
+pub const REGISTERS_END: u16 = 0x4000;
+const CTRL: u16 = 0;
+const MASK: u16 = 1;
+const STATUS: u16 = 2;
+const OAM_ADDRESS: u16 = 3;
+const OAM_DATA: u16 = 4;
+const SCROLL: u16 = 5;
+const ADDRESS: u16 = 6;
+const DATA: u16 = 7;
+const REGISTER_COUNT: u16 = 8;
+
+const CTRL_NAMETABLE: u8 = 0b0000_0011;
+const CTRL_INCREMENT_32: u8 = 0b0000_0100;
+const CTRL_SPRITE_TABLE: u8 = 0b0000_1000;
+const CTRL_BACKGROUND_TABLE: u8 = 0b0001_0000;
+const CTRL_TALL_SPRITES: u8 = 0b0010_0000;
+const CTRL_NMI: u8 = 0b1000_0000;
+
+const MASK_GREYSCALE: u8 = 0b0000_0001;
+const MASK_BACKGROUND_LEFT: u8 = 0b0000_0010;
+const MASK_SPRITES_LEFT: u8 = 0b0000_0100;
+const MASK_BACKGROUND: u8 = 0b0000_1000;
+const MASK_SPRITES: u8 = 0b0001_0000;
+
+const STATUS_OVERFLOW: u8 = 0b0010_0000;
+const STATUS_SPRITE_0_HIT: u8 = 0b0100_0000;
+const STATUS_VBLANK: u8 = 0b1000_0000;
+/// What the rest of PPUSTATUS reads back as, whatever was last on the PPU's data bus.
+const STATUS_OPEN_BUS: u8 = 0b0001_1111;
+/// The palette is 6 bits, the top 2 of a PPUDATA read from it are open bus too.
+const PALETTE_UNUSED: u8 = 0b1100_0000;
+
+const SPRITE_ATTRIBUTE_PALETTE: u8 = 0b0000_0011;
+/// Bits that don't exist in OAM, they always read back as 0.
+const SPRITE_ATTRIBUTE_UNUSED: u8 = 0b0001_1100;
+const SPRITE_ATTRIBUTE_BEHIND_BACKGROUND: u8 = 0b0010_0000;
+const SPRITE_ATTRIBUTE_FLIP_HORIZONTAL: u8 = 0b0100_0000;
+const SPRITE_ATTRIBUTE_FLIP_VERTICAL: u8 = 0b1000_0000;
+
+// v and t are laid out as yyy NN YYYYY XXXXX: fine y, nametable, coarse y and coarse x
+const COARSE_X: u16 = 0x001f;
+const COARSE_Y: u16 = 0x03e0;
+const NAMETABLE_X: u16 = 0x0400;
+const NAMETABLE_Y: u16 = 0x0800;
+const FINE_Y: u16 = 0x7000;
+/// Everything copied from t to v at the end of each line, the rest is copied before the first.
+const HORIZONTAL: u16 = COARSE_X | NAMETABLE_X;
+const VERTICAL: u16 = COARSE_Y | NAMETABLE_Y | FINE_Y;
+const ADDRESS_MASK: u16 = 0x3fff;
+
+const NAMETABLES_START: u16 = 0x2000;
+const ATTRIBUTE_TABLE_OFFSET: u16 = 0x03c0;
+const PATTERN_TABLE_SIZE: u16 = 0x1000;
+const TILE_SIZE: u16 = 16;
+const TILE_PLANE_SIZE: u16 = 8;
+const TILES_WIDE: u16 = 32;
+const TILES_HIGH: u16 = 30;
+
+pub const OAM_SIZE: usize = 256;
+const SPRITE_SIZE: usize = 4;
+const SPRITES_PER_LINE: usize = 8;
+const SPRITE_WIDTH: usize = 8;
+
+const DOTS_PER_SCANLINE: u16 = 341;
+const VISIBLE_SCANLINES: u16 = 240;
+
+#[derive(Serialize, Deserialize)]
+pub struct PPU {
+    /// Sets how many lines there are after the visible ones, kept when loading a state.
+    region: Region,
+    /// The RC2C05s', kept when loading a state like the region.
+    #[serde(skip)]
+    swaps_ctrl_and_mask: bool,
+    /// Read back in PPUSTATUS's low bits instead of open bus, kept when loading a state.
+    #[serde(skip)]
+    status_id: Option<u8>,
+    ctrl: u8,
+    mask: u8,
+    vblank: bool,
+    sprite_0_hit: bool,
+    sprite_overflow: bool,
+    oam_address: u8,
+    #[serde(with = "save_state::hex")]
+    oam: [u8; OAM_SIZE],
+    v: u16,
+    t: u16,
+    x: u8,
+    w: bool,
+    /// PPUDATA reads are a read behind, except for the palette.
+    read_buffer: u8,
+    /// The last value written to a register, which unused bits read back as.
+    latch: u8,
+    scanline: u16,
+    dot: u16,
+    /// Frames started, including the one being drawn.
+    frame: u64,
+    /// Whether the NMI output is high, it fires on the rising edge.
+    nmi_line: bool,
+    nmi_pending: bool,
+
+    // background fetches for the next tile, and shift registers for the two being drawn
+    tile: u8,
+    attribute: u8,
+    pattern_low: u8,
+    pattern_high: u8,
+    pattern_shift_low: u16,
+    pattern_shift_high: u16,
+    attribute_shift_low: u16,
+    attribute_shift_high: u16,
+
+    // the sprites on the line being drawn
+    sprite_count: usize,
+    sprite_0_on_line: bool,
+    sprite_xs: [u8; SPRITES_PER_LINE],
+    sprite_attributes: [u8; SPRITES_PER_LINE],
+    /// Already flipped horizontally, so bit 7 is always the leftmost pixel.
+    sprite_patterns_low: [u8; SPRITES_PER_LINE],
+    sprite_patterns_high: [u8; SPRITES_PER_LINE],
+
+    /// Drawn into as it goes, so it's only a whole frame between frames.
+    #[serde(skip, default = "Framebuffer::new")]
+    framebuffer: Framebuffer,
+}
+
+impl PPU {
+    /// The Vs. System PPU type is for the RC2C05s' differences and the RP2C04s' colors.
+    pub fn new(region: Region, vs_ppu_type: Option<VsPPUType>) -> Self {
+        Self {
+            region,
+            swaps_ctrl_and_mask: vs_ppu_type.is_some_and(|x| x.swaps_ctrl_and_mask()),
+            status_id: vs_ppu_type.and_then(|x| x.status_id()),
+            ctrl: 0,
+            mask: 0,
+            vblank: false,
+            sprite_0_hit: false,
+            sprite_overflow: false,
+            oam_address: 0,
+            oam: [0; OAM_SIZE],
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            latch: 0,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            nmi_line: false,
+            nmi_pending: false,
+            tile: 0,
+            attribute: 0,
+            pattern_low: 0,
+            pattern_high: 0,
+            pattern_shift_low: 0,
+            pattern_shift_high: 0,
+            attribute_shift_low: 0,
+            attribute_shift_high: 0,
+            sprite_count: 0,
+            sprite_0_on_line: false,
+            sprite_xs: [0; SPRITES_PER_LINE],
+            sprite_attributes: [0; SPRITES_PER_LINE],
+            sprite_patterns_low: [0; SPRITES_PER_LINE],
+            sprite_patterns_high: [0; SPRITES_PER_LINE],
+            framebuffer: Framebuffer::with_vs_ppu_type(vs_ppu_type),
+        }
+    }
+
+    /// Takes everything but the region, Vs. System differences and the framebuffer from the state.
+    pub fn load_state(&mut self, state: Self) {
+        *self = Self {
+            region: self.region,
+            swaps_ctrl_and_mask: self.swaps_ctrl_and_mask,
+            status_id: self.status_id,
+            framebuffer: std::mem::replace(&mut self.framebuffer, Framebuffer::new()),
+            ..state
+        };
+    }
+
+    /// The reset button only clears the registers the game sets up, the rest carries on.
+    pub fn reset(&mut self) {
+        self.ctrl = 0;
+        self.mask = 0;
+        self.w = false;
+        self.read_buffer = 0;
+        self.update_nmi();
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    /// Frames started, a frame starts on the first visible line.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Whether the NMI output has gone high since last asked.
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    /// The address is anywhere in $2000-$3FFF, which mirrors the 8 registers. Only reads from PPUSTATUS, OAMDATA and
+    /// PPUDATA drive the bus, the others read back whatever was last written.
+    pub fn read8<M>(&mut self, address: u16, video: &mut M) -> u8
+    where
+        M: Memory,
+    {
+        let result = match address % REGISTER_COUNT {
+            STATUS => {
+                let result = self.peek_status();
+                self.vblank = false;
+                self.w = false;
+                self.update_nmi();
+                result
+            }
+            DATA => {
+                let address = self.v & ADDRESS_MASK;
+                let result = if address >= video::IMAGE_PALETTE_START {
+                    // the palette answers straight away, and the buffer gets the nametable underneath it
+                    self.read_buffer = video.read8(address - PATTERN_TABLE_SIZE);
+                    (video.read8(address) & !PALETTE_UNUSED) | (self.latch & PALETTE_UNUSED)
+                } else {
+                    std::mem::replace(&mut self.read_buffer, video.read8(address))
+                };
+                self.increment_address();
+                result
+            }
+            _ => self.peek8(address, video),
+        };
+        self.latch = result;
+        result
+    }
+
+    /// As read, without any side effects.
+    pub fn peek8<M>(&self, address: u16, video: &M) -> u8
+    where
+        M: Memory,
+    {
+        match address % REGISTER_COUNT {
+            STATUS => self.peek_status(),
+            OAM_DATA => {
+                let value = self.oam[self.oam_address as usize];
+                if (self.oam_address as usize) % SPRITE_SIZE == 2 {
+                    value & !SPRITE_ATTRIBUTE_UNUSED
+                } else {
+                    value
+                }
+            }
+            DATA => {
+                let address = self.v & ADDRESS_MASK;
+                if address >= video::IMAGE_PALETTE_START {
+                    (video.peek8(address) & !PALETTE_UNUSED) | (self.latch & PALETTE_UNUSED)
+                } else {
+                    self.read_buffer
+                }
+            }
+            _ => self.latch,
+        }
+    }
+
+    pub fn write8<M>(&mut self, address: u16, value: u8, video: &mut M)
+    where
+        M: Memory,
+    {
+        self.latch = value;
+        let register = match address % REGISTER_COUNT {
+            CTRL if self.swaps_ctrl_and_mask => MASK,
+            MASK if self.swaps_ctrl_and_mask => CTRL,
+            x => x,
+        };
+        match register {
+            CTRL => {
+                self.ctrl = value;
+                self.t = (self.t & !(NAMETABLE_X | NAMETABLE_Y))
+                    | (((value & CTRL_NAMETABLE) as u16) << 10);
+                self.update_nmi();
+            }
+            MASK => self.mask = value,
+            OAM_ADDRESS => self.oam_address = value,
+            OAM_DATA => self.write_oam(value),
+            SCROLL => {
+                if self.w {
+                    self.t = (self.t & !(COARSE_Y | FINE_Y))
+                        | (((value & 0b0000_0111) as u16) << 12)
+                        | (((value & 0b1111_1000) as u16) << 2);
+                } else {
+                    self.t = (self.t & !COARSE_X) | ((value >> 3) as u16);
+                    self.x = value & 0b0000_0111;
+                }
+                self.w = !self.w;
+            }
+            ADDRESS => {
+                if self.w {
+                    self.t = (self.t & 0xff00) | (value as u16);
+                    self.v = self.t;
+                } else {
+                    self.t = (self.t & 0x00ff) | (((value & 0b0011_1111) as u16) << 8);
+                }
+                self.w = !self.w;
+            }
+            DATA => {
+                video.write8(self.v & ADDRESS_MASK, value);
+                self.increment_address();
+            }
+            _ => (),
+        }
+    }
+
+    /// Writes the next byte of OAM, as PPUOAMDATA and sprite DMA both do.
+    pub fn write_oam(&mut self, value: u8) {
+        self.oam[self.oam_address as usize] = value;
+        self.oam_address = self.oam_address.wrapping_add(1);
+    }
+
+    /// Advances a dot, drawing into the framebuffer on visible lines.
+    pub fn step<M>(&mut self, video: &mut M)
+    where
+        M: Memory,
+    {
+        let visible = self.scanline < VISIBLE_SCANLINES;
+        let pre_render = self.scanline == self.pre_render_scanline();
+
+        if pre_render && self.dot == 1 {
+            self.vblank = false;
+            self.sprite_0_hit = false;
+            self.sprite_overflow = false;
+            self.update_nmi();
+        }
+        if self.scanline == self.vblank_scanline() && self.dot == 1 {
+            self.vblank = true;
+            self.update_nmi();
+        }
+
+        if (visible || pre_render) && self.rendering() {
+            self.step_background(video);
+            if self.dot == 257 {
+                if visible {
+                    self.evaluate_sprites(video);
+                } else {
+                    self.sprite_count = 0;
+                    self.sprite_0_on_line = false;
+                }
+            }
+        }
+
+        if visible && (1..=WIDTH as u16).contains(&self.dot) {
+            self.draw_pixel(video);
+        }
+
+        self.dot += 1;
+        // on NTSC the pre-render line is a dot short on odd frames when rendering
+        let skip = pre_render
+            && self.dot == DOTS_PER_SCANLINE - 1
+            && self.frame % 2 == 1
+            && self.region.skips_odd_frame_dot();
+        if self.dot == DOTS_PER_SCANLINE || (skip && self.rendering()) {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == self.region.scanlines() {
+                self.scanline = 0;
+                self.frame += 1;
+            }
+        }
+    }
+
+    fn vblank_scanline(&self) -> u16 {
+        VISIBLE_SCANLINES + self.region.post_render_scanlines()
+    }
+
+    fn pre_render_scanline(&self) -> u16 {
+        self.region.scanlines() - 1
+    }
+
+    fn peek_status(&self) -> u8 {
+        (if self.vblank { STATUS_VBLANK } else { 0 })
+            | (if self.sprite_0_hit {
+                STATUS_SPRITE_0_HIT
+            } else {
+                0
+            })
+            | (if self.sprite_overflow {
+                STATUS_OVERFLOW
+            } else {
+                0
+            })
+            | self.status_id.unwrap_or(self.latch & STATUS_OPEN_BUS)
+    }
+
+    fn rendering(&self) -> bool {
+        self.mask & (MASK_BACKGROUND | MASK_SPRITES) != 0
+    }
+
+    fn update_nmi(&mut self) {
+        let line = self.vblank && self.ctrl & CTRL_NMI != 0;
+        if line && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = line;
+    }
+
+    /// PPUDATA accesses move on by 1 or 32, whichever PPUCTRL says.
+    fn increment_address(&mut self) {
+        let increment = if self.ctrl & CTRL_INCREMENT_32 != 0 {
+            TILES_WIDE
+        } else {
+            1
+        };
+        self.v = self.v.wrapping_add(increment) & 0x7fff;
+    }
+
+    /// The fetches and shifts for the line being drawn and the first two tiles of the next, with v following along.
+    fn step_background<M>(&mut self, video: &mut M)
+    where
+        M: Memory,
+    {
+        if (2..=257).contains(&self.dot) || (321..=337).contains(&self.dot) {
+            self.pattern_shift_low <<= 1;
+            self.pattern_shift_high <<= 1;
+            self.attribute_shift_low <<= 1;
+            self.attribute_shift_high <<= 1;
+
+            match (self.dot - 1) % 8 {
+                0 => {
+                    self.load_shift_registers();
+                    self.tile = video.read8(NAMETABLES_START | (self.v & 0x0fff));
+                }
+                2 => {
+                    let address = NAMETABLES_START
+                        | ATTRIBUTE_TABLE_OFFSET
+                        | (self.v & (NAMETABLE_X | NAMETABLE_Y))
+                        | ((self.v >> 4) & 0b11_1000)
+                        | ((self.v >> 2) & 0b00_0111);
+                    // each byte covers 4x4 tiles, 2 bits for each 2x2 quarter
+                    let shift = ((self.v >> 4) & 0b100) | (self.v & 0b010);
+                    self.attribute = (video.read8(address) >> shift) & 0b11;
+                }
+                4 => self.pattern_low = video.read8(self.background_pattern_address()),
+                6 => {
+                    self.pattern_high =
+                        video.read8(self.background_pattern_address() + TILE_PLANE_SIZE)
+                }
+                7 => self.increment_x(),
+                _ => (),
+            }
+        }
+        match self.dot {
+            256 => self.increment_y(),
+            257 => {
+                self.load_shift_registers();
+                self.v = (self.v & !HORIZONTAL) | (self.t & HORIZONTAL);
+            }
+            280..=304 if self.scanline == self.pre_render_scanline() => {
+                self.v = (self.v & !VERTICAL) | (self.t & VERTICAL);
+            }
+            // unused nametable fetches, which some mappers count
+            338 | 340 => {
+                video.read8(NAMETABLES_START | (self.v & 0x0fff));
+            }
+            _ => (),
+        }
+    }
+
+    fn background_pattern_address(&self) -> u16 {
+        let table = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 {
+            PATTERN_TABLE_SIZE
+        } else {
+            0
+        };
+        table + (self.tile as u16) * TILE_SIZE + (self.v >> 12)
+    }
+
+    /// The next tile goes in the low 8 bits, which are shifted up and out over the next 8 dots.
+    fn load_shift_registers(&mut self) {
+        self.pattern_shift_low = (self.pattern_shift_low & 0xff00) | self.pattern_low as u16;
+        self.pattern_shift_high = (self.pattern_shift_high & 0xff00) | self.pattern_high as u16;
+        let fill = |bit: u8| if bit != 0 { 0x00ff } else { 0 };
+        self.attribute_shift_low =
+            (self.attribute_shift_low & 0xff00) | fill(self.attribute & 0b01);
+        self.attribute_shift_high =
+            (self.attribute_shift_high & 0xff00) | fill(self.attribute & 0b10);
+    }
+
+    fn increment_x(&mut self) {
+        if self.v & COARSE_X == COARSE_X {
+            self.v = (self.v & !COARSE_X) ^ NAMETABLE_X;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /// Fine y, then coarse y, wrapping into the nametable below after row 29.
+    fn increment_y(&mut self) {
+        if self.v & FINE_Y != FINE_Y {
+            self.v += 1 << 12;
+            return;
+        }
+        self.v &= !FINE_Y;
+        let coarse_y = (self.v & COARSE_Y) >> 5;
+        let coarse_y = if coarse_y == TILES_HIGH - 1 {
+            self.v ^= NAMETABLE_Y;
+            0
+        } else if coarse_y == TILES_WIDE - 1 {
+            // rows 30 and 31 are the attribute table, and wrap without switching nametables
+            0
+        } else {
+            coarse_y + 1
+        };
+        self.v = (self.v & !COARSE_Y) | (coarse_y << 5);
+    }
+
+    /// Finds the first 8 sprites on the next line and fetches their patterns for it. The real thing spreads this over
+    /// the line, and its overflow flag has false positives and negatives this doesn't.
+    fn evaluate_sprites<M>(&mut self, video: &mut M)
+    where
+        M: Memory,
+    {
+        let height = if self.ctrl & CTRL_TALL_SPRITES != 0 {
+            16
+        } else {
+            8
+        };
+        self.sprite_count = 0;
+        self.sprite_0_on_line = false;
+        for (i, sprite) in self.oam.chunks_exact(SPRITE_SIZE).enumerate() {
+            let row = self.scanline.wrapping_sub(sprite[0] as u16);
+            if row >= height {
+                continue;
+            }
+            if self.sprite_count == SPRITES_PER_LINE {
+                self.sprite_overflow = true;
+                break;
+            }
+            let (tile, attributes, x) = (sprite[1], sprite[2], sprite[3]);
+            let row = if attributes & SPRITE_ATTRIBUTE_FLIP_VERTICAL != 0 {
+                height - 1 - row
+            } else {
+                row
+            };
+            let address = if height == 16 {
+                // the table comes from the tile number rather than PPUCTRL
+                (tile as u16 & 1) * PATTERN_TABLE_SIZE
+                    + (tile as u16 & !1) * TILE_SIZE
+                    + (row & 8) * 2
+                    + (row & 7)
+            } else {
+                let table = if self.ctrl & CTRL_SPRITE_TABLE != 0 {
+                    PATTERN_TABLE_SIZE
+                } else {
+                    0
+                };
+                table + (tile as u16) * TILE_SIZE + row
+            };
+            let mut low = video.read8(address);
+            let mut high = video.read8(address + TILE_PLANE_SIZE);
+            if attributes & SPRITE_ATTRIBUTE_FLIP_HORIZONTAL != 0 {
+                low = low.reverse_bits();
+                high = high.reverse_bits();
+            }
+
+            let slot = self.sprite_count;
+            self.sprite_xs[slot] = x;
+            self.sprite_attributes[slot] = attributes;
+            self.sprite_patterns_low[slot] = low;
+            self.sprite_patterns_high[slot] = high;
+            self.sprite_0_on_line |= i == 0;
+            self.sprite_count += 1;
+        }
+    }
+
+    fn draw_pixel<M>(&mut self, video: &M)
+    where
+        M: Memory,
+    {
+        let x = (self.dot - 1) as usize;
+        let y = self.scanline as usize;
+
+        let background = if self.mask & MASK_BACKGROUND != 0
+            && (x >= SPRITE_WIDTH || self.mask & MASK_BACKGROUND_LEFT != 0)
+        {
+            let bit = 15 - self.x as u16;
+            let pixel = (((self.pattern_shift_high >> bit) & 1) << 1)
+                | ((self.pattern_shift_low >> bit) & 1);
+            let palette = (((self.attribute_shift_high >> bit) & 1) << 1)
+                | ((self.attribute_shift_low >> bit) & 1);
+            (pixel as u8, palette as u8)
+        } else {
+            (0, 0)
+        };
+
+        let sprite = if self.mask & MASK_SPRITES != 0
+            && (x >= SPRITE_WIDTH || self.mask & MASK_SPRITES_LEFT != 0)
+        {
+            (0..self.sprite_count).find_map(|i| {
+                let offset = x.wrapping_sub(self.sprite_xs[i] as usize);
+                if offset >= SPRITE_WIDTH {
+                    return None;
+                }
+                let bit = 7 - offset;
+                let pixel = (((self.sprite_patterns_high[i] >> bit) & 1) << 1)
+                    | ((self.sprite_patterns_low[i] >> bit) & 1);
+                (pixel != 0).then_some((i, pixel, self.sprite_attributes[i]))
+            })
+        } else {
+            None
+        };
+
+        let index = match (background, sprite) {
+            ((0, _), None) => 0,
+            ((pixel, palette), None) => palette * 4 + pixel,
+            ((0, _), Some((_, pixel, attributes))) => sprite_palette_index(pixel, attributes),
+            ((pixel, palette), Some((i, sprite_pixel, attributes))) => {
+                if i == 0 && self.sprite_0_on_line && x != WIDTH - 1 {
+                    self.sprite_0_hit = true;
+                }
+                if attributes & SPRITE_ATTRIBUTE_BEHIND_BACKGROUND != 0 {
+                    palette * 4 + pixel
+                } else {
+                    sprite_palette_index(sprite_pixel, attributes)
+                }
+            }
+        };
+        // the palette is inside the PPU, so looking at it isn't a bus access
+        let mut color = video.peek8(video::IMAGE_PALETTE_START + index as u16);
+        if self.mask & MASK_GREYSCALE != 0 {
+            color &= 0b0011_0000;
+        }
+        self.framebuffer.set(x, y, color);
+    }
+}
+
+fn sprite_palette_index(pixel: u8, attributes: u8) -> u8 {
+    (video::SPRITE_PALETTE_START - video::IMAGE_PALETTE_START) as u8
+        + (attributes & SPRITE_ATTRIBUTE_PALETTE) * 4
+        + pixel
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PPU, STATUS_VBLANK};
+    use crate::{cartridge_file::VsPPUType, region::Region, test_utils::test::TestMemory};
+
+    #[test]
+    pub fn vs_system() {
+        let mut video = TestMemory::new();
+        let mut ppu = PPU::new(Region::NTSC, Some(VsPPUType::RC2C05_02));
+        ppu.write8(0x2000, 0x1e, &mut video);
+        ppu.write8(0x2001, 0x80, &mut video);
+        assert_eq!((ppu.ctrl, ppu.mask), (0x80, 0x1e));
+        ppu.vblank = true;
+        assert_eq!(ppu.read8(0x2002, &mut video), STATUS_VBLANK | 0x3d);
+
+        // the others leave them be and read open bus
+        let mut ppu = PPU::new(Region::NTSC, Some(VsPPUType::RP2C03B));
+        ppu.write8(0x2000, 0x1e, &mut video);
+        ppu.write8(0x2001, 0x9f, &mut video);
+        assert_eq!((ppu.ctrl, ppu.mask), (0x1e, 0x9f));
+        assert_eq!(ppu.read8(0x2002, &mut video), 0x1f);
+    }
+}
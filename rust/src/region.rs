@@ -0,0 +1,162 @@
+// see https://www.nesdev.org/wiki/Cycle_reference_chart
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge_file::TVSystem;
+
+const MASTER_CLOCK_RATE_NTSC: f64 = 236.25e6 / 11.0;
+const MASTER_CLOCK_RATE_PAL: f64 = 26.601712e6;
+
+const DOTS_PER_SCANLINE: f64 = 341.0;
+
+/// The console a game runs on, which sets every clock rate and how long a frame is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Region {
+    NTSC,
+    PAL,
+    /// The common famiclone, a PAL frame with NTSC CPU:PPU timing and APU tables.
+    Dendy,
+}
+
+impl Region {
+    /// Files that run on both get the NTSC console.
+    pub fn from_tv_system(tv_system: TVSystem) -> Self {
+        match tv_system {
+            TVSystem::NTSC | TVSystem::Both => Region::NTSC,
+            TVSystem::PAL => Region::PAL,
+            TVSystem::Dendy => Region::Dendy,
+        }
+    }
+
+    fn master_clock_rate(&self) -> f64 {
+        match self {
+            Region::NTSC => MASTER_CLOCK_RATE_NTSC,
+            Region::PAL | Region::Dendy => MASTER_CLOCK_RATE_PAL,
+        }
+    }
+
+    /// Master clocks per CPU cycle.
+    pub fn cpu_divider(&self) -> u64 {
+        match self {
+            Region::NTSC => 12,
+            Region::PAL => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    /// Master clocks per PPU dot.
+    pub fn ppu_divider(&self) -> u64 {
+        match self {
+            Region::NTSC => 4,
+            Region::PAL | Region::Dendy => 5,
+        }
+    }
+
+    /// In Hz.
+    pub fn cpu_clock_rate(&self) -> f64 {
+        self.master_clock_rate() / (self.cpu_divider() as f64)
+    }
+
+    /// 3 everywhere but PAL, where it's 3.2.
+    pub fn ppu_dots_per_cpu_cycle(&self) -> f64 {
+        (self.cpu_divider() as f64) / (self.ppu_divider() as f64)
+    }
+
+    /// Including the pre-render line.
+    pub fn scanlines(&self) -> u16 {
+        match self {
+            Region::NTSC => 262,
+            Region::PAL | Region::Dendy => 312,
+        }
+    }
+
+    /// Idle lines between the last visible one and vblank starting. Dendy puts most of its extra lines here, so games
+    /// written for NTSC still get the vblank length they expect.
+    pub fn post_render_scanlines(&self) -> u16 {
+        match self {
+            Region::NTSC | Region::PAL => 1,
+            Region::Dendy => 51,
+        }
+    }
+
+    /// Lines with the vblank flag set, the NMI fires at the start of the first one.
+    pub fn vblank_scanlines(&self) -> u16 {
+        match self {
+            Region::NTSC | Region::Dendy => 20,
+            Region::PAL => 70,
+        }
+    }
+
+    /// Only NTSC skips a dot on odd frames when rendering is enabled.
+    pub fn skips_odd_frame_dot(&self) -> bool {
+        matches!(self, Region::NTSC)
+    }
+
+    /// In Hz, with rendering enabled.
+    pub fn frame_rate(&self) -> f64 {
+        let dots = (self.scanlines() as f64) * DOTS_PER_SCANLINE
+            - if self.skips_odd_frame_dot() { 0.5 } else { 0.0 };
+        self.master_clock_rate() / (self.ppu_divider() as f64) / dots
+    }
+
+    /// CPU cycles per frame, fractional as frames don't line up with CPU cycles.
+    pub fn cpu_cycles_per_frame(&self) -> f64 {
+        self.cpu_clock_rate() / self.frame_rate()
+    }
+
+    /// Whether the APU uses the PAL noise and DMC periods and frame counter sequence.
+    pub fn has_pal_apu(&self) -> bool {
+        matches!(self, Region::PAL)
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ntsc" => Ok(Region::NTSC),
+            "pal" => Ok(Region::PAL),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!(
+                "unknown region {:?}, expected ntsc, pal or dendy",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Region;
+
+    #[test]
+    pub fn timing() {
+        let close = |a: f64, b: f64| (a - b).abs() < 0.001;
+
+        assert!(close(Region::NTSC.cpu_clock_rate(), 1_789_772.727));
+        assert!(close(Region::PAL.cpu_clock_rate(), 1_662_607.0));
+        assert!(close(Region::Dendy.cpu_clock_rate(), 1_773_447.467));
+
+        assert!(close(Region::NTSC.ppu_dots_per_cpu_cycle(), 3.0));
+        assert!(close(Region::PAL.ppu_dots_per_cpu_cycle(), 3.2));
+        assert!(close(Region::Dendy.ppu_dots_per_cpu_cycle(), 3.0));
+
+        assert!(close(Region::NTSC.frame_rate(), 60.0988));
+        assert!(close(Region::PAL.frame_rate(), 50.0070));
+        assert!(close(Region::Dendy.frame_rate(), 50.0070));
+        assert!(close(Region::NTSC.cpu_cycles_per_frame(), 29780.5));
+        assert!(close(Region::PAL.cpu_cycles_per_frame(), 33247.5));
+        assert!(close(Region::Dendy.cpu_cycles_per_frame(), 35464.0));
+
+        // everyone gets 240 visible lines and the pre-render line
+        for region in [Region::NTSC, Region::PAL, Region::Dendy] {
+            assert_eq!(
+                region.scanlines() - region.post_render_scanlines() - region.vblank_scanlines() - 1,
+                240
+            );
+        }
+    }
+}
@@ -0,0 +1,317 @@
+// Rewinding, from snapshots taken every few frames and the input since.
+//
+// Most of a machine doesn't change between snapshots, so only every KEYFRAME_INTERVAL-th snapshot is kept whole and
+// the rest are XORed against the keyframe before them, leaving runs of zeros that are stored as a count. Snapshots
+// come from bincode rather than save states' JSON so everything stays at the same offset from one to the next.
+// Rewinding restores the newest snapshot at or before where it's going and runs forward to it with the recorded
+// input.
+
+use std::{collections::VecDeque, io};
+
+/// Snapshots per keyframe, including the keyframe.
+const KEYFRAME_INTERVAL: u32 = 30;
+/// Shorter runs of zeros are kept in with the bytes around them, as a run costs 8 bytes.
+const MIN_ZERO_RUN: usize = 8;
+
+/// What can be rewound, run a frame at a time.
+pub trait Machine {
+    /// What was pressed during a frame.
+    type Input: Clone;
+
+    /// Everything that changes as it runs, the same length from one frame to the next where possible.
+    fn snapshot(&self) -> Vec<u8>;
+    fn restore(&mut self, snapshot: &[u8]);
+    fn run_frame(&mut self, input: &Self::Input) -> io::Result<()>;
+}
+
+enum Data {
+    Keyframe(Vec<u8>),
+    /// Against the keyframe before it.
+    Delta(Vec<u8>),
+}
+
+struct Snapshot {
+    /// Taken at the start of this frame.
+    frame: u64,
+    data: Data,
+}
+
+impl Snapshot {
+    fn is_keyframe(&self) -> bool {
+        matches!(self.data, Data::Keyframe(_))
+    }
+
+    fn size(&self) -> usize {
+        match &self.data {
+            Data::Keyframe(x) | Data::Delta(x) => x.len(),
+        }
+    }
+}
+
+pub struct Rewind<M>
+where
+    M: Machine,
+{
+    /// The most frames it can go back.
+    length: u64,
+    /// Frames between snapshots.
+    interval: u64,
+    /// In bytes, of snapshots. The newest keyframe and its deltas are always kept, even if they don't fit.
+    budget: usize,
+    /// The frame the machine is about to run.
+    frame: u64,
+    snapshots: VecDeque<Snapshot>,
+    size: usize,
+    /// For every frame from the oldest snapshot on.
+    inputs: VecDeque<M::Input>,
+}
+
+impl<M> Rewind<M>
+where
+    M: Machine,
+{
+    pub fn new(length: u64, interval: u64, budget: usize) -> Self {
+        assert!(interval > 0, "snapshot interval of 0 frames");
+        Self {
+            length,
+            interval,
+            budget,
+            frame: 0,
+            snapshots: VecDeque::new(),
+            size: 0,
+            inputs: VecDeque::new(),
+        }
+    }
+
+    /// Runs a frame, recording its input and taking a snapshot first when one's due.
+    pub fn run_frame(&mut self, machine: &mut M, input: M::Input) -> io::Result<()> {
+        let taken = self.snapshots.back().is_some_and(|x| x.frame == self.frame);
+        if self.frame.is_multiple_of(self.interval) && !taken {
+            self.take_snapshot(machine);
+        }
+        machine.run_frame(&input)?;
+        self.inputs.push_back(input);
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// Goes back the given number of frames, or as far as it can, and returns how many it went back.
+    pub fn rewind(&mut self, machine: &mut M, frames: u64) -> io::Result<u64> {
+        let Some(oldest) = self.snapshots.front() else {
+            return Ok(0);
+        };
+        let target = self.frame.saturating_sub(frames).max(oldest.frame);
+        let index = self
+            .snapshots
+            .iter()
+            .rposition(|x| x.frame <= target)
+            .unwrap();
+        machine.restore(&self.decode(index));
+
+        let first_input = self.frame - (self.inputs.len() as u64);
+        let start = self.snapshots[index].frame;
+        for frame in start..target {
+            machine.run_frame(&self.inputs[(frame - first_input) as usize])?;
+        }
+
+        for snapshot in self.snapshots.drain((index + 1)..) {
+            self.size -= snapshot.size();
+        }
+        self.inputs.truncate((target - first_input) as usize);
+        let result = self.frame - target;
+        self.frame = target;
+        Ok(result)
+    }
+
+    /// How many frames back it can go.
+    pub fn available(&self) -> u64 {
+        self.snapshots
+            .front()
+            .map_or(0, |oldest| self.frame - oldest.frame)
+    }
+
+    /// Bytes of snapshots held.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn take_snapshot(&mut self, machine: &M) {
+        let snapshot = machine.snapshot();
+        let since_keyframe = self.snapshots.iter().rev().position(Snapshot::is_keyframe);
+        let data = match since_keyframe {
+            Some(x) if (x as u32) < KEYFRAME_INTERVAL - 1 => {
+                let Data::Keyframe(keyframe) = &self.snapshots[self.snapshots.len() - 1 - x].data
+                else {
+                    unreachable!()
+                };
+                Data::Delta(delta(keyframe, &snapshot))
+            }
+            _ => Data::Keyframe(snapshot),
+        };
+        let snapshot = Snapshot {
+            frame: self.frame,
+            data,
+        };
+        self.size += snapshot.size();
+        self.snapshots.push_back(snapshot);
+        self.trim();
+    }
+
+    /// Drops the oldest keyframe and its deltas while over budget, or while the next keyframe alone goes back far
+    /// enough.
+    fn trim(&mut self) {
+        while let Some(next_keyframe) = self
+            .snapshots
+            .iter()
+            .skip(1)
+            .find(|x| x.is_keyframe())
+            .map(|x| x.frame)
+        {
+            if self.size <= self.budget && self.frame - next_keyframe < self.length {
+                break;
+            }
+            while self.snapshots.front().unwrap().frame < next_keyframe {
+                let snapshot = self.snapshots.pop_front().unwrap();
+                self.size -= snapshot.size();
+            }
+        }
+        let first_input = self.frame - (self.inputs.len() as u64);
+        let oldest = self.snapshots.front().unwrap().frame;
+        self.inputs.drain(..((oldest - first_input) as usize));
+    }
+
+    fn decode(&self, index: usize) -> Vec<u8> {
+        match &self.snapshots[index].data {
+            Data::Keyframe(x) => x.clone(),
+            Data::Delta(x) => {
+                let keyframe = self
+                    .snapshots
+                    .range(..index)
+                    .rev()
+                    .find_map(|x| match &x.data {
+                        Data::Keyframe(keyframe) => Some(keyframe),
+                        Data::Delta(_) => None,
+                    })
+                    .unwrap();
+                apply(keyframe, x)
+            }
+        }
+    }
+}
+
+/// The snapshot's length, then runs of a count of zeros to skip, a count of bytes and the bytes, all XORed with the
+/// keyframe. Counts are little endian u32s and a snapshot longer than the keyframe is XORed with zeros past its end.
+fn delta(keyframe: &[u8], snapshot: &[u8]) -> Vec<u8> {
+    let xor = snapshot
+        .iter()
+        .enumerate()
+        .map(|(i, x)| x ^ keyframe.get(i).copied().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let mut result = (snapshot.len() as u32).to_le_bytes().to_vec();
+    let mut i = 0;
+    while i < xor.len() {
+        let start = i;
+        while i < xor.len() && xor[i] == 0 {
+            i += 1;
+        }
+        let zeros = i - start;
+        let literal = i;
+        while i < xor.len() && xor[i..].iter().take(MIN_ZERO_RUN).any(|&x| x != 0) {
+            i += 1;
+        }
+        // trailing zeros don't need a run
+        if i > literal {
+            result.extend_from_slice(&(zeros as u32).to_le_bytes());
+            result.extend_from_slice(&((i - literal) as u32).to_le_bytes());
+            result.extend_from_slice(&xor[literal..i]);
+        }
+    }
+    result
+}
+
+fn apply(keyframe: &[u8], delta: &[u8]) -> Vec<u8> {
+    let u32_at = |i: usize| u32::from_le_bytes(delta[i..(i + 4)].try_into().unwrap()) as usize;
+    let mut result = keyframe.to_vec();
+    result.resize(u32_at(0), 0);
+    let mut position = 0;
+    let mut i = 4;
+    while i < delta.len() {
+        position += u32_at(i);
+        let count = u32_at(i + 4);
+        i += 8;
+        for x in &delta[i..(i + count)] {
+            result[position] ^= x;
+            position += 1;
+        }
+        i += count;
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::{Machine, Rewind};
+
+    /// Adds each frame's input to a byte of RAM, the frame picks which.
+    struct Counter {
+        ram: Vec<u8>,
+        frame: u64,
+    }
+
+    impl Machine for Counter {
+        type Input = u8;
+
+        fn snapshot(&self) -> Vec<u8> {
+            let mut result = self.frame.to_le_bytes().to_vec();
+            result.extend_from_slice(&self.ram);
+            result
+        }
+
+        fn restore(&mut self, snapshot: &[u8]) {
+            self.frame = u64::from_le_bytes(snapshot[..8].try_into().unwrap());
+            self.ram = snapshot[8..].to_vec();
+        }
+
+        fn run_frame(&mut self, input: &u8) -> io::Result<()> {
+            let i = (self.frame as usize * 7) % self.ram.len();
+            self.ram[i] = self.ram[i].wrapping_add(*input);
+            self.frame += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn rewind() {
+        let mut machine = Counter {
+            ram: vec![0; 0x800],
+            frame: 0,
+        };
+        let mut rewind = Rewind::new(600, 4, 1 << 20);
+        let mut history = Vec::new();
+        for frame in 0..1000u64 {
+            history.push(machine.snapshot());
+            rewind.run_frame(&mut machine, (frame % 251) as u8).unwrap();
+        }
+
+        // back to between snapshots, then far enough that it stops at the oldest
+        assert_eq!(rewind.rewind(&mut machine, 10).unwrap(), 10);
+        assert_eq!(machine.snapshot(), history[990]);
+        let available = rewind.available();
+        assert!((600..(600 + 4 * 30)).contains(&available));
+        assert_eq!(rewind.rewind(&mut machine, 10_000).unwrap(), available);
+        assert_eq!(machine.snapshot(), history[(990 - available) as usize]);
+        assert_eq!(rewind.available(), 0);
+
+        // over budget drops whole keyframes and their deltas, never the newest
+        let mut small = Rewind::new(600, 1, 0x4000);
+        for _ in 0..200 {
+            small.run_frame(&mut machine, 1).unwrap();
+        }
+        assert!(small.size() <= 0x4000);
+        assert!(small.available() >= 30);
+        small.rewind(&mut machine, 1).unwrap();
+        assert_eq!(small.rewind(&mut machine, 1).unwrap(), 1);
+    }
+}
@@ -0,0 +1,382 @@
+// Fixes up headers of known dumps, since plenty out there have garbage in bytes 7-15 or the wrong mirroring.
+// The embedded database is only a handful of entries, --database takes a bigger one like NES 2.0 DB's nes20db.xml, see
+// https://forums.nesdev.org/viewtopic.php?t=19940
+
+use sha1::{Digest, Sha1};
+use std::{error::Error, fmt::Display};
+
+use crate::cartridge_file::{Cartridge, NametableArrangement, TVSystem};
+
+const DATABASE: &str = include_str!("rom_database.csv");
+
+#[derive(Debug, Clone, Copy)]
+pub enum DatabaseError {
+    /// 1-based line number.
+    BadLine(usize),
+    /// 1-based index of the <game> element in nes20db.xml.
+    BadGame(usize),
+}
+
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for DatabaseError {}
+
+pub struct Entry {
+    crc32: Option<u32>,
+    sha1: Option<[u8; 20]>,
+    mapper: u16,
+    submapper: u8,
+    /// None when it's up to the mapper.
+    mirroring: Option<NametableArrangement>,
+    battery: bool,
+    tv_system: TVSystem,
+    name: String,
+}
+
+impl Entry {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Something `correct` changed in a header, from what it was to what it is now.
+#[derive(Debug, Clone, Copy)]
+pub enum Correction {
+    Mapper(u16, u16),
+    Submapper(u8, u8),
+    Mirroring(NametableArrangement, NametableArrangement),
+    Battery(bool, bool),
+    TVSystem(TVSystem, TVSystem),
+}
+
+impl Display for Correction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Correction::Mapper(from, to) => write!(f, "mapper {} -> {}", from, to),
+            Correction::Submapper(from, to) => write!(f, "submapper {} -> {}", from, to),
+            Correction::Mirroring(from, to) => write!(f, "mirroring {:?} -> {:?}", from, to),
+            Correction::Battery(from, to) => write!(f, "battery {} -> {}", from, to),
+            Correction::TVSystem(from, to) => write!(f, "tv system {:?} -> {:?}", from, to),
+        }
+    }
+}
+
+pub struct RomDatabase {
+    entries: Vec<Entry>,
+}
+
+impl RomDatabase {
+    /// The database built into the binary.
+    pub fn embedded() -> Self {
+        Self::parse(DATABASE).expect("embedded rom database is valid")
+    }
+
+    /// Either format, going by whether it looks like XML.
+    pub fn parse_any(text: &str) -> Result<Self, DatabaseError> {
+        if text.trim_start().starts_with('<') {
+            Self::parse_nes20db(text)
+        } else {
+            Self::parse(text)
+        }
+    }
+
+    /// Same format as rom_database.csv.
+    pub fn parse(text: &str) -> Result<Self, DatabaseError> {
+        let mut entries = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            entries.push(parse_entry(line).ok_or(DatabaseError::BadLine(i + 1))?);
+        }
+        Ok(Self { entries })
+    }
+
+    /// NES 2.0 DB's XML. Each <game> is keyed by the hashes of its <rom>, which is PRG ROM followed by CHR ROM, and named
+    /// after the comment holding its file name.
+    pub fn parse_nes20db(text: &str) -> Result<Self, DatabaseError> {
+        let mut entries = Vec::new();
+        for (i, game) in text.split("<game>").skip(1).enumerate() {
+            let game = game.split("</game>").next().unwrap_or(game);
+            entries.push(parse_game(game).ok_or(DatabaseError::BadGame(i + 1))?);
+        }
+        Ok(Self { entries })
+    }
+
+    /// Entries from the other database are only used when this one has no match.
+    pub fn with_fallback(mut self, fallback: RomDatabase) -> Self {
+        self.entries.extend(fallback.entries);
+        self
+    }
+
+    /// Goes by the ROM's actual size, not the whole blocks it's loaded into.
+    pub fn lookup(&self, cartridge: &Cartridge) -> Option<&Entry> {
+        let rom = cartridge
+            .prg_rom_bytes()
+            .chain(cartridge.chr_rom_bytes())
+            .collect::<Vec<_>>();
+        let crc32 = crc32fast::hash(&rom);
+        let sha1: [u8; 20] = Sha1::digest(&rom).into();
+
+        self.entries
+            .iter()
+            .find(|x| x.sha1 == Some(sha1))
+            .or_else(|| {
+                self.entries
+                    .iter()
+                    .find(|x| x.sha1.is_none() && x.crc32 == Some(crc32))
+            })
+    }
+
+    /// Overwrites the header with whatever the database knows about the cartridge. Meant to be run between loading and
+    /// `memory::new`.
+    pub fn correct(&self, cartridge: &mut Cartridge) -> (Option<&Entry>, Vec<Correction>) {
+        let Some(entry) = self.lookup(cartridge) else {
+            return (None, Vec::new());
+        };
+        let header = cartridge.header_mut();
+        let mut corrections = Vec::new();
+
+        if header.mapper_number() != entry.mapper {
+            corrections.push(Correction::Mapper(header.mapper_number(), entry.mapper));
+        }
+        if header.submapper() != entry.submapper {
+            corrections.push(Correction::Submapper(header.submapper(), entry.submapper));
+        }
+        if !corrections.is_empty() {
+            header.set_mapper(entry.mapper, entry.submapper);
+        }
+
+        if let Some(mirroring) = entry.mirroring {
+            if header.nametable_arrangement() != mirroring {
+                corrections.push(Correction::Mirroring(
+                    header.nametable_arrangement(),
+                    mirroring,
+                ));
+                header.set_nametable_arrangement(mirroring);
+            }
+        }
+        if header.has_battery_backed_prg_ram() != entry.battery {
+            corrections.push(Correction::Battery(
+                header.has_battery_backed_prg_ram(),
+                entry.battery,
+            ));
+            header.set_battery_backed_prg_ram(entry.battery);
+        }
+        if header.tv_system() != entry.tv_system {
+            corrections.push(Correction::TVSystem(header.tv_system(), entry.tv_system));
+            header.set_tv_system(entry.tv_system);
+        }
+
+        (Some(entry), corrections)
+    }
+}
+
+fn parse_entry(line: &str) -> Option<Entry> {
+    let fields = line.splitn(8, ',').map(|x| x.trim()).collect::<Vec<_>>();
+    let [crc32, sha1, mapper, submapper, mirroring, battery, tv_system, name] = fields[..] else {
+        return None;
+    };
+    let crc32 = if crc32.is_empty() {
+        None
+    } else {
+        Some(u32::from_str_radix(crc32, 16).ok()?)
+    };
+    let sha1 = if sha1.is_empty() {
+        None
+    } else {
+        Some(parse_sha1(sha1)?)
+    };
+    if crc32.is_none() && sha1.is_none() {
+        return None;
+    }
+    Some(Entry {
+        crc32,
+        sha1,
+        mapper: mapper.parse().ok()?,
+        submapper: submapper.parse().ok()?,
+        mirroring: match mirroring {
+            "-" => None,
+            _ => Some(mirroring.parse().ok()?),
+        },
+        battery: match battery {
+            "0" => false,
+            "1" => true,
+            _ => return None,
+        },
+        tv_system: tv_system.parse().ok()?,
+        name: name.to_string(),
+    })
+}
+
+/// What's between <game> and </game>.
+fn parse_game(game: &str) -> Option<Entry> {
+    let name = game
+        .split_once("<!--")
+        .and_then(|(_, x)| x.split_once("-->"))
+        .map(|(x, _)| x.trim())
+        .unwrap_or_default();
+    // the comment is the file name, but the extension isn't part of the name
+    let name = name
+        .rsplit_once('.')
+        .filter(|(_, extension)| !extension.contains(' '))
+        .map_or(name, |(x, _)| x);
+    Some(Entry {
+        crc32: Some(u32::from_str_radix(attribute(game, "rom", "crc32")?, 16).ok()?),
+        sha1: Some(parse_sha1(attribute(game, "rom", "sha1")?)?),
+        mapper: attribute(game, "pcb", "mapper")?.parse().ok()?,
+        submapper: attribute(game, "pcb", "submapper")?.parse().ok()?,
+        mirroring: match attribute(game, "pcb", "mirroring")? {
+            "H" => Some(NametableArrangement::Horizontal),
+            "V" => Some(NametableArrangement::Vertical),
+            "4" => Some(NametableArrangement::FourScreenMirroring),
+            // mapper controlled
+            _ => None,
+        },
+        battery: match attribute(game, "pcb", "battery")? {
+            "0" => false,
+            "1" => true,
+            _ => return None,
+        },
+        tv_system: match attribute(game, "console", "region")? {
+            "0" => TVSystem::NTSC,
+            "1" => TVSystem::PAL,
+            "2" => TVSystem::Both,
+            "3" => TVSystem::Dendy,
+            _ => return None,
+        },
+        name: name.to_string(),
+    })
+}
+
+/// The value of name="..." in the first <element ...> tag.
+fn attribute<'a>(text: &'a str, element: &str, name: &str) -> Option<&'a str> {
+    // from the space before the first attribute
+    let start = text.find(&format!("<{} ", element))? + 1 + element.len();
+    let (tag, _) = text[start..].split_once('>')?;
+    let (_, value) = tag.split_once(&format!(" {}=\"", name))?;
+    value.split_once('"').map(|(x, _)| x)
+}
+
+fn parse_sha1(text: &str) -> Option<[u8; 20]> {
+    if text.len() != 40 {
+        return None;
+    }
+    let mut result = [0; 20];
+    for (i, x) in result.iter_mut().enumerate() {
+        *x = u8::from_str_radix(text.get((i * 2)..(i * 2 + 2))?, 16).ok()?;
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod test {
+    use sha1::{Digest, Sha1};
+
+    use super::{Correction, DatabaseError, RomDatabase};
+    use crate::cartridge_file::{Cartridge, NametableArrangement, TVSystem};
+
+    #[test]
+    pub fn corrects_header() {
+        RomDatabase::embedded();
+
+        // horizontal mirroring, and junk in the unused byte 11
+        let mut data = vec![
+            b'N', b'E', b'S', 0x1a, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x02, b'D', 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        let rom = vec![0x42; 16 * 1024 + 8 * 1024];
+        data.extend_from_slice(&rom);
+        let mut cartridge = Cartridge::from_bytes(data).unwrap();
+        assert_eq!(cartridge.header().tv_system(), TVSystem::PAL);
+
+        let database = RomDatabase::parse(&format!(
+            "# comment\n{:08x},,0,2,v,1,ntsc,Test\n",
+            crc32fast::hash(&rom)
+        ))
+        .unwrap();
+        let (entry, corrections) = database.correct(&mut cartridge);
+        assert_eq!(entry.unwrap().name(), "Test");
+        assert!(matches!(
+            corrections[..],
+            [
+                Correction::Submapper(0, 2),
+                Correction::Mirroring(
+                    NametableArrangement::Horizontal,
+                    NametableArrangement::Vertical
+                ),
+                Correction::Battery(false, true),
+                Correction::TVSystem(TVSystem::PAL, TVSystem::NTSC),
+            ]
+        ));
+
+        let header = cartridge.header();
+        assert!(header.is_nes_2_0());
+        assert_eq!(header.mapper_number(), 0);
+        assert_eq!(header.submapper(), 2);
+        assert_eq!(
+            header.nametable_arrangement(),
+            NametableArrangement::Vertical
+        );
+        assert!(header.has_battery_backed_prg_ram());
+        assert_eq!(header.prg_nvram_size().in_bytes(), 8 * 1024);
+        assert_eq!(header.tv_system(), TVSystem::NTSC);
+
+        assert!(database.correct(&mut cartridge).1.is_empty());
+    }
+
+    #[test]
+    pub fn nes20db() {
+        // NES 2.0 with 2^6 * 3 = 192 bytes of PRG ROM, less than a block
+        let mut data = b"NES\x1a\x19\x00\x00\x08\x00\x0f\x00\x00\x00\x00\x00\x00".to_vec();
+        let rom = (0..192).map(|x| x as u8).collect::<Vec<_>>();
+        data.extend_from_slice(&rom);
+        let mut cartridge = Cartridge::from_bytes(data).unwrap();
+        assert_eq!(cartridge.pgr_rom()[0].len(), 16 * 1024);
+
+        let sha1 = Sha1::digest(&rom)
+            .iter()
+            .map(|x| format!("{:02X}", x))
+            .collect::<String>();
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<nes20db date="2024-01-01">
+<game>
+<!-- Test Game (World).nes -->
+<prgrom size="192" crc32="{crc32:08X}" sha1="{sha1}" sum16="0000"/>
+<rom size="192" crc32="{crc32:08X}" sha1="{sha1}"/>
+<pcb mapper="0" submapper="0" mirroring="V" battery="1"/>
+<console type="0" region="1"/>
+</game>
+</nes20db>
+"#,
+            crc32 = crc32fast::hash(&rom),
+        );
+        let database = RomDatabase::parse_any(&xml)
+            .unwrap()
+            .with_fallback(RomDatabase::embedded());
+        let (entry, corrections) = database.correct(&mut cartridge);
+        assert_eq!(entry.unwrap().name(), "Test Game (World)");
+        assert!(matches!(
+            corrections[..],
+            [
+                Correction::Mirroring(
+                    NametableArrangement::Horizontal,
+                    NametableArrangement::Vertical
+                ),
+                Correction::Battery(false, true),
+                Correction::TVSystem(TVSystem::NTSC, TVSystem::PAL),
+            ]
+        ));
+
+        assert!(matches!(
+            RomDatabase::parse_any("<nes20db>\n<game>\n<pcb mapper=\"0\"/>\n</game>"),
+            Err(DatabaseError::BadGame(1))
+        ));
+    }
+}
@@ -0,0 +1,158 @@
+// Getting from a path on the command line to a ROM, the same way for every command.
+
+use anyhow::Context;
+use log::*;
+use std::{fs::File, io::Read, path::Path};
+
+use crate::{
+    archive,
+    cartridge_file::{Cartridge, Strictness},
+    patch,
+    rom_database::RomDatabase,
+    unif_file,
+};
+
+pub struct Loader {
+    /// Which file to take from a zip, instead of the first ROM in it.
+    zip_entry: Option<String>,
+    auto_patch: bool,
+    database: RomDatabase,
+}
+
+impl Loader {
+    pub fn new(zip_entry: Option<String>, auto_patch: bool, database: RomDatabase) -> Self {
+        Self {
+            zip_entry,
+            auto_patch,
+            database,
+        }
+    }
+
+    /// Whether game.ips, game.bps or game.ups next to game.nes get applied.
+    pub fn auto_patch(&self) -> bool {
+        self.auto_patch
+    }
+
+    pub fn database(&self) -> &RomDatabase {
+        &self.database
+    }
+
+    /// The file's bytes, taken out of any zip or gzip and then patched.
+    pub fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        let buffer = archive::extract(read_file(path)?, self.zip_entry.as_deref())
+            .with_context(|| format!("extracting {:?}", path))?;
+        if self.auto_patch {
+            apply_adjacent_patch(path, buffer)
+        } else {
+            Ok(buffer)
+        }
+    }
+
+    /// As read, then parsed with the header corrected from the ROM database.
+    pub fn cartridge(&self, path: &Path) -> anyhow::Result<Cartridge> {
+        self.cartridge_with_strictness(path, Strictness::Lenient)
+    }
+
+    pub fn cartridge_with_strictness(
+        &self,
+        path: &Path,
+        strictness: Strictness,
+    ) -> anyhow::Result<Cartridge> {
+        let mut cartridge = parse_cartridge(self.read(path)?, strictness)
+            .with_context(|| format!("reading {:?}", path))?;
+        let (entry, corrections) = self.database.correct(&mut cartridge);
+        if let Some(entry) = entry {
+            info!("{:?}: found in rom database as {:?}", path, entry.name());
+        }
+        for correction in corrections {
+            info!("{:?}: corrected {}", path, correction);
+        }
+        Ok(cartridge)
+    }
+}
+
+/// An iNES/NES 2.0 or UNIF file, going by its magic number. UNIF files are made of chunks, so there's nothing for
+/// strictness to reject.
+pub fn parse_cartridge(buffer: Vec<u8>, strictness: Strictness) -> anyhow::Result<Cartridge> {
+    if buffer.starts_with(b"UNIF") {
+        Ok(unif_file::from_bytes(&buffer)?)
+    } else {
+        Ok(Cartridge::from_bytes_with_strictness(buffer, strictness)?)
+    }
+}
+
+pub fn read_file(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut f = File::open(path).with_context(|| format!("opening {:?}", path))?;
+    let mut buffer = Vec::new();
+    f.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Patches with the first of game.ips, game.bps or game.ups found next to game.nes.
+fn apply_adjacent_patch(path: &Path, buffer: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    for extension in patch::EXTENSIONS {
+        let patch_path = path.with_extension(extension);
+        if patch_path.exists() {
+            let patched = patch::apply(&read_file(&patch_path)?, &buffer)
+                .with_context(|| format!("applying {:?}", patch_path))?;
+            info!("applied {:?}", patch_path);
+            return Ok(patched);
+        }
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Write};
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::Loader;
+    use crate::{cartridge_file::NametableArrangement, rom_database::RomDatabase};
+
+    #[test]
+    pub fn patched_zip() {
+        let directory = std::env::temp_dir().join(format!("rom_loader-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        // horizontal mirroring, which the database says is wrong once it's patched
+        let mut rom = b"NES\x1a\x01\x01\0\0\0\0\0\0\0\0\0\0".to_vec();
+        rom.resize(rom.len() + 16 * 1024 + 8 * 1024, 0);
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file("game.nes", SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(&rom).unwrap();
+        let zip_path = directory.join("game.zip");
+        std::fs::write(&zip_path, writer.finish().unwrap().into_inner()).unwrap();
+        // puts $42 at $8000
+        std::fs::write(
+            directory.join("game.ips"),
+            b"PATCH\x00\x00\x10\x00\x01\x42EOF",
+        )
+        .unwrap();
+
+        let mut patched = vec![0; 16 * 1024 + 8 * 1024];
+        patched[0] = 0x42;
+        let database = RomDatabase::parse(&format!(
+            "{:08x},,0,0,v,0,ntsc,Test\n",
+            crc32fast::hash(&patched)
+        ))
+        .unwrap();
+
+        let unpatched = Loader::new(None, false, RomDatabase::parse("").unwrap())
+            .cartridge(&zip_path)
+            .unwrap();
+        assert_eq!(unpatched.pgr_rom()[0][0], 0);
+
+        let cartridge = Loader::new(None, true, database)
+            .cartridge(&zip_path)
+            .unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(cartridge.pgr_rom()[0][0], 0x42);
+        assert_eq!(
+            cartridge.header().nametable_arrangement(),
+            NametableArrangement::Vertical
+        );
+    }
+}
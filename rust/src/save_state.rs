@@ -0,0 +1,263 @@
+// Save states, a snapshot of everything that changes as a machine runs.
+//
+// A state is MAGIC, VERSION and the CRC32 of the ROM it was made with, all little endian, then the components' serde
+// representation as deflated JSON. JSON keeps it forward compatible: fields a build doesn't know about are ignored and
+// fields added after a state was made need #[serde(default)]. VERSION only changes when a layout changes in a way
+// neither can cope with.
+
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::region::Region;
+
+const MAGIC: &[u8] = b"NESS";
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = MAGIC.len() + 2 + 4;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    NotASaveState,
+    UnsupportedVersion(u16),
+    /// Made with a different ROM, CRC32s given.
+    WrongRom {
+        expected: u32,
+        actual: u32,
+    },
+    WrongRegion {
+        expected: Region,
+        actual: Region,
+    },
+    Corrupt(String),
+}
+
+impl Display for SaveStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for SaveStateError {}
+
+impl From<serde_json::Error> for SaveStateError {
+    fn from(e: serde_json::Error) -> Self {
+        SaveStateError::Corrupt(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for SaveStateError {
+    fn from(e: std::io::Error) -> Self {
+        SaveStateError::Corrupt(e.to_string())
+    }
+}
+
+pub fn encode<T>(rom_crc32: u32, state: &T) -> Vec<u8>
+where
+    T: Serialize,
+{
+    let mut result = MAGIC.to_vec();
+    result.extend_from_slice(&VERSION.to_le_bytes());
+    result.extend_from_slice(&rom_crc32.to_le_bytes());
+    let mut encoder = DeflateEncoder::new(result, Compression::fast());
+    // neither can fail, it's all in memory and every component serializes
+    serde_json::to_writer(&mut encoder, state).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Checks the state was made with the same ROM before decoding it.
+pub fn decode<T>(rom_crc32: u32, data: &[u8]) -> Result<T, SaveStateError>
+where
+    T: DeserializeOwned,
+{
+    if data.len() < HEADER_SIZE || !data.starts_with(MAGIC) {
+        return Err(SaveStateError::NotASaveState);
+    }
+    let version = u16::from_le_bytes([data[4], data[5]]);
+    if version != VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    let actual = u32::from_le_bytes([data[6], data[7], data[8], data[9]]);
+    if actual != rom_crc32 {
+        return Err(SaveStateError::WrongRom {
+            expected: rom_crc32,
+            actual,
+        });
+    }
+    let mut json = Vec::new();
+    DeflateDecoder::new(&data[HEADER_SIZE..]).read_to_end(&mut json)?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// For components behind trait objects, which hand their state over as a Value.
+pub fn to_value<T>(state: &T) -> Value
+where
+    T: Serialize,
+{
+    serde_json::to_value(state).unwrap()
+}
+
+pub fn from_value<T>(state: Value) -> Result<T, SaveStateError>
+where
+    T: DeserializeOwned,
+{
+    Ok(serde_json::from_value(state)?)
+}
+
+/// Numbered slots live next to the file they're for, game.nes's slot 1 is game.ss1.
+pub fn slot_path(path: &Path, slot: u8) -> PathBuf {
+    path.with_extension(format!("ss{}", slot))
+}
+
+pub fn write_slot(path: &Path, slot: u8, state: &[u8]) -> std::io::Result<()> {
+    std::fs::File::create(slot_path(path, slot))?.write_all(state)
+}
+
+pub fn read_slot(path: &Path, slot: u8) -> std::io::Result<Vec<u8>> {
+    std::fs::read(slot_path(path, slot))
+}
+
+/// Byte arrays as hex strings rather than arrays of numbers, for `#[serde(with = "save_state::hex")]`. Smaller, and
+/// serde only does arrays up to 32 long.
+pub mod hex {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T, S>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: AsRef<[u8]>,
+        S: Serializer,
+    {
+        serializer.serialize_str(&encode(bytes.as_ref()))
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: TryFrom<Vec<u8>>,
+        D: Deserializer<'de>,
+    {
+        let bytes = decode(&String::deserialize(deserializer)?).map_err(D::Error::custom)?;
+        let length = bytes.len();
+        T::try_from(bytes).map_err(|_| D::Error::custom(format!("wrong length {}", length)))
+    }
+
+    pub fn encode(bytes: &[u8]) -> String {
+        bytes.iter().map(|x| format!("{:02x}", x)).collect()
+    }
+
+    pub fn decode(s: &str) -> Result<Vec<u8>, String> {
+        if !s.len().is_multiple_of(2) {
+            return Err(format!("odd length hex {}", s.len()));
+        }
+        (0..s.len())
+            .step_by(2)
+            .map(|i| {
+                s.get(i..(i + 2))
+                    .and_then(|x| u8::from_str_radix(x, 16).ok())
+                    .ok_or_else(|| format!("bad hex at {}", i))
+            })
+            .collect()
+    }
+
+    /// As hex, for optional memory.
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<T, S>(bytes: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            T: AsRef<[u8]>,
+            S: Serializer,
+        {
+            match bytes {
+                Some(bytes) => serializer.serialize_some(&super::encode(bytes.as_ref())),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+        where
+            T: TryFrom<Vec<u8>>,
+            D: Deserializer<'de>,
+        {
+            #[derive(Deserialize)]
+            struct Wrapper<T>(#[serde(with = "super")] T)
+            where
+                T: TryFrom<Vec<u8>>;
+
+            Ok(Option::<Wrapper<T>>::deserialize(deserializer)?.map(|x| x.0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::{Deserialize, Serialize};
+
+    use super::SaveStateError;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Old {
+        #[serde(with = "super::hex")]
+        ram: [u8; 64],
+        a: u8,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct New {
+        #[serde(with = "super::hex")]
+        ram: [u8; 64],
+        a: u8,
+        #[serde(default, with = "super::hex::option")]
+        extra: Option<Vec<u8>>,
+    }
+
+    #[test]
+    pub fn compatibility() {
+        let mut ram = [0; 64];
+        ram[3] = 0xa5;
+        let state = super::encode(0x1234, &Old { ram, a: 7 });
+
+        assert!(matches!(
+            super::decode::<Old>(0x4321, &state),
+            Err(SaveStateError::WrongRom {
+                expected: 0x4321,
+                actual: 0x1234
+            })
+        ));
+        assert!(matches!(
+            super::decode::<Old>(0x1234, &state[1..]),
+            Err(SaveStateError::NotASaveState)
+        ));
+
+        // newer builds default what's missing, older ones ignore what they don't know
+        let new: New = super::decode(0x1234, &state).unwrap();
+        assert_eq!(
+            new,
+            New {
+                ram,
+                a: 7,
+                extra: None
+            }
+        );
+        let newer = super::encode(
+            0x1234,
+            &New {
+                extra: Some(vec![1, 2]),
+                ..new
+            },
+        );
+        assert_eq!(
+            super::decode::<Old>(0x1234, &newer).unwrap(),
+            Old { ram, a: 7 }
+        );
+        assert_eq!(
+            super::decode::<New>(0x1234, &newer).unwrap().extra,
+            Some(vec![1, 2])
+        );
+    }
+}
@@ -0,0 +1,108 @@
+// Frames as image files, see https://netpbm.sourceforge.net/doc/ppm.html and https://www.w3.org/TR/png/
+
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use flate2::{write::ZlibEncoder, Compression};
+
+use crate::framebuffer::{Framebuffer, HEIGHT, WIDTH};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const PNG_BIT_DEPTH: u8 = 8;
+const PNG_COLOR_TYPE_RGB: u8 = 2;
+const PNG_FILTER_NONE: u8 = 0;
+
+/// Binary PPM.
+pub fn write_ppm<W>(w: &mut W, frame: &Framebuffer) -> io::Result<()>
+where
+    W: Write,
+{
+    write!(w, "P6\n{} {}\n255\n", WIDTH, HEIGHT)?;
+    w.write_all(&frame.to_rgb())
+}
+
+/// 8-bit RGB, unfiltered.
+pub fn write_png<W>(w: &mut W, frame: &Framebuffer) -> io::Result<()>
+where
+    W: Write,
+{
+    w.write_all(PNG_SIGNATURE)?;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(WIDTH as u32).to_be_bytes());
+    header.extend_from_slice(&(HEIGHT as u32).to_be_bytes());
+    // then compression, filter and interlace methods, all the default
+    header.extend_from_slice(&[PNG_BIT_DEPTH, PNG_COLOR_TYPE_RGB, 0, 0, 0]);
+    write_chunk(w, b"IHDR", &header)?;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in frame.to_rgb().chunks(WIDTH * 3) {
+        encoder.write_all(&[PNG_FILTER_NONE])?;
+        encoder.write_all(row)?;
+    }
+    write_chunk(w, b"IDAT", &encoder.finish()?)?;
+
+    write_chunk(w, b"IEND", &[])
+}
+
+/// PNG by the path's extension, PPM otherwise.
+pub fn write_file(path: &Path, frame: &Framebuffer) -> io::Result<()> {
+    let mut w = io::BufWriter::new(std::fs::File::create(path)?);
+    match path.extension().and_then(|x| x.to_str()) {
+        Some(x) if x.eq_ignore_ascii_case("png") => write_png(&mut w, frame)?,
+        _ => write_ppm(&mut w, frame)?,
+    }
+    w.flush()
+}
+
+fn write_chunk<W>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()>
+where
+    W: Write,
+{
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    w.write_all(&crc.finalize().to_be_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
+
+    use crate::framebuffer::{Framebuffer, HEIGHT, WIDTH};
+
+    #[test]
+    pub fn png() {
+        let mut frame = Framebuffer::new();
+        let blank = frame.hash();
+        frame.set(3, 1, 0x30);
+        assert_ne!(frame.hash(), blank);
+
+        let mut ppm = Vec::new();
+        super::write_ppm(&mut ppm, &frame).unwrap();
+        assert!(ppm.starts_with(b"P6\n256 240\n255\n"));
+        assert_eq!(ppm.len(), 15 + WIDTH * HEIGHT * 3);
+
+        let mut png = Vec::new();
+        super::write_png(&mut png, &frame).unwrap();
+        assert!(png.starts_with(super::PNG_SIGNATURE));
+        // every IEND chunk is the same
+        assert!(png.ends_with(b"\0\0\0\0IEND\xae\x42\x60\x82"));
+        let idat_length = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let mut rows = Vec::new();
+        ZlibDecoder::new(&png[41..(41 + idat_length)])
+            .read_to_end(&mut rows)
+            .unwrap();
+        assert_eq!(rows.len(), (1 + WIDTH * 3) * HEIGHT);
+        let pixel = 1 + WIDTH * 3 + 1 + 3 * 3;
+        assert_eq!(&rows[pixel..(pixel + 3)], &[0xff, 0xfe, 0xff]);
+    }
+}
@@ -3,10 +3,122 @@ pub mod test {
     use log::*;
     use std::fmt::Debug;
 
-    use crate::logging_utils::logger_builder;
+    use crate::{cartridge_file::Cartridge, logging_utils::logger_builder, memory::Memory};
 
+    /// Draws a row of tile 1 along the top and sprite 0, tile 2, at (16, 1), then copies $0200 to OAM every NMI.
+    const PICTURE_PROGRAM: [u8; 0x73] = [
+        0x78, // SEI
+        0xd8, // CLD
+        0xa2, 0xff, // LDX #$FF
+        0x9a, // TXS
+        0x2c, 0x02, 0x20, // BIT $2002
+        0x10, 0xfb, // BPL -5
+        0x2c, 0x02, 0x20, // BIT $2002
+        0x10, 0xfb, // BPL -5
+        0xa9, 0x3f, // LDA #$3F
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x00, // LDA #$00
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa2, 0x00, // LDX #$00
+        0xbd, 0x73, 0x80, // LDA PALETTE,X
+        0x8d, 0x07, 0x20, // STA $2007
+        0xe8, // INX
+        0xe0, 0x20, // CPX #$20
+        0xd0, 0xf5, // BNE -11
+        0xa9, 0x20, // LDA #$20
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x00, // LDA #$00
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x01, // LDA #$01
+        0xa2, 0x20, // LDX #$20
+        0x8d, 0x07, 0x20, // STA $2007
+        0xca, // DEX
+        0xd0, 0xfa, // BNE -6
+        0xa9, 0xff, // LDA #$FF
+        0xa2, 0x00, // LDX #$00
+        0x9d, 0x00, 0x02, // STA $0200,X
+        0xe8, // INX
+        0xd0, 0xfa, // BNE -6
+        0xa9, 0x00, // LDA #$00
+        0x8d, 0x00, 0x02, // STA $0200
+        0xa9, 0x02, // LDA #$02
+        0x8d, 0x01, 0x02, // STA $0201
+        0xa9, 0x00, // LDA #$00
+        0x8d, 0x02, 0x02, // STA $0202
+        0xa9, 0x10, // LDA #$10
+        0x8d, 0x03, 0x02, // STA $0203
+        0xa9, 0x00, // LDA #$00
+        0x8d, 0x05, 0x20, // STA $2005
+        0x8d, 0x05, 0x20, // STA $2005
+        0xa9, 0x80, // LDA #$80
+        0x8d, 0x00, 0x20, // STA $2000
+        0xa9, 0x1e, // LDA #$1E
+        0x8d, 0x01, 0x20, // STA $2001
+        0x4c, 0x6a, 0x80, // JMP self
+        // NMI
+        0xa9, 0x02, // LDA #$02
+        0x8d, 0x14, 0x40, // STA $4014
+        0x40, // RTI
+    ];
+    const PICTURE_NMI_ADDRESS: u16 = 0x806d;
+    const PICTURE_PALETTE: [u8; 0x20] = [
+        0x0f, 0x30, 0x16, 0x27, 0x0f, 0x00, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x0f, 0x00, 0x00,
+        0x00, 0x0f, 0x12, 0x2a, 0x38, 0x0f, 0x00, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x0f, 0x00,
+        0x00, 0x00,
+    ];
+
+    /// NROM with 16k of PRG and 8k of CHR ROM. The program goes at $8000, which is where it resets to, and NMIs and
+    /// IRQs go to the given address.
+    pub fn nrom(program: &[u8], nmi: u16, chr: &[u8]) -> Cartridge {
+        let mut prg = vec![0; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x3ffa..0x3ffc].copy_from_slice(&nmi.to_le_bytes());
+        prg[0x3ffc..0x3ffe].copy_from_slice(&0x8000u16.to_le_bytes());
+        prg[0x3ffe..0x4000].copy_from_slice(&nmi.to_le_bytes());
+
+        let mut data = b"NES\x1a\x01\x01\0\0\0\0\0\0\0\0\0\0".to_vec();
+        data.extend_from_slice(&prg);
+        let chr_start = data.len();
+        data.resize(chr_start + 0x2000, 0);
+        data[chr_start..(chr_start + chr.len())].copy_from_slice(chr);
+        Cartridge::from_bytes(data).unwrap()
+    }
+
+    /// A still picture, see PICTURE_PROGRAM. The background is color $30 and the sprite $38, on $0F.
+    pub fn picture() -> Cartridge {
+        let mut program = PICTURE_PROGRAM.to_vec();
+        program.extend_from_slice(&PICTURE_PALETTE);
+        // tile 1 is all color 1, tile 2 all color 3
+        let mut chr = vec![0; 0x30];
+        chr[0x10..0x18].fill(0xff);
+        chr[0x20..0x30].fill(0xff);
+        nrom(&program, PICTURE_NMI_ADDRESS, &chr)
+    }
+
+    /// Safe to call from every test, only the first one sets up logging.
     pub fn init() {
-        logger_builder().is_test(true).init();
+        let _ = logger_builder().is_test(true).try_init();
+    }
+
+    /// 64k of RAM and nothing else.
+    pub struct TestMemory {
+        data: [u8; 0x10000],
+    }
+
+    impl TestMemory {
+        pub fn new() -> Self {
+            Self { data: [0; 0x10000] }
+        }
+    }
+
+    impl Memory for TestMemory {
+        fn peek8(&self, address: u16) -> u8 {
+            self.data[address as usize]
+        }
+
+        fn write8(&mut self, address: u16, value: u8) {
+            self.data[address as usize] = value
+        }
     }
 
     pub struct TestResults {
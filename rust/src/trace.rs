@@ -0,0 +1,208 @@
+// Execution traces in the format of Nintendulator's nestest.log, see https://www.qmtpro.com/~nes/misc/nestest.log
+
+use crate::{
+    cpu::CPU,
+    disasm::{self, AddressingMode},
+    endians::Word,
+    memory::Memory,
+    region::Region,
+};
+
+const DOTS_PER_SCANLINE: u64 = 341;
+
+/// The line for the instruction the CPU is about to execute, the memory it shows is as it was before.
+pub fn line<M>(cpu: &CPU, m: &M, region: Region) -> String
+where
+    M: Memory,
+{
+    let instruction = disasm::disassemble(m, cpu.pc);
+    let bytes = instruction
+        .bytes
+        .iter()
+        .map(|x| format!("{:02X}", x))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let dots = ((cpu.clock as f64) * region.ppu_dots_per_cpu_cycle()) as u64;
+    format!(
+        "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        cpu.pc,
+        bytes,
+        if instruction.opcode.official { ' ' } else { '*' },
+        text(&instruction, cpu, m),
+        cpu.a,
+        cpu.x,
+        cpu.y,
+        cpu.flags.bits(),
+        cpu.sp,
+        (dots / DOTS_PER_SCANLINE) % (region.scanlines() as u64),
+        dots % DOTS_PER_SCANLINE,
+        cpu.clock
+    )
+}
+
+/// The disassembly with the effective address and whatever is there.
+fn text<M>(instruction: &disasm::Instruction, cpu: &CPU, m: &M) -> String
+where
+    M: Memory,
+{
+    // Nintendulator's name for ISC
+    let mnemonic = match instruction.opcode.mnemonic {
+        "ISC" => "ISB",
+        x => x,
+    };
+    let operand = instruction.operand();
+    let zero_page_pointer = |address: u8| -> u16 {
+        Word {
+            low: m.peek8(address as u16),
+            high: m.peek8(address.wrapping_add(1) as u16),
+        }
+        .into()
+    };
+    match instruction.opcode.mode {
+        AddressingMode::Implied => mnemonic.to_string(),
+        AddressingMode::Accumulator => format!("{} A", mnemonic),
+        AddressingMode::Immediate => format!("{} #${:02X}", mnemonic, operand),
+        AddressingMode::ZeroPage => {
+            format!("{} ${:02X} = {:02X}", mnemonic, operand, m.peek8(operand))
+        }
+        AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+            let (index, register) = if instruction.opcode.mode == AddressingMode::ZeroPageX {
+                (cpu.x, 'X')
+            } else {
+                (cpu.y, 'Y')
+            };
+            let address = (operand as u8).wrapping_add(index) as u16;
+            format!(
+                "{} ${:02X},{} @ {:02X} = {:02X}",
+                mnemonic,
+                operand,
+                register,
+                address,
+                m.peek8(address)
+            )
+        }
+        // jumps don't read anything
+        AddressingMode::Absolute if matches!(mnemonic, "JMP" | "JSR") => {
+            format!("{} ${:04X}", mnemonic, operand)
+        }
+        AddressingMode::Absolute => {
+            format!("{} ${:04X} = {:02X}", mnemonic, operand, m.peek8(operand))
+        }
+        AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+            let (index, register) = if instruction.opcode.mode == AddressingMode::AbsoluteX {
+                (cpu.x, 'X')
+            } else {
+                (cpu.y, 'Y')
+            };
+            let address = operand.wrapping_add(index as u16);
+            format!(
+                "{} ${:04X},{} @ {:04X} = {:02X}",
+                mnemonic,
+                operand,
+                register,
+                address,
+                m.peek8(address)
+            )
+        }
+        AddressingMode::Indirect => {
+            // the pointer's high byte comes from the start of the same page
+            let target: u16 = Word {
+                low: m.peek8(operand),
+                high: m.peek8((operand & 0xff00) | (operand.wrapping_add(1) & 0x00ff)),
+            }
+            .into();
+            format!("{} (${:04X}) = {:04X}", mnemonic, operand, target)
+        }
+        AddressingMode::IndirectX => {
+            let pointer = (operand as u8).wrapping_add(cpu.x);
+            let address = zero_page_pointer(pointer);
+            format!(
+                "{} (${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                mnemonic,
+                operand,
+                pointer,
+                address,
+                m.peek8(address)
+            )
+        }
+        AddressingMode::IndirectY => {
+            let pointer = zero_page_pointer(operand as u8);
+            let address = pointer.wrapping_add(cpu.y as u16);
+            format!(
+                "{} (${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                mnemonic,
+                operand,
+                pointer,
+                address,
+                m.peek8(address)
+            )
+        }
+        AddressingMode::Relative => format!("{} ${:04X}", mnemonic, operand),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{
+        cartridge_file::Cartridge, cpu::CPU, flags::Flags, memory::Memory, region::Region,
+        test_utils,
+    };
+
+    const ROM_PATH: &str = "../submodules/nes-test-roms/other/nestest.nes";
+    const LOG_PATH: &str = "../submodules/nes-test-roms/other/nestest.log";
+
+    /// Just enough of an NROM board for nestest, with the APU and I/O registers reading back $FF as they do in the
+    /// reference log.
+    struct NestestMemory {
+        ram: [u8; 0x800],
+        prg_rom: Vec<u8>,
+    }
+
+    impl Memory for NestestMemory {
+        fn peek8(&self, address: u16) -> u8 {
+            match address {
+                0x0000..=0x1fff => self.ram[(address as usize) % self.ram.len()],
+                0x8000..=0xffff => self.prg_rom[((address - 0x8000) as usize) % self.prg_rom.len()],
+                _ => 0xff,
+            }
+        }
+
+        fn write8(&mut self, address: u16, value: u8) {
+            if let 0x0000..=0x1fff = address {
+                self.ram[(address as usize) % self.ram.len()] = value;
+            }
+        }
+    }
+
+    /// Runs nestest's automated mode from $C000 and compares every line up to the first one that differs.
+    #[test]
+    #[ignore = "needs submodules/nes-test-roms, run with --ignored once it's checked out"]
+    pub fn nestest() {
+        test_utils::test::init();
+
+        let rom = fs::read(ROM_PATH).unwrap_or_else(|e| panic!("reading {}: {}", ROM_PATH, e));
+        let log =
+            fs::read_to_string(LOG_PATH).unwrap_or_else(|e| panic!("reading {}: {}", LOG_PATH, e));
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        let mut m = NestestMemory {
+            ram: [0; 0x800],
+            prg_rom: cartridge.pgr_rom().iter().flatten().copied().collect(),
+        };
+        let mut cpu = CPU::new();
+        cpu.pc = 0xc000;
+        cpu.sp = 0xfd;
+        cpu.flags = Flags::from_bits_retain(0x24);
+        // the reset sequence
+        cpu.clock = 7;
+
+        for (number, expected) in log.lines().enumerate() {
+            let actual = super::line(&cpu, &m, Region::NTSC);
+            assert_eq!(actual, expected, "first difference at line {}", number + 1);
+            cpu.step(&mut m);
+        }
+        // official and unofficial opcode results
+        assert_eq!((m.peek8(0x02), m.peek8(0x03)), (0, 0));
+    }
+}
@@ -0,0 +1,247 @@
+// see https://www.nesdev.org/wiki/UNIF
+
+use std::{error::Error, fmt::Display};
+
+use crate::cartridge_file::{
+    Cartridge, CartridgeError, HeaderBuilder, NametableArrangement, TVSystem,
+};
+
+#[derive(Debug, Clone)]
+pub enum UnifError {
+    BadHeader,
+    BadChunk([u8; 4]),
+    MissingBoardName,
+    MissingPRGROM,
+    UnrecognizedBoard(String),
+    ChecksumMismatch([u8; 4]),
+    Cartridge(CartridgeError),
+}
+
+impl Display for UnifError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for UnifError {}
+
+impl From<CartridgeError> for UnifError {
+    fn from(value: CartridgeError) -> Self {
+        UnifError::Cartridge(value)
+    }
+}
+
+const MAGIC: &[u8] = b"UNIF";
+const HEADER_SIZE: usize = 32;
+
+/// Board names, without any NES-/HVC- prefix, and the iNES mapper that implements them.
+const BOARDS: &[(&str, u16)] = &[
+    ("NROM", 0),
+    ("NROM-128", 0),
+    ("NROM-256", 0),
+    ("RROM", 0),
+    ("RROM-128", 0),
+    ("SAROM", 1),
+    ("SBROM", 1),
+    ("SCROM", 1),
+    ("SEROM", 1),
+    ("SGROM", 1),
+    ("SKROM", 1),
+    ("SLROM", 1),
+    ("SL1ROM", 1),
+    ("SNROM", 1),
+    ("SOROM", 1),
+    ("UNROM", 2),
+    ("UOROM", 2),
+    ("CNROM", 3),
+    ("TBROM", 4),
+    ("TEROM", 4),
+    ("TFROM", 4),
+    ("TGROM", 4),
+    ("TKROM", 4),
+    ("TLROM", 4),
+    ("TR1ROM", 4),
+    ("TSROM", 4),
+    ("TVROM", 4),
+    ("EKROM", 5),
+    ("ELROM", 5),
+    ("ETROM", 5),
+    ("EWROM", 5),
+    ("AMROM", 7),
+    ("ANROM", 7),
+    ("AOROM", 7),
+    ("PNROM", 9),
+    ("FJROM", 10),
+];
+
+/// Parses a UNIF file, producing the same thing `Cartridge::from_bytes` would for the equivalent NES 2.0 file.
+pub fn from_bytes(data: &[u8]) -> Result<Cartridge, UnifError> {
+    if data.len() < HEADER_SIZE || !data.starts_with(MAGIC) {
+        return Err(UnifError::BadHeader);
+    }
+    let mut remaining_data = &data[HEADER_SIZE..];
+
+    let mut board = None;
+    let mut prg: [Option<&[u8]>; 16] = [None; 16];
+    let mut chr: [Option<&[u8]>; 16] = [None; 16];
+    let mut prg_checksums: [Option<u32>; 16] = [None; 16];
+    let mut chr_checksums: [Option<u32>; 16] = [None; 16];
+    let mut mirroring = None;
+    let mut battery = false;
+    let mut tv_system = 0;
+
+    while !remaining_data.is_empty() {
+        if remaining_data.len() < 8 {
+            return Err(UnifError::BadHeader);
+        }
+        let id: [u8; 4] = remaining_data[0..4].try_into().unwrap();
+        let length = u32::from_le_bytes(remaining_data[4..8].try_into().unwrap()) as usize;
+        remaining_data = &remaining_data[8..];
+        if remaining_data.len() < length {
+            return Err(UnifError::BadChunk(id));
+        }
+        let chunk = &remaining_data[0..length];
+        remaining_data = &remaining_data[length..];
+
+        // numbered chunks end in a hex digit
+        let index = || {
+            (id[3] as char)
+                .to_digit(16)
+                .map(|x| x as usize)
+                .ok_or(UnifError::BadChunk(id))
+        };
+        match &id[0..3] {
+            b"PRG" => prg[index()?] = Some(chunk),
+            b"CHR" => chr[index()?] = Some(chunk),
+            b"PCK" => prg_checksums[index()?] = Some(read_checksum(id, chunk)?),
+            b"CCK" => chr_checksums[index()?] = Some(read_checksum(id, chunk)?),
+            _ => match &id {
+                b"MAPR" => {
+                    let end = chunk.iter().position(|x| *x == 0).unwrap_or(chunk.len());
+                    board = Some(String::from_utf8_lossy(&chunk[0..end]).into_owned());
+                }
+                b"MIRR" => mirroring = Some(*chunk.first().ok_or(UnifError::BadChunk(id))?),
+                b"BATR" => battery = chunk.first().map(|x| *x != 0).unwrap_or(true),
+                b"TVCI" => tv_system = *chunk.first().ok_or(UnifError::BadChunk(id))?,
+                // names, dumper info, controller types, etc.
+                _ => (),
+            },
+        }
+    }
+
+    let board = board.ok_or(UnifError::MissingBoardName)?;
+    let mapper = mapper_for_board(&board).ok_or(UnifError::UnrecognizedBoard(board))?;
+
+    for (i, (data, checksum)) in prg.iter().zip(prg_checksums.iter()).enumerate() {
+        verify_checksum(*b"PRG0", i, *data, *checksum)?;
+    }
+    for (i, (data, checksum)) in chr.iter().zip(chr_checksums.iter()).enumerate() {
+        verify_checksum(*b"CHR0", i, *data, *checksum)?;
+    }
+
+    let prg = prg.iter().flatten().copied().collect::<Vec<_>>().concat();
+    let chr = chr.iter().flatten().copied().collect::<Vec<_>>().concat();
+    if prg.is_empty() {
+        return Err(UnifError::MissingPRGROM);
+    }
+
+    // 8k of PRG RAM, and 8k of CHR RAM when there's no CHR ROM
+    let ram_size = 8 * 1024;
+    let header = HeaderBuilder::new()
+        .prg_rom_size(prg.len())
+        .chr_rom_size(chr.len())
+        .mapper(mapper, 0)
+        .nametable_arrangement(match mirroring {
+            Some(1) => NametableArrangement::Vertical,
+            Some(4) => NametableArrangement::FourScreenMirroring,
+            // single screen and mapper controlled are up to the mapper anyway
+            _ => NametableArrangement::Horizontal,
+        })
+        .battery(battery)
+        .prg_ram_size(
+            if battery { 0 } else { ram_size },
+            if battery { ram_size } else { 0 },
+        )
+        .chr_ram_size(if chr.is_empty() { ram_size } else { 0 }, 0)
+        .tv_system(match tv_system {
+            0 => TVSystem::NTSC,
+            1 => TVSystem::PAL,
+            _ => TVSystem::Both,
+        })
+        .build()?;
+
+    let mut result = header.to_bytes().to_vec();
+    result.extend_from_slice(&prg);
+    result.extend_from_slice(&chr);
+    Ok(Cartridge::from_bytes(result)?)
+}
+
+fn mapper_for_board(board: &str) -> Option<u16> {
+    let name = ["NES-", "HVC-", "UNL-"]
+        .iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(board);
+    BOARDS
+        .iter()
+        .find(|(x, _)| x.eq_ignore_ascii_case(name))
+        .map(|(_, mapper)| *mapper)
+}
+
+fn read_checksum(id: [u8; 4], chunk: &[u8]) -> Result<u32, UnifError> {
+    Ok(u32::from_le_bytes(
+        chunk.try_into().map_err(|_| UnifError::BadChunk(id))?,
+    ))
+}
+
+fn verify_checksum(
+    mut id: [u8; 4],
+    index: usize,
+    data: Option<&[u8]>,
+    checksum: Option<u32>,
+) -> Result<(), UnifError> {
+    id[3] = format!("{:X}", index).as_bytes()[0];
+    match (data, checksum) {
+        (Some(data), Some(checksum)) if crc32fast::hash(data) != checksum => {
+            Err(UnifError::ChecksumMismatch(id))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{from_bytes, UnifError};
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut result = id.to_vec();
+        result.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        result.extend_from_slice(data);
+        result
+    }
+
+    #[test]
+    pub fn nrom() {
+        let prg = vec![0xea; 16 * 1024];
+        let chr = vec![0x55; 8 * 1024];
+        let mut data = b"UNIF".to_vec();
+        data.resize(32, 0);
+        data.extend(chunk(b"MAPR", b"NES-NROM-128\0"));
+        data.extend(chunk(b"MIRR", &[1]));
+        data.extend(chunk(b"PRG0", &prg));
+        data.extend(chunk(b"PCK0", &crc32fast::hash(&prg).to_le_bytes()));
+        data.extend(chunk(b"CHR0", &chr));
+
+        let cartridge = from_bytes(&data).unwrap();
+        assert_eq!(cartridge.header().mapper_number(), 0);
+        assert_eq!(cartridge.pgr_rom().len(), 1);
+        assert_eq!(cartridge.pgr_rom()[0][0], 0xea);
+        assert_eq!(cartridge.chr_rom().len(), 1);
+        assert_eq!(cartridge.chr_rom()[0][0], 0x55);
+
+        data.extend(chunk(b"CCK0", &0u32.to_le_bytes()));
+        assert!(matches!(
+            from_bytes(&data),
+            Err(UnifError::ChecksumMismatch(x)) if &x == b"CHR0"
+        ));
+    }
+}
@@ -0,0 +1,39 @@
+// see http://soundfile.sapp.org/doc/WaveFormat/
+
+use std::io::{self, Write};
+
+const BITS_PER_SAMPLE: u16 = 16;
+const CHANNELS: u16 = 1;
+
+/// Writes mono 16-bit PCM. Samples are expected to be in -1..1 and are clamped to that.
+pub fn write<W>(w: &mut W, sample_rate: u32, samples: &[f32]) -> io::Result<()>
+where
+    W: Write,
+{
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+    let byte_rate = sample_rate * (block_align as u32);
+    let data_size = (samples.len() as u32) * (block_align as u32);
+
+    w.write_all(b"RIFF")?;
+    w.write_all(&(36 + data_size).to_le_bytes())?;
+    w.write_all(b"WAVE")?;
+
+    w.write_all(b"fmt ")?;
+    w.write_all(&16u32.to_le_bytes())?;
+    // PCM
+    w.write_all(&1u16.to_le_bytes())?;
+    w.write_all(&CHANNELS.to_le_bytes())?;
+    w.write_all(&sample_rate.to_le_bytes())?;
+    w.write_all(&byte_rate.to_le_bytes())?;
+    w.write_all(&block_align.to_le_bytes())?;
+    w.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+    w.write_all(b"data")?;
+    w.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        let sample = (sample.clamp(-1.0, 1.0) * (i16::MAX as f32)) as i16;
+        w.write_all(&sample.to_le_bytes())?;
+    }
+
+    Ok(())
+}